@@ -0,0 +1,94 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pngne::checksum::crc32;
+use pngne::{Chunk, ChunkType, Png};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+// Sizes of the image data in the generated files, split into IDAT chunks
+// of 64 KiB the way encoders usually do
+const SIZES: [usize; 3] = [4 * 1024, 256 * 1024, 4 * 1024 * 1024];
+const IDAT_SIZE: usize = 64 * 1024;
+
+// Xorshift noise, as incompressible as real image data
+fn noise(length: usize) -> Vec<u8> {
+  let mut state = 0x2545f4914f6cdd1du64;
+  (0..length)
+    .map(|_| {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      (state >> 56) as u8
+    })
+    .collect()
+}
+
+fn fixture(size: usize) -> Png {
+  let mut chunks = vec![Chunk::new(ChunkType::IHDR, vec![0, 0, 4, 0, 0, 0, 4, 0, 8, 2, 0, 0, 0])];
+  chunks.extend(noise(size).chunks(IDAT_SIZE).map(|data| Chunk::new(ChunkType::IDAT, data.to_vec())));
+  chunks.push(Chunk::new(ChunkType::TEXT, b"Comment\0generated for benchmarks".to_vec()));
+  chunks.push(Chunk::new(ChunkType::IEND, vec![]));
+  Png::from_chunks(chunks)
+}
+
+fn chunk_try_from(c: &mut Criterion) {
+  let mut group = c.benchmark_group("Chunk::try_from");
+  for size in SIZES {
+    let bytes = Chunk::new(ChunkType::from_str("ruSt").unwrap(), noise(size)).as_bytes();
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(size), &bytes, |b, bytes| {
+      b.iter(|| Chunk::try_from(black_box(bytes.as_slice())).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+  let mut group = c.benchmark_group("parse");
+  for size in SIZES {
+    let bytes = fixture(size).as_bytes();
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_with_input(BenchmarkId::new("Png::try_from", size), &bytes, |b, bytes| {
+      b.iter(|| Png::try_from(black_box(bytes.as_slice())).unwrap())
+    });
+    group.bench_with_input(BenchmarkId::new("Png::parse_borrowed", size), &bytes, |b, bytes| {
+      b.iter(|| Png::parse_borrowed(black_box(bytes.as_slice())).unwrap())
+    });
+    group.bench_with_input(BenchmarkId::new("Png::from_reader", size), &bytes, |b, bytes| {
+      b.iter(|| Png::from_reader(black_box(bytes.as_slice())).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn verify_crcs(c: &mut Criterion) {
+  let mut group = c.benchmark_group("CRC");
+  for size in SIZES {
+    let bytes = fixture(size).as_bytes();
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_with_input(BenchmarkId::new("crc32", size), &bytes, |b, bytes| b.iter(|| crc32(black_box(bytes))));
+    group.bench_with_input(BenchmarkId::new("Png::verify_crcs_parallel", size), &bytes, |b, bytes| {
+      b.iter(|| Png::verify_crcs_parallel(black_box(bytes)).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn write_to(c: &mut Criterion) {
+  let mut group = c.benchmark_group("Png::write_to");
+  for size in SIZES {
+    let png = fixture(size);
+    let mut out = Vec::with_capacity(size * 2);
+    group.throughput(Throughput::Bytes(png.as_bytes().len() as u64));
+    group.bench_with_input(BenchmarkId::from_parameter(size), &png, |b, png| {
+      b.iter(|| {
+        out.clear();
+        png.write_to(&mut out).unwrap();
+        black_box(out.len())
+      })
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, chunk_try_from, parse, verify_crcs, write_to);
+criterion_main!(benches);
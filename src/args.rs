@@ -0,0 +1,1031 @@
+use pngne::chunk_type::ChunkType;
+use pngne::chunks::LastModified;
+use pngne::diagnostics;
+use pngne::error::Result;
+use pngne::limits::Limits;
+use pngne::png::ParseMode;
+use pngne::payload::Compression;
+use age::x25519::Recipient;
+use clap::{ArgAction, ArgGroup, Args, Command, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use log::LevelFilter;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Parser)]
+#[command(name = "pngne", version, about = "Hide secret messages in PNG files")]
+pub struct Cli {
+  #[command(flatten)]
+  pub global: GlobalArgs,
+  #[command(subcommand)]
+  pub command: PngMeArgs,
+}
+
+// The one place the full command tree is built, used for parsing help
+// output and for generating shell completions
+pub fn command() -> Command {
+  Cli::command()
+}
+
+#[derive(Args, Clone, Copy, Default)]
+pub struct GlobalArgs {
+  /// Emit machine-readable JSON instead of text
+  #[arg(long, global = true)]
+  pub json: bool,
+  /// Show parse progress and CRC checks, repeat for byte offsets
+  #[arg(short, long, action = ArgAction::Count, global = true)]
+  pub verbose: u8,
+  /// Only report errors
+  #[arg(short, long, global = true, conflicts_with = "verbose")]
+  pub quiet: bool,
+  /// Split the image data into IDAT chunks of at most this many bytes when writing
+  #[arg(long, global = true, value_name = "BYTES", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+  pub idat_chunk_size: Option<usize>,
+  /// Refuse files with a chunk holding more data than this
+  #[arg(long, global = true, value_name = "BYTES")]
+  pub max_chunk_length: Option<usize>,
+  /// Refuse files with more chunks than this [default: 100000]
+  #[arg(long, global = true, value_name = "COUNT")]
+  pub max_chunks: Option<usize>,
+  /// Refuse zTXt, iTXt and iCCP chunks that inflate to more than this
+  #[arg(long, global = true, value_name = "BYTES")]
+  pub max_inflated_size: Option<usize>,
+  /// Refuse image data that inflates to more than RATIO times its size
+  #[arg(long, global = true, value_name = "RATIO")]
+  pub max_inflate_ratio: Option<usize>,
+  /// Fail on any bad CRC, truncated chunk or invalid chunk type
+  #[arg(long, global = true, conflicts_with = "lenient")]
+  pub strict: bool,
+  /// Read whatever is readable in damaged files, warning about the rest
+  #[arg(long, global = true)]
+  pub lenient: bool,
+}
+
+impl GlobalArgs {
+  // The mode given by --strict or --lenient, `default` when neither is
+  pub fn parse_mode(&self, default: ParseMode) -> ParseMode {
+    match (self.strict, self.lenient) {
+      (true, _) => ParseMode::Strict,
+      (_, true) => ParseMode::Lenient,
+      _ => default,
+    }
+  }
+
+  // What reading a file may take, from the --max-* flags
+  pub fn limits(&self) -> Limits {
+    let default = Limits::default();
+    Limits {
+      max_chunk_length: self.max_chunk_length.unwrap_or(default.max_chunk_length),
+      max_chunks: self.max_chunks.unwrap_or(default.max_chunks),
+      max_inflated_size: self.max_inflated_size.unwrap_or(default.max_inflated_size),
+      max_inflate_ratio: self.max_inflate_ratio.unwrap_or(default.max_inflate_ratio),
+    }
+  }
+
+  pub fn log_level(&self) -> LevelFilter {
+    match (self.quiet, self.verbose) {
+      (true, _) => LevelFilter::Error,
+      (false, 0) => LevelFilter::Info,
+      (false, 1) => LevelFilter::Debug,
+      (false, _) => LevelFilter::Trace,
+    }
+  }
+}
+
+#[derive(Subcommand)]
+pub enum PngMeArgs {
+  /// Hide a message in a new chunk of a PNG file
+  Encode(EncodeArgs),
+  /// Print the message stored in the first chunk of the given type
+  Decode(DecodeArgs),
+  /// Remove chunks of the given type from a PNG file
+  Remove(RemoveArgs),
+  /// List every chunk in a PNG file
+  Print(PrintArgs),
+  /// Report PNG files containing chunk types outside the PNG specification
+  Scan(ScanArgs),
+  /// Recompute and fix every chunk CRC in a PNG file
+  Repair(RepairArgs),
+  /// Keep every complete chunk of a truncated or damaged PNG file
+  Recover(RecoverArgs),
+  /// Remove every ancillary chunk from a PNG file
+  Strip(StripArgs),
+  /// Write the raw data of the first chunk of the given type to a file
+  Extract(ExtractArgs),
+  /// Insert a chunk at a chosen position in a PNG file
+  Insert(InsertArgs),
+  /// Replace the data of the first chunk of the given type, keeping its position
+  Replace(ReplaceArgs),
+  /// Compare the chunk structure of two PNG files
+  Diff(DiffArgs),
+  /// Copy ancillary, safe-to-copy chunks from one PNG file into another
+  Merge(MergeArgs),
+  /// Summarize image properties from the IHDR chunk
+  Info(InfoArgs),
+  /// Check signature, CRCs and chunk ordering, reporting every problem
+  Verify(VerifyArgs),
+  /// Print a hex and ASCII dump of a chunk's data
+  Hexdump(HexdumpArgs),
+  /// Print a shell completion script to stdout
+  Completions(CompletionsArgs),
+  /// Search the data of every chunk for a regex or byte pattern
+  Grep(GrepArgs),
+  /// Set the tIME chunk to the current time or a given timestamp
+  Touch(TouchArgs),
+  /// Set the physical pixel density stored in the pHYs chunk
+  SetDpi(SetDpiArgs),
+  /// Work with animated PNGs
+  #[command(subcommand)]
+  Apng(ApngCommand),
+  /// Re-deflate the image data, leaving every other chunk untouched
+  Recompress(RecompressArgs),
+  /// Write the pixels of a PNG file as raw 8-bit RGBA
+  ToRaw(ToRawArgs),
+  /// Build a PNG file from raw 8-bit RGBA pixels
+  FromRaw(FromRawArgs),
+  /// List every payload stored in a PNG file, with its id if it has one
+  ListMessages(ListMessagesArgs),
+  /// Report how many payload bytes each mode can hide in a PNG file
+  Capacity(CapacityArgs),
+  /// Score how likely a PNG file is to hide data, listing what gave it away
+  Detect(DetectArgs),
+  /// Report, extract or strip data hidden after the IEND chunk
+  Trailing(TrailingArgs),
+  /// Convert an Apple CgBI ("crushed") PNG from an iOS app back to a standard PNG
+  Uncrush(UncrushArgs),
+  /// Show the keyword and text of every tEXt, zTXt and iTXt chunk
+  Metadata(MetadataArgs),
+  /// Store a text under a keyword, replacing any text chunks already using it
+  SetText(SetTextArgs),
+  /// Print the text stored under a keyword
+  GetText(GetTextArgs),
+}
+
+#[derive(Subcommand)]
+pub enum ApngCommand {
+  /// Write every frame of an animated PNG to its own PNG file
+  Split(ApngSplitArgs),
+}
+
+// Where a payload is hidden
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+  /// In a chunk of its own
+  #[default]
+  Chunk,
+  /// In the least significant bits of the pixels, CHUNK_TYPE is ignored
+  Lsb,
+}
+
+#[derive(Args)]
+pub struct EncodeArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. ruSt
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// Message to store in the chunk
+  #[arg(required_unless_present = "data_file")]
+  pub message: Option<String>,
+  /// Store the contents of this file instead of MESSAGE, `-` for stdin
+  #[arg(long, conflicts_with_all = ["message", "as_text"])]
+  pub data_file: Option<PathBuf>,
+  /// Store MESSAGE in a text chunk, using CHUNK_TYPE as its keyword
+  #[arg(long)]
+  pub as_text: bool,
+  /// Store MESSAGE deflated in a zTXt chunk instead of tEXt
+  #[arg(long, requires = "as_text")]
+  pub compress_text: bool,
+  /// Compress the payload before storing it: none, zlib or zstd
+  #[arg(long, value_name = "METHOD", value_parser = parse_compression, conflicts_with = "as_text")]
+  pub compress: Option<Compression>,
+  /// Encrypt the payload with a passphrase, read from PNGNE_PASSPHRASE or prompted for
+  #[arg(long, conflicts_with = "as_text")]
+  pub encrypt: bool,
+  /// Encrypt the payload to this age public key (age1...), can be repeated
+  #[arg(long, value_name = "PUBKEY", value_parser = parse_recipient, conflicts_with_all = ["as_text", "encrypt"])]
+  pub recipient: Vec<Recipient>,
+  /// Tag the payload with an HMAC-SHA256 keyed with the contents of this file
+  #[arg(long, value_name = "KEYFILE", conflicts_with = "as_text")]
+  pub auth_key: Option<PathBuf>,
+  /// Split payloads larger than this across several chunks, after a manifest chunk
+  #[arg(long, value_name = "BYTES", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), conflicts_with = "as_text")]
+  pub fragment_size: Option<usize>,
+  /// Name the payload so it can sit next to others of the same chunk type
+  #[arg(long, value_name = "NAME", value_parser = parse_id, conflicts_with = "as_text")]
+  pub id: Option<String>,
+  /// Store the payload in a chunk, or in the pixels, which survives stripping ancillary chunks
+  #[arg(long, value_enum, default_value_t = Mode::Chunk)]
+  pub mode: Mode,
+  /// Low bits of each sample to use with --mode lsb, more holds more but shows more
+  #[arg(long, value_name = "BITS", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+  pub lsb_bits: u8,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+  /// Treat FILE_PATH as a directory and process every PNG below it
+  #[arg(short, long, conflicts_with = "output")]
+  pub recursive: bool,
+  /// Files processed at once with --recursive, 0 for one per CPU
+  #[arg(short, long, default_value_t = 0, requires = "recursive")]
+  pub jobs: usize,
+  /// Report what would change without writing anything
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct DecodeArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. ruSt
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// Read the tEXt, zTXt or iTXt chunk whose keyword is CHUNK_TYPE
+  #[arg(long)]
+  pub as_text: bool,
+  /// Decrypt the payload with a passphrase, read from PNGNE_PASSPHRASE or prompted for
+  #[arg(long, conflicts_with = "as_text")]
+  pub decrypt: bool,
+  /// Decrypt the payload with the age secret keys in this file, can be repeated
+  #[arg(long, value_name = "KEYFILE", conflicts_with = "as_text")]
+  pub identity: Vec<PathBuf>,
+  /// Refuse payloads without a valid HMAC-SHA256 keyed with the contents of this file
+  #[arg(long, value_name = "KEYFILE", conflicts_with = "as_text")]
+  pub auth_key: Option<PathBuf>,
+  /// Decode the payload named NAME instead of the first one
+  #[arg(long, value_name = "NAME", value_parser = parse_id, conflicts_with = "as_text")]
+  pub id: Option<String>,
+  /// Read the payload from a chunk, or from the pixels
+  #[arg(long, value_enum, default_value_t = Mode::Chunk)]
+  pub mode: Mode,
+  /// Write the payload to this file byte for byte instead of printing it
+  #[arg(short, long, conflicts_with = "recursive")]
+  pub out: Option<PathBuf>,
+  /// Treat FILE_PATH as a directory and process every PNG below it
+  #[arg(short, long)]
+  pub recursive: bool,
+  /// Files processed at once with --recursive, 0 for one per CPU
+  #[arg(short, long, default_value_t = 0, requires = "recursive")]
+  pub jobs: usize,
+  /// Keep running and re-run whenever FILE_PATH changes
+  #[arg(short, long, conflicts_with = "recursive")]
+  pub watch: bool,
+}
+
+#[derive(Args)]
+pub struct RemoveArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. ruSt
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// Remove every matching chunk instead of only the first
+  #[arg(long)]
+  pub all: bool,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+  /// Treat FILE_PATH as a directory and process every PNG below it
+  #[arg(short, long, conflicts_with = "output")]
+  pub recursive: bool,
+  /// Files processed at once with --recursive, 0 for one per CPU
+  #[arg(short, long, default_value_t = 0, requires = "recursive")]
+  pub jobs: usize,
+  /// Report what would change without writing anything
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct PrintArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Treat FILE_PATH as a directory and process every PNG below it
+  #[arg(short, long)]
+  pub recursive: bool,
+  /// Files processed at once with --recursive, 0 for one per CPU
+  #[arg(short, long, default_value_t = 0, requires = "recursive")]
+  pub jobs: usize,
+  /// Keep running and re-run whenever FILE_PATH changes
+  #[arg(short, long, conflicts_with = "recursive")]
+  pub watch: bool,
+  /// Never color the chunk table, even on a terminal
+  #[arg(long)]
+  pub no_color: bool,
+  /// Print each chunk with a template instead of the table, e.g.
+  /// "{index} {type} {length} {crc:08x}"
+  #[arg(long)]
+  pub format: Option<String>,
+  /// Fail on warnings with these codes instead of showing them, e.g.
+  /// --deny trailing-data,crc-mismatch
+  #[arg(long, value_name = "CODE", value_delimiter = ',', value_parser = parse_code, conflicts_with = "format")]
+  pub deny: Vec<&'static str>,
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+  /// Directory to search, or a single PNG file
+  pub path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RepairArgs {
+  /// PNG file to repair
+  pub file_path: PathBuf,
+  /// Write the repaired PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+  /// Report what would change without writing anything
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct RecoverArgs {
+  /// PNG file to recover
+  pub file_path: PathBuf,
+  /// Write the recovered PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+  /// End the recovered file with an IEND chunk if its own was lost
+  #[arg(long)]
+  pub add_iend: bool,
+  /// Report what would be recovered without writing anything
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct StripArgs {
+  /// PNG file to strip
+  pub file_path: PathBuf,
+  /// Ancillary chunk types to keep, e.g. --keep tRNS,gAMA
+  #[arg(long, value_delimiter = ',', value_parser = parse_chunk_type)]
+  pub keep: Vec<ChunkType>,
+  /// Write the stripped PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+  /// Treat FILE_PATH as a directory and process every PNG below it
+  #[arg(short, long, conflicts_with = "output")]
+  pub recursive: bool,
+  /// Files processed at once with --recursive, 0 for one per CPU
+  #[arg(short, long, default_value_t = 0, requires = "recursive")]
+  pub jobs: usize,
+  /// Report what would change without writing anything
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct ExtractArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. iCCP
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// Where to write the chunk data, `-` for stdout
+  #[arg(long)]
+  pub out: PathBuf,
+}
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("position").args(["before", "after", "index"])))]
+pub struct InsertArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. ruSt
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// File whose contents become the chunk data
+  #[arg(long)]
+  pub data_file: PathBuf,
+  /// Insert before the first chunk of this type
+  #[arg(long, value_parser = parse_chunk_type)]
+  pub before: Option<ChunkType>,
+  /// Insert after the last chunk of this type
+  #[arg(long, value_parser = parse_chunk_type)]
+  pub after: Option<ChunkType>,
+  /// Insert at this chunk index
+  #[arg(long)]
+  pub index: Option<usize>,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ReplaceArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. ruSt
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// New data for the chunk
+  pub new_data: String,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+  /// First PNG file
+  pub file_a: PathBuf,
+  /// Second PNG file
+  pub file_b: PathBuf,
+}
+
+#[derive(Args)]
+pub struct MergeArgs {
+  /// PNG file to copy chunks from
+  #[arg(long)]
+  pub from: PathBuf,
+  /// PNG file to copy chunks into
+  #[arg(long)]
+  pub into: PathBuf,
+  /// Write the merged PNG here instead of editing the --into file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Also decode the EXIF metadata stored in the eXIf chunk
+  #[arg(long)]
+  pub exif: bool,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+  /// PNG file to check
+  pub file_path: PathBuf,
+  /// Treat FILE_PATH as a directory and check every PNG below it
+  #[arg(short, long)]
+  pub recursive: bool,
+  /// Threads used to check CRCs, or files checked at once with
+  /// --recursive, 0 for one per CPU
+  #[arg(short, long, default_value_t = 1)]
+  pub jobs: usize,
+  /// Keep running and re-run whenever FILE_PATH changes
+  #[arg(short, long, conflicts_with = "recursive")]
+  pub watch: bool,
+  /// Treat warnings with these codes as problems, e.g. --deny trailing-data
+  #[arg(long, value_name = "CODE", value_delimiter = ',', value_parser = parse_code)]
+  pub deny: Vec<&'static str>,
+}
+
+#[derive(Args)]
+pub struct HexdumpArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Four letter chunk type, e.g. tEXt
+  #[arg(value_parser = parse_chunk_type)]
+  pub chunk_type: ChunkType,
+  /// Byte offset into the chunk data to start at
+  #[arg(long, default_value_t = 0)]
+  pub offset: usize,
+  /// Maximum number of bytes to dump
+  #[arg(long)]
+  pub length: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+  /// Shell to generate completions for
+  #[arg(value_enum)]
+  pub shell: Shell,
+}
+
+#[derive(Args)]
+pub struct GrepArgs {
+  /// Regular expression to search for, matched against raw bytes
+  pub pattern: String,
+  /// Directory to search, or a single PNG file
+  pub path: PathBuf,
+  /// Treat PATTERN as a literal string instead of a regex
+  #[arg(short = 'F', long, conflicts_with = "hex")]
+  pub fixed_strings: bool,
+  /// Treat PATTERN as hex encoded bytes, e.g. deadbeef
+  #[arg(long)]
+  pub hex: bool,
+  /// Only search chunks of this type
+  #[arg(short = 't', long = "type", value_parser = parse_chunk_type)]
+  pub chunk_type: Option<ChunkType>,
+}
+
+#[derive(Args)]
+pub struct TouchArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// UTC timestamp to store instead of the current time, e.g. 2024-02-29T13:05:09Z
+  #[arg(short, long, value_parser = parse_timestamp)]
+  pub time: Option<LastModified>,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct SetDpiArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// Horizontal resolution in dots per inch
+  pub dpi: f64,
+  /// Vertical resolution, if it differs from the horizontal one
+  pub dpi_y: Option<f64>,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ApngSplitArgs {
+  /// Animated PNG file to read
+  pub file_path: PathBuf,
+  /// Directory to write frame_000.png, frame_001.png, ... into
+  #[arg(short, long)]
+  pub out_dir: PathBuf,
+}
+
+#[derive(Args)]
+pub struct RecompressArgs {
+  /// PNG file to modify
+  pub file_path: PathBuf,
+  /// zlib compression level, from 0 (none) to 9 (smallest)
+  #[arg(short, long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+  pub level: u32,
+  /// Compress with zopfli instead of zlib, which is much slower but smaller
+  #[cfg(feature = "zopfli")]
+  #[arg(long, conflicts_with = "level")]
+  pub zopfli: bool,
+  /// Write the modified PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+  /// Report the new size without writing anything
+  #[arg(long)]
+  pub dry_run: bool,
+}
+
+#[derive(Args)]
+pub struct ToRawArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// File to write the pixels to, `-` for stdout
+  #[arg(short, long)]
+  pub out: PathBuf,
+}
+
+#[derive(Args)]
+pub struct FromRawArgs {
+  /// File of RGBA pixels, row by row, `-` for stdin
+  pub file_path: PathBuf,
+  /// Width of the image in pixels
+  #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+  pub width: u32,
+  /// Height of the image in pixels
+  #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+  pub height: u32,
+  /// PNG file to write, `-` for stdout
+  #[arg(short, long)]
+  pub out: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ListMessagesArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct CapacityArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Account for compressing the payload: none, zlib or zstd
+  #[arg(long, value_name = "METHOD", value_parser = parse_compression)]
+  pub compress: Option<Compression>,
+  /// Account for encrypting the payload with a passphrase
+  #[arg(long)]
+  pub encrypt: bool,
+  /// Account for encrypting the payload to this age public key, can be repeated
+  #[arg(long, value_name = "PUBKEY", value_parser = parse_recipient, conflicts_with = "encrypt")]
+  pub recipient: Vec<Recipient>,
+  /// Account for the HMAC-SHA256 tag encode --auth-key adds
+  #[arg(long)]
+  pub authenticate: bool,
+  /// Account for naming the payload NAME
+  #[arg(long, value_name = "NAME", value_parser = parse_id)]
+  pub id: Option<String>,
+  /// Low bits of each sample used with --mode lsb
+  #[arg(long, value_name = "BITS", default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+  pub lsb_bits: u8,
+}
+
+#[derive(Args)]
+pub struct DetectArgs {
+  /// PNG file to examine
+  pub file_path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct TrailingArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Write the data after IEND here, `-` for stdout
+  #[arg(long, value_name = "PATH")]
+  pub extract: Option<PathBuf>,
+  /// Remove the data after IEND, ending the file at IEND
+  #[arg(long)]
+  pub strip: bool,
+  /// Write the stripped PNG here instead of editing the file in place
+  #[arg(short, long, requires = "strip")]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct UncrushArgs {
+  /// CgBI file to convert
+  pub file_path: PathBuf,
+  /// Write the standard PNG here instead of converting the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct MetadataArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+}
+
+#[derive(Args)]
+pub struct SetTextArgs {
+  /// PNG file to edit
+  pub file_path: PathBuf,
+  /// Keyword to store the text under, e.g. Author
+  pub keyword: String,
+  /// Text to store, in tEXt if it's all Latin-1 and iTXt otherwise
+  pub text: String,
+  /// Write the edited PNG here instead of editing the file in place
+  #[arg(short, long)]
+  pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct GetTextArgs {
+  /// PNG file to read
+  pub file_path: PathBuf,
+  /// Keyword of the text to print
+  pub keyword: String,
+}
+
+fn parse_timestamp(s: &str) -> Result<LastModified, String> {
+  LastModified::from_str(s).map_err(|e| e.to_string())
+}
+
+fn parse_compression(s: &str) -> Result<Compression, String> {
+  Compression::from_str(s).map_err(|e| e.to_string())
+}
+
+fn parse_recipient(s: &str) -> Result<Recipient, String> {
+  Recipient::from_str(s).map_err(|e| e.to_string())
+}
+
+fn parse_id(s: &str) -> Result<String, String> {
+  match s.len() {
+    1..=255 => Ok(s.to_string()),
+    length => Err(format!("ids are 1 to 255 bytes, this one is {}", length)),
+  }
+}
+
+fn parse_code(s: &str) -> Result<&'static str, String> {
+  diagnostics::CODES
+    .iter()
+    .find(|&&code| code == s)
+    .copied()
+    .ok_or_else(|| format!("unknown code `{}`, expected one of {}", s, diagnostics::CODES.join(", ")))
+}
+
+fn parse_chunk_type(s: &str) -> Result<ChunkType, String> {
+  ChunkType::from_str(s).map_err(|e| format!("invalid chunk type `{}`: {}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_cli(list: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("pngne").chain(list.iter().copied()))
+    }
+
+    fn parse(list: &[&str]) -> Result<PngMeArgs, clap::Error> {
+        parse_cli(list).map(|cli| cli.command)
+    }
+
+    #[test]
+    fn test_touch_time() {
+        let cli = parse_cli(&["touch", "dice.png", "--time", "2024-02-29T13:05:09Z"]).unwrap();
+        let PngMeArgs::Touch(args) = cli.command else { panic!("expected touch") };
+        assert_eq!(args.time.unwrap().to_string(), "2024-02-29T13:05:09Z");
+
+        assert!(parse_cli(&["touch", "dice.png", "--time", "tomorrow"]).is_err());
+    }
+
+    #[test]
+    fn test_cli_definition() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn test_parse_encode() {
+        let parsed = parse(&["encode", "dice.png", "ruSt", "hello"]).unwrap();
+        let PngMeArgs::Encode(encode) = parsed else { panic!("expected encode") };
+        assert_eq!(encode.file_path, PathBuf::from("dice.png"));
+        assert_eq!(encode.chunk_type.to_string(), "ruSt");
+        assert_eq!(encode.message.as_deref(), Some("hello"));
+        assert!(encode.output.is_none());
+
+        let parsed = parse(&["encode", "-", "ruSt", "hello", "--output", "-"]).unwrap();
+        let PngMeArgs::Encode(encode) = parsed else { panic!("expected encode") };
+        assert_eq!(encode.output, Some(PathBuf::from("-")));
+        assert_eq!((encode.mode, encode.lsb_bits), (Mode::Chunk, 1));
+
+        let parsed = parse(&["encode", "dice.png", "ruSt", "hello", "--mode", "lsb", "--lsb-bits", "2"]).unwrap();
+        let PngMeArgs::Encode(encode) = parsed else { panic!("expected encode") };
+        assert_eq!((encode.mode, encode.lsb_bits), (Mode::Lsb, 2));
+        assert!(parse(&["encode", "dice.png", "ruSt", "hello", "--lsb-bits", "9"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_decode() {
+        let parsed = parse(&["decode", "dice.png", "ruSt"]).unwrap();
+        let PngMeArgs::Decode(decode) = parsed else { panic!("expected decode") };
+        assert_eq!(decode.file_path, PathBuf::from("dice.png"));
+        assert_eq!(decode.chunk_type.to_string(), "ruSt");
+        assert!(decode.id.is_none());
+        assert_eq!(decode.mode, Mode::Chunk);
+
+        let parsed = parse(&["decode", "dice.png", "ruSt", "--id", "notes"]).unwrap();
+        let PngMeArgs::Decode(decode) = parsed else { panic!("expected decode") };
+        assert_eq!(decode.id.as_deref(), Some("notes"));
+        assert!(parse(&["decode", "dice.png", "ruSt", "--id", ""]).is_err());
+
+        let parsed = parse(&["decode", "dice.png", "ruSt", "--mode", "lsb"]).unwrap();
+        let PngMeArgs::Decode(decode) = parsed else { panic!("expected decode") };
+        assert_eq!(decode.mode, Mode::Lsb);
+    }
+
+    #[test]
+    fn test_parse_remove() {
+        let parsed = parse(&["remove", "dice.png", "ruSt"]).unwrap();
+        let PngMeArgs::Remove(remove) = parsed else { panic!("expected remove") };
+        assert_eq!(remove.chunk_type.to_string(), "ruSt");
+        assert!(!remove.all);
+
+        let parsed = parse(&["remove", "--all", "dice.png", "ruSt"]).unwrap();
+        let PngMeArgs::Remove(remove) = parsed else { panic!("expected remove") };
+        assert_eq!(remove.file_path, PathBuf::from("dice.png"));
+        assert!(remove.all);
+
+        let parsed = parse(&["remove", "dice.png", "ruSt", "-o", "out.png"]).unwrap();
+        let PngMeArgs::Remove(remove) = parsed else { panic!("expected remove") };
+        assert_eq!(remove.output, Some(PathBuf::from("out.png")));
+    }
+
+    #[test]
+    fn test_parse_print() {
+        let parsed = parse(&["print", "dice.png"]).unwrap();
+        let PngMeArgs::Print(print) = parsed else { panic!("expected print") };
+        assert_eq!(print.file_path, PathBuf::from("dice.png"));
+    }
+
+    #[test]
+    fn test_parse_recursive() {
+        let parsed = parse(&["print", "-r", "screenshots"]).unwrap();
+        let PngMeArgs::Print(print) = parsed else { panic!("expected print") };
+        assert!(print.recursive);
+
+        let parsed = parse(&["decode", "--recursive", "screenshots", "ruSt"]).unwrap();
+        let PngMeArgs::Decode(decode) = parsed else { panic!("expected decode") };
+        assert!(decode.recursive);
+        assert_eq!(decode.jobs, 0);
+
+        let parsed = parse(&["remove", "-r", "-j", "8", "screenshots", "ruSt"]).unwrap();
+        let PngMeArgs::Remove(remove) = parsed else { panic!("expected remove") };
+        assert_eq!(remove.jobs, 8);
+
+        assert!(parse(&["encode", "-r", "screenshots", "ruSt", "hi", "-o", "out.png"]).is_err());
+        assert!(parse(&["print", "-j", "2", "dice.png"]).is_err());
+        assert!(parse(&["remove", "-r", "screenshots", "ruSt", "-o", "out.png"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_scan() {
+        let parsed = parse(&["scan", "screenshots"]).unwrap();
+        let PngMeArgs::Scan(scan) = parsed else { panic!("expected scan") };
+        assert_eq!(scan.path, PathBuf::from("screenshots"));
+    }
+
+    #[test]
+    fn test_parse_repair() {
+        let parsed = parse(&["repair", "dice.png", "-o", "fixed.png"]).unwrap();
+        let PngMeArgs::Repair(repair) = parsed else { panic!("expected repair") };
+        assert_eq!(repair.file_path, PathBuf::from("dice.png"));
+        assert_eq!(repair.output, Some(PathBuf::from("fixed.png")));
+    }
+
+    #[test]
+    fn test_parse_strip() {
+        let parsed = parse(&["strip", "dice.png"]).unwrap();
+        let PngMeArgs::Strip(strip) = parsed else { panic!("expected strip") };
+        assert!(strip.keep.is_empty());
+
+        let parsed = parse(&["strip", "dice.png", "--keep", "tRNS,gAMA", "--keep", "pHYs"]).unwrap();
+        let PngMeArgs::Strip(strip) = parsed else { panic!("expected strip") };
+        let keep: Vec<String> = strip.keep.iter().map(|c| c.to_string()).collect();
+        assert_eq!(keep, vec!["tRNS", "gAMA", "pHYs"]);
+
+        assert!(parse(&["strip", "dice.png", "--keep", "tRN5"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_extract() {
+        let parsed = parse(&["extract", "dice.png", "iCCP", "--out", "profile.icc"]).unwrap();
+        let PngMeArgs::Extract(extract) = parsed else { panic!("expected extract") };
+        assert_eq!(extract.chunk_type.to_string(), "iCCP");
+        assert_eq!(extract.out, PathBuf::from("profile.icc"));
+
+        assert!(parse(&["extract", "dice.png", "iCCP"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_insert() {
+        let parsed = parse(&["insert", "dice.png", "ruSt", "--data-file", "x.bin", "--before", "IEND"]).unwrap();
+        let PngMeArgs::Insert(insert) = parsed else { panic!("expected insert") };
+        assert_eq!(insert.data_file, PathBuf::from("x.bin"));
+        assert_eq!(insert.before.unwrap().to_string(), "IEND");
+        assert!(insert.after.is_none());
+        assert!(insert.index.is_none());
+
+        let parsed = parse(&["insert", "dice.png", "ruSt", "--data-file", "x.bin", "--index", "2"]).unwrap();
+        let PngMeArgs::Insert(insert) = parsed else { panic!("expected insert") };
+        assert_eq!(insert.index, Some(2));
+
+        assert!(parse(&["insert", "dice.png", "ruSt", "--data-file", "x.bin", "--before", "IEND", "--after", "IHDR"]).is_err());
+        assert!(parse(&["insert", "dice.png", "ruSt"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_replace() {
+        let parsed = parse(&["replace", "dice.png", "ruSt", "new message"]).unwrap();
+        let PngMeArgs::Replace(replace) = parsed else { panic!("expected replace") };
+        assert_eq!(replace.chunk_type.to_string(), "ruSt");
+        assert_eq!(replace.new_data, "new message");
+    }
+
+    #[test]
+    fn test_parse_diff() {
+        let parsed = parse(&["diff", "a.png", "b.png"]).unwrap();
+        let PngMeArgs::Diff(diff) = parsed else { panic!("expected diff") };
+        assert_eq!(diff.file_a, PathBuf::from("a.png"));
+        assert_eq!(diff.file_b, PathBuf::from("b.png"));
+    }
+
+    #[test]
+    fn test_parse_merge() {
+        let parsed = parse(&["merge", "--from", "old.png", "--into", "new.png"]).unwrap();
+        let PngMeArgs::Merge(merge) = parsed else { panic!("expected merge") };
+        assert_eq!(merge.from, PathBuf::from("old.png"));
+        assert_eq!(merge.into, PathBuf::from("new.png"));
+        assert!(merge.output.is_none());
+
+        assert!(parse(&["merge", "--from", "old.png"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let parsed = parse(&["info", "dice.png"]).unwrap();
+        let PngMeArgs::Info(info) = parsed else { panic!("expected info") };
+        assert_eq!(info.file_path, PathBuf::from("dice.png"));
+    }
+
+    #[test]
+    fn test_parse_verify() {
+        let parsed = parse(&["verify", "dice.png"]).unwrap();
+        let PngMeArgs::Verify(verify) = parsed else { panic!("expected verify") };
+        assert_eq!(verify.file_path, PathBuf::from("dice.png"));
+        assert_eq!(verify.jobs, 1);
+
+        let parsed = parse(&["verify", "-j", "4", "dice.png"]).unwrap();
+        let PngMeArgs::Verify(verify) = parsed else { panic!("expected verify") };
+        assert_eq!(verify.jobs, 4);
+    }
+
+    #[test]
+    fn test_parse_hexdump() {
+        let parsed = parse(&["hexdump", "dice.png", "IDAT", "--offset", "16", "--length", "32"]).unwrap();
+        let PngMeArgs::Hexdump(hexdump) = parsed else { panic!("expected hexdump") };
+        assert_eq!(hexdump.chunk_type.to_string(), "IDAT");
+        assert_eq!(hexdump.offset, 16);
+        assert_eq!(hexdump.length, Some(32));
+
+        let parsed = parse(&["hexdump", "dice.png", "IDAT"]).unwrap();
+        let PngMeArgs::Hexdump(hexdump) = parsed else { panic!("expected hexdump") };
+        assert_eq!(hexdump.offset, 0);
+        assert_eq!(hexdump.length, None);
+    }
+
+    #[test]
+    fn test_parse_completions() {
+        let parsed = parse(&["completions", "powershell"]).unwrap();
+        let PngMeArgs::Completions(completions) = parsed else { panic!("expected completions") };
+        assert_eq!(completions.shell, Shell::PowerShell);
+
+        assert!(parse(&["completions", "tcsh"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        for command in ["print", "verify"] {
+            assert!(parse(&[command, "--watch", "dice.png"]).is_ok());
+        }
+        let parsed = parse(&["decode", "-w", "dice.png", "ruSt"]).unwrap();
+        let PngMeArgs::Decode(decode) = parsed else { panic!("expected decode") };
+        assert!(decode.watch);
+
+        assert!(parse(&["print", "--watch", "--recursive", "images"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        let parsed = parse(&["encode", "--dry-run", "dice.png", "ruSt", "hello"]).unwrap();
+        let PngMeArgs::Encode(encode) = parsed else { panic!("expected encode") };
+        assert!(encode.dry_run);
+
+        for list in [&["remove", "dice.png", "ruSt", "--dry-run"][..], &["strip", "dice.png", "--dry-run"], &["repair", "dice.png", "--dry-run"]] {
+            assert!(parse(list).is_ok(), "{:?}", list);
+        }
+    }
+
+    #[test]
+    fn test_parse_verbosity() {
+        let level = |list: &[&str]| parse_cli(list).unwrap().global.log_level();
+
+        assert_eq!(level(&["print", "dice.png"]), LevelFilter::Info);
+        assert_eq!(level(&["print", "-v", "dice.png"]), LevelFilter::Debug);
+        assert_eq!(level(&["-vv", "print", "dice.png"]), LevelFilter::Trace);
+        assert_eq!(level(&["print", "dice.png", "--quiet"]), LevelFilter::Error);
+        assert!(parse_cli(&["print", "-q", "-v", "dice.png"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_no_color() {
+        let parsed = parse(&["print", "dice.png", "--no-color"]).unwrap();
+        let PngMeArgs::Print(print) = parsed else { panic!("expected print") };
+        assert!(print.no_color);
+    }
+
+    #[test]
+    fn test_parse_grep() {
+        let parsed = parse(&["grep", "-F", "a.b", "images", "--type", "tEXt"]).unwrap();
+        let PngMeArgs::Grep(grep) = parsed else { panic!("expected grep") };
+        assert_eq!(grep.pattern, "a.b");
+        assert_eq!(grep.path, PathBuf::from("images"));
+        assert!(grep.fixed_strings);
+        assert_eq!(grep.chunk_type.unwrap().to_string(), "tEXt");
+
+        assert!(parse(&["grep", "--hex", "-F", "00ff", "images"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let parsed = parse(&["print", "dice.png", "--format", "{type} {crc:x}"]).unwrap();
+        let PngMeArgs::Print(print) = parsed else { panic!("expected print") };
+        assert_eq!(print.format.as_deref(), Some("{type} {crc:x}"));
+    }
+
+    #[test]
+    fn test_parse_json() {
+        assert!(!parse_cli(&["print", "dice.png"]).unwrap().global.json);
+        assert!(parse_cli(&["print", "dice.png", "--json"]).unwrap().global.json);
+        assert!(parse_cli(&["--json", "decode", "dice.png", "ruSt"]).unwrap().global.json);
+    }
+
+    #[test]
+    fn test_parse_invalid_chunk_type() {
+        assert!(parse(&["encode", "dice.png", "ru5t", "hello"]).is_err());
+        assert!(parse(&["decode", "dice.png", "toolong"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_missing_arguments() {
+        assert!(parse(&["encode", "dice.png"]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_unknown_subcommand() {
+        assert!(parse(&["frobnicate"]).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::pixels;
+use crate::png::Png;
+use crate::zlib;
+
+// Apple's iOS build tools "crush" PNGs for the GPU: a CgBI chunk goes in
+// front of IHDR, the image data becomes a bare deflate stream with no zlib
+// header or Adler-32, and RGB(A) pixels are stored as BGR(A) with their
+// color premultiplied by alpha. The chunk structure is still standard, so
+// such files parse, but other decoders can't show them.
+pub fn is_cgbi(png: &Png) -> bool {
+  png.chunks().next().is_some_and(|chunk| *chunk.chunk_type() == ChunkType::CGBI)
+}
+
+// A standard PNG with the same image as the crushed `png`. The other
+// chunks are kept as they are.
+pub fn uncrush(png: &Png) -> Result<Png> {
+  if !is_cgbi(png) {
+    return Err(Error::ChunkNotFound);
+  }
+  let ihdr = png.ihdr()?;
+  if png.chunk_by_type("IDAT").is_none() {
+    return Err(Error::ChunkNotFound);
+  }
+  let mut image = pixels::decode(&zlib::inflate_raw("IDAT", &png.compressed_image_data())?, &ihdr)?;
+
+  match (ihdr.color_type, ihdr.bit_depth) {
+    (2, 8) => image.data.chunks_exact_mut(3).for_each(|pixel| pixel.swap(0, 2)),
+    (6, 8) => image.data.chunks_exact_mut(4).for_each(unpremultiply),
+    // Only 8-bit RGB and RGBA are ever crushed
+    _ => log::debug!("leaving the {} pixels as they are", ihdr.color_type_name()),
+  }
+
+  let chunks: Vec<Chunk> = png.chunks().skip(1).cloned().collect();
+  let mut uncrushed = Png::from_chunks(chunks);
+  uncrushed.set_image_data(&image)?;
+  uncrushed.set_trailing_data(png.trailing_data().to_vec());
+  Ok(uncrushed)
+}
+
+// BGRA premultiplied by alpha to straight RGBA
+fn unpremultiply(pixel: &mut [u8]) {
+  pixel.swap(0, 2);
+  let alpha = pixel[3] as u32;
+  for sample in &mut pixel[..3] {
+    *sample = match alpha {
+      0 => 0,
+      _ => ((*sample as u32 * 255 + alpha / 2) / alpha).min(255) as u8,
+    };
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::Ihdr;
+    use crate::pixels::ImageData;
+
+    // Crushes `image` the way Apple's tools do, given BGRA pixels that are
+    // already premultiplied
+    fn crushed(ihdr: Ihdr, pixels: Vec<u8>) -> Png {
+        let zlib = zlib::deflate(&pixels::encode(&ImageData { ihdr, data: pixels }).unwrap());
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::CGBI, vec![0x50, 0, 0x20, 0x06]),
+            Chunk::from(ihdr),
+            Chunk::new(ChunkType::IDAT, zlib[2..zlib.len() - 4].to_vec()),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ])
+    }
+
+    fn ihdr(color_type: u8) -> Ihdr {
+        Ihdr { width: 2, height: 1, bit_depth: 8, color_type, compression_method: 0, filter_method: 0, interlace_method: 0 }
+    }
+
+    #[test]
+    fn test_uncrush_rgba() {
+        // Opaque red, then blue at half alpha, stored as 0x80 premultiplied
+        let png = crushed(ihdr(6), vec![0, 0, 255, 255, 0x80, 0, 0, 0x80]);
+        assert!(is_cgbi(&png));
+        assert!(png.decode_pixels().is_err());
+
+        let uncrushed = uncrush(&png).unwrap();
+        assert!(!is_cgbi(&uncrushed));
+        let names: Vec<String> = uncrushed.chunks().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(names, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(uncrushed.decode_pixels().unwrap().data, vec![255, 0, 0, 255, 0, 0, 255, 0x80]);
+    }
+
+    #[test]
+    fn test_uncrush_rgb() {
+        let png = crushed(ihdr(2), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(uncrush(&png).unwrap().decode_pixels().unwrap().data, vec![3, 2, 1, 6, 5, 4]);
+
+        let mut transparent = [9, 9, 9, 0];
+        unpremultiply(&mut transparent);
+        assert_eq!(transparent, [0, 0, 0, 0]);
+        assert_eq!(uncrush(&uncrush(&png).unwrap()).err(), Some(Error::ChunkNotFound));
+    }
+}
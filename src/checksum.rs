@@ -0,0 +1,68 @@
+// The CRC-32 used by PNG chunks and fragment manifests. With the default
+// `crc32fast` feature it uses the CPU's CRC or carry-less multiply
+// instructions where there are any. Without it the table-driven `crc` crate
+// does the work.
+#[cfg(feature = "crc32fast")]
+pub struct Crc32(crc32fast::Hasher);
+
+#[cfg(not(feature = "crc32fast"))]
+pub struct Crc32(crc::crc32::Digest);
+
+#[cfg(feature = "crc32fast")]
+impl Crc32 {
+  pub fn new() -> Crc32 {
+    Crc32(crc32fast::Hasher::new())
+  }
+
+  pub fn update(&mut self, bytes: &[u8]) {
+    self.0.update(bytes);
+  }
+
+  pub fn finish(self) -> u32 {
+    self.0.finalize()
+  }
+}
+
+#[cfg(not(feature = "crc32fast"))]
+impl Crc32 {
+  pub fn new() -> Crc32 {
+    Crc32(crc::crc32::Digest::new(crc::crc32::IEEE))
+  }
+
+  pub fn update(&mut self, bytes: &[u8]) {
+    crc::crc32::Hasher32::write(&mut self.0, bytes);
+  }
+
+  pub fn finish(self) -> u32 {
+    crc::crc32::Hasher32::sum32(&self.0)
+  }
+}
+
+impl Default for Crc32 {
+  fn default() -> Self {
+    Crc32::new()
+  }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = Crc32::new();
+  crc.update(bytes);
+  crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"RuStThis is where your secret message will be!"), 2882656334);
+
+        let mut crc = Crc32::new();
+        crc.update(b"RuSt");
+        crc.update(b"This is where your secret message will be!");
+        assert_eq!(crc.finish(), 2882656334);
+    }
+}
@@ -1,8 +1,17 @@
-use crc::crc32::checksum_ieee;
+use crate::checksum::Crc32;
 use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
 use std::convert::TryFrom;
 use std::fmt;
+use std::io::Write;
 
+// The spec caps chunk lengths at 2^31 - 1 bytes
+pub const MAX_LENGTH: usize = (1 << 31) - 1;
+
+// Fields are private so the length and CRC always match the type and data.
+// Build chunks with `new`, or parse them with `try_from`. Equality and
+// hashing cover the CRC too, though it always follows from the type and data.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Chunk {
   length: u32,
   chunk_type: ChunkType,
@@ -10,18 +19,97 @@ pub struct Chunk {
   crc: u32
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Error {
-  InputTooSmall(usize),
-  ChunkTypeNotValid([u8; 4]),
-  CrcMissMatch(u32, u32),
-  InvalidHeader([u8; 8]),
-  NotOk,
-  ChunkDoesNotExsist,
-  TooSmall,
-  ValueNotInRange,
-  StrNotCorrctLngth,
-  None
+// The CRC of a chunk, fed its type up front and then its data in as many
+// pieces as it arrives in, so nothing has to be joined just to checksum it
+pub struct CrcHasher(Crc32);
+
+impl CrcHasher {
+  pub fn new(chunk_type: &ChunkType) -> CrcHasher {
+    let mut crc = Crc32::new();
+    crc.update(&chunk_type.bytes());
+    CrcHasher(crc)
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    self.0.update(data);
+  }
+
+  pub fn finish(self) -> u32 {
+    self.0.finish()
+  }
+}
+
+// Reads the length field at the start of `value` and checks that the whole
+// chunk it announces fits, returning the data length. Offsets in errors are
+// relative to `value`.
+pub(crate) fn framed_length(value: &[u8]) -> Result<usize> {
+  if value.len() < 12 {
+    return Err(Error::TruncatedChunk { expected: 12, actual: value.len(), offset: 0 });
+  }
+
+  let length = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+  if length as usize > MAX_LENGTH {
+    return Err(Error::LengthFieldInvalid { length, offset: 0 });
+  }
+
+  let length = length as usize;
+  if value.len() - 12 < length {
+    return Err(Error::TruncatedChunk { expected: length + 12, actual: value.len(), offset: 0 });
+  }
+  Ok(length)
+}
+
+// A read-only view of a chunk inside a larger buffer. Unlike `Chunk` the
+// data is borrowed rather than copied, which adds up when scanning many files
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkRef<'a> {
+  chunk_type: ChunkType,
+  data: &'a [u8],
+  crc: u32,
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+  type Error = Error;
+
+  fn try_from(value: &'a [u8]) -> Result<Self> {
+    let length = framed_length(value)?;
+
+    let type_bytes = [value[4], value[5], value[6], value[7]];
+    let chunk_type = ChunkType::try_from(type_bytes).map_err(|_| Error::ChunkTypeNotValid(type_bytes))?;
+
+    let crc_bytes = &value[length + 8..length + 12];
+    let stored = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+    let mut hasher = CrcHasher::new(&chunk_type);
+    hasher.update(&value[8..length + 8]);
+    let computed = hasher.finish();
+    if computed != stored {
+      return Err(Error::CrcMismatch(computed, stored));
+    }
+
+    Ok(Self { chunk_type, data: &value[8..length + 8], crc: stored })
+  }
+}
+
+impl<'a> ChunkRef<'a> {
+  pub fn length(&self) -> u32 {
+    self.data.len() as u32
+  }
+
+  pub fn chunk_type(&self) -> &ChunkType {
+    &self.chunk_type
+  }
+
+  pub fn data(&self) -> &'a [u8] {
+    self.data
+  }
+
+  pub fn crc(&self) -> u32 {
+    self.crc
+  }
+
+  pub fn to_chunk(&self) -> Chunk {
+    Chunk::new(self.chunk_type, self.data.to_vec())
+  }
 }
 
 impl fmt::Display for Chunk {
@@ -39,79 +127,84 @@ impl fmt::Display for Chunk {
 impl TryFrom<&[u8]> for Chunk {
   type Error = Error;
 
-  fn try_from(value: &[u8]) -> Result<Self, Error> {
-    if value.len() < 12 {
-      return Err(Error::InputTooSmall(value.len()));
-    }
-    
-    let (data_length, value) = value.split_at(4);
-    
-    let data_length = match data_length.try_into() {
-      Ok(dat) => u32::from_be_bytes(dat) as usize,
-      Err(_) => return Err(Error::NotOk)
-    };
-    
-    let (chunk_type, value) = value.split_at(4);
-
-    let chunk_type: &[u8; 4] = match chunk_type.try_into() {
-      Ok(dat) => dat,
-      Err(_) => return Err(Error::NotOk)
-    };
-    
-    let chunk_type = match ChunkType::try_from(chunk_type) {
-      Ok(dat) => dat,
-      Err(_) => return Err(Error::ChunkTypeNotValid(chunk_type.to_owned()))
-    };
+  fn try_from(value: &[u8]) -> Result<Self> {
+    let (chunk, stored_crc) = Chunk::try_from_lenient(value)?;
 
-    let (data, value) = value.split_at(data_length);
-    let (crc_true, _) = value.split_at(4);
-
-    let bytes: Vec<u8> = chunk_type
-      .bytes()
-      .iter()
-      .chain(data.iter())
-      .copied()
-      .collect();
-    
-    let crc = checksum_ieee(&bytes);
-    let true_crc = match crc_true.try_into() {
-      Ok(dat) => u32::from_be_bytes(dat),
-      Err(_) => return Err(Error::NotOk)
-    };
-  
-    if crc != true_crc {
-      return Err(Error::CrcMissMatch(crc, true_crc))
+    if chunk.crc != stored_crc {
+      return Err(Error::CrcMismatch(chunk.crc, stored_crc))
     }
 
-    let new = Self {
-        length: data_length as u32,
-        chunk_type,
-        data: data.into(),
-        crc
-    };
-  
-    Ok(new)
-    
+    Ok(chunk)
   }
 }
 
 impl Chunk {
+  // Parses a chunk without rejecting a bad CRC. The returned chunk carries
+  // the CRC computed from its contents, alongside the CRC stored in `value`.
+  pub fn try_from_lenient(value: &[u8]) -> Result<(Chunk, u32)> {
+    let length = framed_length(value)?;
+
+    let type_bytes = [value[4], value[5], value[6], value[7]];
+    let chunk_type = ChunkType::try_from(type_bytes).map_err(|_| Error::ChunkTypeNotValid(type_bytes))?;
+
+    let crc_bytes = &value[length + 8..length + 12];
+    let stored_crc = u32::from_be_bytes([crc_bytes[0], crc_bytes[1], crc_bytes[2], crc_bytes[3]]);
+
+    Ok((Chunk::new(chunk_type, value[8..length + 8].to_vec()), stored_crc))
+  }
+
+  // Panics if `data` is over `MAX_LENGTH` bytes, since the length field
+  // couldn't hold it. Use `try_new` for data of any size.
   pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-    let bytes: Vec<u8> = chunk_type
-      .bytes()
-      .iter()
-      .chain(data.iter())
-      .copied()
-      .collect();
-
-    let crc = checksum_ieee(&bytes);
-    
-    Self { 
+    match Chunk::try_new(chunk_type, data) {
+      Ok(chunk) => chunk,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  pub fn try_new(chunk_type: ChunkType, data: Vec<u8>) -> Result<Chunk> {
+    if data.len() > MAX_LENGTH {
+      return Err(Error::ChunkTooLarge(data.len()));
+    }
+    let mut chunk = Self { 
       length: data.len() as u32, 
       chunk_type, 
       data, 
-      crc
-   }
+      crc: 0
+    };
+    chunk.recompute_crc();
+    Ok(chunk)
+  }
+
+  // For parsers that already ran `data` through a `CrcHasher`
+  pub(crate) fn with_crc(chunk_type: ChunkType, data: Vec<u8>, crc: u32) -> Chunk {
+    Self { length: data.len() as u32, chunk_type, data, crc }
+  }
+
+  // Replaces the data, updating the length and CRC to match. Panics like
+  // `new` on data over `MAX_LENGTH` bytes.
+  pub fn set_data(&mut self, data: Vec<u8>) {
+    if data.len() > MAX_LENGTH {
+      panic!("{}", Error::ChunkTooLarge(data.len()));
+    }
+    self.length = data.len() as u32;
+    self.data = data;
+    self.recompute_crc();
+  }
+
+  pub fn set_chunk_type(&mut self, chunk_type: ChunkType) {
+    self.chunk_type = chunk_type;
+    self.recompute_crc();
+  }
+
+  // Computes the CRC over the type and data again and returns it. Chunks
+  // parsed leniently already carry the computed CRC, this is for callers
+  // that want to be sure before writing a repaired file.
+  pub fn recompute_crc(&mut self) -> u32 {
+    let mut hasher = CrcHasher::new(&self.chunk_type);
+    hasher.update(&self.data);
+    self.crc = hasher.finish();
+    self.crc
   }
   
   pub fn length(&self) -> u32 {
@@ -130,22 +223,37 @@ impl Chunk {
     self.crc
   }
   
-  pub fn data_as_string(&self) -> Result<String, Error> {
-    match String::from_utf8(self.data.clone()) {
-      Ok(dat) => return Ok(dat),
-      Err(_) => return Err(Error::NotOk)
-    }
+  // Hands over the data without copying it
+  pub fn into_data(self) -> Vec<u8> {
+    self.data
+  }
+
+  pub fn data_as_str(&self) -> Result<&str> {
+    std::str::from_utf8(&self.data).map_err(|e| Error::InvalidUtf8 { offset: e.valid_up_to() })
+  }
+
+  pub fn data_as_string(&self) -> Result<String> {
+    self.data_as_str().map(str::to_string)
   }
   
+  // Streams the chunk in its on-disk layout straight into `writer`
+  pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+    writer.write_all(&self.length.to_be_bytes())?;
+    writer.write_all(&self.chunk_type.bytes())?;
+    writer.write_all(&self.data)?;
+    writer.write_all(&self.crc.to_be_bytes())?;
+    Ok(())
+  }
+
+  // The whole chunk as it appears in a file: length, type, data and CRC,
+  // with the integers big-endian. `data()` is the data alone.
   pub fn as_bytes(&self) -> Vec<u8> {
-    self.data.len()
-      .to_be_bytes()
-      .iter()
-      .chain(self.chunk_type.bytes().iter())
-      .chain(self.data.iter())
-      .chain(self.crc().to_be_bytes().iter())
-      .copied()
-      .collect()
+    let mut bytes = Vec::with_capacity(self.data.len() + 12);
+    bytes.extend_from_slice(&self.length.to_be_bytes());
+    bytes.extend_from_slice(&self.chunk_type.bytes());
+    bytes.extend_from_slice(&self.data);
+    bytes.extend_from_slice(&self.crc.to_be_bytes());
+    bytes
   }
 }
 
@@ -182,6 +290,15 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_try_new_too_large() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        assert!(Chunk::try_new(chunk_type, vec![0; 10]).is_ok());
+        // Zeroed allocations stay untouched, so this costs no real memory
+        let data = vec![0; MAX_LENGTH + 1];
+        assert_eq!(Chunk::try_new(chunk_type, data).err(), Some(Error::ChunkTooLarge(MAX_LENGTH + 1)));
+    }
+
     #[test]
     fn test_chunk_length() {
         let chunk = testing_chunk();
@@ -202,6 +319,16 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_chunk_str() {
+        let chunk = testing_chunk();
+        assert_eq!(chunk.data_as_str(), Ok("This is where your secret message will be!"));
+        assert_eq!(chunk.into_data(), b"This is where your secret message will be!".to_vec());
+
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"ok\xff".to_vec());
+        assert_eq!(chunk.data_as_str(), Err(Error::InvalidUtf8 { offset: 2 }));
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -256,6 +383,29 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_lenient_chunk_from_bytes() {
+        let data_length: u32 = 42;
+        let chunk_type = "RuSt".as_bytes();
+        let message_bytes = b"This is where your secret message will be!";
+        let crc: u32 = 2882656333;
+
+        let chunk_data: Vec<u8> = data_length
+            .to_be_bytes()
+            .iter()
+            .chain(chunk_type.iter())
+            .chain(message_bytes.iter())
+            .chain(crc.to_be_bytes().iter())
+            .copied()
+            .collect();
+
+        let (chunk, stored_crc) = Chunk::try_from_lenient(chunk_data.as_ref()).unwrap();
+
+        assert_eq!(stored_crc, 2882656333);
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.length(), 42);
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -276,4 +426,152 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    pub fn test_write_to() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"This is where your secret message will be!".to_vec());
+        let mut written = vec![];
+        chunk.write_to(&mut written).unwrap();
+
+        assert_eq!(written, chunk.as_bytes());
+        assert_eq!(&written[..8], b"\0\0\0\x2aRuSt");
+        assert_eq!(&written[written.len() - 4..], &2882656334u32.to_be_bytes());
+    }
+
+    #[test]
+    pub fn test_as_bytes_round_trip() {
+        for data in [vec![], b"This is where your secret message will be!".to_vec()] {
+            let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), data.clone());
+            let bytes = chunk.as_bytes();
+
+            assert_eq!(bytes.len(), data.len() + 12);
+            assert_eq!(&bytes[8..bytes.len() - 4], &data[..]);
+            assert_eq!(Chunk::try_from(bytes.as_slice()).unwrap().as_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    pub fn test_setters() {
+        let mut chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![]);
+        chunk.set_data(b"This is where your secret message will be!".to_vec());
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), 2882656334);
+
+        chunk.set_chunk_type(ChunkType::from_str("ruSt").unwrap());
+        let expected = Chunk::new(ChunkType::from_str("ruSt").unwrap(), chunk.data().to_vec());
+        assert_eq!(chunk.as_bytes(), expected.as_bytes());
+        assert_eq!(chunk.recompute_crc(), expected.crc());
+    }
+
+    #[test]
+    pub fn test_crc_hasher() {
+        let mut hasher = CrcHasher::new(&ChunkType::from_str("RuSt").unwrap());
+        for piece in b"This is where your secret message will be!".chunks(5) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), 2882656334);
+    }
+
+    #[test]
+    pub fn test_chunk_derives() {
+        let chunk = testing_chunk();
+        let copy = chunk.clone();
+        assert_eq!(chunk, copy);
+        assert_ne!(chunk, Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![]));
+
+        let set: std::collections::HashSet<Chunk> = [chunk, copy, Chunk::new(ChunkType::IEND, vec![])].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    pub fn test_chunk_ref() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"This is where your secret message will be!".to_vec());
+        let bytes = chunk.as_bytes();
+        let view = ChunkRef::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(view.length(), 42);
+        assert_eq!(&view.chunk_type().to_string(), "RuSt");
+        assert_eq!(view.data(), chunk.data());
+        assert_eq!(view.crc(), 2882656334);
+        // The data points into the original buffer, it is not a copy
+        assert_eq!(view.data().as_ptr(), bytes[8..].as_ptr());
+        assert_eq!(view.to_chunk().as_bytes(), bytes);
+    }
+
+    #[test]
+    pub fn test_invalid_chunk_ref() {
+        let mut bytes = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"data".to_vec()).as_bytes();
+
+        assert_eq!(ChunkRef::try_from(&bytes[..11]), Err(Error::TruncatedChunk { expected: 12, actual: 11, offset: 0 }));
+        assert_eq!(ChunkRef::try_from(&bytes[..15]), Err(Error::TruncatedChunk { expected: 16, actual: 15, offset: 0 }));
+        assert_eq!(Chunk::try_from_lenient(&bytes[..15]).err(), Some(Error::TruncatedChunk { expected: 16, actual: 15, offset: 0 }));
+
+        let mut oversized = bytes.clone();
+        oversized[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(Chunk::try_from(oversized.as_slice()).err(), Some(Error::LengthFieldInvalid { length: u32::MAX, offset: 0 }));
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(matches!(ChunkRef::try_from(bytes.as_slice()), Err(Error::CrcMismatch(_, _))));
+
+        bytes[4] = b'1';
+        assert_eq!(ChunkRef::try_from(bytes.as_slice()), Err(Error::ChunkTypeNotValid(*b"1uSt")));
+    }
+
+    // Framings broken at the edges of each field: short input, lengths
+    // past the end of the data, the largest valid and smallest invalid
+    // length, bad type bytes and a wrong CRC. Each has to come back as the
+    // same error from every chunk parser, never as a panic.
+    #[test]
+    pub fn test_malformed_chunks() {
+        let cases: Vec<(&[u8], Error)> = vec![
+            (b"", Error::TruncatedChunk { expected: 12, actual: 0, offset: 0 }),
+            (b"\0\0\0", Error::TruncatedChunk { expected: 12, actual: 3, offset: 0 }),
+            (b"\0\0\0\0IEND\xaeB`", Error::TruncatedChunk { expected: 12, actual: 11, offset: 0 }),
+            (b"\0\0\0\x01IEND\xaeB`\x82", Error::TruncatedChunk { expected: 13, actual: 12, offset: 0 }),
+            (b"\0\0\x01\0ruSt\0\0\0\0\0\0", Error::TruncatedChunk { expected: 268, actual: 14, offset: 0 }),
+            (b"\x7f\xff\xff\xffIEND\xaeB`\x82", Error::TruncatedChunk { expected: MAX_LENGTH + 12, actual: 12, offset: 0 }),
+            (b"\x80\0\0\0IEND\xaeB`\x82", Error::LengthFieldInvalid { length: 1 << 31, offset: 0 }),
+            (b"\xff\xff\xff\xffIEND\xaeB`\x82", Error::LengthFieldInvalid { length: u32::MAX, offset: 0 }),
+            (b"\0\0\0\0IE\0D\xaeB`\x82", Error::ChunkTypeNotValid(*b"IE\0D")),
+            (b"\0\0\0\0\xffEND\xaeB`\x82", Error::ChunkTypeNotValid(*b"\xffEND")),
+            (b"\0\0\0\0IEND\0\0\0\0", Error::CrcMismatch(0xae426082, 0)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(Chunk::try_from(input).err().as_ref(), Some(&expected), "{:02x?}", input);
+            assert_eq!(ChunkRef::try_from(input).err().as_ref(), Some(&expected), "{:02x?}", input);
+            if !matches!(expected, Error::CrcMismatch(_, _)) {
+                assert_eq!(Chunk::try_from_lenient(input).err(), Some(expected), "{:02x?}", input);
+            }
+        }
+    }
+
+    // Every truncation and every single bit flip of a valid chunk
+    #[test]
+    pub fn test_mutated_chunks() {
+        let bytes = testing_chunk().as_bytes();
+
+        for end in 0..bytes.len() {
+            let expected = if end < 12 { 12 } else { bytes.len() };
+            let truncated = Error::TruncatedChunk { expected, actual: end, offset: 0 };
+            assert_eq!(Chunk::try_from(&bytes[..end]).err().as_ref(), Some(&truncated));
+            assert_eq!(ChunkRef::try_from(&bytes[..end]).err(), Some(truncated));
+        }
+
+        for index in 0..bytes.len() {
+            for bit in 0..8 {
+                let mut mutated = bytes.clone();
+                mutated[index] ^= 1 << bit;
+
+                let strict = Chunk::try_from(mutated.as_slice());
+                assert!(strict.is_err(), "flipping bit {} of byte {} went unnoticed", bit, index);
+                assert_eq!(ChunkRef::try_from(mutated.as_slice()).err().as_ref(), strict.as_ref().err());
+
+                // Only a CRC problem gets past the lenient parser
+                let lenient = Chunk::try_from_lenient(mutated.as_slice());
+                assert_eq!(lenient.is_ok(), matches!(strict, Err(Error::CrcMismatch(_, _))));
+            }
+        }
+    }
 }
@@ -1,171 +1,150 @@
 use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
-use crate::chunk::Error;
 
-#[derive(Eq, PartialEq, Debug)]
-pub struct ChunkType {
-    chunk_type: Vec<char>,
+// Why bytes or a string can't be a chunk type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkTypeError {
+    // Chunk types are exactly four bytes
+    InvalidLength(usize),
+    // Every byte has to be an ASCII letter
+    InvalidByte(u8),
 }
 
-impl fmt::Display for ChunkType {
+impl fmt::Display for ChunkTypeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut is_error = false;
-        let mut error = fmt::Result::Ok(());
-
-        for i in &self.chunk_type {
-            match write!(f, "{}", i) {
-                Ok(_) => {}
-                Err(e) => {
-                    is_error = true;
-                    error = fmt::Result::Err(e);
-                    break;
-                }
-            }
+        match self {
+            ChunkTypeError::InvalidLength(length) => write!(f, "chunk types are 4 bytes, not {}", length),
+            ChunkTypeError::InvalidByte(byte) => write!(f, "byte {:#04x} isn't an ASCII letter", byte),
         }
+    }
+}
 
-        if is_error {
-            return error;
-        } else {
-            return fmt::Result::Ok(());
-        }
+impl std::error::Error for ChunkTypeError {}
+
+// Always four ASCII letters, checked on construction. Small enough to pass
+// around by value. Ordering is by byte, so uppercase (critical) types sort
+// before lowercase ones.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct ChunkType {
+    chunk_type: [u8; 4],
+}
+
+impl fmt::Display for ChunkType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = Error;
+    type Error = ChunkTypeError;
 
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
-        let mut chunk_type = vec![];
-        let mut is_error = Error::None;
-
-        for i in value {
-            match i {
-                65..=90 => chunk_type.push(i as char),
-                97..=122 => chunk_type.push(i as char),
-                _ => is_error = Error::ValueNotInRange,
-            }
-        }
-
-        if is_error != Error::None {
-            Err(is_error)
-        } else {
-            Ok(Self { chunk_type })
+        match value.iter().find(|byte| !byte.is_ascii_alphabetic()) {
+            Some(&byte) => Err(ChunkTypeError::InvalidByte(byte)),
+            None => Ok(Self { chunk_type: value }),
         }
     }
 }
 
 impl TryFrom<&[u8; 4]> for ChunkType {
-    type Error = Error;
+    type Error = ChunkTypeError;
 
     fn try_from(value: &[u8; 4]) -> Result<Self, Self::Error> {
-        let mut chunk_type = vec![];
-        let mut is_error = Error::None;
-
-        for i in value {
-            match i {
-                65..=90 => chunk_type.push(*i as char),
-                97..=122 => chunk_type.push(*i as char),
-                _ => is_error = Error::ValueNotInRange,
-            }
-        }
-
-        if is_error != Error::None {
-            Err(is_error)
-        } else {
-            Ok(Self { chunk_type })
-        }
+        ChunkType::try_from(*value)
     }
 }
 
 impl FromStr for ChunkType {
-    type Err = Error;
+    type Err = ChunkTypeError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut is_error = Error::StrNotCorrctLngth;
-        let mut chunk_type = vec![];
-
-        if s.len() == 4 {
-            is_error = Error::None;
-        } else {
-            return Err(is_error);
-        }
-
-        for i in s.chars() {
-            let i = i as u8;
-            match i {
-                65..=90 => chunk_type.push(i as char),
-                97..=122 => chunk_type.push(i as char),
-                _ => is_error = Error::ValueNotInRange,
-            }
-        }
-
-        if is_error != Error::None {
-            Err(is_error)
-        } else {
-            Ok(Self { chunk_type })
-        }
+        let bytes: [u8; 4] = s.as_bytes().try_into().map_err(|_| ChunkTypeError::InvalidLength(s.len()))?;
+        ChunkType::try_from(bytes)
     }
 }
 
+// Chunk types defined by the PNG specification and its registered extensions
+pub const REGISTERED_CHUNK_TYPES: [&str; 34] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "cHRM", "cICP", "gAMA", "iCCP", "mDCV", "cLLI", "sBIT", "sRGB",
+    "bKGD", "hIST", "tRNS", "eXIf", "pHYs", "sPLT", "tIME", "iTXt", "tEXt", "zTXt", "acTL", "fcTL",
+    "fdAT", "oFFs", "pCAL", "sCAL", "gIFg", "gIFt", "gIFx", "sTER", "dSIG", "fRAc",
+];
+
 impl ChunkType {
+    // Common chunk types, for lookups that shouldn't go through `from_str`
+    pub const IHDR: ChunkType = ChunkType { chunk_type: *b"IHDR" };
+    pub const PLTE: ChunkType = ChunkType { chunk_type: *b"PLTE" };
+    pub const IDAT: ChunkType = ChunkType { chunk_type: *b"IDAT" };
+    pub const IEND: ChunkType = ChunkType { chunk_type: *b"IEND" };
+    pub const CHRM: ChunkType = ChunkType { chunk_type: *b"cHRM" };
+    pub const GAMA: ChunkType = ChunkType { chunk_type: *b"gAMA" };
+    pub const ICCP: ChunkType = ChunkType { chunk_type: *b"iCCP" };
+    pub const SBIT: ChunkType = ChunkType { chunk_type: *b"sBIT" };
+    pub const SRGB: ChunkType = ChunkType { chunk_type: *b"sRGB" };
+    pub const BKGD: ChunkType = ChunkType { chunk_type: *b"bKGD" };
+    pub const HIST: ChunkType = ChunkType { chunk_type: *b"hIST" };
+    pub const TRNS: ChunkType = ChunkType { chunk_type: *b"tRNS" };
+    pub const EXIF: ChunkType = ChunkType { chunk_type: *b"eXIf" };
+    pub const PHYS: ChunkType = ChunkType { chunk_type: *b"pHYs" };
+    pub const SPLT: ChunkType = ChunkType { chunk_type: *b"sPLT" };
+    pub const TIME: ChunkType = ChunkType { chunk_type: *b"tIME" };
+    pub const ITXT: ChunkType = ChunkType { chunk_type: *b"iTXt" };
+    pub const TEXT: ChunkType = ChunkType { chunk_type: *b"tEXt" };
+    pub const ZTXT: ChunkType = ChunkType { chunk_type: *b"zTXt" };
+    pub const ACTL: ChunkType = ChunkType { chunk_type: *b"acTL" };
+    pub const FCTL: ChunkType = ChunkType { chunk_type: *b"fcTL" };
+    pub const FDAT: ChunkType = ChunkType { chunk_type: *b"fdAT" };
+    // Apple's, not registered; marks a crushed iOS PNG
+    pub const CGBI: ChunkType = ChunkType { chunk_type: *b"CgBI" };
+
     pub fn bytes(&self) -> [u8; 4] {
         self.chunk_type
-            .iter()
-            .map(|x| *x as u8)
-            .collect::<Vec<u8>>()
-            .try_into()
-            .unwrap()
+    }
+
+    pub fn as_str(&self) -> &str {
+        // ASCII letters are always valid UTF-8
+        std::str::from_utf8(&self.chunk_type).unwrap()
     }
 
     pub fn is_valid(&self) -> bool {
         let bytes = self.bytes();
 
-        if bytes[2] & 32 == 0 {
-            true
-        } else {
-            false
-        }
+        bytes[2] & 32 == 0
     }
 
     pub fn is_critical(&self) -> bool {
         let bytes = self.bytes();
 
-        if bytes[0] & 32 > 0 {
-            false
-        } else {
-            true
-        }
+        bytes[0] & 32 == 0
     }
 
     pub fn is_public(&self) -> bool {
         let bytes = self.bytes();
 
-        if bytes[1] & 32 == 0 {
-            true
-        } else {
-            false
-        }
+        bytes[1] & 32 == 0
     }
 
     pub fn is_reserved_bit_valid(&self) -> bool {
         let bytes = self.bytes();
 
-        if bytes[2] & 32 == 0 {
-            true
-        } else {
-            false
-        }
+        bytes[2] & 32 == 0
+    }
+
+    pub fn is_registered(&self) -> bool {
+        REGISTERED_CHUNK_TYPES.contains(&self.as_str())
     }
 
     pub fn is_safe_to_copy(&self) -> bool {
         let bytes = self.bytes();
 
-        if bytes[3] & 32 == 0 {
-            false
-        } else {
-            true
-        }
+        bytes[3] & 32 != 0
+    }
+}
+
+impl AsRef<str> for ChunkType {
+    fn as_ref(&self) -> &str {
+        self.as_str()
     }
 }
 
@@ -253,6 +232,33 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_is_registered() {
+        assert!(ChunkType::from_str("IHDR").unwrap().is_registered());
+        assert!(ChunkType::from_str("tEXt").unwrap().is_registered());
+        assert!(!ChunkType::from_str("RuSt").unwrap().is_registered());
+        assert!(!ChunkType::from_str("text").unwrap().is_registered());
+    }
+
+    #[test]
+    pub fn test_chunk_type_errors() {
+        assert_eq!(ChunkType::from_str("Ru1t"), Err(ChunkTypeError::InvalidByte(b'1')));
+        assert_eq!(ChunkType::from_str("RuSty"), Err(ChunkTypeError::InvalidLength(5)));
+        assert_eq!(ChunkType::from_str("Ru\u{e9}"), Err(ChunkTypeError::InvalidByte(0xc3)));
+        assert_eq!(ChunkType::try_from(*b"Ru\0t"), Err(ChunkTypeError::InvalidByte(0)));
+        assert_eq!(ChunkTypeError::InvalidLength(5).to_string(), "chunk types are 4 bytes, not 5");
+    }
+
+    #[test]
+    pub fn test_constants() {
+        for chunk_type in [ChunkType::IHDR, ChunkType::TEXT, ChunkType::ZTXT, ChunkType::FDAT] {
+            assert_eq!(ChunkType::from_str(chunk_type.as_str()), Ok(chunk_type));
+            assert!(chunk_type.is_registered());
+        }
+        assert_eq!(ChunkType::TEXT.to_string(), "tEXt");
+        assert!(ChunkType::IDAT.is_critical());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
@@ -266,4 +272,22 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
+
+    #[test]
+    pub fn test_chunk_type_copy_hash() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let copy = chunk_type;
+        assert_eq!(copy.bytes(), chunk_type.bytes());
+
+        let set: std::collections::HashSet<ChunkType> = [chunk_type, copy, ChunkType::IEND].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&ChunkType::IEND));
+    }
+
+    #[test]
+    pub fn test_chunk_type_ord() {
+        let mut chunk_types = vec![ChunkType::TEXT, ChunkType::IEND, ChunkType::IDAT, ChunkType::GAMA];
+        chunk_types.sort();
+        assert_eq!(chunk_types, vec![ChunkType::IDAT, ChunkType::IEND, ChunkType::GAMA, ChunkType::TEXT]);
+    }
 }
@@ -0,0 +1,277 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::chunks::Ihdr;
+use std::convert::TryFrom;
+
+fn expect(chunk: &Chunk, chunk_type: &str) -> Result<()> {
+  if chunk.chunk_type().to_string() != chunk_type {
+    return Err(Error::InvalidChunkData(format!("expected {}, got {}", chunk_type, chunk.chunk_type())));
+  }
+  Ok(())
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+  u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationControl {
+  pub num_frames: u32,
+  // 0 means loop forever
+  pub num_plays: u32,
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "acTL")?;
+    let data = chunk.data();
+    if data.len() != 8 {
+      return Err(Error::InvalidChunkData(format!("acTL must be 8 bytes, got {}", data.len())));
+    }
+
+    let actl = Self { num_frames: be_u32(data), num_plays: be_u32(&data[4..]) };
+    if actl.num_frames == 0 {
+      return Err(Error::InvalidChunkData("acTL: an animation needs at least one frame".to_string()));
+    }
+    Ok(actl)
+  }
+}
+
+impl From<AnimationControl> for Chunk {
+  fn from(actl: AnimationControl) -> Chunk {
+    let mut data = actl.num_frames.to_be_bytes().to_vec();
+    data.extend(actl.num_plays.to_be_bytes());
+    Chunk::new(ChunkType::ACTL, data)
+  }
+}
+
+// What happens to the frame's region before the next frame is rendered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisposeOp {
+  None,
+  Background,
+  Previous,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+  Source,
+  Over,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameControl {
+  pub sequence_number: u32,
+  pub width: u32,
+  pub height: u32,
+  pub x_offset: u32,
+  pub y_offset: u32,
+  pub delay_num: u16,
+  pub delay_den: u16,
+  pub dispose_op: DisposeOp,
+  pub blend_op: BlendOp,
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "fcTL")?;
+    let data = chunk.data();
+    if data.len() != 26 {
+      return Err(Error::InvalidChunkData(format!("fcTL must be 26 bytes, got {}", data.len())));
+    }
+
+    let dispose_op = match data[24] {
+      0 => DisposeOp::None,
+      1 => DisposeOp::Background,
+      2 => DisposeOp::Previous,
+      op => return Err(Error::InvalidChunkData(format!("fcTL: unknown dispose op {}", op))),
+    };
+    let blend_op = match data[25] {
+      0 => BlendOp::Source,
+      1 => BlendOp::Over,
+      op => return Err(Error::InvalidChunkData(format!("fcTL: unknown blend op {}", op))),
+    };
+
+    let fctl = Self {
+      sequence_number: be_u32(data),
+      width: be_u32(&data[4..]),
+      height: be_u32(&data[8..]),
+      x_offset: be_u32(&data[12..]),
+      y_offset: be_u32(&data[16..]),
+      delay_num: u16::from_be_bytes([data[20], data[21]]),
+      delay_den: u16::from_be_bytes([data[22], data[23]]),
+      dispose_op,
+      blend_op,
+    };
+    if fctl.width == 0 || fctl.height == 0 {
+      return Err(Error::InvalidChunkData(format!("fcTL: frame {} has no area", fctl.sequence_number)));
+    }
+    Ok(fctl)
+  }
+}
+
+impl From<FrameControl> for Chunk {
+  fn from(fctl: FrameControl) -> Chunk {
+    let mut data = vec![];
+    for value in [fctl.sequence_number, fctl.width, fctl.height, fctl.x_offset, fctl.y_offset] {
+      data.extend(value.to_be_bytes());
+    }
+    data.extend(fctl.delay_num.to_be_bytes());
+    data.extend(fctl.delay_den.to_be_bytes());
+    data.extend([fctl.dispose_op as u8, fctl.blend_op as u8]);
+    Chunk::new(ChunkType::FCTL, data)
+  }
+}
+
+impl FrameControl {
+  // Frame delay in seconds; a denominator of 0 means hundredths
+  pub fn delay(&self) -> f64 {
+    let den = if self.delay_den == 0 { 100 } else { self.delay_den };
+    self.delay_num as f64 / den as f64
+  }
+
+  // The frame has to lie within the canvas IHDR describes
+  pub fn validate_for(&self, ihdr: &Ihdr) -> Result<()> {
+    let fits = |offset: u32, size: u32, canvas: u32| offset.checked_add(size).is_some_and(|end| end <= canvas);
+    if !fits(self.x_offset, self.width, ihdr.width) || !fits(self.y_offset, self.height, ihdr.height) {
+      return Err(Error::InvalidChunkData(format!(
+        "fcTL: frame {} ({}x{} at {},{}) lies outside the {}x{} canvas",
+        self.sequence_number,
+        self.width,
+        self.height,
+        self.x_offset,
+        self.y_offset,
+        ihdr.width,
+        ihdr.height
+      )));
+    }
+    Ok(())
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameData {
+  pub sequence_number: u32,
+  pub data: Vec<u8>,
+}
+
+impl TryFrom<&Chunk> for FrameData {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "fdAT")?;
+    let data = chunk.data();
+    if data.len() < 4 {
+      return Err(Error::InvalidChunkData("fdAT: missing sequence number".to_string()));
+    }
+    Ok(Self { sequence_number: be_u32(data), data: data[4..].to_vec() })
+  }
+}
+
+impl From<FrameData> for Chunk {
+  fn from(fdat: FrameData) -> Chunk {
+    let mut data = fdat.sequence_number.to_be_bytes().to_vec();
+    data.extend(fdat.data);
+    Chunk::new(ChunkType::FDAT, data)
+  }
+}
+
+// Sequence number of an fcTL or fdAT chunk, None for any other chunk
+pub fn sequence_number(chunk_type: &str, data: &[u8]) -> Option<u32> {
+  match chunk_type {
+    "fcTL" | "fdAT" if data.len() >= 4 => Some(be_u32(data)),
+    _ => None,
+  }
+}
+
+// fcTL and fdAT sequence numbers have to count up from 0 without gaps.
+// Returns the position, expected and actual number of every break.
+pub fn check_sequence(numbers: &[u32]) -> Vec<(usize, u32, u32)> {
+  let mut breaks = vec![];
+  let mut expected = 0;
+  for (position, &number) in numbers.iter().enumerate() {
+    if number != expected {
+      breaks.push((position, expected, number));
+    }
+    expected = number.wrapping_add(1);
+  }
+  breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_fctl() -> FrameControl {
+        FrameControl {
+            sequence_number: 1,
+            width: 10,
+            height: 20,
+            x_offset: 5,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 0,
+            dispose_op: DisposeOp::Background,
+            blend_op: BlendOp::Over,
+        }
+    }
+
+    fn ihdr(width: u32, height: u32) -> Ihdr {
+        Ihdr {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: 6,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    #[test]
+    fn test_animation_control() {
+        let actl = AnimationControl { num_frames: 3, num_plays: 0 };
+        assert_eq!(AnimationControl::try_from(&Chunk::from(actl)), Ok(actl));
+
+        let empty = AnimationControl { num_frames: 0, num_plays: 0 };
+        assert!(AnimationControl::try_from(&Chunk::from(empty)).is_err());
+    }
+
+    #[test]
+    fn test_frame_control() {
+        let chunk = Chunk::from(testing_fctl());
+        assert_eq!(chunk.data().len(), 26);
+        assert_eq!(FrameControl::try_from(&chunk), Ok(testing_fctl()));
+        assert_eq!(testing_fctl().delay(), 0.01);
+
+        assert!(testing_fctl().validate_for(&ihdr(15, 20)).is_ok());
+        assert!(testing_fctl().validate_for(&ihdr(14, 20)).is_err());
+
+        let mut data = chunk.data().to_vec();
+        data[24] = 3;
+        assert!(FrameControl::try_from(&Chunk::new(ChunkType::FCTL, data)).is_err());
+    }
+
+    #[test]
+    fn test_frame_data() {
+        let fdat = FrameData { sequence_number: 2, data: vec![1, 2, 3] };
+        let chunk = Chunk::from(fdat.clone());
+
+        assert_eq!(chunk.data(), &[0, 0, 0, 2, 1, 2, 3]);
+        assert_eq!(FrameData::try_from(&chunk), Ok(fdat));
+        assert_eq!(sequence_number("fdAT", chunk.data()), Some(2));
+        assert_eq!(sequence_number("IDAT", chunk.data()), None);
+    }
+
+    #[test]
+    fn test_check_sequence() {
+        assert!(check_sequence(&[0, 1, 2, 3]).is_empty());
+        assert_eq!(check_sequence(&[1, 2]), vec![(0, 0, 1)]);
+        assert_eq!(check_sequence(&[0, 1, 3, 4, 4]), vec![(2, 2, 3), (4, 5, 4)]);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::chunks::text::{latin1_encode, split_keyword, validate_keyword};
+use crate::zlib::{deflate, inflate};
+use std::convert::TryFrom;
+use std::fmt;
+
+// gAMA and cHRM store values multiplied by 100000
+const FIXED_POINT: f64 = 100000.0;
+
+fn expect(chunk: &Chunk, chunk_type: &str, length: Option<usize>) -> Result<()> {
+  if chunk.chunk_type().to_string() != chunk_type {
+    return Err(Error::InvalidChunkData(format!("expected {}, got {}", chunk_type, chunk.chunk_type())));
+  }
+  match length {
+    Some(length) if chunk.data().len() != length => {
+      Err(Error::InvalidChunkData(format!("{} must be {} bytes, got {}", chunk_type, length, chunk.data().len())))
+    }
+    _ => Ok(()),
+  }
+}
+
+fn be_u32(data: &[u8]) -> u32 {
+  u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gamma(pub u32);
+
+impl TryFrom<&Chunk> for Gamma {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "gAMA", Some(4))?;
+    match be_u32(chunk.data()) {
+      0 => Err(Error::InvalidChunkData("gAMA: gamma must not be zero".to_string())),
+      gamma => Ok(Gamma(gamma)),
+    }
+  }
+}
+
+impl From<Gamma> for Chunk {
+  fn from(gamma: Gamma) -> Chunk {
+    Chunk::new(ChunkType::GAMA, gamma.0.to_be_bytes().to_vec())
+  }
+}
+
+impl Gamma {
+  pub fn from_f64(gamma: f64) -> Self {
+    Gamma((gamma * FIXED_POINT).round() as u32)
+  }
+
+  pub fn value(&self) -> f64 {
+    self.0 as f64 / FIXED_POINT
+  }
+}
+
+// CIE 1931 x,y chromaticities of the white point and the three primaries,
+// as stored in the chunk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chromaticities {
+  pub white: (u32, u32),
+  pub red: (u32, u32),
+  pub green: (u32, u32),
+  pub blue: (u32, u32),
+}
+
+impl TryFrom<&Chunk> for Chromaticities {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "cHRM", Some(32))?;
+    let data = chunk.data();
+    let point = |i: usize| (be_u32(&data[i * 8..]), be_u32(&data[i * 8 + 4..]));
+
+    Ok(Self { white: point(0), red: point(1), green: point(2), blue: point(3) })
+  }
+}
+
+impl From<Chromaticities> for Chunk {
+  fn from(chrm: Chromaticities) -> Chunk {
+    let data = [chrm.white, chrm.red, chrm.green, chrm.blue]
+      .iter()
+      .flat_map(|(x, y)| x.to_be_bytes().into_iter().chain(y.to_be_bytes()))
+      .collect();
+    Chunk::new(ChunkType::CHRM, data)
+  }
+}
+
+impl Chromaticities {
+  // White point, red, green and blue as floating point x,y pairs
+  pub fn points(&self) -> [(f64, f64); 4] {
+    [self.white, self.red, self.green, self.blue].map(|(x, y)| (x as f64 / FIXED_POINT, y as f64 / FIXED_POINT))
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderingIntent {
+  Perceptual,
+  RelativeColorimetric,
+  Saturation,
+  AbsoluteColorimetric,
+}
+
+impl fmt::Display for RenderingIntent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      RenderingIntent::Perceptual => "perceptual",
+      RenderingIntent::RelativeColorimetric => "relative colorimetric",
+      RenderingIntent::Saturation => "saturation",
+      RenderingIntent::AbsoluteColorimetric => "absolute colorimetric",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Srgb {
+  pub intent: RenderingIntent,
+}
+
+impl TryFrom<&Chunk> for Srgb {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "sRGB", Some(1))?;
+    let intent = match chunk.data()[0] {
+      0 => RenderingIntent::Perceptual,
+      1 => RenderingIntent::RelativeColorimetric,
+      2 => RenderingIntent::Saturation,
+      3 => RenderingIntent::AbsoluteColorimetric,
+      intent => return Err(Error::InvalidChunkData(format!("sRGB: unknown rendering intent {}", intent))),
+    };
+    Ok(Self { intent })
+  }
+}
+
+impl From<Srgb> for Chunk {
+  fn from(srgb: Srgb) -> Chunk {
+    Chunk::new(ChunkType::SRGB, vec![srgb.intent as u8])
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+  name: String,
+  compressed: Vec<u8>,
+}
+
+impl TryFrom<&Chunk> for IccProfile {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    expect(chunk, "iCCP", None)?;
+    let (name, rest) = split_keyword("iCCP", chunk.data())?;
+    match rest.split_first() {
+      Some((0, compressed)) => Ok(Self { name, compressed: compressed.to_vec() }),
+      Some((method, _)) => Err(Error::InvalidChunkData(format!("iCCP: unknown compression method {}", method))),
+      None => Err(Error::InvalidChunkData("iCCP: missing compression method".to_string())),
+    }
+  }
+}
+
+impl From<IccProfile> for Chunk {
+  fn from(icc: IccProfile) -> Chunk {
+    // The name was checked to be Latin-1 when the IccProfile was built
+    let mut data = latin1_encode(&icc.name).unwrap();
+    data.extend([0, 0]);
+    data.extend(icc.compressed);
+    Chunk::new(ChunkType::ICCP, data)
+  }
+}
+
+impl IccProfile {
+  pub fn new(name: &str, profile: &[u8]) -> Result<Self> {
+    validate_keyword(name)?;
+    Ok(Self { name: name.to_string(), compressed: deflate(profile) })
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn profile(&self) -> Result<Vec<u8>> {
+    inflate("iCCP", &self.compressed)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_gamma() {
+        let gamma = Gamma::try_from(&chunk("gAMA", &[0, 0, 177, 143])).unwrap();
+        assert_eq!(gamma, Gamma(45455));
+        assert_eq!(gamma.value(), 0.45455);
+        assert_eq!(Gamma::from_f64(1.0 / 2.2), gamma);
+        assert_eq!(Chunk::from(gamma).data(), &[0, 0, 177, 143]);
+
+        assert!(Gamma::try_from(&chunk("gAMA", &[0, 0, 0, 0])).is_err());
+        assert!(Gamma::try_from(&chunk("gAMA", &[1, 2])).is_err());
+    }
+
+    #[test]
+    fn test_chromaticities() {
+        let chrm = Chromaticities {
+            white: (31270, 32900),
+            red: (64000, 33000),
+            green: (30000, 60000),
+            blue: (15000, 6000),
+        };
+        let encoded = Chunk::from(chrm);
+
+        assert_eq!(encoded.data().len(), 32);
+        assert_eq!(Chromaticities::try_from(&encoded), Ok(chrm));
+        assert_eq!(chrm.points()[0], (0.3127, 0.329));
+        assert!(Chromaticities::try_from(&chunk("cHRM", &[0; 31])).is_err());
+    }
+
+    #[test]
+    fn test_srgb() {
+        let srgb = Srgb::try_from(&chunk("sRGB", &[1])).unwrap();
+        assert_eq!(srgb.intent, RenderingIntent::RelativeColorimetric);
+        assert_eq!(srgb.intent.to_string(), "relative colorimetric");
+        assert_eq!(Chunk::from(srgb).data(), &[1]);
+
+        assert!(Srgb::try_from(&chunk("sRGB", &[4])).is_err());
+    }
+
+    #[test]
+    fn test_icc_profile() {
+        let icc = IccProfile::new("Display P3", b"fake profile bytes").unwrap();
+        let decoded = IccProfile::try_from(&Chunk::from(icc.clone())).unwrap();
+
+        assert_eq!(decoded, icc);
+        assert_eq!(decoded.name(), "Display P3");
+        assert_eq!(decoded.profile().unwrap(), b"fake profile bytes");
+
+        assert!(IccProfile::try_from(&chunk("iCCP", b"name\0\x01data")).is_err());
+        assert!(IccProfile::try_from(&chunk("iCCP", b"name\0\0not zlib")).unwrap().profile().is_err());
+    }
+}
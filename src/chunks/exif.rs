@@ -0,0 +1,204 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use std::convert::TryFrom;
+
+const ORIENTATION: u16 = 0x0112;
+const MAKE: u16 = 0x010f;
+const MODEL: u16 = 0x0110;
+const DATE_TIME: u16 = 0x0132;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+// TIFF field types this reader understands
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+
+fn invalid<T>(message: &str) -> Result<T> {
+  Err(Error::InvalidChunkData(format!("eXIf: {}", message)))
+}
+
+// The subset of tags `info --exif` shows
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExifTags {
+  pub orientation: Option<u16>,
+  pub make: Option<String>,
+  pub model: Option<String>,
+  pub date_time: Option<String>,
+  pub date_time_original: Option<String>,
+  pub has_gps: bool,
+}
+
+// A raw TIFF-structured EXIF payload
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exif {
+  data: Vec<u8>,
+}
+
+impl TryFrom<&Chunk> for Exif {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "eXIf" {
+      return Err(Error::InvalidChunkData(format!("expected eXIf, got {}", chunk.chunk_type())));
+    }
+    Exif::new(chunk.data().to_vec())
+  }
+}
+
+impl From<Exif> for Chunk {
+  fn from(exif: Exif) -> Chunk {
+    Chunk::new(ChunkType::EXIF, exif.data)
+  }
+}
+
+impl Exif {
+  pub fn new(data: Vec<u8>) -> Result<Self> {
+    match data.get(..4) {
+      Some(b"II*\0") | Some(b"MM\0*") => Ok(Self { data }),
+      _ => invalid("payload does not start with a TIFF header"),
+    }
+  }
+
+  pub fn raw(&self) -> &[u8] {
+    &self.data
+  }
+
+  fn big_endian(&self) -> bool {
+    self.data[0] == b'M'
+  }
+
+  fn u16_at(&self, offset: usize) -> Result<u16> {
+    match self.data.get(offset..offset + 2) {
+      Some(b) if self.big_endian() => Ok(u16::from_be_bytes([b[0], b[1]])),
+      Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+      None => invalid(&format!("offset {} is past the end of the payload", offset)),
+    }
+  }
+
+  fn u32_at(&self, offset: usize) -> Result<u32> {
+    match self.data.get(offset..offset + 4) {
+      Some(b) if self.big_endian() => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+      Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+      None => invalid(&format!("offset {} is past the end of the payload", offset)),
+    }
+  }
+
+  // Tag, type, count and the offset of the value of each IFD entry
+  fn entries(&self, ifd: usize) -> Result<Vec<(u16, u16, usize, usize)>> {
+    let count = self.u16_at(ifd)? as usize;
+    (0..count)
+      .map(|i| {
+        let entry = ifd + 2 + i * 12;
+        let (tag, kind, count) = (self.u16_at(entry)?, self.u16_at(entry + 2)?, self.u32_at(entry + 4)? as usize);
+        // Values that don't fit in the entry's 4 bytes are stored elsewhere
+        let size = match kind {
+          SHORT => 2,
+          LONG => 4,
+          _ => 1,
+        };
+        let value = if count.saturating_mul(size) > 4 { self.u32_at(entry + 8)? as usize } else { entry + 8 };
+        Ok((tag, kind, count, value))
+      })
+      .collect()
+  }
+
+  fn ascii(&self, kind: u16, count: usize, offset: usize) -> Option<String> {
+    if kind != ASCII {
+      return None;
+    }
+    let bytes = self.data.get(offset..offset.checked_add(count)?)?;
+    let text: String = bytes.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect();
+    Some(text.trim().to_string())
+  }
+
+  fn pointer(&self, kind: u16, offset: usize) -> Result<usize> {
+    match kind {
+      LONG => Ok(self.u32_at(offset)? as usize),
+      _ => invalid("IFD pointer has the wrong type"),
+    }
+  }
+
+  pub fn tags(&self) -> Result<ExifTags> {
+    let mut tags = ExifTags::default();
+    let ifd0 = self.u32_at(4)? as usize;
+
+    for (tag, kind, count, value) in self.entries(ifd0)? {
+      match tag {
+        ORIENTATION if kind == SHORT => tags.orientation = Some(self.u16_at(value)?),
+        MAKE => tags.make = self.ascii(kind, count, value),
+        MODEL => tags.model = self.ascii(kind, count, value),
+        DATE_TIME => tags.date_time = self.ascii(kind, count, value),
+        GPS_IFD => tags.has_gps = true,
+        EXIF_IFD => {
+          let exif_ifd = self.pointer(kind, value)?;
+          for (tag, kind, count, value) in self.entries(exif_ifd)? {
+            if tag == DATE_TIME_ORIGINAL {
+              tags.date_time_original = self.ascii(kind, count, value);
+            }
+          }
+        }
+        _ => {}
+      }
+    }
+    Ok(tags)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A little endian payload with orientation, model, a GPS pointer and an
+    // EXIF sub-IFD holding DateTimeOriginal
+    fn testing_exif() -> Vec<u8> {
+        let mut data = b"II*\0".to_vec();
+        data.extend(8u32.to_le_bytes());
+
+        // IFD0 at 8: 4 entries, then the next IFD offset
+        data.extend(4u16.to_le_bytes());
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            [tag.to_le_bytes().to_vec(), kind.to_le_bytes().to_vec(), count.to_le_bytes().to_vec(), value.to_le_bytes().to_vec()].concat()
+        };
+        // IFD0 ends at 8 + 2 + 4 * 12 + 4 = 62
+        data.extend(entry(ORIENTATION, SHORT, 1, 6));
+        data.extend(entry(MODEL, ASCII, 9, 62));
+        data.extend(entry(EXIF_IFD, LONG, 1, 71));
+        data.extend(entry(GPS_IFD, LONG, 1, 0));
+        data.extend(0u32.to_le_bytes());
+        data.extend(b"Pixel 8\0\0");
+
+        // EXIF IFD at 71
+        data.extend(1u16.to_le_bytes());
+        data.extend(entry(DATE_TIME_ORIGINAL, ASCII, 20, 89));
+        data.extend(0u32.to_le_bytes());
+        data.extend(b"2024:02:29 13:05:09\0");
+        data
+    }
+
+    #[test]
+    fn test_tags() {
+        let exif = Exif::new(testing_exif()).unwrap();
+        let tags = exif.tags().unwrap();
+
+        assert_eq!(tags.orientation, Some(6));
+        assert_eq!(tags.model.as_deref(), Some("Pixel 8"));
+        assert_eq!(tags.make, None);
+        assert_eq!(tags.date_time_original.as_deref(), Some("2024:02:29 13:05:09"));
+        assert!(tags.has_gps);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk: Chunk = Exif::new(testing_exif()).unwrap().into();
+        assert_eq!(Exif::try_from(&chunk).unwrap().raw(), testing_exif().as_slice());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Exif::new(b"JFIF".to_vec()).is_err());
+        assert!(Exif::new(b"II*\0\xff\0\0\0".to_vec()).unwrap().tags().is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use std::convert::TryFrom;
+
+// Largest width or height the spec allows
+const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ihdr {
+  pub width: u32,
+  pub height: u32,
+  pub bit_depth: u8,
+  pub color_type: u8,
+  pub compression_method: u8,
+  pub filter_method: u8,
+  pub interlace_method: u8,
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "IHDR" {
+      return Err(Error::InvalidChunkData(format!("expected IHDR, got {}", chunk.chunk_type())));
+    }
+
+    let data = chunk.data();
+    if data.len() != 13 {
+      return Err(Error::InvalidChunkData(format!("IHDR must be 13 bytes, got {}", data.len())));
+    }
+
+    let ihdr = Self {
+      width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+      height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+      bit_depth: data[8],
+      color_type: data[9],
+      compression_method: data[10],
+      filter_method: data[11],
+      interlace_method: data[12],
+    };
+    ihdr.validate()?;
+    Ok(ihdr)
+  }
+}
+
+impl From<Ihdr> for Chunk {
+  fn from(ihdr: Ihdr) -> Chunk {
+    let mut data = Vec::with_capacity(13);
+    data.extend_from_slice(&ihdr.width.to_be_bytes());
+    data.extend_from_slice(&ihdr.height.to_be_bytes());
+    data.extend_from_slice(&[
+      ihdr.bit_depth,
+      ihdr.color_type,
+      ihdr.compression_method,
+      ihdr.filter_method,
+      ihdr.interlace_method,
+    ]);
+
+    Chunk::new(ChunkType::IHDR, data)
+  }
+}
+
+impl Ihdr {
+  // Bit depths the spec allows for each color type
+  pub fn allowed_bit_depths(color_type: u8) -> &'static [u8] {
+    match color_type {
+      0 => &[1, 2, 4, 8, 16],
+      2 | 4 | 6 => &[8, 16],
+      3 => &[1, 2, 4, 8],
+      _ => &[],
+    }
+  }
+
+  pub fn validate(&self) -> Result<()> {
+    let invalid = |message: String| Err(Error::InvalidChunkData(format!("IHDR: {}", message)));
+
+    if self.width == 0 || self.width > MAX_DIMENSION {
+      return invalid(format!("width {} is out of range", self.width));
+    }
+    if self.height == 0 || self.height > MAX_DIMENSION {
+      return invalid(format!("height {} is out of range", self.height));
+    }
+
+    let allowed = Ihdr::allowed_bit_depths(self.color_type);
+    if allowed.is_empty() {
+      return invalid(format!("unknown color type {}", self.color_type));
+    }
+    if !allowed.contains(&self.bit_depth) {
+      return invalid(format!("bit depth {} is not allowed for color type {}", self.bit_depth, self.color_type));
+    }
+
+    if self.compression_method != 0 {
+      return invalid(format!("unknown compression method {}", self.compression_method));
+    }
+    if self.filter_method != 0 {
+      return invalid(format!("unknown filter method {}", self.filter_method));
+    }
+    if self.interlace_method > 1 {
+      return invalid(format!("unknown interlace method {}", self.interlace_method));
+    }
+    Ok(())
+  }
+
+  pub fn channels(&self) -> usize {
+    match self.color_type {
+      2 => 3,
+      4 => 2,
+      6 => 4,
+      _ => 1,
+    }
+  }
+
+  pub fn bits_per_pixel(&self) -> usize {
+    self.channels() * self.bit_depth as usize
+  }
+
+  pub fn color_type_name(&self) -> &'static str {
+    match self.color_type {
+      0 => "grayscale",
+      2 => "truecolor",
+      3 => "indexed",
+      4 => "grayscale with alpha",
+      6 => "truecolor with alpha",
+      _ => "unknown",
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_ihdr() -> Ihdr {
+        Ihdr {
+            width: 640,
+            height: 480,
+            bit_depth: 8,
+            color_type: 6,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 1,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk: Chunk = testing_ihdr().into();
+
+        assert_eq!(&chunk.chunk_type().to_string(), "IHDR");
+        assert_eq!(chunk.data(), &[0, 0, 2, 128, 0, 0, 1, 224, 8, 6, 0, 0, 1]);
+        assert_eq!(Ihdr::try_from(&chunk), Ok(testing_ihdr()));
+    }
+
+    #[test]
+    fn test_wrong_chunk() {
+        let chunk = Chunk::new(ChunkType::IHDR, vec![0; 9]);
+        assert!(matches!(Ihdr::try_from(&chunk), Err(Error::InvalidChunkData(_))));
+
+        let chunk = Chunk::new(ChunkType::TEXT, vec![0; 13]);
+        assert!(matches!(Ihdr::try_from(&chunk), Err(Error::InvalidChunkData(_))));
+    }
+
+    #[test]
+    fn test_bit_depth_combinations() {
+        for (color_type, bit_depth, valid) in [
+            (0, 1, true),
+            (0, 16, true),
+            (2, 8, true),
+            (2, 4, false),
+            (3, 8, true),
+            (3, 16, false),
+            (4, 16, true),
+            (6, 1, false),
+            (5, 8, false),
+        ] {
+            let ihdr = Ihdr { color_type, bit_depth, ..testing_ihdr() };
+            assert_eq!(ihdr.validate().is_ok(), valid, "color type {} bit depth {}", color_type, bit_depth);
+        }
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let invalid = [
+            Ihdr { width: 0, ..testing_ihdr() },
+            Ihdr { height: 1 << 31, ..testing_ihdr() },
+            Ihdr { compression_method: 1, ..testing_ihdr() },
+            Ihdr { filter_method: 1, ..testing_ihdr() },
+            Ihdr { interlace_method: 2, ..testing_ihdr() },
+        ];
+
+        for ihdr in invalid {
+            let chunk: Chunk = ihdr.into();
+            assert!(matches!(Ihdr::try_from(&chunk), Err(Error::InvalidChunkData(_))), "{:?}", ihdr);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::chunks::text::{latin1_encode, split_keyword, validate_keyword};
+use crate::zlib::{deflate, inflate};
+use std::convert::TryFrom;
+
+fn invalid<T>(message: &str) -> Result<T> {
+  Err(Error::InvalidChunkData(format!("iTXt: {}", message)))
+}
+
+// Splits off the next null-terminated UTF-8 field
+fn next_field<'a>(data: &'a [u8], name: &str) -> Result<(String, &'a [u8])> {
+  let Some(end) = data.iter().position(|&b| b == 0) else {
+    return invalid(&format!("missing null separator after {}", name));
+  };
+  match String::from_utf8(data[..end].to_vec()) {
+    Ok(field) => Ok((field, &data[end + 1..])),
+    Err(_) => invalid(&format!("{} is not valid UTF-8", name)),
+  }
+}
+
+// Language tags are RFC 3066 style: alphanumeric words separated by hyphens
+fn validate_language(language: &str) -> Result<()> {
+  if language.is_empty() || language.split('-').all(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric())) {
+    Ok(())
+  } else {
+    invalid(&format!("invalid language tag {:?}", language))
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InternationalText {
+  keyword: String,
+  compressed: bool,
+  language: String,
+  translated_keyword: String,
+  text: String,
+}
+
+impl TryFrom<&Chunk> for InternationalText {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "iTXt" {
+      return Err(Error::InvalidChunkData(format!("expected iTXt, got {}", chunk.chunk_type())));
+    }
+
+    let (keyword, rest) = split_keyword("iTXt", chunk.data())?;
+    let (compressed, rest) = match rest {
+      [0, _, rest @ ..] => (false, rest),
+      [1, 0, rest @ ..] => (true, rest),
+      [1, method, ..] => return invalid(&format!("unknown compression method {}", method)),
+      [flag, _, ..] => return invalid(&format!("invalid compression flag {}", flag)),
+      _ => return invalid("missing compression flag and method"),
+    };
+
+    let (language, rest) = next_field(rest, "language tag")?;
+    validate_language(&language)?;
+    let (translated_keyword, rest) = next_field(rest, "translated keyword")?;
+
+    let text = if compressed { inflate("iTXt", rest)? } else { rest.to_vec() };
+    let Ok(text) = String::from_utf8(text) else {
+      return invalid("text is not valid UTF-8");
+    };
+
+    Ok(Self { keyword, compressed, language, translated_keyword, text })
+  }
+}
+
+impl From<InternationalText> for Chunk {
+  fn from(text: InternationalText) -> Chunk {
+    // The keyword was checked to be Latin-1 when the InternationalText was built
+    let mut data = latin1_encode(&text.keyword).unwrap();
+    data.extend([0, text.compressed as u8, 0]);
+    data.extend(text.language.as_bytes());
+    data.push(0);
+    data.extend(text.translated_keyword.as_bytes());
+    data.push(0);
+    if text.compressed {
+      data.extend(deflate(text.text.as_bytes()));
+    } else {
+      data.extend(text.text.as_bytes());
+    }
+
+    Chunk::new(ChunkType::ITXT, data)
+  }
+}
+
+impl InternationalText {
+  pub fn new(keyword: &str, language: &str, translated_keyword: &str, text: &str, compressed: bool) -> Result<Self> {
+    validate_keyword(keyword)?;
+    validate_language(language)?;
+    if translated_keyword.contains('\0') {
+      return invalid("translated keyword contains a null byte");
+    }
+
+    Ok(Self {
+      keyword: keyword.to_string(),
+      compressed,
+      language: language.to_string(),
+      translated_keyword: translated_keyword.to_string(),
+      text: text.to_string(),
+    })
+  }
+
+  pub fn keyword(&self) -> &str {
+    &self.keyword
+  }
+
+  pub fn is_compressed(&self) -> bool {
+    self.compressed
+  }
+
+  pub fn language(&self) -> &str {
+    &self.language
+  }
+
+  pub fn translated_keyword(&self) -> &str {
+    &self.translated_keyword
+  }
+
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn itxt(data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::ITXT, data.to_vec())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for compressed in [false, true] {
+            let text = InternationalText::new("Title", "ja-JP", "タイトル", "こんにちは", compressed).unwrap();
+            let chunk: Chunk = text.clone().into();
+            assert_eq!(InternationalText::try_from(&chunk), Ok(text));
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let text = InternationalText::try_from(&itxt(b"Author\0\0\0en\0Autor\0J\xc3\xbcrgen")).unwrap();
+
+        assert_eq!(text.keyword(), "Author");
+        assert!(!text.is_compressed());
+        assert_eq!(text.language(), "en");
+        assert_eq!(text.translated_keyword(), "Autor");
+        assert_eq!(text.text(), "Jürgen");
+        assert_eq!(Chunk::from(text).data(), b"Author\0\0\0en\0Autor\0J\xc3\xbcrgen");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(InternationalText::try_from(&itxt(b"Author\0")).is_err());
+        assert!(InternationalText::try_from(&itxt(b"Author\0\x02\0en\0\0text")).is_err());
+        assert!(InternationalText::try_from(&itxt(b"Author\0\x01\x01en\0\0text")).is_err());
+        assert!(InternationalText::try_from(&itxt(b"Author\0\0\0en\0no terminator")).is_err());
+        assert!(InternationalText::try_from(&itxt(b"Author\0\0\0e n\0\0text")).is_err());
+        assert!(InternationalText::try_from(&itxt(b"Author\0\0\0\0\0\xff")).is_err());
+        assert!(InternationalText::try_from(&itxt(b"Author\0\x01\0\0\0not zlib")).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Typed views of the chunks defined by the PNG spec. Each type converts
+// from a raw `Chunk`, validating its contents, and back into one.
+mod apng;
+mod color;
+mod exif;
+mod ihdr;
+mod itxt;
+mod phys;
+mod plte;
+mod text;
+mod time;
+mod transparency;
+mod ztxt;
+
+use crate::chunk::Chunk;
+use crate::error::Result;
+use std::convert::TryFrom;
+
+pub use apng::{check_sequence, sequence_number, AnimationControl, FrameControl};
+pub use color::{Chromaticities, Gamma, IccProfile, Srgb};
+pub use exif::{Exif, ExifTags};
+pub use ihdr::Ihdr;
+pub use itxt::InternationalText;
+pub use phys::PhysicalDims;
+pub use text::TextChunk;
+pub use time::LastModified;
+pub use transparency::{Background, SignificantBits, Transparency};
+pub use ztxt::CompressedText;
+
+pub use {apng::{BlendOp, DisposeOp, FrameData}, color::RenderingIntent, phys::Unit, plte::{Palette, Rgb}};
+
+// Any of the three textual chunk flavors, so callers can treat them alike
+#[derive(Clone, Debug, PartialEq)]
+pub enum Text {
+  Plain(TextChunk),
+  Compressed(CompressedText),
+  International(InternationalText),
+}
+
+impl Text {
+  // Returns None for chunks that aren't tEXt, zTXt or iTXt
+  pub fn from_chunk(chunk: &Chunk) -> Option<Result<Text>> {
+    match &chunk.chunk_type().to_string()[..] {
+      "tEXt" => Some(TextChunk::try_from(chunk).map(Text::Plain)),
+      "zTXt" => Some(CompressedText::try_from(chunk).map(Text::Compressed)),
+      "iTXt" => Some(InternationalText::try_from(chunk).map(Text::International)),
+      _ => None,
+    }
+  }
+
+  // tEXt when `text` is all Latin-1, which is all tEXt can hold, and
+  // otherwise iTXt without a language tag
+  pub fn new(keyword: &str, text: &str) -> Result<Text> {
+    match TextChunk::new(keyword, text) {
+      Ok(plain) => Ok(Text::Plain(plain)),
+      Err(_) => InternationalText::new(keyword, "", "", text, false).map(Text::International),
+    }
+  }
+
+  pub fn keyword(&self) -> &str {
+    match self {
+      Text::Plain(text) => text.keyword(),
+      Text::Compressed(text) => text.keyword(),
+      Text::International(text) => text.keyword(),
+    }
+  }
+
+  pub fn text(&self) -> Result<String> {
+    match self {
+      Text::Plain(text) => Ok(text.text().to_string()),
+      Text::Compressed(text) => text.text(),
+      Text::International(text) => Ok(text.text().to_string()),
+    }
+  }
+}
+
+impl From<Text> for Chunk {
+  fn from(text: Text) -> Chunk {
+    match text {
+      Text::Plain(text) => text.into(),
+      Text::Compressed(text) => text.into(),
+      Text::International(text) => text.into(),
+    }
+  }
+}
@@ -0,0 +1,108 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use std::convert::TryFrom;
+
+const METERS_PER_INCH: f64 = 0.0254;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+  // Only the aspect ratio is known
+  Unknown,
+  Meter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhysicalDims {
+  pub x: u32,
+  pub y: u32,
+  pub unit: Unit,
+}
+
+impl TryFrom<&Chunk> for PhysicalDims {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "pHYs" {
+      return Err(Error::InvalidChunkData(format!("expected pHYs, got {}", chunk.chunk_type())));
+    }
+
+    let data = chunk.data();
+    if data.len() != 9 {
+      return Err(Error::InvalidChunkData(format!("pHYs must be 9 bytes, got {}", data.len())));
+    }
+
+    let unit = match data[8] {
+      0 => Unit::Unknown,
+      1 => Unit::Meter,
+      unit => return Err(Error::InvalidChunkData(format!("pHYs: unknown unit specifier {}", unit))),
+    };
+
+    Ok(Self {
+      x: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+      y: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+      unit,
+    })
+  }
+}
+
+impl From<PhysicalDims> for Chunk {
+  fn from(dims: PhysicalDims) -> Chunk {
+    let mut data = dims.x.to_be_bytes().to_vec();
+    data.extend(dims.y.to_be_bytes());
+    data.push(match dims.unit {
+      Unit::Unknown => 0,
+      Unit::Meter => 1,
+    });
+
+    Chunk::new(ChunkType::PHYS, data)
+  }
+}
+
+impl PhysicalDims {
+  pub fn from_dpi(x: f64, y: f64) -> Self {
+    let per_meter = |dpi: f64| (dpi / METERS_PER_INCH).round() as u32;
+    Self { x: per_meter(x), y: per_meter(y), unit: Unit::Meter }
+  }
+
+  // Horizontal and vertical DPI, if the unit is known
+  pub fn dpi(&self) -> Option<(f64, f64)> {
+    match self.unit {
+      Unit::Meter => Some((self.x as f64 * METERS_PER_INCH, self.y as f64 * METERS_PER_INCH)),
+      Unit::Unknown => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dims = PhysicalDims { x: 11811, y: 2835, unit: Unit::Meter };
+        let chunk: Chunk = dims.into();
+
+        assert_eq!(chunk.data(), &[0, 0, 46, 35, 0, 0, 11, 19, 1]);
+        assert_eq!(PhysicalDims::try_from(&chunk), Ok(dims));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let phys = |data: &[u8]| PhysicalDims::try_from(&Chunk::new(ChunkType::PHYS, data.to_vec()));
+
+        assert!(phys(&[0; 8]).is_err());
+        assert!(phys(&[0, 0, 0, 1, 0, 0, 0, 1, 2]).is_err());
+        assert_eq!(phys(&[0, 0, 0, 1, 0, 0, 0, 2, 0]).unwrap().unit, Unit::Unknown);
+    }
+
+    #[test]
+    fn test_dpi() {
+        let dims = PhysicalDims::from_dpi(300.0, 72.0);
+        assert_eq!((dims.x, dims.y), (11811, 2835));
+
+        let (x, y) = dims.dpi().unwrap();
+        assert_eq!((x.round(), y.round()), (300.0, 72.0));
+        assert_eq!(PhysicalDims { x: 1, y: 2, unit: Unit::Unknown }.dpi(), None);
+    }
+}
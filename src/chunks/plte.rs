@@ -0,0 +1,136 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::chunks::Ihdr;
+use std::convert::TryFrom;
+
+const MAX_ENTRIES: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb {
+  pub r: u8,
+  pub g: u8,
+  pub b: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+  entries: Vec<Rgb>,
+}
+
+impl TryFrom<&Chunk> for Palette {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "PLTE" {
+      return Err(Error::InvalidChunkData(format!("expected PLTE, got {}", chunk.chunk_type())));
+    }
+
+    let data = chunk.data();
+    if data.is_empty() || !data.len().is_multiple_of(3) {
+      return Err(Error::InvalidChunkData(format!("PLTE: length {} is not a positive multiple of 3", data.len())));
+    }
+
+    let entries = data.chunks(3).map(|rgb| Rgb { r: rgb[0], g: rgb[1], b: rgb[2] }).collect();
+    Palette::new(entries)
+  }
+}
+
+impl From<Palette> for Chunk {
+  fn from(palette: Palette) -> Chunk {
+    let data = palette.entries.iter().flat_map(|rgb| [rgb.r, rgb.g, rgb.b]).collect();
+    Chunk::new(ChunkType::PLTE, data)
+  }
+}
+
+impl Palette {
+  pub fn new(entries: Vec<Rgb>) -> Result<Self> {
+    if entries.is_empty() || entries.len() > MAX_ENTRIES {
+      return Err(Error::InvalidChunkData(format!("PLTE: {} entries, expected 1 to {}", entries.len(), MAX_ENTRIES)));
+    }
+    Ok(Self { entries })
+  }
+
+  pub fn entries(&self) -> &[Rgb] {
+    &self.entries
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  // Always false for a parsed palette, PLTE needs at least one entry
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn get(&self, index: u8) -> Option<Rgb> {
+    self.entries.get(index as usize).copied()
+  }
+
+  // Checks the palette against the image it belongs to: PLTE is forbidden for
+  // grayscale images, and an indexed image can't use more entries than its
+  // bit depth can address
+  pub fn validate_for(&self, ihdr: &Ihdr) -> Result<()> {
+    match ihdr.color_type {
+      0 | 4 => Err(Error::InvalidChunkData(format!("PLTE: not allowed for color type {}", ihdr.color_type))),
+      3 if self.len() > 1 << ihdr.bit_depth => Err(Error::InvalidChunkData(format!(
+        "PLTE: {} entries, bit depth {} allows at most {}",
+        self.len(),
+        ihdr.bit_depth,
+        1 << ihdr.bit_depth
+      ))),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plte(data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::PLTE, data)
+    }
+
+    fn ihdr(color_type: u8, bit_depth: u8) -> Ihdr {
+        Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = plte(vec![255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        let palette = Palette::try_from(&chunk).unwrap();
+
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette.get(1), Some(Rgb { r: 0, g: 255, b: 0 }));
+        assert_eq!(palette.get(3), None);
+        assert_eq!(Chunk::from(palette).as_bytes(), chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_invalid_length() {
+        assert!(Palette::try_from(&plte(vec![])).is_err());
+        assert!(Palette::try_from(&plte(vec![1, 2, 3, 4])).is_err());
+        assert!(Palette::try_from(&plte(vec![0; 257 * 3])).is_err());
+        assert!(Palette::try_from(&plte(vec![0; 256 * 3])).is_ok());
+    }
+
+    #[test]
+    fn test_validate_for() {
+        let palette = Palette::try_from(&plte(vec![0; 5 * 3])).unwrap();
+
+        assert!(palette.validate_for(&ihdr(3, 8)).is_ok());
+        assert!(palette.validate_for(&ihdr(2, 8)).is_ok());
+        assert!(palette.validate_for(&ihdr(3, 2)).is_err());
+        assert!(palette.validate_for(&ihdr(0, 8)).is_err());
+    }
+}
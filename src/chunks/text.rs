@@ -0,0 +1,133 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use std::convert::TryFrom;
+
+// Checks a tEXt/zTXt/iTXt keyword: 1 to 79 printable Latin-1 characters,
+// without leading, trailing or consecutive spaces
+pub(crate) fn validate_keyword(keyword: &str) -> Result<()> {
+  let invalid = |message: &str| Err(Error::InvalidChunkData(format!("keyword {:?} {}", keyword, message)));
+
+  let length = keyword.chars().count();
+  if length == 0 || length > 79 {
+    return invalid("must be 1 to 79 characters long");
+  }
+  if !keyword.chars().all(|c| matches!(c as u32, 32..=126 | 161..=255)) {
+    return invalid("contains characters outside printable Latin-1");
+  }
+  if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+    return invalid("has leading, trailing or consecutive spaces");
+  }
+  Ok(())
+}
+
+pub(crate) fn latin1_decode(bytes: &[u8]) -> String {
+  bytes.iter().map(|&b| b as char).collect()
+}
+
+pub(crate) fn latin1_encode(text: &str) -> Result<Vec<u8>> {
+  text
+    .chars()
+    .map(|c| u8::try_from(c as u32).map_err(|_| Error::InvalidChunkData(format!("{:?} is not a Latin-1 character", c))))
+    .collect()
+}
+
+// Splits chunk data at the null byte that ends the keyword
+pub(crate) fn split_keyword<'a>(chunk_type: &str, data: &'a [u8]) -> Result<(String, &'a [u8])> {
+  match data.iter().position(|&b| b == 0) {
+    Some(end) => {
+      let keyword = latin1_decode(&data[..end]);
+      validate_keyword(&keyword)?;
+      Ok((keyword, &data[end + 1..]))
+    }
+    None => Err(Error::InvalidChunkData(format!("{}: missing null separator after keyword", chunk_type))),
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextChunk {
+  keyword: String,
+  text: String,
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "tEXt" {
+      return Err(Error::InvalidChunkData(format!("expected tEXt, got {}", chunk.chunk_type())));
+    }
+
+    let (keyword, text) = split_keyword("tEXt", chunk.data())?;
+    Ok(Self { keyword, text: latin1_decode(text) })
+  }
+}
+
+impl From<TextChunk> for Chunk {
+  fn from(text: TextChunk) -> Chunk {
+    // Both fields were checked to be Latin-1 when the TextChunk was built
+    let mut data = latin1_encode(&text.keyword).unwrap();
+    data.push(0);
+    data.extend(latin1_encode(&text.text).unwrap());
+
+    Chunk::new(ChunkType::TEXT, data)
+  }
+}
+
+impl TextChunk {
+  pub fn new(keyword: &str, text: &str) -> Result<Self> {
+    validate_keyword(keyword)?;
+    latin1_encode(text)?;
+    Ok(Self { keyword: keyword.to_string(), text: text.to_string() })
+  }
+
+  pub fn keyword(&self) -> &str {
+    &self.keyword
+  }
+
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = TextChunk::new("Comment", "café").unwrap();
+        let chunk: Chunk = text.clone().into();
+
+        assert_eq!(&chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Comment\0caf\xe9");
+        assert_eq!(TextChunk::try_from(&chunk), Ok(text));
+    }
+
+    #[test]
+    fn test_from_chunk() {
+        let chunk = Chunk::new(ChunkType::TEXT, b"Title\0a\0b".to_vec());
+        let text = TextChunk::try_from(&chunk).unwrap();
+
+        assert_eq!(text.keyword(), "Title");
+        assert_eq!(text.text(), "a\0b");
+
+        let chunk = Chunk::new(ChunkType::TEXT, b"no separator".to_vec());
+        assert!(TextChunk::try_from(&chunk).is_err());
+    }
+
+    #[test]
+    fn test_invalid_keyword() {
+        assert!(TextChunk::new("", "text").is_err());
+        assert!(TextChunk::new(&"k".repeat(80), "text").is_err());
+        assert!(TextChunk::new(&"k".repeat(79), "text").is_ok());
+        assert!(TextChunk::new(" Title", "text").is_err());
+        assert!(TextChunk::new("Two  spaces", "text").is_err());
+        assert!(TextChunk::new("Tab\there", "text").is_err());
+    }
+
+    #[test]
+    fn test_invalid_text() {
+        assert!(TextChunk::new("Title", "snowman ☃").is_err());
+    }
+}
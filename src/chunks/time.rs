@@ -0,0 +1,179 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LastModified {
+  pub year: u16,
+  pub month: u8,
+  pub day: u8,
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8,
+}
+
+impl TryFrom<&Chunk> for LastModified {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "tIME" {
+      return Err(Error::InvalidChunkData(format!("expected tIME, got {}", chunk.chunk_type())));
+    }
+
+    let data = chunk.data();
+    if data.len() != 7 {
+      return Err(Error::InvalidChunkData(format!("tIME must be 7 bytes, got {}", data.len())));
+    }
+
+    let time = Self {
+      year: u16::from_be_bytes([data[0], data[1]]),
+      month: data[2],
+      day: data[3],
+      hour: data[4],
+      minute: data[5],
+      second: data[6],
+    };
+    time.validate()?;
+    Ok(time)
+  }
+}
+
+impl From<LastModified> for Chunk {
+  fn from(time: LastModified) -> Chunk {
+    let mut data = time.year.to_be_bytes().to_vec();
+    data.extend([time.month, time.day, time.hour, time.minute, time.second]);
+
+    Chunk::new(ChunkType::TIME, data)
+  }
+}
+
+// Accepts `YYYY-MM-DDTHH:MM:SS`, with an optional trailing `Z`; the space
+// separated form is accepted too. tIME is always UTC.
+impl FromStr for LastModified {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let invalid = || Error::InvalidChunkData(format!("invalid timestamp {:?}, expected YYYY-MM-DDTHH:MM:SS", s));
+
+    let trimmed = s.strip_suffix('Z').unwrap_or(s);
+    let (date, time) = trimmed.split_once(['T', ' ']).ok_or_else(invalid)?;
+    let date: Vec<&str> = date.split('-').collect();
+    let time: Vec<&str> = time.split(':').collect();
+    if date.len() != 3 || time.len() != 3 {
+      return Err(invalid());
+    }
+
+    let field = |s: &str| s.parse::<u8>().map_err(|_| invalid());
+    let parsed = Self {
+      year: date[0].parse().map_err(|_| invalid())?,
+      month: field(date[1])?,
+      day: field(date[2])?,
+      hour: field(time[0])?,
+      minute: field(time[1])?,
+      second: field(time[2])?,
+    };
+    parsed.validate()?;
+    Ok(parsed)
+  }
+}
+
+impl fmt::Display for LastModified {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+      self.year, self.month, self.day, self.hour, self.minute, self.second
+    )
+  }
+}
+
+impl LastModified {
+  pub fn now() -> Self {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    Self::from_unix(seconds)
+  }
+
+  pub fn from_unix(seconds: u64) -> Self {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    Self {
+      year: year as u16,
+      month: month as u8,
+      day: day as u8,
+      hour: (time / 3600) as u8,
+      minute: (time / 60 % 60) as u8,
+      second: (time % 60) as u8,
+    }
+  }
+
+  pub fn validate(&self) -> Result<()> {
+    // 60 allows for leap seconds
+    if !(1..=12).contains(&self.month)
+      || !(1..=31).contains(&self.day)
+      || self.hour > 23
+      || self.minute > 59
+      || self.second > 60
+    {
+      return Err(Error::InvalidChunkData(format!("tIME: {} is out of range", self)));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let time = LastModified { year: 2024, month: 2, day: 29, hour: 13, minute: 5, second: 9 };
+        let chunk: Chunk = time.into();
+
+        assert_eq!(chunk.data(), &[7, 232, 2, 29, 13, 5, 9]);
+        assert_eq!(LastModified::try_from(&chunk), Ok(time));
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let time = |data: &[u8]| LastModified::try_from(&Chunk::new(ChunkType::TIME, data.to_vec()));
+
+        assert!(time(&[7, 232, 2, 29, 13, 5]).is_err());
+        assert!(time(&[7, 232, 13, 29, 13, 5, 9]).is_err());
+        assert!(time(&[7, 232, 2, 29, 24, 5, 9]).is_err());
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let time = LastModified::from_str("2024-02-29T13:05:09Z").unwrap();
+        assert_eq!(time, LastModified { year: 2024, month: 2, day: 29, hour: 13, minute: 5, second: 9 });
+        assert_eq!(time.to_string(), "2024-02-29T13:05:09Z");
+        assert_eq!(LastModified::from_str("2024-02-29 13:05:09"), Ok(time));
+
+        assert!(LastModified::from_str("2024-02-29").is_err());
+        assert!(LastModified::from_str("2024-00-29T13:05:09").is_err());
+        assert!(LastModified::from_str("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_from_unix() {
+        assert_eq!(LastModified::from_unix(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(LastModified::from_unix(951782400).to_string(), "2000-02-29T00:00:00Z");
+        assert_eq!(LastModified::from_unix(1709211909).to_string(), "2024-02-29T13:05:09Z");
+    }
+}
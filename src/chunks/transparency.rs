@@ -0,0 +1,259 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::chunks::{Ihdr, Palette};
+
+// tRNS, bKGD and sBIT are laid out differently depending on the color type,
+// so they can only be decoded together with the image's IHDR
+
+fn expect(chunk: &Chunk, chunk_type: &str) -> Result<()> {
+  if chunk.chunk_type().to_string() != chunk_type {
+    return Err(Error::InvalidChunkData(format!("expected {}, got {}", chunk_type, chunk.chunk_type())));
+  }
+  Ok(())
+}
+
+fn expect_length(chunk_type: &str, data: &[u8], length: usize, ihdr: &Ihdr) -> Result<()> {
+  if data.len() != length {
+    return Err(Error::InvalidChunkData(format!(
+      "{} must be {} bytes for color type {}, got {}",
+      chunk_type,
+      length,
+      ihdr.color_type,
+      data.len()
+    )));
+  }
+  Ok(())
+}
+
+fn samples(data: &[u8]) -> Vec<u16> {
+  data.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+// Gray and truecolor samples have to fit in the image's bit depth
+fn check_samples(chunk_type: &str, values: &[u16], ihdr: &Ihdr) -> Result<()> {
+  match values.iter().find(|&&v| ihdr.bit_depth < 16 && v >> ihdr.bit_depth != 0) {
+    Some(v) => Err(Error::InvalidChunkData(format!("{}: sample {} exceeds bit depth {}", chunk_type, v, ihdr.bit_depth))),
+    None => Ok(()),
+  }
+}
+
+fn check_index(chunk_type: &str, count: usize, palette: &Palette) -> Result<()> {
+  if count > palette.len() {
+    return Err(Error::InvalidChunkData(format!(
+      "{}: refers to palette entry {} but PLTE has {}",
+      chunk_type,
+      count - 1,
+      palette.len()
+    )));
+  }
+  Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transparency {
+  // The gray or RGB value that is fully transparent
+  Gray(u16),
+  Rgb(u16, u16, u16),
+  // Alpha for each palette entry, starting from the first
+  Palette(Vec<u8>),
+}
+
+impl From<Transparency> for Chunk {
+  fn from(trns: Transparency) -> Chunk {
+    let data = match trns {
+      Transparency::Gray(gray) => gray.to_be_bytes().to_vec(),
+      Transparency::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+      Transparency::Palette(alpha) => alpha,
+    };
+    Chunk::new(ChunkType::TRNS, data)
+  }
+}
+
+impl Transparency {
+  pub fn decode(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self> {
+    expect(chunk, "tRNS")?;
+    let data = chunk.data();
+
+    match ihdr.color_type {
+      0 => {
+        expect_length("tRNS", data, 2, ihdr)?;
+        let values = samples(data);
+        check_samples("tRNS", &values, ihdr)?;
+        Ok(Transparency::Gray(values[0]))
+      }
+      2 => {
+        expect_length("tRNS", data, 6, ihdr)?;
+        let values = samples(data);
+        check_samples("tRNS", &values, ihdr)?;
+        Ok(Transparency::Rgb(values[0], values[1], values[2]))
+      }
+      3 if data.len() > 256 => Err(Error::InvalidChunkData(format!("tRNS: {} palette entries, expected at most 256", data.len()))),
+      3 => Ok(Transparency::Palette(data.to_vec())),
+      color_type => Err(Error::InvalidChunkData(format!("tRNS: not allowed for color type {}, which has an alpha channel", color_type))),
+    }
+  }
+
+  pub fn validate_for(&self, palette: &Palette) -> Result<()> {
+    match self {
+      Transparency::Palette(alpha) => check_index("tRNS", alpha.len(), palette),
+      _ => Ok(()),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+  Gray(u16),
+  Rgb(u16, u16, u16),
+  PaletteIndex(u8),
+}
+
+impl From<Background> for Chunk {
+  fn from(bkgd: Background) -> Chunk {
+    let data = match bkgd {
+      Background::Gray(gray) => gray.to_be_bytes().to_vec(),
+      Background::Rgb(r, g, b) => [r, g, b].iter().flat_map(|v| v.to_be_bytes()).collect(),
+      Background::PaletteIndex(index) => vec![index],
+    };
+    Chunk::new(ChunkType::BKGD, data)
+  }
+}
+
+impl Background {
+  pub fn decode(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self> {
+    expect(chunk, "bKGD")?;
+    let data = chunk.data();
+
+    match ihdr.color_type {
+      0 | 4 => {
+        expect_length("bKGD", data, 2, ihdr)?;
+        let values = samples(data);
+        check_samples("bKGD", &values, ihdr)?;
+        Ok(Background::Gray(values[0]))
+      }
+      2 | 6 => {
+        expect_length("bKGD", data, 6, ihdr)?;
+        let values = samples(data);
+        check_samples("bKGD", &values, ihdr)?;
+        Ok(Background::Rgb(values[0], values[1], values[2]))
+      }
+      _ => {
+        expect_length("bKGD", data, 1, ihdr)?;
+        Ok(Background::PaletteIndex(data[0]))
+      }
+    }
+  }
+
+  pub fn validate_for(&self, palette: &Palette) -> Result<()> {
+    match self {
+      Background::PaletteIndex(index) => check_index("bKGD", *index as usize + 1, palette),
+      _ => Ok(()),
+    }
+  }
+}
+
+// Number of significant bits in each channel of the original image
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignificantBits(pub Vec<u8>);
+
+impl From<SignificantBits> for Chunk {
+  fn from(sbit: SignificantBits) -> Chunk {
+    Chunk::new(ChunkType::SBIT, sbit.0)
+  }
+}
+
+impl SignificantBits {
+  pub fn decode(chunk: &Chunk, ihdr: &Ihdr) -> Result<Self> {
+    expect(chunk, "sBIT")?;
+    let data = chunk.data();
+
+    let channels = match ihdr.color_type {
+      0 => 1,
+      4 => 2,
+      2 | 3 => 3,
+      _ => 4,
+    };
+    expect_length("sBIT", data, channels, ihdr)?;
+
+    // Palette entries are always 8 bits per channel
+    let depth = if ihdr.color_type == 3 { 8 } else { ihdr.bit_depth };
+    match data.iter().find(|&&bits| bits == 0 || bits > depth) {
+      Some(bits) => Err(Error::InvalidChunkData(format!("sBIT: {} significant bits, expected 1 to {}", bits, depth))),
+      None => Ok(SignificantBits(data.to_vec())),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::Rgb;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn ihdr(color_type: u8, bit_depth: u8) -> Ihdr {
+        Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    fn palette(entries: usize) -> Palette {
+        Palette::new(vec![Rgb { r: 0, g: 0, b: 0 }; entries]).unwrap()
+    }
+
+    #[test]
+    fn test_transparency() {
+        let rgb = Transparency::decode(&chunk("tRNS", &[0, 1, 0, 2, 0, 3]), &ihdr(2, 8)).unwrap();
+        assert_eq!(rgb, Transparency::Rgb(1, 2, 3));
+        assert_eq!(Chunk::from(rgb).data(), &[0, 1, 0, 2, 0, 3]);
+
+        let gray = Transparency::decode(&chunk("tRNS", &[0, 3]), &ihdr(0, 2)).unwrap();
+        assert_eq!(gray, Transparency::Gray(3));
+        assert!(Transparency::decode(&chunk("tRNS", &[0, 4]), &ihdr(0, 2)).is_err());
+
+        let indexed = Transparency::decode(&chunk("tRNS", &[0, 128, 255]), &ihdr(3, 8)).unwrap();
+        assert!(indexed.validate_for(&palette(3)).is_ok());
+        assert!(indexed.validate_for(&palette(2)).is_err());
+
+        assert!(Transparency::decode(&chunk("tRNS", &[0, 1]), &ihdr(4, 8)).is_err());
+        assert!(Transparency::decode(&chunk("tRNS", &[0, 1, 0, 2, 0, 3, 0, 4]), &ihdr(6, 8)).is_err());
+        assert!(Transparency::decode(&chunk("tRNS", &[0, 1, 0, 2]), &ihdr(2, 8)).is_err());
+    }
+
+    #[test]
+    fn test_background() {
+        let gray = Background::decode(&chunk("bKGD", &[1, 0]), &ihdr(4, 16)).unwrap();
+        assert_eq!(gray, Background::Gray(256));
+        assert_eq!(Chunk::from(gray).data(), &[1, 0]);
+
+        let index = Background::decode(&chunk("bKGD", &[2]), &ihdr(3, 8)).unwrap();
+        assert_eq!(index, Background::PaletteIndex(2));
+        assert!(index.validate_for(&palette(3)).is_ok());
+        assert!(index.validate_for(&palette(2)).is_err());
+
+        assert!(Background::decode(&chunk("bKGD", &[1, 0]), &ihdr(6, 8)).is_err());
+        assert!(Background::decode(&chunk("bKGD", &[1, 0]), &ihdr(0, 8)).is_err());
+    }
+
+    #[test]
+    fn test_significant_bits() {
+        let sbit = SignificantBits::decode(&chunk("sBIT", &[5, 6, 5, 8]), &ihdr(6, 8)).unwrap();
+        assert_eq!(sbit, SignificantBits(vec![5, 6, 5, 8]));
+        assert_eq!(Chunk::from(sbit).data(), &[5, 6, 5, 8]);
+
+        assert!(SignificantBits::decode(&chunk("sBIT", &[5, 6, 5]), &ihdr(3, 2)).is_ok());
+        assert!(SignificantBits::decode(&chunk("sBIT", &[5, 6, 5]), &ihdr(6, 8)).is_err());
+        assert!(SignificantBits::decode(&chunk("sBIT", &[9]), &ihdr(0, 8)).is_err());
+        assert!(SignificantBits::decode(&chunk("sBIT", &[0]), &ihdr(0, 8)).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::chunk::Chunk;
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::chunks::text::{latin1_decode, latin1_encode, split_keyword, validate_keyword};
+use crate::zlib::{deflate, inflate};
+use std::convert::TryFrom;
+
+// The only compression method the spec defines: zlib deflate
+const DEFLATE: u8 = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressedText {
+  keyword: String,
+  compressed: Vec<u8>,
+}
+
+impl TryFrom<&Chunk> for CompressedText {
+  type Error = Error;
+
+  fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+    if chunk.chunk_type().to_string() != "zTXt" {
+      return Err(Error::InvalidChunkData(format!("expected zTXt, got {}", chunk.chunk_type())));
+    }
+
+    let (keyword, rest) = split_keyword("zTXt", chunk.data())?;
+    match rest.split_first() {
+      Some((&DEFLATE, compressed)) => Ok(Self { keyword, compressed: compressed.to_vec() }),
+      Some((method, _)) => Err(Error::InvalidChunkData(format!("zTXt: unknown compression method {}", method))),
+      None => Err(Error::InvalidChunkData("zTXt: missing compression method".to_string())),
+    }
+  }
+}
+
+impl From<CompressedText> for Chunk {
+  fn from(text: CompressedText) -> Chunk {
+    // The keyword was checked to be Latin-1 when the CompressedText was built
+    let mut data = latin1_encode(&text.keyword).unwrap();
+    data.extend([0, DEFLATE]);
+    data.extend(text.compressed);
+
+    Chunk::new(ChunkType::ZTXT, data)
+  }
+}
+
+impl CompressedText {
+  pub fn new(keyword: &str, text: &str) -> Result<Self> {
+    validate_keyword(keyword)?;
+    let compressed = deflate(&latin1_encode(text)?);
+    Ok(Self { keyword: keyword.to_string(), compressed })
+  }
+
+  pub fn keyword(&self) -> &str {
+    &self.keyword
+  }
+
+  pub fn compressed(&self) -> &[u8] {
+    &self.compressed
+  }
+
+  pub fn text(&self) -> Result<String> {
+    Ok(latin1_decode(&inflate("zTXt", &self.compressed)?))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = CompressedText::new("Description", &"café ".repeat(100)).unwrap();
+        let chunk: Chunk = text.clone().into();
+
+        assert_eq!(&chunk.chunk_type().to_string(), "zTXt");
+        assert!(chunk.data().starts_with(b"Description\0\0"));
+        assert!(chunk.data().len() < 100);
+
+        let decoded = CompressedText::try_from(&chunk).unwrap();
+        assert_eq!(decoded, text);
+        assert_eq!(decoded.keyword(), "Description");
+        assert_eq!(decoded.text().unwrap(), "café ".repeat(100));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let zt = |data: &[u8]| Chunk::new(ChunkType::ZTXT, data.to_vec());
+
+        assert!(CompressedText::try_from(&zt(b"Title\0")).is_err());
+        assert!(CompressedText::try_from(&zt(b"Title\0\x01abc")).is_err());
+
+        let garbage = CompressedText::try_from(&zt(b"Title\0\0not zlib")).unwrap();
+        assert!(matches!(garbage.text(), Err(Error::InvalidChunkData(_))));
+    }
+}
@@ -0,0 +1,4157 @@
+use crate::args::{self, ApngCommand, ApngSplitArgs, CapacityArgs, Cli, CompletionsArgs, DecodeArgs, DetectArgs, DiffArgs, EncodeArgs, ExtractArgs, FromRawArgs, GetTextArgs, GlobalArgs, GrepArgs, HexdumpArgs, InfoArgs, InsertArgs, ListMessagesArgs, MergeArgs, MetadataArgs, Mode, PngMeArgs, PrintArgs, RecompressArgs, RecoverArgs, RemoveArgs, RepairArgs, ReplaceArgs, ScanArgs, SetDpiArgs, SetTextArgs, StripArgs, ToRawArgs, TouchArgs, TrailingArgs, UncrushArgs, VerifyArgs};
+use pngne::cgbi;
+use pngne::chunk::{self, Chunk};
+use pngne::chunk_type::ChunkType;
+use pngne::chunks::{AnimationControl, Chromaticities, CompressedText, Exif, ExifTags, Gamma, IccProfile, Ihdr, LastModified, PhysicalDims, Srgb, Text, TextChunk};
+use pngne::detect::{self as detection, Detection};
+use pngne::diagnostics::{self, Diagnostic};
+use pngne::error::{Error, Result};
+use pngne::fragment;
+use pngne::index::{ChunkIndex, IndexEntry};
+use pngne::lsb;
+use pngne::payload::{self, Compression, Keys};
+use pngne::parser::{ChunkInfo, PngParser};
+use age::x25519::Identity;
+use pngne::pixels::{self, ImageData};
+use pngne::png::{CrcFix, ParseMode, ParseReport, Png};
+#[cfg(feature = "mmap")]
+use pngne::png::PngMap;
+use pngne::verify::{self as checks, Report};
+use pngne::zlib;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use regex::bytes::Regex;
+
+// Process exit codes. These are part of the command line interface that
+// scripts depend on, so existing values must never be renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+  Success = 0,
+  Usage = 1,
+  ParseFailure = 2,
+  CrcMismatch = 3,
+  ChunkNotFound = 4,
+  Io = 5,
+  VerifyFailed = 6,
+  BatchFailed = 7,
+  Failure = 8,
+  InvalidOrder = 9,
+}
+
+impl ExitReason {
+  pub fn code(self) -> i32 {
+    self as i32
+  }
+}
+
+impl From<&Error> for ExitReason {
+  fn from(error: &Error) -> Self {
+    match error {
+      Error::TruncatedChunk { .. }
+      | Error::LengthFieldInvalid { .. }
+      | Error::ChunkTooLarge(_)
+      | Error::InvalidUtf8 { .. }
+      | Error::ChunkTypeNotValid(_)
+      | Error::InvalidSignature(_)
+      | Error::TooSmall
+      | Error::LimitExceeded { .. }
+      | Error::InvalidChunkType(_)
+      | Error::InvalidChunkData(_)
+      | Error::Payload(_) => ExitReason::ParseFailure,
+      Error::CrcMismatch(_, _) => ExitReason::CrcMismatch,
+      Error::ChunkNotFound | Error::KeywordNotFound(_) => ExitReason::ChunkNotFound,
+      Error::IndexOutOfRange(_) | Error::InvalidPattern(_) | Error::InvalidTemplate(_) => ExitReason::Usage,
+      Error::Io(_) | Error::Watch(_) => ExitReason::Io,
+      Error::VerifyFailed(_) | Error::Denied(_) => ExitReason::VerifyFailed,
+      Error::BatchFailed(_) => ExitReason::BatchFailed,
+      Error::InvalidOrder(_) => ExitReason::InvalidOrder,
+      Error::ThreadPool(_) => ExitReason::Failure,
+    }
+  }
+}
+
+// Runs the command and reports any error, leaving only the exit code for
+// the caller to act on
+pub fn execute(cli: Cli) -> ExitReason {
+  match run(cli) {
+    Ok(()) => ExitReason::Success,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      if let Error::LimitExceeded { limit, .. } = &e {
+        eprintln!("Files this size can be read with a higher {}", limit_flag(limit));
+      }
+      ExitReason::from(&e)
+    }
+  }
+}
+
+// The --max-* flag that sets the limit named in `Error::LimitExceeded`
+fn limit_flag(limit: &str) -> &'static str {
+  match limit {
+    "chunk length" => "--max-chunk-length",
+    "chunk count" => "--max-chunks",
+    "inflated size" => "--max-inflated-size",
+    _ => "--max-inflate-ratio",
+  }
+}
+
+pub fn run(cli: Cli) -> Result<()> {
+  let global = cli.global;
+
+  match cli.command {
+    PngMeArgs::Encode(args) => encode(args, global),
+    PngMeArgs::Decode(args) => decode(args, global),
+    PngMeArgs::Remove(args) => remove(args, global),
+    PngMeArgs::Print(args) => print(args, global),
+    PngMeArgs::Scan(args) => scan(args, global),
+    PngMeArgs::Repair(args) => repair(args, global),
+    PngMeArgs::Recover(args) => recover(args, global),
+    PngMeArgs::Strip(args) => strip(args, global),
+    PngMeArgs::Extract(args) => extract(args, global),
+    PngMeArgs::Insert(args) => insert(args, global),
+    PngMeArgs::Replace(args) => replace(args, global),
+    PngMeArgs::Diff(args) => diff(args, global),
+    PngMeArgs::Merge(args) => merge(args, global),
+    PngMeArgs::Info(args) => info(args, global),
+    PngMeArgs::Verify(args) => verify(args, global),
+    PngMeArgs::Hexdump(args) => hexdump(args, global),
+    PngMeArgs::Completions(args) => completions(args, &mut io::stdout()),
+    PngMeArgs::Grep(args) => grep(args, global),
+    PngMeArgs::Touch(args) => touch(args, global),
+    PngMeArgs::SetDpi(args) => set_dpi(args, global),
+    PngMeArgs::Apng(ApngCommand::Split(args)) => apng_split(args, global),
+    PngMeArgs::Recompress(args) => recompress(args, global),
+    PngMeArgs::ToRaw(args) => to_raw(args, global),
+    PngMeArgs::FromRaw(args) => from_raw(args, global),
+    PngMeArgs::ListMessages(args) => list_messages(args, global),
+    PngMeArgs::Capacity(args) => capacity(args, global),
+    PngMeArgs::Detect(args) => detect(args, global),
+    PngMeArgs::Trailing(args) => trailing(args, global),
+    PngMeArgs::Uncrush(args) => uncrush(args, global),
+    PngMeArgs::Metadata(args) => metadata(args, global),
+    PngMeArgs::SetText(args) => set_text(args, global),
+    PngMeArgs::GetText(args) => get_text(args, global),
+  }
+}
+
+fn json_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+// A path of `-` means stdin when reading and stdout when writing
+fn is_stdio(path: &Path) -> bool {
+  path.as_os_str() == "-"
+}
+
+fn read_png(path: &Path, global: GlobalArgs) -> Result<Png> {
+  log::debug!("reading {}", path.display());
+  if global.parse_mode(ParseMode::Strict) == ParseMode::Lenient {
+    let (png, report) = parse_png(&read_bytes(path)?, global, ParseMode::Lenient)?;
+    for diagnostic in &report.diagnostics {
+      log::warn!("{}: {}", path.display(), diagnostic);
+    }
+    return Ok(png);
+  }
+  // Strict files can be streamed
+  if is_stdio(path) {
+    Png::from_reader_with_limits(io::stdin().lock(), global.limits())
+  } else {
+    Png::from_reader_with_limits(BufReader::new(File::open(path)?), global.limits())
+  }
+}
+
+// Parses `bytes` in the mode asked for with --strict or --lenient, or in
+// `default`. Chunks with a bad CRC and anything else the parser overlooked
+// come back alongside the PNG.
+fn parse_png(bytes: &[u8], global: GlobalArgs, default: ParseMode) -> Result<(Png, ParseReport)> {
+  Png::parse_with(bytes, global.parse_mode(default), global.limits())
+}
+
+fn index_file(path: &Path, global: GlobalArgs) -> Result<ChunkIndex<BufReader<File>>> {
+  ChunkIndex::with_limits(BufReader::new(File::open(path)?), global.limits())
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>> {
+  if is_stdio(path) {
+    let mut bytes = vec![];
+    io::stdin().lock().read_to_end(&mut bytes)?;
+    Ok(bytes)
+  } else {
+    Ok(fs::read(path)?)
+  }
+}
+
+// For commands that stream a file rather than load it
+fn open_reader(path: &Path) -> Result<Box<dyn Read>> {
+  if is_stdio(path) {
+    Ok(Box::new(io::stdin().lock()))
+  } else {
+    Ok(Box::new(BufReader::new(File::open(path)?)))
+  }
+}
+
+fn create_writer(path: &Path) -> Result<Box<dyn Write>> {
+  if is_stdio(path) {
+    Ok(Box::new(BufWriter::new(io::stdout().lock())))
+  } else {
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+  }
+}
+
+// Whether `a` and `b` are the same file, however each path is written
+fn same_file(a: &Path, b: &Path) -> bool {
+  match (fs::canonicalize(a), fs::canonicalize(b)) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => a == b,
+  }
+}
+
+// Has `write` write a temporary file next to `path` and renames it over
+// `path` once it succeeds, so a failure never leaves half a file behind.
+// Stdout is written to directly.
+fn write_replacing(path: &Path, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+  if is_stdio(path) {
+    return write(&mut create_writer(path)?);
+  }
+
+  let name = path.file_name().map_or("output".into(), |name| name.to_string_lossy());
+  let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+  let written = File::create(&temp)
+    .map_err(Error::from)
+    .and_then(|file| {
+      let mut writer = BufWriter::new(file);
+      write(&mut writer)?;
+      Ok(writer.flush()?)
+    })
+    .and_then(|()| Ok(fs::rename(&temp, path)?));
+  if written.is_err() {
+    // Nothing to clean up if creating it was what failed
+    let _ = fs::remove_file(&temp);
+  }
+  written
+}
+
+// File contents for read-only commands. With the mmap feature files are
+// mapped instead of read, stdin still has to be read
+enum FileBytes {
+  Read(Vec<u8>),
+  #[cfg(feature = "mmap")]
+  Mapped(PngMap),
+}
+
+impl Deref for FileBytes {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      FileBytes::Read(bytes) => bytes,
+      #[cfg(feature = "mmap")]
+      FileBytes::Mapped(map) => map.bytes(),
+    }
+  }
+}
+
+fn load_bytes(path: &Path) -> Result<FileBytes> {
+  #[cfg(feature = "mmap")]
+  if !is_stdio(path) {
+    return Ok(FileBytes::Mapped(Png::open_mmap(path)?));
+  }
+  read_bytes(path).map(FileBytes::Read)
+}
+
+fn write_bytes(path: &Path, bytes: &[u8]) -> Result<()> {
+  if is_stdio(path) {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(bytes)?;
+    stdout.flush()?;
+  } else {
+    fs::write(path, bytes)?;
+  }
+  Ok(())
+}
+
+fn write_png(path: &Path, png: &Png) -> Result<()> {
+  log::debug!("writing {} ({} chunks)", path.display(), png.chunks().len());
+  if is_stdio(path) {
+    // Stdout is line buffered, which would split binary data into many writes
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    png.write_to(&mut stdout)?;
+    stdout.flush()?;
+  } else {
+    // Only the chunks that differ from what's on disk are written
+    png.save_in_place(path)?;
+  }
+  Ok(())
+}
+
+// Mutating commands edit in place unless `--output` is given
+fn output_path<'a>(file_path: &'a Path, output: Option<&'a Path>) -> &'a Path {
+  output.unwrap_or(file_path)
+}
+
+fn write_output(png: &mut Png, file_path: &Path, output: Option<&Path>, global: GlobalArgs) -> Result<()> {
+  if let Some(chunk_size) = global.idat_chunk_size {
+    png.resplit_idat(chunk_size);
+  }
+  write_png(output_path(file_path, output), png)
+}
+
+// Walks `dir` depth first, in name order, collecting every `.png` file
+fn find_pngs(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+  let mut entries = fs::read_dir(dir)?
+    .map(|entry| entry.map(|e| e.path()))
+    .collect::<Result<Vec<_>, _>>()?;
+  entries.sort();
+
+  for path in entries {
+    if path.is_dir() {
+      find_pngs(&path, found)?;
+    } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+      found.push(path);
+    }
+  }
+
+  Ok(())
+}
+
+// In JSON mode every report is already a JSON object, so the batch output
+// becomes one document per line
+fn batch<F>(dir: &Path, global: GlobalArgs, jobs: usize, f: F) -> Result<()>
+where
+  F: Fn(&Path) -> Result<String> + Sync,
+{
+  let mut files = vec![];
+  find_pngs(dir, &mut files)?;
+
+  // Files are processed on the pool but reported in the order they were
+  // found, so the output doesn't change from one run to the next
+  let pool = rayon::ThreadPoolBuilder::new()
+    .num_threads(jobs)
+    .build()
+    .map_err(|e| Error::ThreadPool(e.to_string()))?;
+  let results: Vec<Result<String>> = pool.install(|| files.par_iter().map(|path| f(path)).collect());
+
+  let mut failed = 0;
+  for (path, result) in files.iter().zip(results) {
+    match result {
+      Ok(report) if global.json => println!("{}", report),
+      Ok(report) => println!("{}: {}", path.display(), report),
+      Err(e) => {
+        failed += 1;
+        if global.json {
+          let file = json_string(&path.display().to_string());
+          let error = json_string(&e.to_string());
+          println!("{{\"file\":{},\"error\":{}}}", file, error);
+        } else {
+          println!("{}: error: {}", path.display(), e);
+        }
+      }
+    }
+  }
+
+  if !global.json {
+    println!("{} file(s) processed, {} failed", files.len(), failed);
+  }
+  if failed > 0 {
+    Err(Error::BatchFailed(failed))
+  } else {
+    Ok(())
+  }
+}
+
+// Whether `event` is a write to `path`. Only file names are compared since
+// the watched directory is always the parent of `path`
+fn touches(event: &Event, path: &Path) -> bool {
+  matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+    && event.paths.iter().any(|p| p.file_name() == path.file_name())
+}
+
+// Runs `f` now and again after every change to `path` until interrupted.
+// The parent directory is watched instead of the file so that editors which
+// save by renaming a temporary file over the original are still picked up.
+// Failures of `f` are reported but do not end the watch.
+fn watch<F>(path: &Path, mut f: F) -> Result<()>
+where
+  F: FnMut() -> Result<()>,
+{
+  let dir = match path.parent() {
+    Some(dir) if !dir.as_os_str().is_empty() => dir,
+    _ => Path::new("."),
+  };
+
+  let (tx, rx) = mpsc::channel();
+  let mut watcher = notify::recommended_watcher(tx)?;
+  watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+  if let Err(e) = f() {
+    log::error!("{}", e);
+  }
+  for event in &rx {
+    if !touches(&event?, path) {
+      continue;
+    }
+
+    // A single save usually fires a burst of events, run once per burst
+    thread::sleep(Duration::from_millis(100));
+    rx.try_iter().for_each(drop);
+
+    log::info!("--- {} changed", path.display());
+    if let Err(e) = f() {
+      log::error!("{}", e);
+    }
+  }
+  Ok(())
+}
+
+enum Change {
+  Added(String, u32),
+  Removed(String, u32),
+  Rewritten(String, u32),
+  CrcFixed(usize, String, u32, u32),
+  Dropped(usize, String),
+}
+
+impl Change {
+  fn describe(&self) -> String {
+    match self {
+      Change::Added(chunk_type, length) => format!("+ {} ({} bytes)", chunk_type, length),
+      Change::Removed(chunk_type, length) => format!("- {} ({} bytes)", chunk_type, length),
+      Change::Rewritten(chunk_type, length) => format!("~ {} ({} bytes)", chunk_type, length),
+      Change::CrcFixed(index, chunk_type, stored, computed) => {
+        format!("chunk {} ({}): crc {:08x} -> {:08x}", index, chunk_type, stored, computed)
+      }
+      Change::Dropped(offset, message) => format!("offset {:#x}: {}", offset, message),
+    }
+  }
+}
+
+// A pending edit to a PNG. Mutating commands build a plan first and then
+// either apply it or, with --dry-run, only report what it would change
+struct Plan {
+  png: Png,
+  size_before: usize,
+  changes: Vec<Change>,
+}
+
+impl Plan {
+  fn new(png: Png) -> Plan {
+    let size_before = png.as_bytes().len();
+    Plan { png, size_before, changes: vec![] }
+  }
+
+  fn append(&mut self, chunk: Chunk) {
+    self.changes.push(Change::Added(chunk.chunk_type().to_string(), chunk.length()));
+    self.png.append_chunk(chunk);
+  }
+
+  fn set_image_data(&mut self, image: &ImageData) -> Result<()> {
+    self.png.set_image_data(image)?;
+    let length = self.png.chunks_by_type("IDAT").map(Chunk::length).sum();
+    self.changes.push(Change::Rewritten("IDAT".to_string(), length));
+    Ok(())
+  }
+
+  fn remove(&mut self, chunk_type: &str) -> Result<()> {
+    let chunk = self.png.remove_chunk(chunk_type)?;
+    self.changes.push(Change::Removed(chunk.chunk_type().to_string(), chunk.length()));
+    Ok(())
+  }
+
+  fn remove_all(&mut self, chunk_type: &str) -> Result<()> {
+    let removed = self.png.remove_all_by_type(chunk_type);
+    if removed.is_empty() {
+      return Err(Error::ChunkNotFound);
+    }
+
+    for chunk in removed {
+      self.changes.push(Change::Removed(chunk.chunk_type().to_string(), chunk.length()));
+    }
+    Ok(())
+  }
+
+  fn remove_where<F: FnMut(&Chunk) -> bool>(&mut self, predicate: F) {
+    for chunk in self.png.remove_chunks_where(predicate) {
+      self.changes.push(Change::Removed(chunk.chunk_type().to_string(), chunk.length()));
+    }
+  }
+
+  fn count(&self, f: fn(&Change) -> bool) -> usize {
+    self.changes.iter().filter(|change| f(change)).count()
+  }
+
+  fn size_after(&self) -> usize {
+    self.png.as_bytes().len()
+  }
+
+  fn summary(&self) -> String {
+    format!(
+      "{} added, {} removed, {} CRC(s) fixed, {} -> {} bytes",
+      self.count(|c| matches!(c, Change::Added(..))),
+      self.count(|c| matches!(c, Change::Removed(..))),
+      self.count(|c| matches!(c, Change::CrcFixed(..))),
+      self.size_before,
+      self.size_after()
+    )
+  }
+
+  fn json(&self, path: &Path) -> String {
+    let changes: Vec<String> = self.changes.iter().map(|c| json_string(&c.describe())).collect();
+    format!(
+      "{{\"file\":{},\"dry_run\":true,\"changes\":[{}],\"size_before\":{},\"size_after\":{}}}",
+      json_string(&path.display().to_string()),
+      changes.join(","),
+      self.size_before,
+      self.size_after()
+    )
+  }
+
+  fn report(&self, path: &Path, global: GlobalArgs) -> String {
+    if global.json {
+      return self.json(path);
+    }
+
+    let mut lines: Vec<String> = self.changes.iter().map(Change::describe).collect();
+    lines.push(format!("would write {} ({})", path.display(), self.summary()));
+    lines.join("\n")
+  }
+
+  fn apply(&mut self, file_path: &Path, output: Option<&Path>, global: GlobalArgs) -> Result<()> {
+    write_output(&mut self.png, file_path, output, global)
+  }
+}
+
+// Scripts can set this instead of answering a prompt
+const PASSPHRASE_VAR: &str = "PNGNE_PASSPHRASE";
+
+fn read_passphrase() -> Result<String> {
+  match std::env::var(PASSPHRASE_VAR) {
+    Ok(passphrase) => Ok(passphrase),
+    Err(_) => Ok(rpassword::prompt_password("Passphrase: ")?),
+  }
+}
+
+// Identity files hold one AGE-SECRET-KEY-1... per line, as age-keygen
+// writes them, along with # comments
+fn read_identities(paths: &[PathBuf]) -> Result<Vec<Identity>> {
+  let mut identities = vec![];
+  for path in paths {
+    for line in fs::read_to_string(path)?.lines().map(str::trim) {
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      identities.push(Identity::from_str(line).map_err(|e| Error::InvalidChunkData(format!("{}: {}", path.display(), e)))?);
+    }
+  }
+  Ok(identities)
+}
+
+fn read_auth_key(path: Option<&Path>) -> Result<Option<Vec<u8>>> {
+  let Some(path) = path else { return Ok(None) };
+  let key = fs::read(path)?;
+  if key.is_empty() {
+    return Err(Error::InvalidChunkData(format!("{}: auth key is empty", path.display())));
+  }
+  Ok(Some(key))
+}
+
+// The bytes to embed: MESSAGE, or the whole of --data-file, packed if
+// --compress, --encrypt or --id is given
+fn encode_payload(args: &EncodeArgs) -> Result<Vec<u8>> {
+  let data = match (&args.data_file, &args.message) {
+    (Some(data_file), _) => read_bytes(data_file)?,
+    (None, Some(message)) => message.as_bytes().to_vec(),
+    (None, None) => unreachable!("clap requires MESSAGE or --data-file"),
+  };
+
+  if args.compress.is_none() && !args.encrypt && args.recipient.is_empty() && args.auth_key.is_none() && args.id.is_none() {
+    return Ok(data);
+  }
+
+  let compression = args.compress.unwrap_or(Compression::None);
+  let passphrase = if args.encrypt { Some(read_passphrase()?) } else { None };
+  let auth_key = read_auth_key(args.auth_key.as_deref())?;
+  let keys = Keys {
+    passphrase: passphrase.as_deref(),
+    recipients: &args.recipient,
+    auth_key: auth_key.as_deref(),
+    ..Keys::default()
+  };
+  let packed = payload::pack(&data, compression, &keys, args.id.as_deref())?;
+  log::debug!("payload packed with {}: {} -> {} bytes", compression, data.len(), packed.len());
+  Ok(packed)
+}
+
+fn encode_plan(path: &Path, args: &EncodeArgs, payload: &[u8], global: GlobalArgs) -> Result<Plan> {
+  let mut plan = Plan::new(read_png(path, global)?);
+  if args.mode == Mode::Lsb {
+    let mut image = plan.png.decode_pixels()?;
+    lsb::embed(&mut image, payload, args.lsb_bits)?;
+    plan.set_image_data(&image)?;
+    return Ok(plan);
+  }
+
+  if let Some(id) = &args.id {
+    if find_message(&plan.png, args.chunk_type.as_str(), id).is_some() {
+      return Err(Error::InvalidChunkData(format!("{}: there is already a {} payload named {:?}", path.display(), args.chunk_type, id)));
+    }
+  }
+  // --data-file can't be combined with --as-text, so text always comes from MESSAGE
+  let message = args.message.as_deref().unwrap_or_default();
+  if args.compress_text {
+    plan.append(CompressedText::new(args.chunk_type.as_str(), message)?.into());
+  } else if args.as_text {
+    plan.append(TextChunk::new(args.chunk_type.as_str(), message)?.into());
+  } else if let Some(fragment_size) = args.fragment_size.filter(|&size| payload.len() > size) {
+    for data in fragment::split(payload, fragment_size)? {
+      plan.append(Chunk::try_new(args.chunk_type, data)?);
+    }
+  } else {
+    plan.append(Chunk::try_new(args.chunk_type, payload.to_vec())?);
+  }
+
+  // Refuse to write a file that breaks the spec's chunk ordering
+  let violations = plan.png.validate_order();
+  for violation in &violations {
+    log::error!("{}: chunk {}: {}", path.display(), violation.index, violation.message);
+  }
+  if !violations.is_empty() {
+    return Err(Error::InvalidOrder(violations.len()));
+  }
+  Ok(plan)
+}
+
+pub fn encode(args: EncodeArgs, global: GlobalArgs) -> Result<()> {
+  if args.mode == Mode::Lsb && (args.as_text || args.fragment_size.is_some()) {
+    return Err(Error::InvalidChunkData("--mode lsb can't be combined with --as-text or --fragment-size".to_string()));
+  }
+  let payload = encode_payload(&args)?;
+
+  if args.recursive {
+    return batch(&args.file_path, global, args.jobs, |path| {
+      let mut plan = encode_plan(path, &args, &payload, global)?;
+      if args.dry_run {
+        return Ok(if global.json { plan.json(path) } else { plan.summary() });
+      }
+
+      plan.apply(path, None, global)?;
+      let encoded = match args.mode {
+        Mode::Chunk => args.chunk_type.to_string(),
+        Mode::Lsb => "pixels".to_string(),
+      };
+      if global.json {
+        Ok(format!("{{\"file\":{},\"encoded\":{}}}", json_string(&path.display().to_string()), json_string(&encoded)))
+      } else if args.mode == Mode::Lsb {
+        Ok("encoded in the pixels".to_string())
+      } else {
+        Ok(format!("encoded {} chunk", args.chunk_type))
+      }
+    });
+  }
+
+  let mut plan = encode_plan(&args.file_path, &args, &payload, global)?;
+  if args.dry_run {
+    println!("{}", plan.report(&args.file_path, global));
+    return Ok(());
+  }
+  plan.apply(&args.file_path, args.output.as_deref(), global)
+}
+
+// The chunk's data as stored, or the text of a text chunk
+fn decode_payload(path: &Path, args: &DecodeArgs, keys: &Keys, global: GlobalArgs) -> Result<Vec<u8>> {
+  let png = read_png(path, global)?;
+
+  if args.as_text {
+    return find_text(&png, args.chunk_type.as_str()).unwrap_or(Err(Error::ChunkNotFound)).map(String::into_bytes);
+  }
+
+  if args.mode == Mode::Lsb {
+    let data = lsb::extract(&png.decode_pixels()?)?;
+    if let Some(id) = &args.id {
+      if payload::envelope(&data)?.and_then(|envelope| envelope.id).as_ref() != Some(id) {
+        return Err(Error::ChunkNotFound);
+      }
+    }
+    return payload::unpack(&data, keys);
+  }
+
+  let chunk_type = args.chunk_type.to_string();
+  if let Some(id) = &args.id {
+    return match find_message(&png, &chunk_type, id) {
+      Some(message) => payload::unpack(&message.data, keys),
+      None => Err(Error::ChunkNotFound),
+    };
+  }
+
+  match png.chunk_by_type(&chunk_type) {
+    Some(chunk) if fragment::is_manifest(chunk.data()) => {
+      let data = fragment::reassemble(chunk.data(), png.chunks_by_type(&chunk_type).map(Chunk::data))?;
+      payload::unpack(&data, keys)
+    }
+    Some(chunk) => payload::unpack(chunk.data(), keys),
+    None => Err(Error::ChunkNotFound)
+  }
+}
+
+fn decode_file(path: &Path, args: &DecodeArgs, keys: &Keys, global: GlobalArgs) -> Result<String> {
+  Ok(String::from_utf8(decode_payload(path, args, keys, global)?)?)
+}
+
+// Text of the first tEXt, zTXt or iTXt chunk with the given keyword
+fn find_text(png: &Png, keyword: &str) -> Option<Result<String>> {
+  png.chunks().find_map(|chunk| match Text::from_chunk(chunk)? {
+    Ok(text) if text.keyword() == keyword => Some(text.text()),
+    _ => None,
+  })
+}
+
+// Keyword and text of every readable text chunk, whatever its flavor
+fn text_entries(png: &Png) -> Vec<(String, String)> {
+  png
+    .chunks()
+    .filter_map(|chunk| Text::from_chunk(chunk)?.ok())
+    .filter_map(|text| match text.text() {
+      Ok(value) => Some((text.keyword().to_string(), value)),
+      Err(e) => {
+        log::warn!("skipping {} text chunk: {}", text.keyword(), e);
+        None
+      }
+    })
+    .collect()
+}
+
+fn decode_json(path: &Path, args: &DecodeArgs, payload: &str) -> String {
+  format!(
+    "{{\"file\":{},\"chunk_type\":{},\"payload\":{}}}",
+    json_string(&path.display().to_string()),
+    json_string(args.chunk_type.as_str()),
+    json_string(payload),
+  )
+}
+
+pub fn decode(args: DecodeArgs, global: GlobalArgs) -> Result<()> {
+  if args.mode == Mode::Lsb && args.as_text {
+    return Err(Error::InvalidChunkData("--mode lsb can't be combined with --as-text".to_string()));
+  }
+  // Asked for once, however many files get decoded
+  let passphrase = if args.decrypt { Some(read_passphrase()?) } else { None };
+  let identities = read_identities(&args.identity)?;
+  let auth_key = read_auth_key(args.auth_key.as_deref())?;
+  let keys = Keys {
+    passphrase: passphrase.as_deref(),
+    identities: &identities,
+    auth_key: auth_key.as_deref(),
+    ..Keys::default()
+  };
+
+  if args.recursive {
+    return batch(&args.file_path, global, args.jobs, |path| {
+      let payload = decode_file(path, &args, &keys, global)?;
+      Ok(if global.json { decode_json(path, &args, &payload) } else { payload })
+    });
+  }
+
+  if args.watch {
+    return watch(&args.file_path, || decode_once(&args, global, &keys));
+  }
+  decode_once(&args, global, &keys)
+}
+
+fn decode_once(args: &DecodeArgs, global: GlobalArgs, keys: &Keys) -> Result<()> {
+  if let Some(out) = &args.out {
+    let payload = decode_payload(&args.file_path, args, keys, global)?;
+    write_bytes(out, &payload)?;
+    log::info!("Wrote {} bytes to {}", payload.len(), out.display());
+    return Ok(());
+  }
+
+  let payload = decode_file(&args.file_path, args, keys, global)?;
+  if global.json {
+    println!("{}", decode_json(&args.file_path, args, &payload));
+  } else {
+    println!("{}", payload);
+  }
+  Ok(())
+}
+
+// A payload as stored, put back together if it was fragmented
+struct Message {
+  chunk_type: ChunkType,
+  fragments: usize,
+  data: Vec<u8>,
+}
+
+// Every payload in chunks of `chunk_type`, or without one in every chunk
+// that looks like it holds one: unregistered types and packed payloads.
+// Fragments are only read through their manifest.
+fn messages(png: &Png, chunk_type: Option<&str>) -> Vec<Message> {
+  let mut messages = vec![];
+  for chunk in png.chunks() {
+    let data = chunk.data();
+    let wanted = match chunk_type {
+      Some(chunk_type) => chunk.chunk_type().to_string() == chunk_type,
+      None => !chunk.chunk_type().is_registered() || payload::is_packed(data) || fragment::is_manifest(data),
+    };
+    if !wanted || fragment::is_fragment(data) {
+      continue;
+    }
+
+    let chunk_type = *chunk.chunk_type();
+    if !fragment::is_manifest(data) {
+      messages.push(Message { chunk_type, fragments: 0, data: data.to_vec() });
+      continue;
+    }
+
+    let name = chunk_type.to_string();
+    match fragment::reassemble(data, png.chunks_by_type(&name).map(Chunk::data)) {
+      Ok(payload) => {
+        let fragments = fragment::Manifest::parse(data).map_or(0, |manifest| manifest.count as usize);
+        messages.push(Message { chunk_type, fragments, data: payload });
+      }
+      Err(e) => log::warn!("skipping fragmented {} payload: {}", chunk_type, e),
+    }
+  }
+  messages
+}
+
+fn find_message(png: &Png, chunk_type: &str, id: &str) -> Option<Message> {
+  messages(png, Some(chunk_type))
+    .into_iter()
+    .find(|message| matches!(payload::envelope(&message.data), Ok(Some(envelope)) if envelope.id.as_deref() == Some(id)))
+}
+
+fn message_details(message: &Message, envelope: Option<&payload::Envelope>) -> Vec<String> {
+  let mut details = match envelope {
+    None => vec!["bare".to_string()],
+    Some(envelope) => {
+      let mut details = vec![envelope.compression.to_string()];
+      if envelope.encrypted {
+        details.push("encrypted".to_string());
+      }
+      if envelope.authenticated {
+        details.push("authenticated".to_string());
+      }
+      details
+    }
+  };
+  if message.fragments > 0 {
+    details.push(format!("{} fragments", message.fragments));
+  }
+  details
+}
+
+fn messages_text(messages: &[(Message, Option<payload::Envelope>)]) -> String {
+  if messages.is_empty() {
+    return "no payloads found".to_string();
+  }
+  messages
+    .iter()
+    .map(|(message, envelope)| {
+      let id = envelope.as_ref().and_then(|envelope| envelope.id.as_deref()).unwrap_or("-");
+      let details = message_details(message, envelope.as_ref()).join(", ");
+      format!("{}  {}  {} bytes  {}", message.chunk_type, id, message.data.len(), details)
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn messages_json(path: &Path, messages: &[(Message, Option<payload::Envelope>)]) -> String {
+  let entries: Vec<String> = messages
+    .iter()
+    .map(|(message, envelope)| {
+      let id = match envelope.as_ref().and_then(|envelope| envelope.id.as_deref()) {
+        Some(id) => json_string(id),
+        None => "null".to_string(),
+      };
+      let compression = match envelope {
+        Some(envelope) => json_string(&envelope.compression.to_string()),
+        None => "null".to_string(),
+      };
+      format!(
+        "{{\"chunk_type\":{},\"id\":{},\"size\":{},\"packed\":{},\"compression\":{},\"encrypted\":{},\"authenticated\":{},\"fragments\":{}}}",
+        json_string(message.chunk_type.as_str()),
+        id,
+        message.data.len(),
+        envelope.is_some(),
+        compression,
+        envelope.as_ref().is_some_and(|envelope| envelope.encrypted),
+        envelope.as_ref().is_some_and(|envelope| envelope.authenticated),
+        message.fragments,
+      )
+    })
+    .collect();
+  format!("{{\"file\":{},\"messages\":[{}]}}", json_string(&path.display().to_string()), entries.join(","))
+}
+
+pub fn list_messages(args: ListMessagesArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let messages: Vec<(Message, Option<payload::Envelope>)> = messages(&png, None)
+    .into_iter()
+    .filter_map(|message| match payload::envelope(&message.data) {
+      Ok(envelope) => Some((message, envelope)),
+      Err(e) => {
+        log::warn!("skipping {} payload: {}", message.chunk_type, e);
+        None
+      }
+    })
+    .collect();
+
+  if global.json {
+    println!("{}", messages_json(&args.file_path, &messages));
+  } else {
+    println!("{}", messages_text(&messages));
+  }
+  Ok(())
+}
+
+// Payload bytes that fit in `space` bytes once packed as `args` asks, for
+// data that doesn't compress. Unpacked payloads are stored as they are.
+fn payload_capacity(space: usize, args: &CapacityArgs) -> usize {
+  if args.compress.is_none() && !args.encrypt && args.recipient.is_empty() && !args.authenticate && args.id.is_none() {
+    return space;
+  }
+
+  // Only whether there is a passphrase or key matters, not what it is
+  let keys = Keys {
+    passphrase: args.encrypt.then_some(""),
+    recipients: &args.recipient,
+    auth_key: args.authenticate.then_some(&[][..]),
+    ..Keys::default()
+  };
+  space.saturating_sub(payload::overhead(space, args.compress.unwrap_or(Compression::None), &keys, args.id.as_deref()))
+}
+
+pub fn capacity(args: CapacityArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let ihdr = png.ihdr()?;
+  let chunk_capacity = payload_capacity(chunk::MAX_LENGTH, &args);
+  let lsb_capacity = lsb::capacity(&ihdr, args.lsb_bits).map(|space| payload_capacity(space, &args));
+
+  if global.json {
+    let lsb = match &lsb_capacity {
+      Ok(capacity) => capacity.to_string(),
+      Err(_) => "null".to_string(),
+    };
+    println!(
+      "{{\"file\":{},\"chunk\":{},\"lsb\":{},\"lsb_bits\":{}}}",
+      json_string(&args.file_path.display().to_string()),
+      chunk_capacity,
+      lsb,
+      args.lsb_bits
+    );
+    return Ok(());
+  }
+
+  println!("chunk: {} bytes per chunk, more across several with --fragment-size", chunk_capacity);
+  match lsb_capacity {
+    Ok(capacity) => println!("lsb: {} bytes at {} bit(s) per sample", capacity, args.lsb_bits),
+    Err(Error::InvalidChunkData(message)) => println!("lsb: unsupported, {}", message.trim_start_matches("lsb: ")),
+    Err(e) => return Err(e),
+  }
+  Ok(())
+}
+
+fn detection_text(detection: &Detection) -> String {
+  let mut lines = vec![format!("score: {}/100", detection.score)];
+  lines.extend(detection.findings.iter().map(|finding| format!("  offset {:#010x}: {}", finding.offset, finding.message)));
+  lines.join("\n")
+}
+
+fn detection_json(path: &Path, detection: &Detection) -> String {
+  let findings: Vec<String> = detection
+    .findings
+    .iter()
+    .map(|finding| format!("{{\"offset\":{},\"weight\":{},\"message\":{}}}", finding.offset, finding.weight, json_string(&finding.message)))
+    .collect();
+  format!(
+    "{{\"file\":{},\"score\":{},\"findings\":[{}]}}",
+    json_string(&path.display().to_string()),
+    detection.score,
+    findings.join(",")
+  )
+}
+
+pub fn detect(args: DetectArgs, global: GlobalArgs) -> Result<()> {
+  let bytes = load_bytes(&args.file_path)?;
+  let detection = detection::detect_with_limits(&bytes, global.limits())?;
+  if global.json {
+    println!("{}", detection_json(&args.file_path, &detection));
+  } else {
+    println!("{}", detection_text(&detection));
+  }
+  Ok(())
+}
+
+pub fn trailing(args: TrailingArgs, global: GlobalArgs) -> Result<()> {
+  let (mut png, report) = parse_png(&read_bytes(&args.file_path)?, global, ParseMode::Strict)?;
+  for diagnostic in report.diagnostics.iter().filter(|d| d.code != diagnostics::TRAILING_DATA) {
+    log::warn!("{}: {}", args.file_path.display(), diagnostic);
+  }
+  let length = png.trailing_data().len();
+  let offset = report.trailing_offset;
+
+  if global.json {
+    println!(
+      "{{\"file\":{},\"offset\":{},\"length\":{}}}",
+      json_string(&args.file_path.display().to_string()),
+      offset,
+      length
+    );
+  } else if length == 0 {
+    log::info!("No data after IEND in {}", args.file_path.display());
+  } else {
+    log::info!("{} bytes after IEND at offset {:#x}", length, offset);
+  }
+
+  if let Some(extract) = &args.extract {
+    write_bytes(extract, png.trailing_data())?;
+  }
+  if args.strip && length > 0 {
+    png.set_trailing_data(vec![]);
+    write_output(&mut png, &args.file_path, args.output.as_deref(), global)?;
+    log::info!("Stripped {} bytes from {}", length, output_path(&args.file_path, args.output.as_deref()).display());
+  }
+  Ok(())
+}
+
+pub fn uncrush(args: UncrushArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  if !cgbi::is_cgbi(&png) {
+    log::error!("{} has no CgBI chunk, it isn't a crushed PNG", args.file_path.display());
+    return Err(Error::ChunkNotFound);
+  }
+
+  let mut uncrushed = cgbi::uncrush(&png)?;
+  write_output(&mut uncrushed, &args.file_path, args.output.as_deref(), global)?;
+  log::info!("Uncrushed {}", output_path(&args.file_path, args.output.as_deref()).display());
+  Ok(())
+}
+
+// A tEXt, zTXt or iTXt chunk as `metadata` shows it. The value is an error
+// when a compressed text doesn't inflate.
+struct MetadataEntry {
+  chunk_type: String,
+  keyword: String,
+  // iTXt only
+  language: Option<String>,
+  translated_keyword: Option<String>,
+  value: Result<String>,
+}
+
+fn metadata_entries(png: &Png) -> Vec<MetadataEntry> {
+  let mut entries = vec![];
+  for (index, chunk) in png.chunks().enumerate() {
+    let text = match Text::from_chunk(chunk) {
+      Some(Ok(text)) => text,
+      Some(Err(e)) => {
+        log::warn!("skipping {} chunk {}: {}", chunk.chunk_type(), index, e);
+        continue;
+      }
+      None => continue,
+    };
+    let (language, translated_keyword) = match &text {
+      Text::International(itxt) => (Some(itxt.language().to_string()), Some(itxt.translated_keyword().to_string())),
+      _ => (None, None),
+    };
+    entries.push(MetadataEntry {
+      chunk_type: chunk.chunk_type().to_string(),
+      keyword: text.keyword().to_string(),
+      language,
+      translated_keyword,
+      value: text.text(),
+    });
+  }
+  entries
+}
+
+// One `keyword: value` line per entry, with an iTXt's language tag and
+// translated keyword in brackets when it has them. Lines after the first
+// of a value are indented.
+fn metadata_text(entries: &[MetadataEntry]) -> String {
+  let mut lines = vec![];
+  for entry in entries {
+    let extras: Vec<&str> = [&entry.language, &entry.translated_keyword]
+      .into_iter()
+      .flatten()
+      .map(String::as_str)
+      .filter(|extra| !extra.is_empty())
+      .collect();
+    let key = if extras.is_empty() { entry.keyword.clone() } else { format!("{} [{}]", entry.keyword, extras.join(", ")) };
+    match &entry.value {
+      Ok(value) => lines.push(format!("{}: {}", key, value.replace('\n', "\n  "))),
+      Err(e) => lines.push(format!("{}: ({} can't be read: {})", key, entry.chunk_type, e)),
+    }
+  }
+  lines.join("\n")
+}
+
+fn metadata_json(path: &Path, entries: &[MetadataEntry]) -> String {
+  let optional = |value: &Option<String>| value.as_deref().map_or("null".to_string(), json_string);
+  let entries: Vec<String> = entries
+    .iter()
+    .map(|entry| {
+      format!(
+        "{{\"chunk_type\":{},\"keyword\":{},\"language\":{},\"translated_keyword\":{},\"value\":{},\"error\":{}}}",
+        json_string(&entry.chunk_type),
+        json_string(&entry.keyword),
+        optional(&entry.language),
+        optional(&entry.translated_keyword),
+        entry.value.as_deref().map_or("null".to_string(), json_string),
+        entry.value.as_ref().err().map_or("null".to_string(), |e| json_string(&e.to_string())),
+      )
+    })
+    .collect();
+  format!("{{\"file\":{},\"entries\":[{}]}}", json_string(&path.display().to_string()), entries.join(","))
+}
+
+pub fn metadata(args: MetadataArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let entries = metadata_entries(&png);
+
+  if global.json {
+    println!("{}", metadata_json(&args.file_path, &entries));
+  } else if entries.is_empty() {
+    log::info!("No text chunks in {}", args.file_path.display());
+  } else {
+    println!("{}", metadata_text(&entries));
+  }
+  Ok(())
+}
+
+pub fn set_text(args: SetTextArgs, global: GlobalArgs) -> Result<()> {
+  let mut png = read_png(&args.file_path, global)?;
+  let removed = png.set_text(&args.keyword, &args.text)?;
+  if removed > 0 {
+    log::info!("Removed {} more text chunk(s) with the keyword {:?}", removed, args.keyword);
+  }
+
+  log::info!("{} set", args.keyword);
+  write_output(&mut png, &args.file_path, args.output.as_deref(), global)
+}
+
+pub fn get_text(args: GetTextArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let text = find_text(&png, &args.keyword).ok_or_else(|| Error::KeywordNotFound(args.keyword.clone()))??;
+
+  if global.json {
+    println!(
+      "{{\"file\":{},\"keyword\":{},\"text\":{}}}",
+      json_string(&args.file_path.display().to_string()),
+      json_string(&args.keyword),
+      json_string(&text)
+    );
+  } else {
+    println!("{}", text);
+  }
+  Ok(())
+}
+
+fn remove_plan(path: &Path, args: &RemoveArgs, global: GlobalArgs) -> Result<Plan> {
+  let mut plan = Plan::new(read_png(path, global)?);
+  let chunk_type = args.chunk_type.to_string();
+
+  if args.all {
+    plan.remove_all(&chunk_type)?;
+  } else {
+    plan.remove(&chunk_type)?;
+  }
+  Ok(plan)
+}
+
+pub fn remove(args: RemoveArgs, global: GlobalArgs) -> Result<()> {
+  if args.recursive {
+    return batch(&args.file_path, global, args.jobs, |path| {
+      let mut plan = remove_plan(path, &args, global)?;
+      if args.dry_run {
+        return Ok(if global.json { plan.json(path) } else { plan.summary() });
+      }
+
+      plan.apply(path, None, global)?;
+      if global.json {
+        Ok(format!("{{\"file\":{},\"removed\":{}}}", json_string(&path.display().to_string()), plan.changes.len()))
+      } else {
+        Ok(format!("removed {} {} chunk(s)", plan.changes.len(), args.chunk_type))
+      }
+    });
+  }
+
+  let mut plan = remove_plan(&args.file_path, &args, global)?;
+  if args.dry_run {
+    println!("{}", plan.report(&args.file_path, global));
+    return Ok(());
+  }
+
+  plan.apply(&args.file_path, args.output.as_deref(), global)?;
+  // Keep stdout clean for the PNG itself when writing to `-`
+  log::info!("Removed {} {} chunk(s)", plan.changes.len(), args.chunk_type);
+  Ok(())
+}
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+// Color is only used on a terminal, and never when asked not to either by
+// flag or through the NO_COLOR convention
+fn use_color(no_color: bool) -> bool {
+  !no_color && std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal()
+}
+
+// Renders one row per chunk with aligned columns. Rows are colored by the
+// most important thing about them: a CRC failure, then an unknown private
+// chunk, then a critical chunk.
+fn chunk_table(png: &Png, fixes: &[CrcFix], color: bool) -> String {
+  let header = ["#", "TYPE", "LENGTH", "CRC", "KIND", "SCOPE", "COPY", "STATUS"].map(String::from);
+  let mut rows = vec![(header, None)];
+
+  for (index, chunk) in png.chunks().enumerate() {
+    let chunk_type = chunk.chunk_type();
+    let fix = fixes.iter().find(|fix| fix.index == index);
+
+    let row = [
+      index.to_string(),
+      chunk_type.to_string(),
+      chunk.length().to_string(),
+      format!("{:08x}", fix.map_or(chunk.crc(), |fix| fix.stored)),
+      if chunk_type.is_critical() { "critical" } else { "ancillary" }.to_string(),
+      if chunk_type.is_public() { "public" } else { "private" }.to_string(),
+      if chunk_type.is_safe_to_copy() { "safe-to-copy" } else { "unsafe-to-copy" }.to_string(),
+      match fix {
+        Some(fix) => format!("CRC mismatch, expected {:08x}", fix.computed),
+        None => "ok".to_string(),
+      },
+    ];
+
+    let highlight = if fix.is_some() {
+      Some(RED)
+    } else if !chunk_type.is_public() && !chunk_type.is_registered() {
+      Some(YELLOW)
+    } else if chunk_type.is_critical() {
+      Some(CYAN)
+    } else {
+      None
+    };
+    rows.push((row, highlight));
+  }
+
+  let mut widths = [0; 8];
+  for (row, _) in &rows {
+    for (width, cell) in widths.iter_mut().zip(row) {
+      *width = (*width).max(cell.len());
+    }
+  }
+
+  rows
+    .iter()
+    .map(|(row, highlight)| {
+      let cells: Vec<String> = row
+        .iter()
+        .zip(widths)
+        .enumerate()
+        .map(|(column, (cell, width))| match column {
+          // Numbers read better right aligned
+          0 | 2 => format!("{:>width$}", cell),
+          _ => format!("{:<width$}", cell),
+        })
+        .collect();
+      let line = cells.join("  ").trim_end().to_string();
+
+      match highlight {
+        Some(code) if color => format!("{}{}{}", code, line, RESET),
+        _ => line,
+      }
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn chunk_json(index: usize, offset: usize, chunk: &Chunk) -> String {
+  let chunk_type = chunk.chunk_type();
+
+  format!(
+    "{{\"index\":{},\"type\":{},\"length\":{},\"offset\":{},\"crc\":{},\"critical\":{},\"public\":{},\"reserved_bit_valid\":{},\"safe_to_copy\":{}}}",
+    index,
+    json_string(chunk_type.as_str()),
+    chunk.length(),
+    offset,
+    chunk.crc(),
+    chunk_type.is_critical(),
+    chunk_type.is_public(),
+    chunk_type.is_reserved_bit_valid(),
+    chunk_type.is_safe_to_copy(),
+  )
+}
+
+fn print_json(path: &Path, png: &Png) -> String {
+  let mut offset = Png::header().len();
+  let mut chunks = vec![];
+
+  for (index, chunk) in png.chunks().enumerate() {
+    chunks.push(chunk_json(index, offset, chunk));
+    offset += chunk.length() as usize + 12;
+  }
+
+  format!(
+    "{{\"file\":{},\"chunks\":[{}]}}",
+    json_string(&path.display().to_string()),
+    chunks.join(",")
+  )
+}
+
+// Fields a --format template can refer to, as `{name}` or `{name:spec}`
+// where spec is an optional `0` fill flag, a width and `x`, `X` or `d`.
+// Only the numbers take a radix.
+const TEMPLATE_FIELDS: [&str; 8] = ["index", "type", "length", "crc", "offset", "critical", "public", "safe_to_copy"];
+const NUMERIC_FIELDS: [&str; 4] = ["index", "length", "crc", "offset"];
+
+enum Token {
+  Text(String),
+  Field { name: String, zero: bool, width: usize, radix: char },
+}
+
+struct Template {
+  tokens: Vec<Token>,
+}
+
+impl FromStr for Template {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut tokens = vec![];
+    let mut text = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+      match c {
+        '{' if chars.peek() == Some(&'{') => {
+          chars.next();
+          text.push('{');
+        }
+        '}' if chars.peek() == Some(&'}') => {
+          chars.next();
+          text.push('}');
+        }
+        '{' => {
+          let mut field = String::new();
+          loop {
+            match chars.next() {
+              Some('}') => break,
+              Some(c) => field.push(c),
+              None => return Err(Error::InvalidTemplate(format!("unterminated field {{{}", field))),
+            }
+          }
+          let (name, spec) = field.split_once(':').unwrap_or((&field, ""));
+          if !TEMPLATE_FIELDS.contains(&name) {
+            return Err(Error::InvalidTemplate(format!("unknown field {{{}}}", name)));
+          }
+
+          let bad_spec = || Error::InvalidTemplate(format!("bad format spec {:?} for {{{}}}", spec, name));
+          let radix = spec.chars().last().filter(|c| matches!(c, 'x' | 'X' | 'd'));
+          let digits = &spec[..spec.len() - radix.map_or(0, char::len_utf8)];
+          if !digits.chars().all(|c| c.is_ascii_digit()) || (radix.is_some() && !NUMERIC_FIELDS.contains(&name)) {
+            return Err(bad_spec());
+          }
+          let width = if digits.is_empty() { 0 } else { digits.parse().map_err(|_| bad_spec())? };
+
+          tokens.push(Token::Text(std::mem::take(&mut text)));
+          tokens.push(Token::Field { name: name.to_string(), zero: digits.starts_with('0'), width, radix: radix.unwrap_or('d') });
+        }
+        '}' => return Err(Error::InvalidTemplate("unmatched }".to_string())),
+        c => text.push(c),
+      }
+    }
+
+    tokens.push(Token::Text(text));
+    Ok(Template { tokens })
+  }
+}
+
+impl Template {
+  fn render(&self, index: usize, entry: &IndexEntry) -> String {
+    let chunk_type = entry.chunk_type;
+    let mut out = String::new();
+
+    for token in &self.tokens {
+      let (name, zero, width, radix) = match token {
+        Token::Text(text) => {
+          out.push_str(text);
+          continue;
+        }
+        Token::Field { name, zero, width, radix } => (name.as_str(), *zero, *width, *radix),
+      };
+
+      let number = match name {
+        "index" => Some(index as u64),
+        "length" => Some(entry.length as u64),
+        "crc" => Some(entry.crc as u64),
+        "offset" => Some(entry.offset as u64),
+        _ => None,
+      };
+
+      let value = match (number, radix, zero) {
+        (Some(n), 'x', true) => format!("{:0width$x}", n),
+        (Some(n), 'x', false) => format!("{:width$x}", n),
+        (Some(n), 'X', true) => format!("{:0width$X}", n),
+        (Some(n), 'X', false) => format!("{:width$X}", n),
+        (Some(n), _, true) => format!("{:0width$}", n),
+        (Some(n), _, false) => format!("{:width$}", n),
+        (None, _, _) => {
+          let text = match name {
+            "type" => chunk_type.to_string(),
+            "critical" => chunk_type.is_critical().to_string(),
+            "public" => chunk_type.is_public().to_string(),
+            _ => chunk_type.is_safe_to_copy().to_string(),
+          };
+          format!("{:<width$}", text)
+        }
+      };
+      out.push_str(&value);
+    }
+    out
+  }
+}
+
+fn print_template(entries: &[IndexEntry], template: &Template) -> String {
+  let lines: Vec<String> = entries.iter().enumerate().map(|(index, entry)| template.render(index, entry)).collect();
+  lines.join("\n")
+}
+
+// Returns the report for `path` along with the number of chunks in it
+fn print_file(path: &Path, global: GlobalArgs, color: bool, template: Option<&Template>, deny: &[&str]) -> Result<(usize, String)> {
+  // Templates only show chunk headers, so files are indexed rather than
+  // read. The CRC shown is the stored one and isn't checked.
+  if let Some(template) = template {
+    let entries = if is_stdio(path) { IndexEntry::of(&read_png(path, global)?) } else { index_file(path, global)?.entries().to_vec() };
+    return Ok((entries.len(), print_template(&entries, template)));
+  }
+
+  // Lenient unless asked otherwise, so that chunks with a bad CRC can be
+  // shown as such. What the parser overlooked goes under the table.
+  let (png, mut report) = parse_png(&read_bytes(path)?, global, ParseMode::Lenient)?;
+  report.diagnostics.deny(deny);
+  let denied: Vec<&Diagnostic> = report.diagnostics.errors().collect();
+  if !denied.is_empty() {
+    for diagnostic in &denied {
+      log::error!("{}: {}", path.display(), diagnostic);
+    }
+    return Err(Error::Denied(denied.len()));
+  }
+
+  if global.json {
+    for diagnostic in &report.diagnostics {
+      log::warn!("{}: {}", path.display(), diagnostic);
+    }
+    return Ok((png.chunks().len(), print_json(path, &png)));
+  }
+
+  let mut table = chunk_table(&png, &report.crc_fixes, color);
+  for diagnostic in &report.diagnostics {
+    table.push_str(&format!("\n{}", diagnostic));
+  }
+  let count = png.chunks().len();
+  Ok((count, table))
+}
+
+pub fn print(args: PrintArgs, global: GlobalArgs) -> Result<()> {
+  let color = use_color(args.no_color);
+  let template = args.format.as_deref().map(Template::from_str).transpose()?;
+  let template = template.as_ref();
+  let deny = &args.deny;
+
+  if args.recursive {
+    return batch(&args.file_path, global, args.jobs, |path| {
+      let (count, report) = print_file(path, global, color, template, deny)?;
+      if global.json && template.is_none() {
+        Ok(report)
+      } else {
+        Ok(format!("{} chunk(s)\n{}", count, report))
+      }
+    });
+  }
+
+  if args.watch {
+    return watch(&args.file_path, || {
+      println!("{}", print_file(&args.file_path, global, color, template, deny)?.1);
+      Ok(())
+    });
+  }
+
+  println!("{}", print_file(&args.file_path, global, color, template, deny)?.1);
+  Ok(())
+}
+
+fn unregistered_chunks(chunks: &[ChunkInfo]) -> Vec<&ChunkInfo> {
+  chunks
+    .iter()
+    .filter(|chunk| !chunk.chunk_type.is_registered())
+    .collect()
+}
+
+pub fn scan(args: ScanArgs, global: GlobalArgs) -> Result<()> {
+  let mut files = vec![];
+  if args.path.is_dir() {
+    find_pngs(&args.path, &mut files)?;
+  } else {
+    files.push(args.path.clone());
+  }
+
+  // Only chunk types and lengths are needed, so the parser skips over the
+  // data, reusing its buffers from file to file
+  let mut parser = PngParser::with_limits(global.limits());
+  let mut flagged = 0;
+  for path in &files {
+    let report_error = |e: Error| {
+      if global.json {
+        let error = json_string(&e.to_string());
+        println!("{{\"file\":{},\"error\":{}}}", json_string(&path.display().to_string()), error);
+      } else {
+        println!("{}: error: {}", path.display(), e);
+      }
+    };
+
+    let parsed = if is_stdio(path) { parser.parse(io::stdin().lock()) } else { parser.parse_file(path) };
+    let chunks = match parsed {
+      Ok(chunks) => chunks,
+      Err(e) => {
+        report_error(e);
+        continue;
+      }
+    };
+
+    let found = unregistered_chunks(chunks);
+    if found.is_empty() {
+      continue;
+    }
+    flagged += 1;
+
+    if global.json {
+      let chunks: Vec<String> = found
+        .iter()
+        .map(|c| format!("{{\"type\":{},\"length\":{}}}", json_string(c.chunk_type.as_str()), c.length))
+        .collect();
+      println!("{{\"file\":{},\"chunks\":[{}]}}", json_string(&path.display().to_string()), chunks.join(","));
+    } else {
+      let chunks: Vec<String> = found
+        .iter()
+        .map(|c| format!("{} ({} bytes)", c.chunk_type, c.length))
+        .collect();
+      println!("{}: {}", path.display(), chunks.join(", "));
+    }
+  }
+
+  if !global.json {
+    println!("{} of {} file(s) contain non-standard chunks", flagged, files.len());
+  }
+  Ok(())
+}
+
+pub fn repair(args: RepairArgs, global: GlobalArgs) -> Result<()> {
+  // Repairing is keeping what a lenient parse could read, so --strict
+  // doesn't apply
+  let bytes = read_bytes(&args.file_path)?;
+  let (png, report) = Png::parse_with(&bytes, ParseMode::Lenient, global.limits())?;
+
+  let mut plan = Plan::new(png);
+  plan.size_before = bytes.len();
+  for fix in report.crc_fixes {
+    let chunk_type = plan.png.as_slice()[fix.index].chunk_type().to_string();
+    plan.changes.push(Change::CrcFixed(fix.index, chunk_type, fix.stored, fix.computed));
+  }
+  // Bad CRCs are fixed above and data after IEND is kept
+  let dropped = report.diagnostics.into_iter().filter(|d| d.code != diagnostics::CRC_MISMATCH && d.code != diagnostics::TRAILING_DATA);
+  for diagnostic in dropped {
+    plan.changes.push(Change::Dropped(diagnostic.offset, diagnostic.message));
+  }
+
+  if args.dry_run {
+    println!("{}", plan.report(&args.file_path, global));
+    return Ok(());
+  }
+
+  for change in &plan.changes {
+    log::warn!("{}", change.describe());
+  }
+
+  if plan.changes.is_empty() && args.output.is_none() {
+    log::info!("No CRC errors found");
+    return Ok(());
+  }
+
+  plan.apply(&args.file_path, args.output.as_deref(), global)?;
+  log::info!("Repaired {} chunk(s)", plan.changes.len());
+  Ok(())
+}
+
+// How much of the image data IHDR calls for still inflates, as recovered
+// and expected bytes of scanlines. None without a usable IHDR.
+fn image_data_recovered(png: &Png) -> Option<(usize, usize)> {
+  let expected = pixels::filtered_size(&png.ihdr().ok()?);
+  let inflated = zlib::inflated_size(&png.compressed_image_data(), expected);
+  Some((inflated.min(expected), expected))
+}
+
+fn describe_image_data(recovered: Option<(usize, usize)>) -> String {
+  match recovered {
+    Some((_, 0)) => "image data: IHDR describes an empty image".to_string(),
+    Some((recovered, expected)) => format!(
+      "image data: {} of {} bytes recovered, {:.1}% lost",
+      recovered,
+      expected,
+      100.0 * (expected - recovered) as f64 / expected as f64
+    ),
+    None => "image data: unknown, no valid IHDR was recovered".to_string(),
+  }
+}
+
+fn recover_json(path: &Path, plan: &Plan, recovered: Option<(usize, usize)>, dry_run: bool) -> String {
+  let changes: Vec<String> = plan.changes.iter().map(|c| json_string(&c.describe())).collect();
+  let image_data = match recovered {
+    Some((recovered, expected)) => format!("{{\"recovered\":{},\"expected\":{}}}", recovered, expected),
+    None => "null".to_string(),
+  };
+  format!(
+    "{{\"file\":{},\"dry_run\":{},\"chunk_count\":{},\"changes\":[{}],\"image_data\":{},\"size_before\":{},\"size_after\":{}}}",
+    json_string(&path.display().to_string()),
+    dry_run,
+    plan.png.chunks().len(),
+    changes.join(","),
+    image_data,
+    plan.size_before,
+    plan.size_after()
+  )
+}
+
+// Builds on the salvage parser: every chunk that reads back with a good CRC
+// is kept, in order, and everything between them is dropped
+pub fn recover(args: RecoverArgs, global: GlobalArgs) -> Result<()> {
+  let bytes = read_bytes(&args.file_path)?;
+  let mut chunks = vec![];
+  let mut dropped = vec![];
+  let mut salvage = Png::salvage_with_limits(bytes.as_slice(), global.limits());
+  for chunk in salvage.by_ref() {
+    match chunk {
+      Ok(chunk) => chunks.push(chunk),
+      Err(e) if matches!(e.error, Error::LimitExceeded { .. }) => return Err(e.error),
+      Err(e) => dropped.push(Change::Dropped(e.offset, format!("{}, skipped {} bytes", e.error, e.skipped))),
+    }
+  }
+  if chunks.is_empty() {
+    return Err(Error::ChunkNotFound);
+  }
+
+  let mut png = Png::from_chunks(chunks);
+  png.set_trailing_data(salvage.trailing_data().to_vec());
+  let mut plan = Plan::new(png);
+  plan.size_before = bytes.len();
+  plan.changes = dropped;
+  if args.add_iend && plan.png.chunks().last().is_some_and(|chunk| *chunk.chunk_type() != ChunkType::IEND) {
+    plan.append(Chunk::new(ChunkType::IEND, vec![]));
+  }
+  let recovered = image_data_recovered(&plan.png);
+
+  if args.dry_run {
+    if global.json {
+      println!("{}", recover_json(&args.file_path, &plan, recovered, true));
+    } else {
+      println!("{}\n{}", plan.report(&args.file_path, global), describe_image_data(recovered));
+    }
+    return Ok(());
+  }
+
+  plan.apply(&args.file_path, args.output.as_deref(), global)?;
+  if global.json {
+    println!("{}", recover_json(&args.file_path, &plan, recovered, false));
+    return Ok(());
+  }
+  for change in &plan.changes {
+    log::warn!("{}", change.describe());
+  }
+  log::info!("Recovered {} chunk(s), {} -> {} bytes", plan.png.chunks().len(), plan.size_before, plan.size_after());
+  log::info!("{}", describe_image_data(recovered));
+  Ok(())
+}
+
+pub fn strip(args: StripArgs, global: GlobalArgs) -> Result<()> {
+  let strip = |chunk: &Chunk| !chunk.chunk_type().is_critical() && !args.keep.contains(chunk.chunk_type());
+
+  if args.recursive {
+    return batch(&args.file_path, global, args.jobs, |path| {
+      let mut plan = Plan::new(read_png(path, global)?);
+      plan.remove_where(strip);
+      if args.dry_run {
+        return Ok(if global.json { plan.json(path) } else { plan.summary() });
+      }
+
+      let (removed, saved) = (plan.changes.len(), plan.size_before - plan.size_after());
+      plan.apply(path, None, global)?;
+      if global.json {
+        Ok(format!("{{\"file\":{},\"removed\":{},\"saved\":{}}}", json_string(&path.display().to_string()), removed, saved))
+      } else {
+        Ok(format!("removed {} ancillary chunk(s), saved {} bytes", removed, saved))
+      }
+    });
+  }
+
+  // Writing to another file needs no plan, so stream the chunks across
+  // rather than loading the whole image
+  let streamed = args
+    .output
+    .as_deref()
+    .filter(|&output| !same_file(output, &args.file_path) && !args.dry_run && global.idat_chunk_size.is_none());
+  if let Some(output) = streamed {
+    let (mut removed, mut saved) = (0, 0);
+    let reader = open_reader(&args.file_path)?;
+    write_replacing(output, |writer| {
+      Png::transform_with(reader, writer, global.parse_mode(ParseMode::Strict), global.limits(), |chunk| {
+        if !strip(&chunk) {
+          return Ok(vec![chunk]);
+        }
+        removed += 1;
+        saved += chunk.length() as usize + 12;
+        Ok(vec![])
+      })
+    })?;
+    log::info!("Removed {} ancillary chunk(s), saved {} bytes", removed, saved);
+    return Ok(());
+  }
+
+  let mut plan = Plan::new(read_png(&args.file_path, global)?);
+  plan.remove_where(strip);
+
+  if args.dry_run {
+    println!("{}", plan.report(&args.file_path, global));
+    return Ok(());
+  }
+
+  plan.apply(&args.file_path, args.output.as_deref(), global)?;
+  log::info!(
+    "Removed {} ancillary chunk(s), saved {} bytes",
+    plan.changes.len(),
+    plan.size_before - plan.size_after()
+  );
+  Ok(())
+}
+
+pub fn extract(args: ExtractArgs, global: GlobalArgs) -> Result<()> {
+  if is_stdio(&args.file_path) {
+    let png = read_png(&args.file_path, global)?;
+    return match png.chunk_by_type(args.chunk_type) {
+      Some(chunk) => write_bytes(&args.out, chunk.data()),
+      None => Err(Error::ChunkNotFound)
+    };
+  }
+
+  // Only the wanted chunk is read, however big the file
+  let mut index = index_file(&args.file_path, global)?;
+  let position = index.position(args.chunk_type).ok_or(Error::ChunkNotFound)?;
+  write_bytes(&args.out, index.load_chunk(position)?.data())
+}
+
+pub fn insert(args: InsertArgs, global: GlobalArgs) -> Result<()> {
+  let mut png = read_png(&args.file_path, global)?;
+  let chunk = Chunk::try_new(args.chunk_type, fs::read(&args.data_file)?)?;
+
+  if let Some(before) = &args.before {
+    png.insert_before_type(before.as_str(), chunk)?;
+  } else if let Some(after) = &args.after {
+    png.insert_after_type(after.as_str(), chunk)?;
+  } else if let Some(index) = args.index {
+    png.insert_chunk(index, chunk)?;
+  } else {
+    png.append_chunk(chunk);
+  }
+  write_output(&mut png, &args.file_path, args.output.as_deref(), global)
+}
+
+pub fn replace(args: ReplaceArgs, global: GlobalArgs) -> Result<()> {
+  let mut png = read_png(&args.file_path, global)?;
+
+  png.replace_chunk(args.chunk_type.as_str(), args.new_data.into_bytes())?;
+  write_output(&mut png, &args.file_path, args.output.as_deref(), global)
+}
+
+pub fn touch(args: TouchArgs, global: GlobalArgs) -> Result<()> {
+  let mut png = read_png(&args.file_path, global)?;
+  let time = args.time.unwrap_or_else(LastModified::now);
+
+  let chunk = Chunk::from(time);
+  if png.chunk_by_type("tIME").is_some() {
+    png.replace_chunk("tIME", chunk.into_data())?;
+  } else {
+    png.append_chunk(chunk);
+  }
+
+  log::info!("tIME set to {}", time);
+  write_output(&mut png, &args.file_path, args.output.as_deref(), global)
+}
+
+pub fn set_dpi(args: SetDpiArgs, global: GlobalArgs) -> Result<()> {
+  let mut png = read_png(&args.file_path, global)?;
+  let dims = PhysicalDims::from_dpi(args.dpi, args.dpi_y.unwrap_or(args.dpi));
+
+  let chunk = Chunk::from(dims);
+  if png.chunk_by_type("pHYs").is_some() {
+    png.replace_chunk("pHYs", chunk.into_data())?;
+  } else {
+    // pHYs has to come before the image data
+    match png.insert_before_type("IDAT", chunk) {
+      Err(Error::ChunkNotFound) => png.append_chunk(Chunk::from(dims)),
+      result => result?,
+    }
+  }
+
+  log::info!("pHYs set to {} x {} pixels per meter", dims.x, dims.y);
+  write_output(&mut png, &args.file_path, args.output.as_deref(), global)
+}
+
+// Frames are written as they are stored, without applying the dispose and
+// blend ops, so frames after the first may only cover part of the canvas
+pub fn apng_split(args: ApngSplitArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let frames = png.frames()?;
+
+  fs::create_dir_all(&args.out_dir)?;
+  for (index, frame) in frames.iter().enumerate() {
+    let path = args.out_dir.join(format!("frame_{:03}.png", index));
+    png.frame_png(frame)?.save(&path)?;
+    log::debug!("wrote {}", path.display());
+  }
+
+  log::info!("Wrote {} frame(s) to {}", frames.len(), args.out_dir.display());
+  Ok(())
+}
+
+fn recompress_data(data: &[u8], args: &RecompressArgs) -> Vec<u8> {
+  #[cfg(feature = "zopfli")]
+  if args.zopfli {
+    return zlib::deflate_zopfli(data);
+  }
+  zlib::deflate_level(data, args.level)
+}
+
+pub fn recompress(args: RecompressArgs, global: GlobalArgs) -> Result<()> {
+  let mut png = read_png(&args.file_path, global)?;
+  let before = png.compressed_image_data().len();
+  let size_before = png.as_bytes().len();
+
+  // Keep the chunking the file already uses, if it has more than one IDAT
+  let chunk_size = png.chunk_by_type("IDAT").map_or(0, |chunk| chunk.data().len());
+  let chunk_size = if png.chunks_by_type("IDAT").count() > 1 { chunk_size } else { usize::MAX };
+
+  let compressed = recompress_data(&png.image_data()?, &args);
+  png.set_compressed_image_data(&compressed, chunk_size);
+  let size_after = png.as_bytes().len();
+
+  if global.json {
+    println!(
+      "{{\"file\":{},\"idat_before\":{},\"idat_after\":{},\"size_before\":{},\"size_after\":{}}}",
+      json_string(&args.file_path.display().to_string()),
+      before,
+      compressed.len(),
+      size_before,
+      size_after,
+    );
+  } else {
+    log::info!("Image data: {} -> {} bytes", before, compressed.len());
+    log::info!("File size:  {} -> {} bytes", size_before, size_after);
+  }
+
+  if args.dry_run {
+    return Ok(());
+  }
+  write_output(&mut png, &args.file_path, args.output.as_deref(), global)
+}
+
+pub fn to_raw(args: ToRawArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let ihdr = png.ihdr()?;
+
+  write_bytes(&args.out, &png.decode_rgba8()?)?;
+  log::info!("Wrote {}x{} RGBA pixels to {}", ihdr.width, ihdr.height, args.out.display());
+  Ok(())
+}
+
+pub fn from_raw(args: FromRawArgs, global: GlobalArgs) -> Result<()> {
+  let ihdr = Ihdr {
+    width: args.width,
+    height: args.height,
+    bit_depth: 8,
+    color_type: 6,
+    compression_method: 0,
+    filter_method: 0,
+    interlace_method: 0,
+  };
+  let data = read_bytes(&args.file_path)?;
+
+  let mut png = Png::from_image_data(&ImageData { ihdr, data })?;
+  write_output(&mut png, &args.out, None, global)
+}
+
+// Chunks are matched by type and occurrence, so the second tEXt in A is
+// compared with the second tEXt in B
+enum ChunkDiff<'a> {
+  OnlyInA(usize, &'a Chunk),
+  OnlyInB(usize, &'a Chunk),
+  Changed(usize, &'a Chunk, &'a Chunk),
+}
+
+fn occurrences(chunks: &[Chunk]) -> Vec<usize> {
+  chunks
+    .iter()
+    .enumerate()
+    .map(|(i, chunk)| chunks[..i].iter().filter(|c| c.chunk_type() == chunk.chunk_type()).count())
+    .collect()
+}
+
+fn diff_chunks<'a>(a: &'a [Chunk], b: &'a [Chunk]) -> Vec<ChunkDiff<'a>> {
+  let a_occurrences = occurrences(a);
+  let b_occurrences = occurrences(b);
+  let find = |chunks: &'a [Chunk], found: &[usize], chunk: &Chunk, occurrence: usize| {
+    chunks
+      .iter()
+      .zip(found)
+      .find(|(c, n)| c.chunk_type() == chunk.chunk_type() && **n == occurrence)
+      .map(|(c, _)| c)
+  };
+
+  let mut diffs = vec![];
+  for (chunk, &occurrence) in a.iter().zip(&a_occurrences) {
+    match find(b, &b_occurrences, chunk, occurrence) {
+      Some(other) if other.crc() != chunk.crc() || other.data() != chunk.data() => {
+        diffs.push(ChunkDiff::Changed(occurrence, chunk, other))
+      }
+      Some(_) => {}
+      None => diffs.push(ChunkDiff::OnlyInA(occurrence, chunk)),
+    }
+  }
+  for (chunk, &occurrence) in b.iter().zip(&b_occurrences) {
+    if find(a, &a_occurrences, chunk, occurrence).is_none() {
+      diffs.push(ChunkDiff::OnlyInB(occurrence, chunk));
+    }
+  }
+
+  diffs
+}
+
+fn describe_diff(diff: &ChunkDiff) -> String {
+  match diff {
+    ChunkDiff::OnlyInA(n, chunk) => format!("- {} #{} ({} bytes)", chunk.chunk_type(), n, chunk.length()),
+    ChunkDiff::OnlyInB(n, chunk) => format!("+ {} #{} ({} bytes)", chunk.chunk_type(), n, chunk.length()),
+    ChunkDiff::Changed(n, a, b) => format!(
+      "~ {} #{} length {} -> {} ({:+}), crc {:08x} -> {:08x}",
+      a.chunk_type(),
+      n,
+      a.length(),
+      b.length(),
+      b.length() as i64 - a.length() as i64,
+      a.crc(),
+      b.crc()
+    ),
+  }
+}
+
+fn diff_json(diffs: &[ChunkDiff]) -> String {
+  let chunk_json = |n: &usize, chunk: &Chunk| {
+    format!(
+      "{{\"type\":{},\"occurrence\":{},\"length\":{},\"crc\":{}}}",
+      json_string(chunk.chunk_type().as_str()),
+      n,
+      chunk.length(),
+      chunk.crc()
+    )
+  };
+
+  let mut only_in_a = vec![];
+  let mut only_in_b = vec![];
+  let mut changed = vec![];
+  for diff in diffs {
+    match diff {
+      ChunkDiff::OnlyInA(n, chunk) => only_in_a.push(chunk_json(n, chunk)),
+      ChunkDiff::OnlyInB(n, chunk) => only_in_b.push(chunk_json(n, chunk)),
+      ChunkDiff::Changed(n, a, b) => changed.push(format!(
+        "{{\"type\":{},\"occurrence\":{},\"length_a\":{},\"length_b\":{},\"crc_a\":{},\"crc_b\":{}}}",
+        json_string(a.chunk_type().as_str()),
+        n,
+        a.length(),
+        b.length(),
+        a.crc(),
+        b.crc()
+      )),
+    }
+  }
+
+  format!(
+    "{{\"only_in_a\":[{}],\"only_in_b\":[{}],\"changed\":[{}]}}",
+    only_in_a.join(","),
+    only_in_b.join(","),
+    changed.join(",")
+  )
+}
+
+pub fn diff(args: DiffArgs, global: GlobalArgs) -> Result<()> {
+  let a = read_png(&args.file_a, global)?;
+  let b = read_png(&args.file_b, global)?;
+  let diffs = diff_chunks(a.as_slice(), b.as_slice());
+
+  if global.json {
+    println!("{}", diff_json(&diffs));
+  } else if diffs.is_empty() {
+    println!("No differences");
+  } else {
+    for diff in &diffs {
+      println!("{}", describe_diff(diff));
+    }
+  }
+
+  Ok(())
+}
+
+// Where an ancillary chunk sits relative to the critical chunks around it.
+// Safe-to-copy chunks keep this relationship when they are copied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Region {
+  BeforePlte,
+  BeforeIdat,
+  AfterIdat,
+}
+
+fn chunk_region(chunks: &[Chunk], index: usize) -> Region {
+  let seen = |name: &str| chunks[..index].iter().any(|c| c.chunk_type().to_string() == name);
+
+  if seen("IDAT") {
+    Region::AfterIdat
+  } else if seen("PLTE") {
+    Region::BeforeIdat
+  } else {
+    let plte_follows = chunks[index..].iter().any(|c| c.chunk_type().to_string() == "PLTE");
+    if plte_follows { Region::BeforePlte } else { Region::BeforeIdat }
+  }
+}
+
+fn region_position(png: &Png, region: Region) -> usize {
+  let chunks = png.as_slice();
+  let position_of = |name: &str| chunks.iter().position(|c| c.chunk_type().to_string() == name);
+  let end = position_of("IEND").unwrap_or(chunks.len());
+
+  let position = match region {
+    Region::BeforePlte => position_of("PLTE").or_else(|| position_of("IDAT")),
+    Region::BeforeIdat => position_of("IDAT"),
+    Region::AfterIdat => None,
+  };
+  position.unwrap_or(end)
+}
+
+fn merge_chunks(from: &Png, into: &mut Png) -> Result<usize> {
+  let mut copied = 0;
+
+  for (index, chunk) in from.chunks().enumerate() {
+    let chunk_type = chunk.chunk_type();
+    if chunk_type.is_critical() || !chunk_type.is_safe_to_copy() {
+      continue;
+    }
+
+    let already_present = into
+      .chunks()
+      .any(|c| c.chunk_type() == chunk_type && c.data() == chunk.data());
+    if already_present {
+      continue;
+    }
+
+    let position = region_position(into, chunk_region(from.as_slice(), index));
+    into.insert_chunk(position, chunk.clone())?;
+    copied += 1;
+  }
+
+  Ok(copied)
+}
+
+pub fn merge(args: MergeArgs, global: GlobalArgs) -> Result<()> {
+  let from = read_png(&args.from, global)?;
+  let mut into = read_png(&args.into, global)?;
+
+  let copied = merge_chunks(&from, &mut into)?;
+  write_output(&mut into, &args.into, args.output.as_deref(), global)?;
+  log::info!("Copied {} chunk(s)", copied);
+  Ok(())
+}
+
+// Everything `info` reports about a file
+struct Info {
+  file_size: usize,
+  ihdr: Ihdr,
+  chunk_count: usize,
+  texts: Vec<(String, String)>,
+  gamma: Option<Gamma>,
+  chromaticities: Option<Chromaticities>,
+  srgb: Option<Srgb>,
+  // Profile name and decompressed size
+  icc_profile: Option<(String, usize)>,
+  animation: Option<AnimationControl>,
+  // Only decoded when asked for
+  exif: Option<ExifTags>,
+}
+
+// Decodes the first chunk of a type, logging instead of failing when it's
+// malformed so that `info` still shows everything else
+fn typed_chunk<'a, T: TryFrom<&'a Chunk, Error = Error>>(png: &'a Png, chunk_type: &str) -> Option<T> {
+  match T::try_from(png.chunk_by_type(chunk_type)?) {
+    Ok(typed) => Some(typed),
+    Err(e) => {
+      log::warn!("skipping {} chunk: {}", chunk_type, e);
+      None
+    }
+  }
+}
+
+impl Info {
+  fn new(png: &Png, file_size: usize) -> Result<Info> {
+    let icc_profile = typed_chunk::<IccProfile>(png, "iCCP").and_then(|icc| match icc.profile() {
+      Ok(profile) => Some((icc.name().to_string(), profile.len())),
+      Err(e) => {
+        log::warn!("skipping iCCP chunk: {}", e);
+        None
+      }
+    });
+
+    Ok(Info {
+      file_size,
+      ihdr: png.ihdr()?,
+      chunk_count: png.chunks().len(),
+      texts: text_entries(png),
+      gamma: typed_chunk(png, "gAMA"),
+      chromaticities: typed_chunk(png, "cHRM"),
+      srgb: typed_chunk(png, "sRGB"),
+      icc_profile,
+      animation: typed_chunk(png, "acTL"),
+      exif: None,
+    })
+  }
+
+  fn with_exif(mut self, png: &Png) -> Info {
+    self.exif = typed_chunk::<Exif>(png, "eXIf").and_then(|exif| match exif.tags() {
+      Ok(tags) => Some(tags),
+      Err(e) => {
+        log::warn!("skipping eXIf chunk: {}", e);
+        None
+      }
+    });
+    self
+  }
+}
+
+fn info_text(path: &Path, info: &Info) -> String {
+  let ihdr = &info.ihdr;
+  let interlace = match ihdr.interlace_method {
+    0 => "none",
+    1 => "Adam7",
+    _ => "unknown",
+  };
+
+  let mut lines = vec![
+    format!("File:        {} ({} bytes)", path.display(), info.file_size),
+    format!("Dimensions:  {} x {}", ihdr.width, ihdr.height),
+    format!("Bit depth:   {}", ihdr.bit_depth),
+    format!("Color type:  {} ({})", ihdr.color_type, ihdr.color_type_name()),
+    format!("Compression: {}", ihdr.compression_method),
+    format!("Filter:      {}", ihdr.filter_method),
+    format!("Interlace:   {} ({})", ihdr.interlace_method, interlace),
+    format!("Chunks:      {}", info.chunk_count),
+  ];
+
+  if let Some(actl) = info.animation {
+    let plays = match actl.num_plays {
+      0 => "infinite".to_string(),
+      plays => plays.to_string(),
+    };
+    lines.push(format!("Animation:   animated, {} frames, {} plays", actl.num_frames, plays));
+  }
+  if let Some(gamma) = info.gamma {
+    lines.push(format!("Gamma:       {}", gamma.value()));
+  }
+  if let Some(chrm) = info.chromaticities {
+    let [white, red, green, blue] = chrm.points();
+    lines.push(format!(
+      "Chromaticity: white {:?} red {:?} green {:?} blue {:?}",
+      white, red, green, blue
+    ));
+  }
+  if let Some(srgb) = info.srgb {
+    lines.push(format!("sRGB:        {}", srgb.intent));
+  }
+  if let Some((name, size)) = &info.icc_profile {
+    lines.push(format!("ICC profile: {} ({} bytes)", name, size));
+  }
+  for (keyword, text) in &info.texts {
+    lines.push(format!("Text:        {}: {}", keyword, text));
+  }
+  if let Some(exif) = &info.exif {
+    let camera = [&exif.make, &exif.model].iter().filter_map(|s| s.as_deref()).collect::<Vec<_>>().join(" ");
+    if let Some(orientation) = exif.orientation {
+      lines.push(format!("Orientation: {}", orientation));
+    }
+    if !camera.is_empty() {
+      lines.push(format!("Camera:      {}", camera));
+    }
+    if let Some(taken) = exif.date_time_original.as_ref().or(exif.date_time.as_ref()) {
+      lines.push(format!("Taken:       {}", taken));
+    }
+    lines.push(format!("GPS:         {}", if exif.has_gps { "present" } else { "none" }));
+  }
+  lines.join("\n")
+}
+
+fn info_json(path: &Path, info: &Info) -> String {
+  let ihdr = &info.ihdr;
+  let texts: Vec<String> = info
+    .texts
+    .iter()
+    .map(|(keyword, text)| format!("{{\"keyword\":{},\"text\":{}}}", json_string(keyword), json_string(text)))
+    .collect();
+  let point = |(x, y): (f64, f64)| format!("[{},{}]", x, y);
+  let chromaticities = info.chromaticities.map_or("null".to_string(), |chrm| {
+    let [white, red, green, blue] = chrm.points();
+    format!("{{\"white\":{},\"red\":{},\"green\":{},\"blue\":{}}}", point(white), point(red), point(green), point(blue))
+  });
+  let icc_profile = info.icc_profile.as_ref().map_or("null".to_string(), |(name, size)| {
+    format!("{{\"name\":{},\"size\":{}}}", json_string(name), size)
+  });
+  let optional = |s: &Option<String>| s.as_deref().map_or("null".to_string(), json_string);
+  let exif = info.exif.as_ref().map_or("null".to_string(), |exif| {
+    format!(
+      "{{\"orientation\":{},\"make\":{},\"model\":{},\"date_time\":{},\"date_time_original\":{},\"gps\":{}}}",
+      exif.orientation.map_or("null".to_string(), |o| o.to_string()),
+      optional(&exif.make),
+      optional(&exif.model),
+      optional(&exif.date_time),
+      optional(&exif.date_time_original),
+      exif.has_gps,
+    )
+  });
+
+  format!(
+    "{{\"file\":{},\"file_size\":{},\"width\":{},\"height\":{},\"bit_depth\":{},\"color_type\":{},\"compression_method\":{},\"filter_method\":{},\"interlace_method\":{},\"chunk_count\":{},\"animation\":{},\"gamma\":{},\"chromaticities\":{},\"srgb\":{},\"icc_profile\":{},\"exif\":{},\"text\":[{}]}}",
+    json_string(&path.display().to_string()),
+    info.file_size,
+    ihdr.width,
+    ihdr.height,
+    ihdr.bit_depth,
+    ihdr.color_type,
+    ihdr.compression_method,
+    ihdr.filter_method,
+    ihdr.interlace_method,
+    info.chunk_count,
+    info.animation.map_or("null".to_string(), |actl| format!("{{\"frames\":{},\"plays\":{}}}", actl.num_frames, actl.num_plays)),
+    info.gamma.map_or("null".to_string(), |gamma| gamma.value().to_string()),
+    chromaticities,
+    info.srgb.map_or("null".to_string(), |srgb| json_string(&srgb.intent.to_string())),
+    icc_profile,
+    exif,
+    texts.join(","),
+  )
+}
+
+pub fn info(args: InfoArgs, global: GlobalArgs) -> Result<()> {
+  let bytes = read_bytes(&args.file_path)?;
+  let (png, _) = parse_png(&bytes, global, ParseMode::Strict)?;
+  let mut info = Info::new(&png, bytes.len())?;
+  if args.exif {
+    info = info.with_exif(&png);
+  }
+
+  if global.json {
+    println!("{}", info_json(&args.file_path, &info));
+  } else {
+    println!("{}", info_text(&args.file_path, &info));
+  }
+  Ok(())
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic) -> String {
+  format!(
+    "{{\"code\":{},\"severity\":{},\"offset\":{},\"chunk\":{},\"message\":{}}}",
+    json_string(diagnostic.code),
+    json_string(&diagnostic.severity.to_string()),
+    diagnostic.offset,
+    diagnostic.chunk.map_or("null".to_string(), |index| index.to_string()),
+    json_string(&diagnostic.message),
+  )
+}
+
+fn verify_json(path: &Path, report: &Report) -> String {
+  let diagnostics: Vec<String> = report.diagnostics.iter().map(diagnostic_json).collect();
+  format!(
+    "{{\"file\":{},\"ok\":{},\"chunk_count\":{},\"diagnostics\":[{}]}}",
+    json_string(&path.display().to_string()),
+    report.is_ok(),
+    report.chunk_count,
+    diagnostics.join(","),
+  )
+}
+
+pub fn verify(args: VerifyArgs, global: GlobalArgs) -> Result<()> {
+  // The files are spread over the threads, so each is checked on one
+  if args.recursive {
+    return batch(&args.file_path, global, args.jobs, |path| {
+      let report = verify_file(path, &args.deny, 1, global)?;
+      if !report.is_ok() {
+        for diagnostic in report.diagnostics.errors() {
+          log::error!("{}: {}", path.display(), diagnostic);
+        }
+        return Err(Error::VerifyFailed(report.diagnostics.errors().count()));
+      }
+      if global.json {
+        return Ok(verify_json(path, &report));
+      }
+      let mut lines = vec![format!("OK ({} chunks)", report.chunk_count)];
+      lines.extend(report.diagnostics.iter().map(Diagnostic::to_string));
+      Ok(lines.join("\n"))
+    });
+  }
+
+  if args.watch {
+    return watch(&args.file_path, || verify_once(&args, global));
+  }
+  verify_once(&args, global)
+}
+
+fn verify_file(path: &Path, deny: &[&str], jobs: usize, global: GlobalArgs) -> Result<Report> {
+  let bytes = read_bytes(path)?;
+  let mut report = if jobs == 1 {
+    checks::verify_with_limits(&bytes, global.limits())
+  } else {
+    let pool = rayon::ThreadPoolBuilder::new()
+      .num_threads(jobs)
+      .build()
+      .map_err(|e| Error::ThreadPool(e.to_string()))?;
+    pool.install(|| checks::verify_parallel_with_limits(&bytes, global.limits()))
+  };
+  report.diagnostics.deny(deny);
+  Ok(report)
+}
+
+fn verify_once(args: &VerifyArgs, global: GlobalArgs) -> Result<()> {
+  let report = verify_file(&args.file_path, &args.deny, args.jobs, global)?;
+  let errors = report.diagnostics.errors().count();
+
+  if global.json {
+    println!("{}", verify_json(&args.file_path, &report));
+  } else {
+    for diagnostic in &report.diagnostics {
+      println!("{}: {}", args.file_path.display(), diagnostic);
+    }
+    if report.is_ok() {
+      println!("OK: {} ({} chunks)", args.file_path.display(), report.chunk_count);
+    } else {
+      println!("{} problem(s) found in {}", errors, args.file_path.display());
+    }
+  }
+
+  if report.is_ok() {
+    Ok(())
+  } else {
+    Err(Error::VerifyFailed(errors))
+  }
+}
+
+// Classic 16 bytes per line layout; `start` is the offset of the first
+// byte of `data` within the chunk
+fn hexdump_lines(data: &[u8], start: usize) -> Vec<String> {
+  data
+    .chunks(16)
+    .enumerate()
+    .map(|(i, line)| {
+      let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+      let ascii: String = line
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+      format!("{:08x}  {:<47}  |{}|", start + i * 16, hex.join(" "), ascii)
+    })
+    .collect()
+}
+
+pub fn hexdump(args: HexdumpArgs, global: GlobalArgs) -> Result<()> {
+  let png = read_png(&args.file_path, global)?;
+  let chunk = png
+    .chunk_by_type(args.chunk_type.as_str())
+    .ok_or(Error::ChunkNotFound)?;
+
+  let data = chunk.data();
+  if args.offset > data.len() {
+    return Err(Error::IndexOutOfRange(args.offset));
+  }
+
+  let end = match args.length {
+    Some(length) => data.len().min(args.offset.saturating_add(length)),
+    None => data.len(),
+  };
+
+  for line in hexdump_lines(&data[args.offset..end], args.offset) {
+    println!("{}", line);
+  }
+  Ok(())
+}
+
+pub fn completions<W: Write>(args: CompletionsArgs, out: &mut W) -> Result<()> {
+  let mut command = args::command();
+  let name = command.get_name().to_string();
+  clap_complete::generate(args.shell, &mut command, name, out);
+  Ok(())
+}
+
+fn grep_regex(args: &GrepArgs) -> Result<Regex> {
+  let pattern = if args.hex {
+    let digits: Vec<char> = args.pattern.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+      return Err(Error::InvalidPattern(format!("odd number of hex digits in {:?}", args.pattern)));
+    }
+
+    let mut escaped = String::from("(?-u)");
+    for pair in digits.chunks(2) {
+      let byte: String = pair.iter().collect();
+      u8::from_str_radix(&byte, 16).map_err(|_| Error::InvalidPattern(format!("{:?} is not a hex byte", byte)))?;
+      escaped.push_str(&format!("\\x{}", byte));
+    }
+    escaped
+  } else if args.fixed_strings {
+    regex::escape(&args.pattern)
+  } else {
+    args.pattern.clone()
+  };
+
+  Regex::new(&pattern).map_err(|e| Error::InvalidPattern(e.to_string()))
+}
+
+struct GrepMatch {
+  index: usize,
+  chunk_type: String,
+  offset: usize,
+  bytes: Vec<u8>,
+}
+
+fn grep_png(png: &Png, regex: &Regex, chunk_type: Option<&ChunkType>) -> Vec<GrepMatch> {
+  let mut matches = vec![];
+  for (index, chunk) in png.chunks().enumerate() {
+    if chunk_type.is_some_and(|wanted| wanted != chunk.chunk_type()) {
+      continue;
+    }
+
+    for found in regex.find_iter(chunk.data()) {
+      matches.push(GrepMatch {
+        index,
+        chunk_type: chunk.chunk_type().to_string(),
+        offset: found.start(),
+        bytes: found.as_bytes().to_vec(),
+      });
+    }
+  }
+  matches
+}
+
+// Long matches, e.g. from `.*`, are cut short so each one stays on a line
+fn grep_preview(bytes: &[u8]) -> String {
+  let shown = &bytes[..bytes.len().min(48)];
+  let mut preview = shown.escape_ascii().to_string();
+  if shown.len() < bytes.len() {
+    preview.push_str("...");
+  }
+  preview
+}
+
+pub fn grep(args: GrepArgs, global: GlobalArgs) -> Result<()> {
+  let regex = grep_regex(&args)?;
+
+  let mut files = vec![];
+  if args.path.is_dir() {
+    find_pngs(&args.path, &mut files)?;
+  } else {
+    files.push(args.path.clone());
+  }
+
+  for path in &files {
+    let png = match read_png(path, global) {
+      Ok(png) => png,
+      Err(e) => {
+        log::warn!("{}: skipped: {}", path.display(), e);
+        continue;
+      }
+    };
+
+    for found in grep_png(&png, &regex, args.chunk_type.as_ref()) {
+      if global.json {
+        println!(
+          "{{\"file\":{},\"chunk\":{},\"type\":{},\"offset\":{},\"length\":{}}}",
+          json_string(&path.display().to_string()),
+          found.index,
+          json_string(&found.chunk_type),
+          found.offset,
+          found.bytes.len()
+        );
+      } else {
+        println!(
+          "{}: chunk {} ({}) offset {}: {}",
+          path.display(),
+          found.index,
+          found.chunk_type,
+          found.offset,
+          grep_preview(&found.bytes)
+        );
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Cli;
+    use pngne::chunks::InternationalText;
+    use clap::Parser;
+
+    fn chunk_from_strings(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pngne-{}-{}.png", std::process::id(), name))
+    }
+
+    fn testing_png_file(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        write_png(&path, &png).unwrap();
+        path
+    }
+
+    fn run_cli(args: &[&str]) -> Result<()> {
+        let cli = Cli::try_parse_from(std::iter::once("pngne").chain(args.iter().copied())).unwrap();
+        run(cli)
+    }
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_encode() {
+        let path = testing_png_file("encode");
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden message"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "hidden message");
+        assert_eq!(&png.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_encode_data_file() {
+        let path = testing_png_file("encode-data-file");
+        let data_path = temp_path("encode-data-file.bin");
+        let out_path = temp_path("encode-data-file.out");
+        let data: Vec<u8> = (0..=255).chain([0xff, 0xfe, 0]).collect();
+        fs::write(&data_path, &data).unwrap();
+
+        run_cli(&["encode", path_str(&path), "ruSt", "--data-file", path_str(&data_path)]).unwrap();
+        run_cli(&["decode", path_str(&path), "ruSt", "--out", path_str(&out_path)]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let out = fs::read(&out_path).unwrap();
+        let as_string = decode_file(&path, &DecodeArgs {
+            file_path: path.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            as_text: false,
+            decrypt: false,
+            identity: vec![],
+            auth_key: None,
+            id: None,
+            mode: Mode::Chunk,
+            out: None,
+            recursive: false,
+            jobs: 0,
+            watch: false,
+        }, &Keys::default(), GlobalArgs::default());
+        for path in [&path, &data_path, &out_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), &data[..]);
+        assert_eq!(out, data);
+        assert!(matches!(as_string, Err(Error::InvalidUtf8 { .. })));
+        assert!(Cli::try_parse_from(["pngne", "encode", "a.png", "ruSt"]).is_err());
+        assert!(Cli::try_parse_from(["pngne", "encode", "a.png", "ruSt", "hi", "--data-file", "b"]).is_err());
+    }
+
+    #[test]
+    fn test_encode_compress() {
+        let path = testing_png_file("encode-compress");
+        let message = "compress me ".repeat(100);
+        run_cli(&["encode", path_str(&path), "ruSt", &message, "--compress", "zlib"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let decoded = decode_file(&path, &DecodeArgs {
+            file_path: path.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            as_text: false,
+            decrypt: false,
+            identity: vec![],
+            auth_key: None,
+            id: None,
+            mode: Mode::Chunk,
+            out: None,
+            recursive: false,
+            jobs: 0,
+            watch: false,
+        }, &Keys::default(), GlobalArgs::default());
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert!(payload::is_packed(chunk.data()));
+        assert!(chunk.length() < 100);
+        assert_eq!(decoded, Ok(message));
+        assert!(Cli::try_parse_from(["pngne", "encode", "a.png", "ruSt", "hi", "--compress", "gzip"]).is_err());
+    }
+
+    #[test]
+    fn test_encode_encrypt() {
+        let path = testing_png_file("encode-encrypt");
+        std::env::set_var(PASSPHRASE_VAR, "correct horse");
+        run_cli(&["encode", path_str(&path), "ruSt", "attack at dawn", "--encrypt", "--compress", "zlib"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let args = |decrypt| DecodeArgs {
+            file_path: path.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            as_text: false,
+            decrypt,
+            identity: vec![],
+            auth_key: None,
+            id: None,
+            mode: Mode::Chunk,
+            out: None,
+            recursive: false,
+            jobs: 0,
+            watch: false,
+        };
+        let keys = |passphrase| Keys { passphrase: Some(passphrase), ..Keys::default() };
+        let decrypted = decode_file(&path, &args(true), &keys("correct horse"), GlobalArgs::default());
+        let wrong = decode_file(&path, &args(true), &keys("battery staple"), GlobalArgs::default());
+        let locked = decode_file(&path, &args(false), &Keys::default(), GlobalArgs::default());
+        let decoded = run_cli(&["decode", path_str(&path), "ruSt", "--decrypt"]);
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert!(payload::is_encrypted(chunk.data()));
+        assert!(!chunk.data().windows(6).any(|window| window == b"attack"));
+        assert_eq!(decrypted, Ok("attack at dawn".to_string()));
+        assert!(matches!(wrong, Err(Error::Payload(_))));
+        assert!(matches!(locked, Err(Error::Payload(_))));
+        assert!(decoded.is_ok());
+        assert!(Cli::try_parse_from(["pngne", "encode", "a.png", "ruSt", "hi", "--as-text", "--encrypt"]).is_err());
+    }
+
+    #[test]
+    fn test_encode_recipient() {
+        use age::secrecy::ExposeSecret;
+
+        let path = testing_png_file("encode-recipient");
+        let key_path = temp_path("encode-recipient.key");
+        let identity = Identity::generate();
+        let recipient = identity.to_public().to_string();
+        fs::write(&key_path, format!("# public key: {}\n{}\n", recipient, identity.to_string().expose_secret())).unwrap();
+
+        run_cli(&["encode", path_str(&path), "ruSt", "only for you", "--recipient", &recipient]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let identities = read_identities(std::slice::from_ref(&key_path)).unwrap();
+        let decoded = run_cli(&["decode", path_str(&path), "ruSt", "--identity", path_str(&key_path)]);
+        let locked = run_cli(&["decode", path_str(&path), "ruSt"]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&key_path).unwrap();
+
+        let data = png.chunk_by_type("ruSt").unwrap().data();
+        assert!(payload::is_encrypted(data));
+        assert_eq!(payload::unpack(data, &Keys { identities: &identities, ..Keys::default() }).unwrap(), b"only for you");
+        assert!(decoded.is_ok());
+        assert!(matches!(locked, Err(Error::Payload(_))));
+        assert!(Cli::try_parse_from(["pngne", "encode", "a.png", "ruSt", "hi", "--recipient", "age1nope"]).is_err());
+    }
+
+    #[test]
+    fn test_encode_auth_key() {
+        let path = testing_png_file("encode-auth-key");
+        let key_path = temp_path("encode-auth-key.key");
+        let wrong_key_path = temp_path("encode-auth-key.wrong");
+        fs::write(&key_path, b"our little secret").unwrap();
+        fs::write(&wrong_key_path, b"not the secret").unwrap();
+
+        run_cli(&["encode", path_str(&path), "ruSt", "meet at noon", "--auth-key", path_str(&key_path)]).unwrap();
+        let verified = run_cli(&["decode", path_str(&path), "ruSt", "--auth-key", path_str(&key_path)]);
+        let wrong_key = run_cli(&["decode", path_str(&path), "ruSt", "--auth-key", path_str(&wrong_key_path)]);
+        let unverified = run_cli(&["decode", path_str(&path), "ruSt"]);
+
+        // Flip the last message byte, before the 32 byte tag. The chunk gets
+        // a fresh CRC, so only the tag can tell.
+        let mut png = read_png(&path, GlobalArgs::default()).unwrap();
+        let mut data = png.chunk_by_type("ruSt").unwrap().data().to_vec();
+        let last = data.len() - 33;
+        data[last] ^= 1;
+        png.replace_chunk("ruSt", data).unwrap();
+        png.save(&path).unwrap();
+        let tampered = run_cli(&["decode", path_str(&path), "ruSt", "--auth-key", path_str(&key_path)]);
+        for path in [&path, &key_path, &wrong_key_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(verified.is_ok());
+        assert!(matches!(wrong_key, Err(Error::Payload(_))));
+        assert!(matches!(unverified, Err(Error::Payload(_))));
+        assert!(matches!(tampered, Err(Error::Payload(_))));
+    }
+
+    #[test]
+    fn test_encode_fragments() {
+        let path = testing_png_file("encode-fragments");
+        let data_path = temp_path("encode-fragments.bin");
+        let out_path = temp_path("encode-fragments.out");
+        let data: Vec<u8> = (0..5000).map(|i| (i * 7 % 256) as u8).collect();
+        fs::write(&data_path, &data).unwrap();
+
+        run_cli(&["encode", path_str(&path), "ruSt", "--data-file", path_str(&data_path), "--fragment-size", "2000"]).unwrap();
+        run_cli(&["decode", path_str(&path), "ruSt", "--out", path_str(&out_path)]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let out = fs::read(&out_path).unwrap();
+        for path in [&path, &data_path, &out_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(chunk_types(&png), vec!["IHDR", "ruSt", "ruSt", "ruSt", "ruSt", "IEND"]);
+        assert!(fragment::is_manifest(png.chunk_by_type("ruSt").unwrap().data()));
+        assert!(png.chunks_by_type("ruSt").all(|chunk| chunk.length() <= 2012));
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_encode_named() {
+        let path = testing_png_file("encode-named");
+        let out_path = temp_path("encode-named.out");
+        let long = "a long one ".repeat(50);
+        run_cli(&["encode", path_str(&path), "ruSt", "first", "--id", "one"]).unwrap();
+        run_cli(&["encode", path_str(&path), "ruSt", &long, "--id", "two", "--fragment-size", "200"]).unwrap();
+        run_cli(&["encode", path_str(&path), "ruSt", "bare"]).unwrap();
+        let duplicate = run_cli(&["encode", path_str(&path), "ruSt", "again", "--id", "one"]);
+
+        let mut decoded = vec![];
+        for id in ["two", "one"] {
+            run_cli(&["decode", path_str(&path), "ruSt", "--id", id, "--out", path_str(&out_path)]).unwrap();
+            decoded.push(fs::read_to_string(&out_path).unwrap());
+        }
+        let missing = run_cli(&["decode", path_str(&path), "ruSt", "--id", "three"]);
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        for path in [&path, &out_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert!(duplicate.is_err());
+        assert_eq!(decoded, [long.as_str(), "first"]);
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+
+        let messages: Vec<_> = messages(&png, None).into_iter().map(|m| {
+            let envelope = payload::envelope(&m.data).unwrap();
+            (m, envelope)
+        }).collect();
+        let text = messages_text(&messages);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ruSt  one  ") && lines[0].ends_with("bytes  none"));
+        assert!(lines[1].starts_with("ruSt  two  ") && lines[1].ends_with("none, 3 fragments"));
+        assert_eq!(lines[2], "ruSt  -  4 bytes  bare");
+        assert!(messages_json(&path, &messages).contains("\"id\":\"two\",\"size\":"));
+        assert_eq!(messages_text(&[]), "no payloads found");
+    }
+
+    #[test]
+    fn test_encode_lsb() {
+        let path = temp_path("encode-lsb.png");
+        let out_path = temp_path("encode-lsb.out");
+        let ihdr = Ihdr { width: 16, height: 16, bit_depth: 8, color_type: 2, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let cover = ImageData { ihdr, data: (0..16 * 16 * 3).map(|i| (i % 256) as u8).collect() };
+        write_png(&path, &Png::from_image_data(&cover).unwrap()).unwrap();
+
+        run_cli(&["encode", path_str(&path), "ruSt", "in the pixels", "--mode", "lsb", "--id", "px"]).unwrap();
+        run_cli(&["strip", path_str(&path)]).unwrap();
+        run_cli(&["decode", path_str(&path), "ruSt", "--mode", "lsb", "--out", path_str(&out_path)]).unwrap();
+        let wrong_id = run_cli(&["decode", path_str(&path), "ruSt", "--mode", "lsb", "--id", "other"]);
+        let too_big = run_cli(&["encode", path_str(&path), "ruSt", &"x".repeat(100), "--mode", "lsb"]);
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let out = fs::read_to_string(&out_path).unwrap();
+        for path in [&path, &out_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(out, "in the pixels");
+        assert_eq!(wrong_id, Err(Error::ChunkNotFound));
+        assert!(too_big.is_err());
+        assert_eq!(chunk_types(&png), vec!["IHDR", "IDAT", "IEND"]);
+        let image = png.decode_pixels().unwrap();
+        assert!(image.data.iter().zip(&cover.data).all(|(a, b)| a >> 1 == b >> 1));
+        assert_ne!(image.data, cover.data);
+    }
+
+    #[test]
+    fn test_payload_capacity() {
+        let args = |extra: &[&str]| {
+            let cli = Cli::try_parse_from(["pngne", "capacity", "a.png"].iter().chain(extra)).unwrap();
+            let PngMeArgs::Capacity(args) = cli.command else { panic!("expected capacity") };
+            args
+        };
+
+        assert_eq!(payload_capacity(1000, &args(&[])), 1000);
+        assert_eq!(payload_capacity(1000, &args(&["--id", "notes"])), 1000 - 8 - 6);
+        assert_eq!(payload_capacity(1000, &args(&["--encrypt", "--authenticate"])), 1000 - 8 - 44 - 32);
+        assert!(payload_capacity(1000, &args(&["--compress", "zlib"])) < 1000 - 8);
+        assert_eq!(payload_capacity(10, &args(&["--encrypt"])), 0);
+
+        // What capacity promises actually fits
+        let ihdr = Ihdr { width: 16, height: 16, bit_depth: 8, color_type: 2, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let mut image = ImageData { ihdr, data: vec![0; 16 * 16 * 3] };
+        let space = lsb::capacity(&ihdr, 1).unwrap();
+        let fits = payload_capacity(space, &args(&["--compress", "zlib", "--id", "x"]));
+        let noise: Vec<u8> = (0..fits).map(|i| (i * 151 % 256) as u8 ^ (i / 7) as u8).collect();
+        let packed = payload::pack(&noise, Compression::Zlib, &Keys::default(), Some("x")).unwrap();
+        assert!(lsb::embed(&mut image, &packed, 1).is_ok());
+    }
+
+    #[test]
+    fn test_detection_output() {
+        let detection = Detection {
+            score: 40,
+            findings: vec![detection::Finding { offset: 33, weight: 40, message: "unknown chunk type ruSt (5 bytes)".to_string() }],
+        };
+        assert_eq!(detection_text(&detection), "score: 40/100\n  offset 0x00000021: unknown chunk type ruSt (5 bytes)");
+        assert_eq!(
+            detection_json(Path::new("a.png"), &detection),
+            "{\"file\":\"a.png\",\"score\":40,\"findings\":[{\"offset\":33,\"weight\":40,\"message\":\"unknown chunk type ruSt (5 bytes)\"}]}"
+        );
+    }
+
+    #[test]
+    fn test_encode_as_text() {
+        let path = testing_png_file("encode-text");
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden message", "--as-text"]).unwrap();
+
+        let found = run_cli(&["decode", path_str(&path), "ruSt", "--as-text"]);
+        let missing = run_cli(&["decode", path_str(&path), "nOpe", "--as-text"]);
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let text = TextChunk::try_from(png.chunk_by_type("tEXt").unwrap()).unwrap();
+        assert_eq!(text.keyword(), "ruSt");
+        assert_eq!(text.text(), "hidden message");
+        assert!(png.chunk_by_type("ruSt").is_none());
+        assert!(found.is_ok());
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_encode_compress_text() {
+        let path = testing_png_file("encode-ztxt");
+        let message = "squeeze me ".repeat(50);
+        run_cli(&["encode", path_str(&path), "ruSt", &message, "--as-text", "--compress-text"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let decoded = decode_file(&path, &DecodeArgs {
+            file_path: path.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            as_text: true,
+            decrypt: false,
+            identity: vec![],
+            auth_key: None,
+            id: None,
+            mode: Mode::Chunk,
+            out: None,
+            recursive: false,
+            jobs: 0,
+            watch: false,
+        }, &Keys::default(), GlobalArgs::default());
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("zTXt").unwrap();
+        assert!(chunk.length() < 100);
+        assert_eq!(decoded, Ok(message));
+    }
+
+    #[test]
+    fn test_encode_checks_order() {
+        let path = temp_path("encode-order");
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("IDAT", &[]),
+            chunk_from_strings("IEND", &[]),
+        ])
+        .save(&path)
+        .unwrap();
+
+        let allowed = run_cli(&["encode", path_str(&path), "tEXt", "Comment\0fine"]);
+        let misplaced = run_cli(&["encode", path_str(&path), "pHYs", "late"]);
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(allowed.is_ok());
+        assert_eq!(misplaced, Err(Error::InvalidOrder(1)));
+        assert!(png.chunk_by_type("pHYs").is_none());
+    }
+
+    #[test]
+    fn test_encode_with_output() {
+        let path = testing_png_file("encode-output");
+        let output = temp_path("encode-output-new");
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden message", "--output", path_str(&output)]).unwrap();
+
+        let original = read_png(&path, GlobalArgs::default()).unwrap();
+        let encoded = read_png(&output, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        assert!(original.chunk_by_type("ruSt").is_none());
+        assert!(encoded.chunk_by_type("ruSt").is_some());
+    }
+
+    #[test]
+    fn test_decode() {
+        let path = testing_png_file("decode");
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden message"]).unwrap();
+
+        let found = run_cli(&["decode", path_str(&path), "ruSt"]);
+        let missing = run_cli(&["decode", path_str(&path), "nOpe"]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(found.is_ok());
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    fn encode_twice(path: &Path) {
+        for message in ["first", "second"] {
+            run_cli(&["encode", path_str(path), "ruSt", message]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remove_first() {
+        let path = testing_png_file("remove-first");
+        encode_twice(&path);
+        run_cli(&["remove", path_str(&path), "ruSt"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let chunk = png.chunk_by_type("ruSt").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "second");
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let path = testing_png_file("remove-all");
+        encode_twice(&path);
+        run_cli(&["remove", path_str(&path), "ruSt", "--all"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        let missing = run_cli(&["remove", path_str(&path), "ruSt", "--all"]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(png.chunk_by_type("ruSt").is_none());
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    fn testing_png_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngne-{}-{}", std::process::id(), name));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["a.png", "nested/b.PNG"] {
+            fs::rename(testing_png_file(&format!("{}-{}", name, file.replace('/', "-"))), dir.join(file)).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a png").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_find_pngs() {
+        let dir = testing_png_dir("find");
+        let mut found = vec![];
+        find_pngs(&dir, &mut found).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found, vec![dir.join("a.png"), dir.join("nested/b.PNG")]);
+    }
+
+    #[test]
+    fn test_recursive_encode_and_decode() {
+        let dir = testing_png_dir("recursive");
+        run_cli(&["encode", "-r", path_str(&dir), "ruSt", "batch message"]).unwrap();
+
+        let decoded = decode_file(&dir.join("nested/b.PNG"), &DecodeArgs {
+            file_path: dir.clone(),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            as_text: false,
+            decrypt: false,
+            identity: vec![],
+            auth_key: None,
+            id: None,
+            mode: Mode::Chunk,
+            out: None,
+            recursive: true,
+            jobs: 0,
+            watch: false,
+        }, &Keys::default(), GlobalArgs::default());
+        let all_decoded = run_cli(&["decode", "-r", path_str(&dir), "ruSt"]);
+        let serial = run_cli(&["decode", "-r", "-j", "1", path_str(&dir), "ruSt"]);
+        run_cli(&["remove", "-r", "--jobs", "4", path_str(&dir), "ruSt"]).unwrap();
+        let after_remove = run_cli(&["decode", "-r", path_str(&dir), "ruSt"]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(decoded, Ok("batch message".to_string()));
+        assert!(all_decoded.is_ok());
+        assert!(serial.is_ok());
+        assert_eq!(after_remove, Err(Error::BatchFailed(2)));
+    }
+
+    #[test]
+    fn test_recursive_strip_and_verify() {
+        let dir = testing_png_dir("recursive-strip");
+        run_cli(&["encode", "-r", path_str(&dir), "ruSt", "batch message"]).unwrap();
+        let dry_run = run_cli(&["strip", "-r", path_str(&dir), "--dry-run"]);
+        let kept = run_cli(&["decode", "-r", path_str(&dir), "ruSt"]);
+        run_cli(&["strip", "-r", "-j", "2", path_str(&dir)]).unwrap();
+        let stripped = run_cli(&["decode", "-r", path_str(&dir), "ruSt"]);
+        // The testing files have no IDAT, so they fail verification
+        let unverified = run_cli(&["verify", "-r", "--jobs", "2", path_str(&dir)]);
+
+        for path in [dir.join("a.png"), dir.join("nested/b.PNG")] {
+            let mut png = read_png(&path, GlobalArgs::default()).unwrap();
+            png.insert_chunk(1, chunk_from_strings("IDAT", &zlib::deflate(&[0, 0, 0, 0, 0]))).unwrap();
+            png.save(&path).unwrap();
+        }
+        let verified = run_cli(&["verify", "-r", path_str(&dir)]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(dry_run.is_ok());
+        assert!(kept.is_ok());
+        assert_eq!(stripped, Err(Error::BatchFailed(2)));
+        assert_eq!(unverified, Err(Error::BatchFailed(2)));
+        assert!(verified.is_ok(), "{:?}", verified);
+        assert!(Cli::try_parse_from(["pngne", "strip", "-r", "dir", "-o", "out.png"]).is_err());
+        assert!(Cli::try_parse_from(["pngne", "verify", "-r", "dir", "--watch"]).is_err());
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("ruSt"), "\"ruSt\"");
+        assert_eq!(json_string("a \"quoted\" \\ path"), "\"a \\\"quoted\\\" \\\\ path\"");
+        assert_eq!(json_string("line\nbreak\u{1}"), "\"line\\nbreak\\u0001\"");
+    }
+
+    #[test]
+    fn test_print_json() {
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("ruSt", b"hi"),
+        ]);
+        let json = print_json(Path::new("dice.png"), &png);
+
+        assert!(json.starts_with("{\"file\":\"dice.png\",\"chunks\":[{\"index\":0,\"type\":\"IHDR\",\"length\":13,\"offset\":8,"));
+        assert!(json.contains("{\"index\":1,\"type\":\"ruSt\",\"length\":2,\"offset\":33,"));
+        assert!(json.contains(&format!("\"crc\":{},", png.as_slice()[1].crc())));
+        assert!(json.contains("\"critical\":false,\"public\":false,\"reserved_bit_valid\":true,\"safe_to_copy\":true}]}"));
+    }
+
+    #[test]
+    fn test_decode_json() {
+        let args = DecodeArgs {
+            file_path: PathBuf::from("dice.png"),
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            as_text: false,
+            decrypt: false,
+            identity: vec![],
+            auth_key: None,
+            id: None,
+            mode: Mode::Chunk,
+            out: None,
+            recursive: false,
+            jobs: 0,
+            watch: false,
+        };
+        assert_eq!(
+            decode_json(Path::new("dice.png"), &args, "hi \"there\""),
+            "{\"file\":\"dice.png\",\"chunk_type\":\"ruSt\",\"payload\":\"hi \\\"there\\\"\"}"
+        );
+    }
+
+    #[test]
+    fn test_unregistered_chunks() {
+        let bytes = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("ruSt", b"hidden"),
+            chunk_from_strings("tEXt", b"Title\0dice"),
+            chunk_from_strings("IEND", &[]),
+        ])
+        .as_bytes();
+        let mut parser = PngParser::new();
+        let found = unregistered_chunks(parser.parse(bytes.as_slice()).unwrap());
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(&found[0].chunk_type.to_string(), "ruSt");
+    }
+
+    #[test]
+    fn test_scan() {
+        let dir = testing_png_dir("scan");
+        run_cli(&["encode", path_str(&dir.join("a.png")), "ruSt", "hidden"]).unwrap();
+        fs::write(dir.join("broken.png"), b"not a png").unwrap();
+
+        let result = run_cli(&["scan", path_str(&dir)]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_repair() {
+        let path = testing_png_file("repair");
+        let mut bytes = fs::read(&path).unwrap();
+        // Last byte of the IHDR CRC
+        bytes[32] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let broken = read_png(&path, GlobalArgs::default());
+        run_cli(&["repair", path_str(&path)]).unwrap();
+        let repaired = read_png(&path, GlobalArgs::default());
+        fs::remove_file(&path).unwrap();
+
+        assert!(broken.is_err());
+        assert!(repaired.is_ok());
+    }
+
+    #[test]
+    fn test_repair_truncated() {
+        let path = testing_png_file("repair-truncated");
+        let mut bytes = fs::read(&path).unwrap();
+        // Cut off in the middle of IEND, after a chunk with a bad type
+        bytes.truncate(bytes.len() - 6);
+        for message in [b"gone", b"kept"] {
+            bytes.splice(33..33, Chunk::new(ChunkType::from_str("ruSt").unwrap(), message.to_vec()).as_bytes());
+        }
+        bytes[53] = b'1';
+        fs::write(&path, &bytes).unwrap();
+
+        let strict = run_cli(&["print", "--strict", path_str(&path)]);
+        let lenient = read_png(&path, GlobalArgs { lenient: true, ..GlobalArgs::default() });
+        run_cli(&["repair", path_str(&path)]).unwrap();
+        let repaired = read_png(&path, GlobalArgs::default());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(strict, Err(Error::ChunkTypeNotValid(*b"1uSt")));
+        assert_eq!(chunk_types(&lenient.unwrap()), vec!["IHDR", "ruSt"]);
+        assert_eq!(chunk_types(&repaired.unwrap()), vec!["IHDR", "ruSt"]);
+        assert!(Cli::try_parse_from(["pngne", "print", "a.png", "--strict", "--lenient"]).is_err());
+    }
+
+    #[test]
+    fn test_recover() {
+        let path = temp_path("recover");
+        let output = temp_path("recover-out");
+        // A 10x100 grayscale image, stored rather than deflated so that
+        // cutting the stream loses a known share of it
+        let compressed = zlib::deflate_level(&[0; 1100], 0);
+        let mut bytes = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0, 0, 0, 10, 0, 0, 0, 100, 8, 0, 0, 0, 0]),
+            chunk_from_strings("IDAT", &compressed[..600]),
+            chunk_from_strings("IDAT", &compressed[600..]),
+            chunk_from_strings("IEND", &[]),
+        ])
+        .as_bytes();
+        bytes.truncate(700);
+        fs::write(&path, &bytes).unwrap();
+
+        let dry_run = run_cli(&["recover", path_str(&path), "--dry-run"]);
+        run_cli(&["recover", path_str(&path), "--add-iend", "-o", path_str(&output)]).unwrap();
+        let recovered = read_png(&output, GlobalArgs::default()).unwrap();
+        let untouched = fs::read(&path).unwrap();
+
+        // Data after IEND isn't damage and is written back as it was
+        let mut trailing = Png::from_chunks(vec![chunk_from_strings("IHDR", &[0; 13]), chunk_from_strings("IEND", &[])]).as_bytes();
+        trailing.extend(b"\0\0\0\x05 twenty six appended bytes");
+        fs::write(&path, &trailing).unwrap();
+        run_cli(&["recover", path_str(&path), "-o", path_str(&output)]).unwrap();
+        let kept = fs::read(&output).unwrap();
+
+        fs::write(&path, b"nothing to see here").unwrap();
+        let nothing = run_cli(&["recover", path_str(&path)]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        assert!(dry_run.is_ok());
+        assert_eq!(untouched, bytes);
+        assert_eq!(chunk_types(&recovered), vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(image_data_recovered(&recovered), Some((593, 1100)));
+        assert_eq!(describe_image_data(Some((593, 1100))), "image data: 593 of 1100 bytes recovered, 46.1% lost");
+        assert_eq!(nothing, Err(Error::ChunkNotFound));
+        assert_eq!(kept, trailing);
+    }
+
+    #[test]
+    fn test_trailing() {
+        let path = testing_png_file("trailing");
+        let extracted = temp_path("trailing-extracted");
+        let stripped = temp_path("trailing-stripped");
+        let png = fs::read(&path).unwrap();
+        let mut bytes = png.clone();
+        bytes.extend(b"PK\x03\x04 a zip after IEND");
+        fs::write(&path, &bytes).unwrap();
+
+        let verified = run_cli(&["verify", path_str(&path)]);
+        run_cli(&["trailing", path_str(&path), "--extract", path_str(&extracted)]).unwrap();
+        run_cli(&["trailing", path_str(&path), "--strip", "-o", path_str(&stripped)]).unwrap();
+        let copy = fs::read(&stripped).unwrap();
+        let untouched = fs::read(&path).unwrap();
+        run_cli(&["trailing", path_str(&path), "--strip"]).unwrap();
+        let in_place = fs::read(&path).unwrap();
+        let data = fs::read(&extracted).unwrap();
+        for file in [&path, &extracted, &stripped] {
+            fs::remove_file(file).unwrap();
+        }
+
+        // Only the missing IDAT fails verification
+        assert_eq!(verified, Err(Error::VerifyFailed(1)));
+        assert_eq!(data, b"PK\x03\x04 a zip after IEND");
+        assert_eq!(copy, png);
+        assert_eq!(untouched, bytes);
+        assert_eq!(in_place, png);
+        assert!(Cli::try_parse_from(["pngne", "trailing", "a.png", "-o", "b.png"]).is_err());
+    }
+
+    #[test]
+    fn test_uncrush() {
+        let path = temp_path("uncrush");
+        let output = temp_path("uncrush-out");
+        let ihdr = Ihdr { width: 1, height: 1, bit_depth: 8, color_type: 6, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let zlib = zlib::deflate(&[0, 0x40, 0, 0, 0x80]);
+        let crushed = Png::from_chunks(vec![
+            chunk_from_strings("CgBI", &[0x50, 0, 0x20, 0x06]),
+            Chunk::from(ihdr),
+            chunk_from_strings("IDAT", &zlib[2..zlib.len() - 4]),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        crushed.save(&path).unwrap();
+
+        let verified = run_cli(&["verify", path_str(&path)]);
+        run_cli(&["uncrush", path_str(&path), "-o", path_str(&output)]).unwrap();
+        let uncrushed = read_png(&output, GlobalArgs::default()).unwrap();
+        let again = run_cli(&["uncrush", path_str(&output)]);
+        run_cli(&["uncrush", path_str(&path)]).unwrap();
+        let in_place = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        assert_eq!(verified, Err(Error::VerifyFailed(1)));
+        assert_eq!(chunk_types(&uncrushed), vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(uncrushed.decode_pixels().unwrap().data, vec![0, 0, 0x80, 0x80]);
+        assert_eq!(in_place.as_bytes(), uncrushed.as_bytes());
+        assert_eq!(again, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut png = Png::from_chunks(vec![chunk_from_strings("IHDR", &[0; 13]), chunk_from_strings("IEND", &[])]);
+        png.append_chunk(TextChunk::new("Title", "plain").unwrap().into());
+        png.append_chunk(CompressedText::new("Comment", "first line\nsecond line").unwrap().into());
+        png.append_chunk(InternationalText::new("Author", "de", "Autor", "international", true).unwrap().into());
+        png.append_chunk(InternationalText::new("Source", "", "", "untagged", false).unwrap().into());
+        let mut broken = Chunk::from(CompressedText::new("Warning", "lost").unwrap()).data().to_vec();
+        broken.truncate(broken.len() - 6);
+        png.append_chunk(chunk_from_strings("zTXt", &broken));
+        png.append_chunk(chunk_from_strings("tEXt", b"no separator"));
+
+        let entries = metadata_entries(&png);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(metadata_text(&entries[..4]), "Title: plain\n\
+                                                   Comment: first line\n  second line\n\
+                                                   Author [de, Autor]: international\n\
+                                                   Source: untagged");
+        assert!(metadata_text(&entries[4..]).starts_with("Warning: (zTXt can't be read: "));
+        assert_eq!(
+            metadata_json(Path::new("a.png"), &entries[2..3]),
+            "{\"file\":\"a.png\",\"entries\":[{\"chunk_type\":\"iTXt\",\"keyword\":\"Author\",\"language\":\"de\",\
+             \"translated_keyword\":\"Autor\",\"value\":\"international\",\"error\":null}]}"
+        );
+        assert!(metadata_json(Path::new("a.png"), &entries[..1]).contains("\"language\":null,\"translated_keyword\":null"));
+
+        let path = temp_path("metadata");
+        write_png(&path, &png).unwrap();
+        let shown = run_cli(&["metadata", path_str(&path)]);
+        let empty = run_cli(&["metadata", path_str(&testing_png_file("metadata-empty"))]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(temp_path("metadata-empty")).unwrap();
+
+        assert!(shown.is_ok());
+        assert!(empty.is_ok());
+    }
+
+    #[test]
+    fn test_set_and_get_text() {
+        let path = testing_png_file("set-text");
+        let output = temp_path("set-text-out");
+        let mut png = read_png(&path, GlobalArgs::default()).unwrap();
+        png.append_chunk(TextChunk::new("Author", "first").unwrap().into());
+        png.append_chunk(CompressedText::new("Author", "second").unwrap().into());
+        write_png(&path, &png).unwrap();
+
+        run_cli(&["set-text", path_str(&path), "Author", "Jane"]).unwrap();
+        run_cli(&["set-text", path_str(&path), "Title", "日本語", "-o", path_str(&output)]).unwrap();
+        let edited = read_png(&path, GlobalArgs::default()).unwrap();
+        let copy = read_png(&output, GlobalArgs::default()).unwrap();
+        let shown = run_cli(&["get-text", path_str(&output), "Title"]);
+        let missing = run_cli(&["get-text", path_str(&path), "Title"]);
+        let invalid = run_cli(&["set-text", path_str(&path), " Author", "Jane"]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        assert_eq!(chunk_types(&edited), vec!["IHDR", "tEXt", "IEND"]);
+        assert_eq!(find_text(&edited, "Author"), Some(Ok("Jane".to_string())));
+        assert_eq!(chunk_types(&copy), vec!["IHDR", "tEXt", "iTXt", "IEND"]);
+        assert_eq!(find_text(&copy, "Title"), Some(Ok("日本語".to_string())));
+        assert!(shown.is_ok());
+        assert_eq!(missing, Err(Error::KeywordNotFound("Title".to_string())));
+        assert_eq!(ExitReason::from(&missing.unwrap_err()), ExitReason::ChunkNotFound);
+        assert!(matches!(invalid, Err(Error::InvalidChunkData(_))));
+    }
+
+    #[test]
+    fn test_strip() {
+        let path = testing_png_file("strip");
+        for (chunk_type, message) in [("ruSt", "hidden"), ("tEXt", "Title\0dice"), ("gAMA", "gama")] {
+            run_cli(&["encode", path_str(&path), chunk_type, message]).unwrap();
+        }
+        run_cli(&["strip", path_str(&path), "--keep", "gAMA"]).unwrap();
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let types: Vec<String> = png.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "gAMA", "IEND"]);
+    }
+
+    #[test]
+    fn test_strip_streamed() {
+        let path = testing_png_file("strip-streamed");
+        let out_path = temp_path("strip-streamed-out");
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden"]).unwrap();
+        run_cli(&["strip", path_str(&path), "--output", path_str(&out_path)]).unwrap();
+
+        let mut expected = read_png(&path, GlobalArgs::default()).unwrap();
+        let streamed = fs::read(&out_path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&out_path).unwrap();
+
+        expected.remove_chunks_where(|chunk| !chunk.chunk_type().is_critical());
+        assert_eq!(streamed, expected.as_bytes());
+    }
+
+    #[test]
+    fn test_strip_streamed_safely() {
+        let path = testing_png_file("strip-safely");
+        let out_path = temp_path("strip-safely-out");
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden"]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        // The same file by another name is stripped in place, not streamed
+        // over itself
+        let same = path.parent().unwrap().join(".").join(path.file_name().unwrap());
+        run_cli(&["strip", path_str(&path), "-o", path_str(&same)]).unwrap();
+        let stripped = read_png(&path, GlobalArgs::default()).unwrap();
+
+        // A failed stream leaves the output as it was
+        fs::write(&path, &bytes[..bytes.len() - 5]).unwrap();
+        fs::write(&out_path, b"untouched").unwrap();
+        let truncated = run_cli(&["strip", path_str(&path), "-o", path_str(&out_path)]);
+        let out = fs::read(&out_path).unwrap();
+
+        // Limits and --lenient apply to the stream
+        fs::write(&path, &bytes).unwrap();
+        let limited = run_cli(&["--max-chunks", "2", "strip", path_str(&path), "-o", path_str(&out_path)]);
+        let mut bad_crc = bytes.clone();
+        bad_crc[32] ^= 0xff;
+        fs::write(&path, &bad_crc).unwrap();
+        let strict = run_cli(&["strip", path_str(&path), "-o", path_str(&out_path)]);
+        let lenient = run_cli(&["--lenient", "strip", path_str(&path), "-o", path_str(&out_path)]);
+        let repaired = read_png(&out_path, GlobalArgs::default()).unwrap();
+        let leftovers = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains("strip-safely-out.png."))
+            .count();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&out_path).unwrap();
+
+        assert_eq!(chunk_types(&stripped), vec!["IHDR", "IEND"]);
+        assert!(matches!(truncated, Err(Error::TruncatedChunk { .. })));
+        assert_eq!(out, b"untouched");
+        assert!(matches!(limited, Err(Error::LimitExceeded { .. })));
+        assert!(matches!(strict, Err(Error::CrcMismatch(_, _))));
+        assert_eq!(lenient, Ok(()));
+        assert_eq!(chunk_types(&repaired), vec!["IHDR", "IEND"]);
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn test_dry_run_writes_nothing() {
+        let path = testing_png_file("dry-run");
+        let before = fs::read(&path).unwrap();
+        run_cli(&["encode", path_str(&path), "ruSt", "hidden", "--dry-run"]).unwrap();
+        run_cli(&["strip", path_str(&path), "--dry-run"]).unwrap();
+        run_cli(&["repair", path_str(&path), "--dry-run"]).unwrap();
+        let missing = run_cli(&["remove", path_str(&path), "ruSt", "--dry-run"]);
+        let after = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(before, after);
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_plan_report() {
+        let mut plan = Plan::new(Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("tIME", &[0; 7]),
+            chunk_from_strings("IEND", &[]),
+        ]));
+        plan.append(chunk_from_strings("ruSt", b"hidden"));
+        plan.remove("tIME").unwrap();
+
+        assert_eq!(plan.report(Path::new("a.png"), GlobalArgs::default()), [
+            "+ ruSt (6 bytes)",
+            "- tIME (7 bytes)",
+            "would write a.png (1 added, 1 removed, 0 CRC(s) fixed, 64 -> 63 bytes)",
+        ].join("\n"));
+        assert_eq!(
+            plan.json(Path::new("a.png")),
+            "{\"file\":\"a.png\",\"dry_run\":true,\"changes\":[\"+ ruSt (6 bytes)\",\"- tIME (7 bytes)\"],\"size_before\":64,\"size_after\":63}"
+        );
+    }
+
+    #[test]
+    fn test_extract() {
+        let path = testing_png_file("extract");
+        let out = temp_path("extract-payload");
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("ruSt", &[0, 159, 146, 150, 255]),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        write_png(&path, &png).unwrap();
+
+        run_cli(&["extract", path_str(&path), "ruSt", "--out", path_str(&out)]).unwrap();
+        let missing = run_cli(&["extract", path_str(&path), "nOpe", "--out", path_str(&out)]);
+        let extracted = fs::read(&out).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&out).unwrap();
+
+        assert_eq!(extracted, vec![0, 159, 146, 150, 255]);
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().map(|c| c.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_insert() {
+        let path = testing_png_file("insert");
+        let data = temp_path("insert-data");
+        fs::write(&data, [1, 2, 3]).unwrap();
+
+        run_cli(&["insert", path_str(&path), "aaAa", "--data-file", path_str(&data)]).unwrap();
+        run_cli(&["insert", path_str(&path), "bbBb", "--data-file", path_str(&data), "--after", "IHDR"]).unwrap();
+        run_cli(&["insert", path_str(&path), "ccCc", "--data-file", path_str(&data), "--before", "IHDR"]).unwrap();
+        run_cli(&["insert", path_str(&path), "ddDd", "--data-file", path_str(&data), "--index", "5"]).unwrap();
+        let out_of_range = run_cli(&["insert", path_str(&path), "eeEe", "--data-file", path_str(&data), "--index", "9"]);
+        let missing = run_cli(&["insert", path_str(&path), "eeEe", "--data-file", path_str(&data), "--after", "PLTE"]);
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&data).unwrap();
+
+        // A chunk after IEND is just more bytes to a parser
+        assert_eq!(chunk_types(&png), vec!["ccCc", "IHDR", "bbBb", "aaAa", "IEND"]);
+        assert_eq!(png.as_slice()[3].data(), &[1, 2, 3]);
+        assert_eq!(png.trailing_data(), chunk_from_strings("ddDd", &[1, 2, 3]).as_bytes());
+        assert_eq!(out_of_range, Err(Error::IndexOutOfRange(9)));
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_replace() {
+        let path = testing_png_file("replace");
+        encode_twice(&path);
+        run_cli(&["insert", path_str(&path), "ruSt", "--data-file", path_str(&path), "--index", "1"]).unwrap();
+        run_cli(&["replace", path_str(&path), "ruSt", "replaced"]).unwrap();
+        let missing = run_cli(&["replace", path_str(&path), "nOpe", "replaced"]);
+
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunk_types(&png), vec!["IHDR", "ruSt", "ruSt", "ruSt", "IEND"]);
+        assert_eq!(&png.as_slice()[1].data_as_string().unwrap(), "replaced");
+        assert_eq!(&png.as_slice()[2].data_as_string().unwrap(), "first");
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_touch() {
+        let path = testing_png_file("touch");
+        run_cli(&["touch", path_str(&path), "--time", "2024-02-29T13:05:09Z"]).unwrap();
+        run_cli(&["touch", path_str(&path), "--time", "2025-01-01T00:00:00Z"]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        run_cli(&["touch", path_str(&path)]).unwrap();
+        let now = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunk_types(&png), vec!["IHDR", "tIME", "IEND"]);
+        let time = LastModified::try_from(png.chunk_by_type("tIME").unwrap()).unwrap();
+        assert_eq!(time.to_string(), "2025-01-01T00:00:00Z");
+        assert!(LastModified::try_from(now.chunk_by_type("tIME").unwrap()).unwrap() > time);
+    }
+
+    #[test]
+    fn test_set_dpi() {
+        let path = temp_path("set-dpi");
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("IDAT", &[]),
+            chunk_from_strings("IEND", &[]),
+        ])
+        .save(&path)
+        .unwrap();
+
+        run_cli(&["set-dpi", path_str(&path), "72"]).unwrap();
+        run_cli(&["set-dpi", path_str(&path), "300", "150"]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunk_types(&png), vec!["IHDR", "pHYs", "IDAT", "IEND"]);
+        let dims = PhysicalDims::try_from(png.chunk_by_type("pHYs").unwrap()).unwrap();
+        assert_eq!((dims.x, dims.y), (11811, 5906));
+    }
+
+    #[test]
+    fn test_apng_split() {
+        let path = temp_path("apng");
+        let dir = std::env::temp_dir().join(format!("pngne-{}-frames", std::process::id()));
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0, 0, 0, 3, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        png.append_chunk(AnimationControl { num_frames: 1, num_plays: 1 }.into());
+        png.append_chunk(chunk_from_strings("IDAT", b"default"));
+        png.append_chunk(chunk_from_strings("fcTL", &[0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 10, 0, 0]));
+        png.append_chunk(chunk_from_strings("fdAT", b"\0\0\0\x01frame"));
+        png.save(&path).unwrap();
+
+        run_cli(&["apng", "split", path_str(&path), "--out-dir", path_str(&dir)]).unwrap();
+        let frame = read_png(&dir.join("frame_000.png"), GlobalArgs::default()).unwrap();
+        let still = testing_png_file("apng-still");
+        let not_animated = run_cli(&["apng", "split", path_str(&still), "-o", path_str(&dir)]);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&still).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(chunk_types(&frame), vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(frame.ihdr().unwrap().width, 2);
+        assert_eq!(frame.chunk_by_type("IDAT").unwrap().data(), b"frame");
+        assert_eq!(not_animated, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_recompress() {
+        let path = temp_path("recompress");
+        let pixels: Vec<u8> = (0..64 * 65).map(|i| if i % 65 == 0 { 0 } else { (i / 65) as u8 }).collect();
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0, 0, 0, 64, 0, 0, 0, 64, 8, 0, 0, 0, 0]),
+            chunk_from_strings("tEXt", b"Comment\0kept"),
+            chunk_from_strings("IDAT", &zlib::deflate_level(&pixels, 0)),
+            chunk_from_strings("IEND", &[]),
+        ])
+        .save(&path)
+        .unwrap();
+        let original = read_png(&path, GlobalArgs::default()).unwrap();
+
+        run_cli(&["recompress", path_str(&path), "--level", "9"]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunk_types(&png), vec!["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(png.as_slice()[1].as_bytes(), original.as_slice()[1].as_bytes());
+        assert!(png.compressed_image_data().len() < original.compressed_image_data().len());
+        assert_eq!(png.image_data().unwrap(), pixels);
+        assert!(Cli::try_parse_from(["pngne", "recompress", "a.png", "--level", "10"]).is_err());
+    }
+
+    #[test]
+    fn test_idat_chunk_size() {
+        let path = temp_path("idat-chunk-size");
+        let compressed = zlib::deflate_level(&[0; 1000], 0);
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0, 0, 0, 10, 0, 0, 0, 99, 8, 0, 0, 0, 0]),
+            chunk_from_strings("IDAT", &compressed),
+            chunk_from_strings("IEND", &[]),
+        ])
+        .save(&path)
+        .unwrap();
+
+        run_cli(&["touch", path_str(&path), "--idat-chunk-size", "256"]).unwrap();
+        let png = read_png(&path, GlobalArgs::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(chunk_types(&png), vec!["IHDR", "IDAT", "IDAT", "IDAT", "IDAT", "tIME", "IEND"]);
+        assert_eq!(png.compressed_image_data(), compressed);
+        assert!(Cli::try_parse_from(["pngne", "touch", "a.png", "--idat-chunk-size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_limits() {
+        let path = testing_png_file("limits");
+        let within = run_cli(&["print", path_str(&path), "--max-chunks", "2", "--max-chunk-length", "13"]);
+        let too_many = run_cli(&["print", path_str(&path), "--max-chunks", "1"]);
+        let too_long = run_cli(&["decode", path_str(&path), "ruSt", "--max-chunk-length", "4"]);
+        let indexed = run_cli(&["extract", path_str(&path), "IEND", "--out", "-", "--max-chunks", "1"]);
+        let salvaged = run_cli(&["recover", path_str(&path), "--dry-run", "--max-chunks", "1"]);
+        let verified = run_cli(&["verify", path_str(&path), "--max-chunks", "1"]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(within.is_ok());
+        assert!(matches!(too_many, Err(Error::LimitExceeded { limit: "chunk count", max: 1, .. })));
+        assert!(matches!(too_long, Err(Error::LimitExceeded { limit: "chunk length", max: 4, .. })));
+        assert!(matches!(indexed, Err(Error::LimitExceeded { limit: "chunk count", .. })));
+        assert!(matches!(salvaged, Err(Error::LimitExceeded { limit: "chunk count", max: 1, .. })));
+        assert_eq!(verified.err(), Some(Error::VerifyFailed(1)));
+        assert_eq!(ExitReason::from(&too_many.unwrap_err()), ExitReason::ParseFailure);
+        assert_eq!(limit_flag("chunk count"), "--max-chunks");
+        assert_eq!(limit_flag("inflate ratio"), "--max-inflate-ratio");
+
+        // Without --max-chunks the default cap applies
+        let cli = Cli::try_parse_from(["pngne", "print", "a.png"]).unwrap();
+        assert_eq!(cli.global.limits(), pngne::limits::Limits::DEFAULT);
+        let cli = Cli::try_parse_from(["pngne", "print", "a.png", "--max-chunks", "200000"]).unwrap();
+        assert_eq!(cli.global.limits().max_chunks, 200_000);
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        let png_path = temp_path("raw.png");
+        let raw_path = temp_path("raw.rgba");
+        let rebuilt_path = temp_path("raw-rebuilt.png");
+        let pixels: Vec<u8> = (0..3 * 2 * 4).map(|i| (i * 11) as u8).collect();
+        Png::from_image_data(&ImageData {
+            ihdr: Ihdr { width: 3, height: 2, bit_depth: 8, color_type: 6, compression_method: 0, filter_method: 0, interlace_method: 0 },
+            data: pixels.clone(),
+        })
+        .unwrap()
+        .save(&png_path)
+        .unwrap();
+
+        run_cli(&["to-raw", path_str(&png_path), "--out", path_str(&raw_path)]).unwrap();
+        let raw = fs::read(&raw_path).unwrap();
+        run_cli(&["from-raw", path_str(&raw_path), "--width", "3", "--height", "2", "--out", path_str(&rebuilt_path)]).unwrap();
+        let rebuilt = read_png(&rebuilt_path, GlobalArgs::default()).unwrap();
+        let wrong_size = run_cli(&["from-raw", path_str(&raw_path), "--width", "2", "--height", "2", "--out", path_str(&rebuilt_path)]);
+        for path in [&png_path, &raw_path, &rebuilt_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(raw, pixels);
+        assert_eq!(chunk_types(&rebuilt), vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(rebuilt.decode_rgba8().unwrap(), pixels);
+        assert!(matches!(wrong_size, Err(Error::InvalidChunkData(_))));
+    }
+
+    #[test]
+    fn test_diff_chunks() {
+        let a = vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("tEXt", b"one"),
+            chunk_from_strings("tEXt", b"two"),
+            chunk_from_strings("ruSt", b"gone"),
+            chunk_from_strings("IEND", &[]),
+        ];
+        let b = vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("tEXt", b"one"),
+            chunk_from_strings("tEXt", b"three"),
+            chunk_from_strings("tEXt", b"four"),
+            chunk_from_strings("IEND", &[]),
+        ];
+        let diffs: Vec<String> = diff_chunks(&a, &b).iter().map(describe_diff).collect();
+
+        assert_eq!(diffs, vec![
+            format!("~ tEXt #1 length 3 -> 5 (+2), crc {:08x} -> {:08x}", a[2].crc(), b[2].crc()),
+            "- ruSt #0 (4 bytes)".to_string(),
+            "+ tEXt #2 (4 bytes)".to_string(),
+        ]);
+        assert!(diff_chunks(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn test_diff_json() {
+        let a = vec![chunk_from_strings("ruSt", b"gone")];
+        let json = diff_json(&diff_chunks(&a, &[]));
+        let expected = format!(
+            "{{\"only_in_a\":[{{\"type\":\"ruSt\",\"occurrence\":0,\"length\":4,\"crc\":{}}}],\"only_in_b\":[],\"changed\":[]}}",
+            a[0].crc()
+        );
+        assert_eq!(json, expected);
+    }
+
+    #[test]
+    fn test_chunk_region() {
+        let chunks = vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("meTa", b"a"),
+            chunk_from_strings("PLTE", &[0; 3]),
+            chunk_from_strings("meTa", b"b"),
+            chunk_from_strings("IDAT", &[]),
+            chunk_from_strings("meTa", b"c"),
+            chunk_from_strings("IEND", &[]),
+        ];
+        assert_eq!(chunk_region(&chunks, 1), Region::BeforePlte);
+        assert_eq!(chunk_region(&chunks, 3), Region::BeforeIdat);
+        assert_eq!(chunk_region(&chunks, 5), Region::AfterIdat);
+
+        let without_plte = vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("meTa", b"a"),
+            chunk_from_strings("IDAT", &[]),
+        ];
+        assert_eq!(chunk_region(&without_plte, 1), Region::BeforeIdat);
+    }
+
+    #[test]
+    fn test_merge_chunks() {
+        let from = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("meTa", b"early"),
+            chunk_from_strings("meTA", b"unsafe"),
+            chunk_from_strings("IDAT", &[1]),
+            chunk_from_strings("meTa", b"late"),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        let mut into = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("IDAT", &[2]),
+            chunk_from_strings("IDAT", &[3]),
+            chunk_from_strings("meTa", b"late"),
+            chunk_from_strings("IEND", &[]),
+        ]);
+
+        let copied = merge_chunks(&from, &mut into).unwrap();
+        let data: Vec<&[u8]> = into.chunks().map(|c| c.data()).collect();
+
+        assert_eq!(copied, 1);
+        assert_eq!(chunk_types(&into), vec!["IHDR", "meTa", "IDAT", "IDAT", "meTa", "IEND"]);
+        assert_eq!(data[1], b"early");
+    }
+
+    #[test]
+    fn test_info_text() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0, 0, 1, 0, 0, 0, 0, 64, 8, 3, 0, 0, 1]),
+            chunk_from_strings("gAMA", &[0, 0, 177, 143]),
+            chunk_from_strings("sRGB", &[0]),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        png.append_chunk(TextChunk::new("Title", "Dice").unwrap().into());
+        let info = Info::new(&png, 1234).unwrap();
+        let text = info_text(Path::new("dice.png"), &info);
+
+        assert!(text.contains("File:        dice.png (1234 bytes)"));
+        assert!(text.contains("Dimensions:  256 x 64"));
+        assert!(text.contains("Color type:  3 (indexed)"));
+        assert!(text.contains("Interlace:   1 (Adam7)"));
+        assert!(text.contains("Chunks:      5"));
+        assert!(text.contains("Gamma:       0.45455"));
+        assert!(text.contains("sRGB:        perceptual"));
+        assert!(text.contains("Text:        Title: Dice"));
+        assert!(!text.contains("ICC profile"));
+        assert!(!text.contains("Animation"));
+
+        let json = info_json(Path::new("dice.png"), &info);
+        assert!(json.contains("\"gamma\":0.45455,\"chromaticities\":null,\"srgb\":\"perceptual\",\"icc_profile\":null,\"exif\":null"));
+    }
+
+    #[test]
+    fn test_info_animation() {
+        let mut png = Png::from_chunks(vec![chunk_from_strings("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]), chunk_from_strings("IEND", &[])]);
+        png.append_chunk(AnimationControl { num_frames: 3, num_plays: 0 }.into());
+        let info = Info::new(&png, 100).unwrap();
+
+        assert!(info_text(Path::new("anim.png"), &info).contains("Animation:   animated, 3 frames, infinite plays"));
+        assert!(info_json(Path::new("anim.png"), &info).contains("\"animation\":{\"frames\":3,\"plays\":0}"));
+    }
+
+    #[test]
+    fn test_info_exif() {
+        let mut exif = b"MM\0*\0\0\0\x08\0\x01".to_vec();
+        exif.extend([0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 3, 0, 0]);
+        exif.extend([0; 4]);
+        let mut png = Png::from_chunks(vec![chunk_from_strings("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]), chunk_from_strings("IEND", &[])]);
+        png.append_chunk(Exif::new(exif).unwrap().into());
+
+        let info = Info::new(&png, 100).unwrap();
+        assert!(!info_text(Path::new("exif.png"), &info).contains("Orientation"));
+
+        let text = info_text(Path::new("exif.png"), &info.with_exif(&png));
+        assert!(text.contains("Orientation: 3"));
+        assert!(text.contains("GPS:         none"));
+        assert!(!text.contains("Camera"));
+    }
+
+    #[test]
+    fn test_text_entries() {
+        let mut png = Png::from_chunks(vec![chunk_from_strings("IHDR", &[0; 13]), chunk_from_strings("IEND", &[])]);
+        png.append_chunk(TextChunk::new("Title", "plain").unwrap().into());
+        png.append_chunk(CompressedText::new("Comment", "compressed").unwrap().into());
+        png.append_chunk(InternationalText::new("Author", "en", "Author", "international", true).unwrap().into());
+
+        assert_eq!(text_entries(&png), vec![
+            ("Title".to_string(), "plain".to_string()),
+            ("Comment".to_string(), "compressed".to_string()),
+            ("Author".to_string(), "international".to_string()),
+        ]);
+        assert_eq!(find_text(&png, "Author"), Some(Ok("international".to_string())));
+        assert_eq!(find_text(&png, "Missing"), None);
+    }
+
+    #[test]
+    fn test_info() {
+        let path = testing_png_file("info");
+        let result = run_cli(&["info", path_str(&path)]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_verify() {
+        let path = testing_png_file("verify");
+        let missing_idat = run_cli(&["verify", path_str(&path)]);
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+        let bad_crc = run_cli(&["verify", path_str(&path)]);
+        let parallel = run_cli(&["verify", "--jobs", "3", path_str(&path)]);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(missing_idat, Err(Error::VerifyFailed(1)));
+        assert_eq!(parallel, Err(Error::VerifyFailed(2)));
+        assert_eq!(bad_crc, Err(Error::VerifyFailed(2)));
+    }
+
+    #[test]
+    fn test_verify_deny() {
+        let path = temp_path("verify-deny");
+        let png = Png::from_chunks(vec![
+            Chunk::from(Ihdr { width: 1, height: 1, bit_depth: 8, color_type: 0, compression_method: 0, filter_method: 0, interlace_method: 0 }),
+            Chunk::new(ChunkType::IDAT, zlib::deflate(&[0, 0])),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        let mut bytes = png.as_bytes();
+        bytes.extend(b"PK\x03\x04");
+        fs::write(&path, &bytes).unwrap();
+
+        let allowed = run_cli(&["verify", path_str(&path)]);
+        let denied = run_cli(&["verify", path_str(&path), "--deny", "duplicate-keyword,trailing-data"]);
+        let printed = run_cli(&["print", path_str(&path), "--deny", "trailing-data"]);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(allowed, Ok(()));
+        assert_eq!(denied, Err(Error::VerifyFailed(1)));
+        assert_eq!(printed, Err(Error::Denied(1)));
+        assert_eq!(ExitReason::from(&Error::Denied(1)), ExitReason::VerifyFailed);
+        assert!(Cli::try_parse_from(["pngne", "verify", "a.png", "--deny", "trailing"]).is_err());
+        assert!(Cli::try_parse_from(["pngne", "print", "a.png", "--deny", "cgbi", "--format", "{type}"]).is_err());
+    }
+
+    #[test]
+    fn test_verify_json() {
+        let mut report = Report { chunk_count: 3, diagnostics: Default::default() };
+        report.diagnostics.push(Diagnostic::error(diagnostics::DUPLICATE_CHUNK, 8, "duplicate IHDR chunk").in_chunk(0));
+        report.diagnostics.push(Diagnostic::warning(diagnostics::TRAILING_DATA, 70, "4 bytes after IEND"));
+
+        assert_eq!(
+            verify_json(Path::new("a.png"), &report),
+            "{\"file\":\"a.png\",\"ok\":false,\"chunk_count\":3,\"diagnostics\":[\
+             {\"code\":\"duplicate-chunk\",\"severity\":\"error\",\"offset\":8,\"chunk\":0,\"message\":\"duplicate IHDR chunk\"},\
+             {\"code\":\"trailing-data\",\"severity\":\"warning\",\"offset\":70,\"chunk\":null,\"message\":\"4 bytes after IEND\"}]}"
+        );
+    }
+
+    #[test]
+    fn test_hexdump_lines() {
+        let data: Vec<u8> = (0x3e..0x52).collect();
+        let lines = hexdump_lines(&data, 32);
+
+        assert_eq!(lines, vec![
+            "00000020  3e 3f 40 41 42 43 44 45 46 47 48 49 4a 4b 4c 4d  |>?@ABCDEFGHIJKLM|",
+            "00000030  4e 4f 50 51                                      |NOPQ|",
+        ]);
+        assert_eq!(hexdump_lines(&[0, b'a', 0x7f], 0), vec![
+            "00000000  00 61 7f                                         |.a.|",
+        ]);
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let path = testing_png_file("hexdump");
+        let window = run_cli(&["hexdump", path_str(&path), "IHDR", "--offset", "4", "--length", "4"]);
+        let past_end = run_cli(&["hexdump", path_str(&path), "IHDR", "--offset", "14"]);
+        let missing = run_cli(&["hexdump", path_str(&path), "tEXt"]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(window.is_ok());
+        assert_eq!(past_end, Err(Error::IndexOutOfRange(14)));
+        assert_eq!(missing, Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_completions() {
+        use clap_complete::Shell;
+
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut out = vec![];
+            completions(CompletionsArgs { shell }, &mut out).unwrap();
+            let script = String::from_utf8(out).unwrap();
+
+            assert!(script.contains("hexdump"), "{:?} script lacks subcommands", shell);
+            assert!(script.contains("recursive"), "{:?} script lacks flags", shell);
+        }
+    }
+
+    #[test]
+    fn test_touches() {
+        use notify::event::{CreateKind, ModifyKind, RemoveKind};
+
+        let path = Path::new("images/dice.png");
+        let event = |kind, name: &str| Event::new(kind).add_path(PathBuf::from("/home/user/images").join(name));
+
+        assert!(touches(&event(EventKind::Modify(ModifyKind::Any), "dice.png"), path));
+        assert!(touches(&event(EventKind::Create(CreateKind::File), "dice.png"), path));
+        assert!(!touches(&event(EventKind::Modify(ModifyKind::Any), "other.png"), path));
+        assert!(!touches(&event(EventKind::Remove(RemoveKind::File), "dice.png"), path));
+    }
+
+    #[test]
+    fn test_exit_reason() {
+        assert_eq!(ExitReason::Success.code(), 0);
+        assert_eq!(ExitReason::from(&Error::InvalidSignature([0; 8])).code(), 2);
+        assert_eq!(ExitReason::from(&Error::CrcMismatch(1, 2)).code(), 3);
+        assert_eq!(ExitReason::from(&Error::ChunkNotFound).code(), 4);
+        assert_eq!(ExitReason::from(&Error::Io("gone".to_string())).code(), 5);
+        assert_eq!(ExitReason::from(&Error::VerifyFailed(2)).code(), 6);
+        assert_eq!(ExitReason::from(&Error::BatchFailed(1)).code(), 7);
+    }
+
+    #[test]
+    fn test_execute() {
+        let cli = |list: &[&str]| Cli::try_parse_from(std::iter::once("pngne").chain(list.iter().copied())).unwrap();
+        let path = testing_png_file("execute");
+
+        let found = execute(cli(&["print", path_str(&path)]));
+        let missing = execute(cli(&["decode", path_str(&path), "ruSt"]));
+        fs::remove_file(&path).unwrap();
+        let unreadable = execute(cli(&["print", path_str(&path)]));
+
+        assert_eq!(found, ExitReason::Success);
+        assert_eq!(missing, ExitReason::ChunkNotFound);
+        assert_eq!(unreadable, ExitReason::Io);
+    }
+
+    fn grep_args(pattern: &str, fixed_strings: bool, hex: bool) -> GrepArgs {
+        GrepArgs { pattern: pattern.to_string(), path: PathBuf::new(), fixed_strings, hex, chunk_type: None }
+    }
+
+    #[test]
+    fn test_grep_regex() {
+        assert!(grep_regex(&grep_args("a.c", false, false)).unwrap().is_match(b"abc"));
+        assert!(!grep_regex(&grep_args("a.c", true, false)).unwrap().is_match(b"abc"));
+        assert!(grep_regex(&grep_args("de ad ff", false, true)).unwrap().is_match(&[0, 0xde, 0xad, 0xff]));
+        assert!(matches!(grep_regex(&grep_args("abc", false, true)), Err(Error::InvalidPattern(_))));
+        assert!(matches!(grep_regex(&grep_args("zz", false, true)), Err(Error::InvalidPattern(_))));
+        assert!(matches!(grep_regex(&grep_args("(", false, false)), Err(Error::InvalidPattern(_))));
+    }
+
+    #[test]
+    fn test_grep_png() {
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("tEXt", b"Comment\0secret marker"),
+            chunk_from_strings("ruSt", b"marker one, marker two"),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        let regex = Regex::new("marker").unwrap();
+
+        let found: Vec<(usize, String, usize)> = grep_png(&png, &regex, None)
+            .into_iter()
+            .map(|m| (m.index, m.chunk_type, m.offset))
+            .collect();
+        assert_eq!(found, vec![
+            (1, "tEXt".to_string(), 15),
+            (2, "ruSt".to_string(), 0),
+            (2, "ruSt".to_string(), 12),
+        ]);
+
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        assert_eq!(grep_png(&png, &regex, Some(&rust)).len(), 2);
+    }
+
+    #[test]
+    fn test_grep_preview() {
+        assert_eq!(grep_preview(b"a\0b"), "a\\x00b");
+        assert_eq!(grep_preview(&[b'x'; 50]), format!("{}...", "x".repeat(48)));
+    }
+
+    #[test]
+    fn test_grep() {
+        let dir = testing_png_dir("grep");
+        run_cli(&["encode", "-r", path_str(&dir), "ruSt", "needle"]).unwrap();
+        let result = run_cli(&["grep", "need.e", path_str(&dir)]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_is_stdio() {
+        assert!(is_stdio(Path::new("-")));
+        assert!(!is_stdio(Path::new("-.png")));
+        assert!(!is_stdio(Path::new("dice.png")));
+    }
+
+    #[test]
+    fn test_output_path() {
+        let input = Path::new("in.png");
+        assert_eq!(output_path(input, None), input);
+        assert_eq!(output_path(input, Some(Path::new("out.png"))), Path::new("out.png"));
+        assert_eq!(output_path(input, Some(Path::new("-"))), Path::new("-"));
+    }
+
+    #[test]
+    fn test_chunk_table() {
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("ruSt", b"hidden"),
+            chunk_from_strings("tEXt", b"a"),
+            chunk_from_strings("IEND", &[]),
+        ]);
+        let fixes = [CrcFix { index: 2, stored: 0xdeadbeef, computed: png.as_slice()[2].crc() }];
+
+        let plain = chunk_table(&png, &fixes, false);
+        let lines: Vec<&str> = plain.lines().collect();
+        assert_eq!(lines[0], "#  TYPE  LENGTH  CRC       KIND       SCOPE    COPY            STATUS");
+        assert_eq!(lines[1], format!("0  IHDR      13  {:08x}  critical   public   unsafe-to-copy  ok", png.as_slice()[0].crc()));
+        assert!(lines[2].starts_with("1  ruSt       6 "));
+        assert!(lines[3].contains("deadbeef"));
+        assert!(lines[3].ends_with(&format!("CRC mismatch, expected {:08x}", png.as_slice()[2].crc())));
+        assert!(!plain.contains('\x1b'));
+
+        let colored = chunk_table(&png, &fixes, true);
+        let lines: Vec<&str> = colored.lines().collect();
+        assert!(!lines[0].contains('\x1b'));
+        assert!(lines[1].starts_with(CYAN));
+        assert!(lines[2].starts_with(YELLOW));
+        assert!(lines[3].starts_with(RED) && lines[3].ends_with(RESET));
+    }
+
+    #[test]
+    fn test_template() {
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", &[0; 13]),
+            chunk_from_strings("ruSt", b"hidden"),
+        ]);
+        let crc = png.as_slice()[1].crc();
+        let entries = IndexEntry::of(&png);
+
+        let template = Template::from_str("{index}:{type} {length:4} {crc:x} {{{offset:06X}}}").unwrap();
+        assert_eq!(print_template(&entries, &template), format!(
+            "0:IHDR   13 {:x} {{000008}}\n1:ruSt    6 {:x} {{000021}}",
+            png.as_slice()[0].crc(),
+            crc
+        ));
+
+        let template = Template::from_str("{type:6}|{critical}|{public}|{safe_to_copy}").unwrap();
+        assert_eq!(template.render(1, &entries[1]), "ruSt  |false|false|true");
+        let template = Template::from_str("{length:0}|{length:d}|{length:04d}|{type:}").unwrap();
+        assert_eq!(template.render(1, &entries[1]), "6|6|0006|ruSt");
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        let invalid = [
+            "{name}", "{crc:wide}", "a } b", "{type", "{length:8", "{length:8xd}", "{crc:dx}", "{crc:x8}", "{crc:-8}",
+            "{type:x}", "{critical:d}", "{index:99999999999999999999}",
+        ];
+        for template in invalid {
+            assert!(matches!(Template::from_str(template), Err(Error::InvalidTemplate(_))), "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_print_shows_bad_crc() {
+        let path = testing_png_file("print-bad-crc");
+        let mut bytes = fs::read(&path).unwrap();
+        // Last byte of the IHDR CRC
+        bytes[32] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let report = print_file(&path, GlobalArgs::default(), false, None, &[]);
+        let denied = print_file(&path, GlobalArgs::default(), false, None, &[diagnostics::CRC_MISMATCH]);
+        fs::remove_file(&path).unwrap();
+
+        let table = report.unwrap().1;
+        assert!(table.contains("CRC mismatch"));
+        assert!(table.lines().last().unwrap().starts_with("offset 0x00000008 (chunk 0): warning[crc-mismatch]: IHDR chunk CRC mismatch"));
+        assert_eq!(denied.err(), Some(Error::Denied(1)));
+    }
+
+    #[test]
+    fn test_print() {
+        let path = testing_png_file("print");
+        let result = run_cli(&["print", path_str(&path)]);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+    }
+}
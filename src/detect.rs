@@ -0,0 +1,327 @@
+use crate::chunk::ChunkRef;
+use crate::error::{Error, Result};
+use crate::limits::{LimitCheck, Limits};
+use crate::lsb;
+use crate::pixels::ImageData;
+use crate::png::Png;
+use std::convert::TryFrom;
+
+// How much each kind of finding adds to the score, which is capped at 100
+const UNKNOWN_CHUNK_WEIGHT: u32 = 40;
+const HIGH_ENTROPY_WEIGHT: u32 = 30;
+const TRAILING_DATA_WEIGHT: u32 = 50;
+const CHI_SQUARE_WEIGHT: u32 = 40;
+
+// Text and metadata rarely come close to random, compressed data does
+const ENTROPY_THRESHOLD: f64 = 7.2;
+const MIN_ENTROPY_SAMPLE: usize = 64;
+// Ancillary chunks whose data is compressed, so high entropy is expected
+const COMPRESSED_CHUNK_TYPES: [&str; 4] = ["zTXt", "iTXt", "iCCP", "fdAT"];
+
+// Sequential LSB embedding evens out the counts of each pair of values
+// 2k and 2k+1. Smaller prefixes catch payloads that only fill the start.
+const CHI_SQUARE_THRESHOLD: f64 = 0.95;
+const MIN_CHI_SQUARE_SAMPLES: usize = 1024;
+const CHI_SQUARE_FRACTIONS: [usize; 6] = [1, 2, 4, 8, 16, 32];
+
+#[derive(Debug, PartialEq)]
+pub struct Finding {
+  pub offset: usize,
+  pub weight: u32,
+  pub message: String,
+}
+
+pub struct Detection {
+  pub score: u32,
+  pub findings: Vec<Finding>,
+}
+
+// Shannon entropy in bits per byte
+pub fn entropy(data: &[u8]) -> f64 {
+  let mut counts = [0usize; 256];
+  for &byte in data {
+    counts[byte as usize] += 1;
+  }
+  let total = data.len() as f64;
+  counts
+    .iter()
+    .filter(|&&count| count > 0)
+    .map(|&count| {
+      let p = count as f64 / total;
+      -p * p.log2()
+    })
+    .sum()
+}
+
+// Lanczos approximation of ln Γ(x)
+fn ln_gamma(x: f64) -> f64 {
+  const COEFFICIENTS: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091, -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+  let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+  let series = COEFFICIENTS.iter().enumerate().fold(1.000000000190015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+  -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// The regularized lower incomplete gamma function P(a, x), by its series
+// below a + 1 and its continued fraction above
+fn gamma_p(a: f64, x: f64) -> f64 {
+  if x <= 0.0 {
+    return 0.0;
+  }
+  let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+  if x < a + 1.0 {
+    let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+    for _ in 0..500 {
+      n += 1.0;
+      term *= x / n;
+      sum += term;
+      if term.abs() < sum.abs() * 1e-12 {
+        break;
+      }
+    }
+    return sum * scale;
+  }
+
+  // Lentz's method for the continued fraction of Q(a, x)
+  let tiny = 1e-300;
+  let mut b = x + 1.0 - a;
+  let mut c = 1.0 / tiny;
+  let mut d = 1.0 / b;
+  let mut h = d;
+  for i in 1..500 {
+    let an = -(i as f64) * (i as f64 - a);
+    b += 2.0;
+    d = an * d + b;
+    d = if d.abs() < tiny { 1.0 / tiny } else { 1.0 / d };
+    c = b + an / c;
+    if c.abs() < tiny {
+      c = tiny;
+    }
+    let delta = d * c;
+    h *= delta;
+    if (delta - 1.0).abs() < 1e-12 {
+      break;
+    }
+  }
+  1.0 - scale * h
+}
+
+// The probability that `samples` carry an LSB payload according to the
+// chi-square attack, None when there are too few samples to tell
+pub fn chi_square(samples: impl Iterator<Item = u8>) -> Option<f64> {
+  let mut counts = [0usize; 256];
+  for sample in samples {
+    counts[sample as usize] += 1;
+  }
+
+  let (mut statistic, mut categories) = (0.0, 0);
+  for pair in counts.chunks_exact(2) {
+    let expected = (pair[0] + pair[1]) as f64 / 2.0;
+    // The test is unreliable for sparse categories
+    if expected < 5.0 {
+      continue;
+    }
+    statistic += (pair[0] as f64 - expected).powi(2) / expected;
+    categories += 1;
+  }
+  if categories < 2 {
+    return None;
+  }
+
+  let freedom = (categories - 1) as f64;
+  Some(1.0 - gamma_p(freedom / 2.0, statistic / 2.0))
+}
+
+fn check_chunks(chunks: &[(usize, ChunkRef)], findings: &mut Vec<Finding>) {
+  for (offset, chunk) in chunks {
+    let chunk_type = chunk.chunk_type();
+    let name = chunk_type.to_string();
+
+    if !chunk_type.is_registered() {
+      findings.push(Finding {
+        offset: *offset,
+        weight: UNKNOWN_CHUNK_WEIGHT,
+        message: format!("unknown chunk type {} ({} bytes)", name, chunk.length()),
+      });
+      continue;
+    }
+
+    let data = chunk.data();
+    if chunk_type.is_critical() || COMPRESSED_CHUNK_TYPES.contains(&name.as_str()) || data.len() < MIN_ENTROPY_SAMPLE {
+      continue;
+    }
+    let bits = entropy(data);
+    if bits > ENTROPY_THRESHOLD {
+      findings.push(Finding {
+        offset: *offset,
+        weight: HIGH_ENTROPY_WEIGHT,
+        message: format!("{} chunk data looks random ({:.2} bits per byte over {} bytes)", name, bits, data.len()),
+      });
+    }
+  }
+}
+
+fn check_pixels(image: &ImageData, offset: usize, findings: &mut Vec<Finding>) {
+  // Indexed images and samples under 8 bits aren't LSB carriers
+  if lsb::capacity(&image.ihdr, 1).is_err() {
+    return;
+  }
+
+  let total = lsb::samples(image).count();
+  for fraction in CHI_SQUARE_FRACTIONS {
+    let window = total / fraction;
+    if window < MIN_CHI_SQUARE_SAMPLES {
+      break;
+    }
+    if let Some(p) = chi_square(lsb::samples(image).take(window)).filter(|&p| p >= CHI_SQUARE_THRESHOLD) {
+      findings.push(Finding {
+        offset,
+        weight: CHI_SQUARE_WEIGHT,
+        message: format!("LSB chi-square test flags the first {} of {} samples (p = {:.3})", window, total, p),
+      });
+      return;
+    }
+  }
+}
+
+// Looks for signs of hidden data in a PNG file: chunk types outside the
+// spec, ancillary chunks full of random looking data, bytes after IEND and
+// pixels whose low bits look like they were overwritten
+pub fn detect(bytes: &[u8]) -> Result<Detection> {
+  detect_with_limits(bytes, Limits::default())
+}
+
+pub fn detect_with_limits(bytes: &[u8], limits: Limits) -> Result<Detection> {
+  let header = Png::header();
+  if bytes.len() < header.len() || &bytes[..header.len()] != header {
+    return Err(Error::InvalidSignature(bytes.get(..8).and_then(|b| b.try_into().ok()).unwrap_or_default()));
+  }
+
+  let mut limits = LimitCheck::new(limits);
+  let mut chunks = vec![];
+  let mut offset = header.len();
+  while offset < bytes.len() {
+    let chunk = ChunkRef::try_from(&bytes[offset..]).map_err(|e| e.offset_by(offset))?;
+    limits.header(chunk.length(), offset)?;
+    limits.data(chunk.chunk_type(), chunk.data(), offset)?;
+    let end = offset + chunk.length() as usize + 12;
+    let is_end = chunk.chunk_type().to_string() == "IEND";
+    chunks.push((offset, chunk));
+    offset = end;
+    if is_end {
+      break;
+    }
+  }
+
+  let mut findings = vec![];
+  check_chunks(&chunks, &mut findings);
+
+  if offset < bytes.len() {
+    findings.push(Finding {
+      offset,
+      weight: TRAILING_DATA_WEIGHT,
+      message: format!("{} bytes after IEND", bytes.len() - offset),
+    });
+  }
+
+  let idat = chunks.iter().find(|(_, chunk)| chunk.chunk_type().to_string() == "IDAT").map(|(offset, _)| *offset);
+  let png = Png::from_chunks(chunks.iter().map(|(_, chunk)| chunk.to_chunk()).collect());
+  match (idat, png.decode_pixels()) {
+    (Some(idat), Ok(image)) => check_pixels(&image, idat, &mut findings),
+    (_, Err(e)) => log::debug!("skipping the pixel checks: {}", e),
+    _ => {}
+  }
+
+  let score = findings.iter().map(|finding| finding.weight).sum::<u32>().min(100);
+  Ok(Detection { score, findings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::chunks::Ihdr;
+    use std::str::FromStr;
+
+    // Deterministic xorshift noise
+    fn noise(length: usize, mut state: u64) -> Vec<u8> {
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 56) as u8
+            })
+            .collect()
+    }
+
+    fn image() -> ImageData {
+        let ihdr = Ihdr { width: 64, height: 64, bit_depth: 8, color_type: 0, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        // A gradient with a little noise, mostly on even values like most
+        // untouched images have uneven pairs
+        let data = noise(64 * 64, 7).iter().enumerate().map(|(i, n)| (i % 64) as u8 * 2 + (n % 8 == 0) as u8).collect();
+        ImageData { ihdr, data }
+    }
+
+    fn png_bytes(image: &ImageData, extra: Vec<Chunk>) -> Vec<u8> {
+        let mut png = Png::from_image_data(image).unwrap();
+        for chunk in extra {
+            png.insert_chunk(1, chunk).unwrap();
+        }
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_clean() {
+        let detection = detect(&png_bytes(&image(), vec![])).unwrap();
+        assert_eq!(detection.findings, vec![]);
+        assert_eq!(detection.score, 0);
+    }
+
+    #[test]
+    fn test_chunk_findings() {
+        let chunk = |chunk_type: &str, data: Vec<u8>| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data);
+        let extra = vec![chunk("ruSt", b"hidden".to_vec()), chunk("tEXt", noise(1000, 3)), chunk("zTXt", noise(1000, 5))];
+        let mut bytes = png_bytes(&image(), extra);
+        bytes.extend(b"appended");
+
+        let detection = detect(&bytes).unwrap();
+        let weights: Vec<u32> = detection.findings.iter().map(|finding| finding.weight).collect();
+        assert_eq!(weights, vec![HIGH_ENTROPY_WEIGHT, UNKNOWN_CHUNK_WEIGHT, TRAILING_DATA_WEIGHT]);
+        assert_eq!(detection.findings[2].message, "8 bytes after IEND");
+        assert_eq!(detection.score, 100);
+    }
+
+    #[test]
+    fn test_lsb_payload() {
+        let mut image = image();
+        let payload = noise(lsb::capacity(&image.ihdr, 1).unwrap(), 11);
+        lsb::embed(&mut image, &payload, 1).unwrap();
+
+        let detection = detect(&png_bytes(&image, vec![])).unwrap();
+        assert_eq!(detection.findings.len(), 1);
+        assert!(detection.findings[0].message.starts_with("LSB chi-square test flags the first 4096 of 4096 samples"));
+        assert_eq!(detection.score, CHI_SQUARE_WEIGHT);
+    }
+
+    #[test]
+    fn test_statistics() {
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert!((entropy(&(0..=255).collect::<Vec<u8>>()) - 8.0).abs() < 1e-9);
+        assert!((gamma_p(1.0, 2.0) - (1.0 - (-2.0f64).exp())).abs() < 1e-9);
+        assert!((gamma_p(3.0, 1.5) - 0.191153).abs() < 1e-6);
+        assert!((gamma_p(3.0, 8.0) - 0.986246).abs() < 1e-6);
+        assert_eq!(chi_square([1u8; 10].into_iter()), None);
+        assert!(detect(b"not a png").is_err());
+    }
+
+    #[test]
+    fn test_detect_limits() {
+        let bytes = png_bytes(&image(), vec![]);
+        let limits = Limits { max_chunk_length: 12, ..Limits::NONE };
+        assert!(matches!(detect_with_limits(&bytes, limits), Err(Error::LimitExceeded { limit: "chunk length", offset: 8, .. })));
+        let limits = Limits { max_inflate_ratio: 1, ..Limits::NONE };
+        assert!(matches!(detect_with_limits(&bytes, limits), Err(Error::LimitExceeded { limit: "inflate ratio", .. })));
+    }
+}
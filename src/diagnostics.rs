@@ -0,0 +1,214 @@
+use std::fmt;
+
+// Codes are kebab-case and stable, for scripts to match on and for
+// `--deny` to name. Each one stands for one kind of problem wherever it's
+// found.
+pub const INVALID_SIGNATURE: &str = "invalid-signature";
+pub const TRUNCATED_CHUNK: &str = "truncated-chunk";
+pub const LENGTH_TOO_LARGE: &str = "length-too-large";
+pub const INVALID_CHUNK_TYPE: &str = "invalid-chunk-type";
+pub const RESERVED_BIT: &str = "reserved-bit";
+pub const CRC_MISMATCH: &str = "crc-mismatch";
+pub const NO_CHUNKS: &str = "no-chunks";
+pub const MISSING_CHUNK: &str = "missing-chunk";
+pub const CHUNK_ORDER: &str = "chunk-order";
+pub const DUPLICATE_CHUNK: &str = "duplicate-chunk";
+pub const DUPLICATE_KEYWORD: &str = "duplicate-keyword";
+pub const INVALID_CHUNK_DATA: &str = "invalid-chunk-data";
+pub const IMAGE_DATA: &str = "image-data";
+pub const CGBI: &str = "cgbi";
+pub const TRAILING_DATA: &str = "trailing-data";
+pub const LIMIT_EXCEEDED: &str = "limit-exceeded";
+
+pub const CODES: [&str; 16] = [
+  INVALID_SIGNATURE, TRUNCATED_CHUNK, LENGTH_TOO_LARGE, INVALID_CHUNK_TYPE, RESERVED_BIT, CRC_MISMATCH, NO_CHUNKS,
+  MISSING_CHUNK, CHUNK_ORDER, DUPLICATE_CHUNK, DUPLICATE_KEYWORD, INVALID_CHUNK_DATA, IMAGE_DATA, CGBI, TRAILING_DATA,
+  LIMIT_EXCEEDED,
+];
+
+// Errors make a file fail verification, warnings are only reported
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Severity::Warning => write!(f, "warning"),
+      Severity::Error => write!(f, "error"),
+    }
+  }
+}
+
+// Something a parser or validator noticed about a file, with where it is:
+// the byte offset and, when it's in one, the index of the chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub code: &'static str,
+  pub severity: Severity,
+  pub message: String,
+  pub offset: usize,
+  pub chunk: Option<usize>,
+}
+
+impl Diagnostic {
+  pub fn error(code: &'static str, offset: usize, message: impl Into<String>) -> Diagnostic {
+    Diagnostic { code, severity: Severity::Error, message: message.into(), offset, chunk: None }
+  }
+
+  pub fn warning(code: &'static str, offset: usize, message: impl Into<String>) -> Diagnostic {
+    Diagnostic { code, severity: Severity::Warning, message: message.into(), offset, chunk: None }
+  }
+
+  pub fn in_chunk(self, index: usize) -> Diagnostic {
+    Diagnostic { chunk: Some(index), ..self }
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "offset {:#010x}", self.offset)?;
+    if let Some(index) = self.chunk {
+      write!(f, " (chunk {})", index)?;
+    }
+    write!(f, ": {}[{}]: {}", self.severity, self.code, self.message)
+  }
+}
+
+// Everything reported about one file, in the order it was found until
+// `sort` puts it in file order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+  items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+  pub fn new() -> Diagnostics {
+    Diagnostics::default()
+  }
+
+  pub fn push(&mut self, diagnostic: Diagnostic) {
+    self.items.push(diagnostic);
+  }
+
+  pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+    self.items.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+    self.items.iter().filter(|diagnostic| diagnostic.severity == Severity::Error)
+  }
+
+  pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+    self.items.iter().filter(|diagnostic| diagnostic.severity == Severity::Warning)
+  }
+
+  pub fn has_errors(&self) -> bool {
+    self.errors().next().is_some()
+  }
+
+  // Turns the warnings with any of `codes` into errors
+  pub fn deny<S: AsRef<str>>(&mut self, codes: &[S]) {
+    for diagnostic in &mut self.items {
+      if codes.iter().any(|code| code.as_ref() == diagnostic.code) {
+        diagnostic.severity = Severity::Error;
+      }
+    }
+  }
+
+  // By offset, keeping the order things were found in at the same offset
+  pub fn sort(&mut self) {
+    self.items.sort_by_key(|diagnostic| diagnostic.offset);
+  }
+
+  // Fills in the chunk of every diagnostic that doesn't have one yet from
+  // its offset, given where each chunk starts and how long it is in full
+  pub fn locate(&mut self, spans: &[(usize, usize)]) {
+    for diagnostic in self.items.iter_mut().filter(|diagnostic| diagnostic.chunk.is_none()) {
+      diagnostic.chunk = spans.iter().position(|&(start, size)| (start..start + size).contains(&diagnostic.offset));
+    }
+  }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+  fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+    self.items.extend(iter);
+  }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+  type Item = &'a Diagnostic;
+  type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.items.iter()
+  }
+}
+
+impl IntoIterator for Diagnostics {
+  type Item = Diagnostic;
+  type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.items.into_iter()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostics() -> Diagnostics {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(Diagnostic::warning(TRAILING_DATA, 100, "4 bytes after IEND"));
+        diagnostics.push(Diagnostic::error(CRC_MISMATCH, 33, "tEXt chunk CRC mismatch").in_chunk(1));
+        diagnostics.push(Diagnostic::error(MISSING_CHUNK, 100, "missing IDAT chunk"));
+        diagnostics
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut diagnostics = diagnostics();
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics.errors().count(), 2);
+        assert_eq!(diagnostics.warnings().count(), 1);
+
+        diagnostics.sort();
+        let codes: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(codes, vec![CRC_MISMATCH, TRAILING_DATA, MISSING_CHUNK]);
+
+        diagnostics.deny(&[TRAILING_DATA]);
+        assert!(diagnostics.warnings().next().is_none());
+        assert!(diagnostics.has_errors());
+        assert!(!Diagnostics::new().has_errors());
+    }
+
+    #[test]
+    fn test_locate_and_display() {
+        let mut diagnostics = diagnostics();
+        diagnostics.locate(&[(8, 25), (33, 20), (53, 47)]);
+        let chunks: Vec<Option<usize>> = diagnostics.iter().map(|diagnostic| diagnostic.chunk).collect();
+        assert_eq!(chunks, vec![None, Some(1), None]);
+
+        diagnostics.push(Diagnostic::error(IMAGE_DATA, 60, "IDAT: invalid stored block lengths"));
+        diagnostics.locate(&[(8, 25), (33, 20), (53, 47)]);
+        let shown: Vec<String> = diagnostics.iter().map(Diagnostic::to_string).collect();
+        assert_eq!(shown, vec![
+            "offset 0x00000064: warning[trailing-data]: 4 bytes after IEND",
+            "offset 0x00000021 (chunk 1): error[crc-mismatch]: tEXt chunk CRC mismatch",
+            "offset 0x00000064: error[missing-chunk]: missing IDAT chunk",
+            "offset 0x0000003c (chunk 2): error[image-data]: IDAT: invalid stored block lengths",
+        ]);
+        assert!(CODES.iter().all(|code| code.chars().all(|c| c.is_ascii_lowercase() || c == '-')));
+    }
+}
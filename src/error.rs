@@ -0,0 +1,134 @@
+use crate::chunk_type::ChunkTypeError;
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Everything that can go wrong in pngne. Errors from other crates are kept
+// as their message, so that errors stay comparable.
+#[derive(Debug, PartialEq, Error)]
+pub enum Error {
+  #[error("I/O error: {0}")]
+  Io(String),
+  #[error("file is too small to be a PNG")]
+  TooSmall,
+  #[error("not a PNG file, the signature is {0:02x?}")]
+  InvalidSignature([u8; 8]),
+  #[error("chunk at offset {offset:#x} is truncated, it needs {expected} bytes but only {actual} are left")]
+  TruncatedChunk { expected: usize, actual: usize, offset: usize },
+  #[error("chunk at offset {offset:#x} claims {length} bytes of data, over the 2^31 - 1 limit")]
+  LengthFieldInvalid { length: u32, offset: usize },
+  #[error("{0} bytes is too much for one chunk, the limit is 2^31 - 1")]
+  ChunkTooLarge(usize),
+  #[error("chunk at offset {offset:#x} goes over the {limit} limit of {max} with {value}")]
+  LimitExceeded { limit: &'static str, value: usize, max: usize, offset: usize },
+  #[error("invalid UTF-8 at byte {offset}")]
+  InvalidUtf8 { offset: usize },
+  #[error("invalid chunk type {:?}", String::from_utf8_lossy(.0))]
+  ChunkTypeNotValid([u8; 4]),
+  #[error("invalid chunk type: {0}")]
+  InvalidChunkType(#[from] ChunkTypeError),
+  #[error("CRC mismatch, computed {0:#010x} but {1:#010x} is stored")]
+  CrcMismatch(u32, u32),
+  #[error("chunk not found")]
+  ChunkNotFound,
+  #[error("no text with the keyword {0:?}")]
+  KeywordNotFound(String),
+  #[error("{0}")]
+  InvalidChunkData(String),
+  #[error("{0}")]
+  Payload(String),
+  #[error("index {0} is out of range")]
+  IndexOutOfRange(usize),
+  #[error("invalid pattern: {0}")]
+  InvalidPattern(String),
+  #[error("invalid template: {0}")]
+  InvalidTemplate(String),
+  #[error("{0} chunk ordering violation(s)")]
+  InvalidOrder(usize),
+  #[error("verification found {0} problem(s)")]
+  VerifyFailed(usize),
+  #[error("{0} warning(s) denied with --deny")]
+  Denied(usize),
+  #[error("{0} file(s) failed")]
+  BatchFailed(usize),
+  #[error("watching for changes failed: {0}")]
+  Watch(String),
+  #[error("thread pool: {0}")]
+  ThreadPool(String),
+}
+
+// A stretch of a damaged file that had to be passed over: why the chunk at
+// `offset` couldn't be read, and how many bytes were skipped to get to the
+// next one
+#[derive(Debug, PartialEq, Error)]
+#[error("{error}, skipped {skipped} bytes from offset {offset:#x}")]
+pub struct ChunkError {
+  pub offset: usize,
+  pub skipped: usize,
+  pub error: Error,
+}
+
+impl Error {
+  // Chunk parsers report offsets from the start of the slice they were
+  // given. File parsers add where that slice starts in the file.
+  pub fn offset_by(self, base: usize) -> Error {
+    match self {
+      Error::TruncatedChunk { expected, actual, offset } => Error::TruncatedChunk { expected, actual, offset: base + offset },
+      Error::LengthFieldInvalid { length, offset } => Error::LengthFieldInvalid { length, offset: base + offset },
+      Error::LimitExceeded { limit, value, max, offset } => Error::LimitExceeded { limit, value, max, offset: base + offset },
+      error => error,
+    }
+  }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+  fn from(e: std::string::FromUtf8Error) -> Self {
+    Error::InvalidUtf8 { offset: e.utf8_error().valid_up_to() }
+  }
+}
+
+impl From<std::io::Error> for Error {
+  fn from(e: std::io::Error) -> Self {
+    Error::Io(e.to_string())
+  }
+}
+
+impl From<notify::Error> for Error {
+  fn from(e: notify::Error) -> Self {
+    Error::Watch(e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Error::CrcMismatch(1, 0xdeadbeef).to_string(), "CRC mismatch, computed 0x00000001 but 0xdeadbeef is stored");
+        assert_eq!(Error::ChunkTypeNotValid(*b"ru5t").to_string(), "invalid chunk type \"ru5t\"");
+        assert_eq!(Error::from(ChunkTypeError::InvalidByte(b'5')).to_string(), "invalid chunk type: byte 0x35 isn't an ASCII letter");
+        assert_eq!(
+            Error::TruncatedChunk { expected: 20, actual: 11, offset: 0 }.offset_by(0x21).to_string(),
+            "chunk at offset 0x21 is truncated, it needs 20 bytes but only 11 are left"
+        );
+        assert_eq!(
+            Error::LimitExceeded { limit: "chunk count", value: 1001, max: 1000, offset: 0x4000 }.to_string(),
+            "chunk at offset 0x4000 goes over the chunk count limit of 1000 with 1001"
+        );
+        assert_eq!(Error::ChunkTooLarge(1 << 31).to_string(), "2147483648 bytes is too much for one chunk, the limit is 2^31 - 1");
+        assert_eq!(Error::KeywordNotFound("Title".to_string()).to_string(), "no text with the keyword \"Title\"");
+        let skipped = ChunkError { offset: 0x21, skipped: 16, error: Error::ChunkTypeNotValid(*b"1uSt") };
+        assert_eq!(skipped.to_string(), "invalid chunk type \"1uSt\", skipped 16 bytes from offset 0x21");
+        assert_eq!(Error::Payload("payload: header is truncated".to_string()).to_string(), "payload: header is truncated");
+    }
+
+    #[test]
+    fn test_conversions() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        assert_eq!(Error::from(io), Error::Io("missing".to_string()));
+        assert_eq!(Error::from(String::from_utf8(b"ok\xff".to_vec()).unwrap_err()), Error::InvalidUtf8 { offset: 2 });
+        let boxed: Box<dyn std::error::Error> = Box::new(Error::ChunkNotFound);
+        assert_eq!(boxed.to_string(), "chunk not found");
+    }
+}
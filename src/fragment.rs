@@ -0,0 +1,153 @@
+use crate::checksum::crc32;
+use crate::error::{Error, Result};
+
+// A payload too big for one chunk is stored as a manifest chunk followed by
+// fragment chunks, all of the same type.
+//
+//   manifest: magic (4) | version (1) | fragment count (4) | length (4) | crc (4)
+//   fragment: magic (4) | crc (4) | index (4) | bytes
+//
+// The CRC-32 of the whole payload doubles as the id tying fragments to their
+// manifest, so other chunks of the same type are left alone.
+const MANIFEST_MAGIC: [u8; 4] = *b"\x89PNM";
+const FRAGMENT_MAGIC: [u8; 4] = *b"\x89PNF";
+const VERSION: u8 = 1;
+const MANIFEST_SIZE: usize = 17;
+const FRAGMENT_HEADER_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Manifest {
+  pub count: u32,
+  pub length: u32,
+  pub crc: u32,
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+  u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+impl Manifest {
+  pub fn parse(data: &[u8]) -> Result<Manifest> {
+    if !is_manifest(data) {
+      return Err(Error::Payload("fragment: not a manifest".to_string()));
+    }
+    if data.len() != MANIFEST_SIZE {
+      return Err(Error::Payload(format!("fragment: manifest is {} bytes, expected {}", data.len(), MANIFEST_SIZE)));
+    }
+    if data[4] != VERSION {
+      return Err(Error::Payload(format!("fragment: unsupported manifest version {}", data[4])));
+    }
+    Ok(Manifest { count: read_u32(data, 5), length: read_u32(data, 9), crc: read_u32(data, 13) })
+  }
+
+  pub fn to_bytes(self) -> Vec<u8> {
+    let mut data = MANIFEST_MAGIC.to_vec();
+    data.push(VERSION);
+    for value in [self.count, self.length, self.crc] {
+      data.extend(value.to_be_bytes());
+    }
+    data
+  }
+}
+
+pub fn is_manifest(data: &[u8]) -> bool {
+  data.starts_with(&MANIFEST_MAGIC)
+}
+
+pub fn is_fragment(data: &[u8]) -> bool {
+  data.starts_with(&FRAGMENT_MAGIC)
+}
+
+// Splits `payload` into fragments of at most `fragment_size` payload bytes
+// each, and returns them after the manifest that describes them
+pub fn split(payload: &[u8], fragment_size: usize) -> Result<Vec<Vec<u8>>> {
+  let too_big = || Error::Payload(format!("fragment: a {} byte payload is too big to fragment", payload.len()));
+  let length = u32::try_from(payload.len()).map_err(|_| too_big())?;
+  let crc = crc32(payload);
+
+  let fragments: Vec<Vec<u8>> = payload
+    .chunks(fragment_size.max(1))
+    .enumerate()
+    .map(|(index, bytes)| [&FRAGMENT_MAGIC[..], &crc.to_be_bytes(), &(index as u32).to_be_bytes(), bytes].concat())
+    .collect();
+  let count = u32::try_from(fragments.len()).map_err(|_| too_big())?;
+
+  let mut chunks = vec![Manifest { count, length, crc }.to_bytes()];
+  chunks.extend(fragments);
+  Ok(chunks)
+}
+
+// Puts the payload described by `manifest` back together from the data of
+// every chunk that might hold one of its fragments, in any order
+pub fn reassemble<'a>(manifest: &[u8], chunks: impl IntoIterator<Item = &'a [u8]>) -> Result<Vec<u8>> {
+  let manifest = Manifest::parse(manifest)?;
+  let mut fragments: Vec<Option<&[u8]>> = vec![None; manifest.count as usize];
+
+  for data in chunks {
+    if !is_fragment(data) || data.len() < FRAGMENT_HEADER_SIZE || read_u32(data, 4) != manifest.crc {
+      continue;
+    }
+    let index = read_u32(data, 8) as usize;
+    match fragments.get_mut(index) {
+      Some(slot @ None) => *slot = Some(&data[FRAGMENT_HEADER_SIZE..]),
+      Some(Some(_)) => return Err(Error::Payload(format!("fragment: fragment {} appears twice", index))),
+      None => return Err(Error::Payload(format!("fragment: index {} is out of range, the manifest lists {}", index, manifest.count))),
+    }
+  }
+
+  let missing: Vec<String> = (0..fragments.len()).filter(|&i| fragments[i].is_none()).map(|i| i.to_string()).collect();
+  if !missing.is_empty() {
+    return Err(Error::Payload(format!("fragment: missing fragment(s) {} of {}", missing.join(", "), manifest.count)));
+  }
+
+  let payload: Vec<u8> = fragments.into_iter().flatten().flatten().copied().collect();
+  if payload.len() != manifest.length as usize || crc32(&payload) != manifest.crc {
+    return Err(Error::Payload("fragment: reassembled payload doesn't match its manifest".to_string()));
+  }
+  Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_reassemble() {
+        let payload: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let chunks = split(&payload, 300).unwrap();
+
+        assert_eq!(chunks.len(), 5);
+        assert!(is_manifest(&chunks[0]));
+        assert!(chunks[1..].iter().all(|chunk| is_fragment(chunk)));
+        assert_eq!(Manifest::parse(&chunks[0]).unwrap(), Manifest { count: 4, length: 1000, crc: crc32(&payload) });
+        assert!(chunks[1..].iter().all(|chunk| chunk.len() <= FRAGMENT_HEADER_SIZE + 300));
+
+        let fragments = chunks[1..].iter().rev().map(Vec::as_slice);
+        assert_eq!(reassemble(&chunks[0], fragments).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_reassemble_ignores_other_chunks() {
+        let a = split(b"first payload", 4).unwrap();
+        let b = split(b"second payload", 4).unwrap();
+        let all = a.iter().chain(&b).map(Vec::as_slice).chain([&b"unrelated"[..]]);
+
+        assert_eq!(reassemble(&b[0], all).unwrap(), b"second payload");
+    }
+
+    #[test]
+    fn test_reassemble_errors() {
+        let chunks = split(b"a payload in pieces", 5).unwrap();
+
+        let missing = reassemble(&chunks[0], chunks[1..3].iter().map(Vec::as_slice));
+        assert_eq!(missing, Err(Error::Payload("fragment: missing fragment(s) 2, 3 of 4".to_string())));
+        assert!(reassemble(&chunks[0], chunks[1..].iter().chain(&chunks[1..2]).map(Vec::as_slice)).is_err());
+
+        let mut corrupt = chunks.clone();
+        corrupt[2][FRAGMENT_HEADER_SIZE] ^= 1;
+        assert!(reassemble(&corrupt[0], corrupt[1..].iter().map(Vec::as_slice)).is_err());
+
+        assert!(Manifest::parse(&chunks[1]).is_err());
+        assert!(Manifest::parse(&chunks[0][..10]).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::error::{Error, Result};
+use crate::chunks::Ihdr;
+use crate::pixels::ImageData;
+use crate::png::Png;
+use image::{DynamicImage, ImageBuffer, Pixel};
+use std::convert::TryFrom;
+
+fn buffer<P: Pixel>(width: u32, height: u32, data: Vec<P::Subpixel>) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+  ImageBuffer::from_raw(width, height, data)
+    .ok_or_else(|| Error::InvalidChunkData(format!("image data doesn't fill a {}x{} image", width, height)))
+}
+
+fn be_samples(data: &[u8]) -> Vec<u16> {
+  data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+fn be_bytes(samples: &[u16]) -> Vec<u8> {
+  samples.iter().flat_map(|sample| sample.to_be_bytes()).collect()
+}
+
+// Images keep their color type and bit depth where `image` has a matching
+// buffer. Indexed images, sub-byte samples and tRNS are expanded to RGBA8.
+impl TryFrom<&Png> for DynamicImage {
+  type Error = Error;
+
+  fn try_from(png: &Png) -> Result<Self> {
+    let image = png.decode_pixels()?;
+    let Ihdr { width, height, bit_depth, color_type, .. } = image.ihdr;
+
+    if color_type == 3 || bit_depth < 8 || png.chunk_by_type("tRNS").is_some() {
+      return Ok(DynamicImage::ImageRgba8(buffer(width, height, png.decode_rgba8()?)?));
+    }
+
+    Ok(match (color_type, bit_depth) {
+      (0, 8) => DynamicImage::ImageLuma8(buffer(width, height, image.data)?),
+      (2, 8) => DynamicImage::ImageRgb8(buffer(width, height, image.data)?),
+      (4, 8) => DynamicImage::ImageLumaA8(buffer(width, height, image.data)?),
+      (_, 8) => DynamicImage::ImageRgba8(buffer(width, height, image.data)?),
+      (0, _) => DynamicImage::ImageLuma16(buffer(width, height, be_samples(&image.data))?),
+      (2, _) => DynamicImage::ImageRgb16(buffer(width, height, be_samples(&image.data))?),
+      (4, _) => DynamicImage::ImageLumaA16(buffer(width, height, be_samples(&image.data))?),
+      _ => DynamicImage::ImageRgba16(buffer(width, height, be_samples(&image.data))?),
+    })
+  }
+}
+
+// Builds a minimal PNG: IHDR, IDAT and IEND. Taking the image by value
+// keeps `Png::try_from(bytes.as_ref())` unambiguous.
+impl TryFrom<DynamicImage> for Png {
+  type Error = Error;
+
+  fn try_from(image: DynamicImage) -> Result<Self> {
+    let (width, height) = (image.width(), image.height());
+    let (color_type, bit_depth, data) = match image {
+      DynamicImage::ImageLuma8(buffer) => (0, 8, buffer.into_raw()),
+      DynamicImage::ImageRgb8(buffer) => (2, 8, buffer.into_raw()),
+      DynamicImage::ImageLumaA8(buffer) => (4, 8, buffer.into_raw()),
+      DynamicImage::ImageRgba8(buffer) => (6, 8, buffer.into_raw()),
+      DynamicImage::ImageLuma16(buffer) => (0, 16, be_bytes(buffer.as_raw())),
+      DynamicImage::ImageRgb16(buffer) => (2, 16, be_bytes(buffer.as_raw())),
+      DynamicImage::ImageLumaA16(buffer) => (4, 16, be_bytes(buffer.as_raw())),
+      // Floating point samples have no PNG equivalent
+      image => (6, 16, be_bytes(image.to_rgba16().as_raw())),
+    };
+
+    let ihdr = Ihdr {
+      width,
+      height,
+      bit_depth,
+      color_type,
+      compression_method: 0,
+      filter_method: 0,
+      interlace_method: 0,
+    };
+    Png::from_image_data(&ImageData { ihdr, data })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use image::{GenericImageView, Luma, Rgba};
+    use std::str::FromStr;
+
+    fn png(ihdr: Ihdr, data: Vec<u8>, chunks: Vec<Chunk>) -> Png {
+        let mut png = Png::from_chunks(chunks);
+        png.append_chunk(Chunk::new(ChunkType::IEND, vec![]));
+        png.set_image_data(&ImageData { ihdr, data }).unwrap();
+        png
+    }
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Ihdr {
+        Ihdr { width, height, bit_depth, color_type, compression_method: 0, filter_method: 0, interlace_method: 0 }
+    }
+
+    #[test]
+    fn test_png_to_image() {
+        let rgb = png(ihdr(2, 1, 8, 2), vec![1, 2, 3, 4, 5, 6], vec![]);
+        let image = DynamicImage::try_from(&rgb).unwrap();
+        assert_eq!(image, DynamicImage::ImageRgb8(ImageBuffer::from_raw(2, 1, vec![1, 2, 3, 4, 5, 6]).unwrap()));
+
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+        let indexed = png(ihdr(2, 2, 1, 3), vec![0x40, 0x80], vec![chunk("PLTE", &[0, 0, 0, 255, 255, 255]), chunk("tRNS", &[0])]);
+        let image = DynamicImage::try_from(&indexed).unwrap();
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(1, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(0, 1), Rgba([255, 255, 255, 255]));
+
+        assert_eq!(DynamicImage::try_from(&Png::from_chunks(vec![])), Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let images = [
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(3, 2, |x, y| Luma([(x * 1000 + y) as u16]))),
+            DynamicImage::ImageRgba8(ImageBuffer::from_fn(2, 3, |x, y| Rgba([x as u8, y as u8, 7, 200]))),
+        ];
+
+        for image in images {
+            let png = Png::try_from(image.clone()).unwrap();
+            assert_eq!(png.chunks().map(|chunk| chunk.chunk_type().to_string()).collect::<Vec<_>>(), ["IHDR", "IDAT", "IEND"]);
+            assert_eq!(DynamicImage::try_from(&png).unwrap(), image);
+        }
+    }
+
+    #[test]
+    fn test_float_image() {
+        let image = DynamicImage::ImageRgb32F(ImageBuffer::from_pixel(1, 1, image::Rgb([1.0, 0.5, 0.0])));
+        let png = Png::try_from(image.clone()).unwrap();
+        let ihdr = png.ihdr().unwrap();
+
+        assert_eq!((ihdr.color_type, ihdr.bit_depth), (6, 16));
+        assert_eq!(DynamicImage::try_from(&png).unwrap(), DynamicImage::ImageRgba16(image.to_rgba16()));
+    }
+}
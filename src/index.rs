@@ -0,0 +1,212 @@
+use crate::chunk::{self, Chunk, CrcHasher};
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::limits::{LimitCheck, Limits};
+use crate::png::Png;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+
+// Where a chunk is in its file and what its header and stored CRC say
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexEntry {
+  pub chunk_type: ChunkType,
+  pub length: u32,
+  pub offset: usize,
+  pub crc: u32,
+}
+
+impl IndexEntry {
+  // The entries of chunks already in memory, offsets as they'd be written
+  pub fn of(png: &Png) -> Vec<IndexEntry> {
+    let mut offset = Png::header().len();
+    png
+      .chunks()
+      .map(|chunk| {
+        let entry = IndexEntry { chunk_type: *chunk.chunk_type(), length: chunk.length(), offset, crc: chunk.crc() };
+        offset += chunk.length() as usize + 12;
+        entry
+      })
+      .collect()
+  }
+}
+
+// The chunk layout of a file, found by seeking from header to header
+// without reading any chunk data. Chunks are then loaded one at a time, so
+// looking at one small chunk of a huge file only reads that chunk. The
+// CRCs are only checked for chunks that get loaded.
+pub struct ChunkIndex<R> {
+  reader: R,
+  entries: Vec<IndexEntry>,
+  trailing: usize,
+  limits: Limits,
+}
+
+impl<R: Read + Seek> ChunkIndex<R> {
+  pub fn new(reader: R) -> Result<ChunkIndex<R>> {
+    ChunkIndex::with_limits(reader, Limits::default())
+  }
+
+  // Lengths and counts are checked here, inflated sizes as chunks are
+  // loaded. The inflate ratio needs all the image data in order, so it
+  // isn't checked at all.
+  pub fn with_limits(mut reader: R, limits: Limits) -> Result<ChunkIndex<R>> {
+    let end = reader.seek(SeekFrom::End(0))? as usize;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut header = [0; 8];
+    if end < header.len() {
+      return Err(Error::TooSmall);
+    }
+    reader.read_exact(&mut header)?;
+    if &header != Png::header() {
+      return Err(Error::InvalidSignature(header));
+    }
+
+    let mut entries = vec![];
+    let mut check = LimitCheck::new(limits);
+    let mut offset = header.len();
+    while offset < end {
+      let remaining = end - offset;
+      if remaining < 12 {
+        return Err(Error::TruncatedChunk { expected: 12, actual: remaining, offset });
+      }
+
+      let mut head = [0; 8];
+      reader.seek(SeekFrom::Start(offset as u64))?;
+      reader.read_exact(&mut head)?;
+
+      let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+      if length as usize > chunk::MAX_LENGTH {
+        return Err(Error::LengthFieldInvalid { length, offset });
+      }
+      let type_bytes = [head[4], head[5], head[6], head[7]];
+      let chunk_type = ChunkType::try_from(type_bytes).map_err(|_| Error::ChunkTypeNotValid(type_bytes))?;
+      check.header(length, offset)?;
+
+      let size = length as usize + 12;
+      if remaining < size {
+        return Err(Error::TruncatedChunk { expected: size, actual: remaining, offset });
+      }
+
+      let mut crc = [0; 4];
+      reader.seek(SeekFrom::Current(length as i64))?;
+      reader.read_exact(&mut crc)?;
+
+      entries.push(IndexEntry { chunk_type, length, offset, crc: u32::from_be_bytes(crc) });
+      offset += size;
+      if chunk_type == ChunkType::IEND {
+        break;
+      }
+    }
+
+    Ok(ChunkIndex { reader, entries, trailing: end - offset, limits })
+  }
+
+  pub fn entries(&self) -> &[IndexEntry] {
+    &self.entries
+  }
+
+  // How many bytes follow IEND, left unread
+  pub fn trailing_length(&self) -> usize {
+    self.trailing
+  }
+
+  // The index of the first chunk of `chunk_type`
+  pub fn position(&self, chunk_type: impl AsRef<str>) -> Option<usize> {
+    self.entries.iter().position(|entry| entry.chunk_type.as_str() == chunk_type.as_ref())
+  }
+
+  // Reads the `n`th chunk's data and checks it against the stored CRC
+  pub fn load_chunk(&mut self, n: usize) -> Result<Chunk> {
+    let entry = *self.entries.get(n).ok_or(Error::IndexOutOfRange(n))?;
+
+    let mut data = vec![];
+    self.reader.seek(SeekFrom::Start(entry.offset as u64 + 8))?;
+    self.reader.by_ref().take(entry.length as u64).read_to_end(&mut data)?;
+    if data.len() < entry.length as usize {
+      // The file shrank since it was indexed
+      return Err(Error::TruncatedChunk { expected: entry.length as usize + 12, actual: data.len() + 8, offset: entry.offset });
+    }
+
+    let mut hasher = CrcHasher::new(&entry.chunk_type);
+    hasher.update(&data);
+    let computed = hasher.finish();
+    if computed != entry.crc {
+      return Err(Error::CrcMismatch(computed, entry.crc));
+    }
+    if entry.chunk_type != ChunkType::IDAT {
+      LimitCheck::new(self.limits).data(&entry.chunk_type, &data, entry.offset)?;
+    }
+    Ok(Chunk::with_crc(entry.chunk_type, data, computed))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    fn png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()),
+            Chunk::new(ChunkType::IDAT, vec![1; 1000]),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ])
+    }
+
+    #[test]
+    fn test_index() {
+        let png = png();
+        let mut index = ChunkIndex::new(Cursor::new(png.as_bytes())).unwrap();
+
+        assert_eq!(index.entries(), IndexEntry::of(&png).as_slice());
+        assert_eq!(index.entries()[2], IndexEntry { chunk_type: ChunkType::IDAT, length: 1000, offset: 51, crc: png.as_slice()[2].crc() });
+        assert_eq!(index.position("ruSt"), Some(1));
+        assert_eq!(index.position(ChunkType::TEXT), None);
+
+        assert_eq!(index.load_chunk(1).unwrap(), png.as_slice()[1]);
+        assert_eq!(index.load_chunk(3).unwrap(), png.as_slice()[3]);
+        assert_eq!(index.load_chunk(4).err(), Some(Error::IndexOutOfRange(4)));
+        assert_eq!(index.trailing_length(), 0);
+
+        let mut bytes = png.as_bytes();
+        bytes.extend(b"after the end");
+        let index = ChunkIndex::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(index.entries().len(), 4);
+        assert_eq!(index.trailing_length(), 13);
+    }
+
+    #[test]
+    fn test_index_errors() {
+        let mut bytes = png().as_bytes();
+        assert_eq!(ChunkIndex::new(Cursor::new(&bytes[..4])).err(), Some(Error::TooSmall));
+        assert_eq!(
+            ChunkIndex::new(Cursor::new(&bytes[..100])).err(),
+            Some(Error::TruncatedChunk { expected: 1012, actual: 49, offset: 51 })
+        );
+
+        // A bad CRC only shows when that chunk is loaded
+        bytes[60] ^= 1;
+        let mut index = ChunkIndex::new(Cursor::new(bytes)).unwrap();
+        assert!(index.load_chunk(1).is_ok());
+        assert!(matches!(index.load_chunk(2), Err(Error::CrcMismatch(_, _))));
+
+        let limits = Limits { max_chunk_length: 999, ..Limits::NONE };
+        assert_eq!(
+            ChunkIndex::with_limits(Cursor::new(png().as_bytes()), limits).err(),
+            Some(Error::LimitExceeded { limit: "chunk length", value: 1000, max: 999, offset: 51 })
+        );
+
+        // Inflated sizes wait for the chunk to be loaded
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend(crate::zlib::deflate(&[b'a'; 1_000_000]));
+        let mut png = png();
+        png.insert_chunk(1, Chunk::new(ChunkType::ZTXT, ztxt)).unwrap();
+        let limits = Limits { max_inflated_size: 1000, ..Limits::NONE };
+        let mut index = ChunkIndex::with_limits(Cursor::new(png.as_bytes()), limits).unwrap();
+        assert!(matches!(index.load_chunk(1), Err(Error::LimitExceeded { limit: "inflated size", offset: 33, .. })));
+        assert!(index.load_chunk(3).is_ok());
+    }
+}
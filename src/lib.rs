@@ -0,0 +1,31 @@
+// Reading, editing and hiding data in PNG files. The `pngne` binary is a
+// command line layer over this library.
+pub mod cgbi;
+pub mod checksum;
+pub mod chunk;
+pub mod chunk_type;
+pub mod chunks;
+pub mod detect;
+pub mod diagnostics;
+pub mod error;
+pub mod fragment;
+#[cfg(feature = "image-interop")]
+pub mod image_interop;
+pub mod index;
+pub mod limits;
+pub mod lsb;
+pub mod parser;
+pub mod payload;
+pub mod pixels;
+pub mod png;
+pub mod salvage;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod verify;
+pub mod zlib;
+
+pub use chunk::{Chunk, ChunkRef};
+pub use chunk_type::{ChunkType, ChunkTypeError};
+pub use error::{Error, Result};
+pub use parser::PngParser;
+pub use png::Png;
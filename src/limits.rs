@@ -0,0 +1,241 @@
+use crate::chunk::{self, Chunk};
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::zlib;
+use flate2::{Decompress, FlushDecompress, Status};
+
+// Bounds on what the parsers accept, for reading files that can't be
+// trusted. A few bytes of PNG can claim gigabytes of chunk data, hold
+// millions of empty chunks or inflate to far more than they look.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+  // Largest chunk data accepted, in bytes
+  pub max_chunk_length: usize,
+  // Most chunks in one file
+  pub max_chunks: usize,
+  // Largest zTXt, iTXt or iCCP data once inflated, in bytes
+  pub max_inflated_size: usize,
+  // Most the image data may inflate to, as a multiple of the IDAT data
+  pub max_inflate_ratio: usize,
+}
+
+impl Limits {
+  // Only what the spec itself requires
+  pub const NONE: Limits = Limits {
+    max_chunk_length: chunk::MAX_LENGTH,
+    max_chunks: usize::MAX,
+    max_inflated_size: usize::MAX,
+    max_inflate_ratio: usize::MAX,
+  };
+
+  // What parsing uses unless told otherwise: no limits but a chunk count
+  // far above anything a real image needs. Each chunk costs memory and
+  // time however little data it has, so a small file packed with empty
+  // chunks could otherwise tie up a parser for a long time.
+  pub const DEFAULT: Limits = Limits { max_chunks: 100_000, ..Limits::NONE };
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Limits::DEFAULT
+  }
+}
+
+// Applies `Limits` to chunks one at a time as a parser comes across them.
+// The image data is inflated as it goes past, without keeping the output,
+// only when there is a ratio to check.
+pub(crate) struct LimitCheck {
+  limits: Limits,
+  chunks: usize,
+  idat: Option<Decompress>,
+  idat_length: usize,
+}
+
+impl LimitCheck {
+  pub fn new(limits: Limits) -> LimitCheck {
+    let idat = (limits.max_inflate_ratio != usize::MAX).then(|| Decompress::new(true));
+    LimitCheck { limits, chunks: 0, idat, idat_length: 0 }
+  }
+
+  fn within(limit: &'static str, value: usize, max: usize, offset: usize) -> Result<()> {
+    if value > max {
+      return Err(Error::LimitExceeded { limit, value, max, offset });
+    }
+    Ok(())
+  }
+
+  // Called with each chunk's header, before its data is read
+  pub fn header(&mut self, length: u32, offset: usize) -> Result<()> {
+    self.chunks += 1;
+    LimitCheck::within("chunk count", self.chunks, self.limits.max_chunks, offset)?;
+    LimitCheck::within("chunk length", length as usize, self.limits.max_chunk_length, offset)
+  }
+
+  // Called with each chunk once its data has been read
+  pub fn chunk(&mut self, chunk: &Chunk, offset: usize) -> Result<()> {
+    self.data(chunk.chunk_type(), chunk.data(), offset)
+  }
+
+  // Like `chunk`, for parsers that don't build a `Chunk`
+  pub fn data(&mut self, chunk_type: &ChunkType, data: &[u8], offset: usize) -> Result<()> {
+    if *chunk_type == ChunkType::IDAT {
+      return self.image_data(data, offset);
+    }
+
+    let max = self.limits.max_inflated_size;
+    if max == usize::MAX {
+      return Ok(());
+    }
+    match compressed_stream(chunk_type, data) {
+      Some(stream) => LimitCheck::within("inflated size", zlib::inflated_size(stream, max), max, offset),
+      None => Ok(()),
+    }
+  }
+
+  // Whether `data` needs the whole of a chunk of this type at once. The
+  // image data can instead be passed a piece at a time to `image_data`.
+  pub fn needs_data(&self, chunk_type: &ChunkType) -> bool {
+    self.limits.max_inflated_size != usize::MAX && matches!(*chunk_type, ChunkType::ZTXT | ChunkType::ITXT | ChunkType::ICCP)
+  }
+
+  pub fn image_data(&mut self, data: &[u8], offset: usize) -> Result<()> {
+    let Some(decompress) = self.idat.as_mut() else {
+      return Ok(());
+    };
+    self.idat_length += data.len();
+    let max = self.limits.max_inflate_ratio.saturating_mul(self.idat_length);
+
+    let mut buffer = [0; 8 * 1024];
+    let mut input = data;
+    loop {
+      let (consumed, produced) = (decompress.total_in(), decompress.total_out());
+      let status = decompress.decompress(input, &mut buffer, FlushDecompress::None);
+      input = &input[(decompress.total_in() - consumed) as usize..];
+
+      let inflated = decompress.total_out() as usize;
+      if inflated > max {
+        let ratio = inflated.div_ceil(self.idat_length);
+        return LimitCheck::within("inflate ratio", ratio, self.limits.max_inflate_ratio, offset);
+      }
+
+      match status {
+        // Whether the stream is any good is for other checks to say
+        Ok(Status::StreamEnd) | Err(_) => {
+          self.idat = None;
+          return Ok(());
+        }
+        _ if decompress.total_out() == produced && (input.is_empty() || decompress.total_in() == consumed) => return Ok(()),
+        _ => {}
+      }
+    }
+  }
+}
+
+// The zlib stream in a zTXt, iCCP or compressed iTXt chunk, if it has one
+// where it should be
+fn compressed_stream<'a>(chunk_type: &ChunkType, data: &'a [u8]) -> Option<&'a [u8]> {
+  let after_keyword = data.iter().position(|&b| b == 0)? + 1;
+  match *chunk_type {
+    ChunkType::ZTXT | ChunkType::ICCP => data.get(after_keyword + 1..),
+    ChunkType::ITXT => {
+      if data.get(after_keyword) != Some(&1) {
+        return None;
+      }
+      // Past the flag, the method, the language tag and the translated keyword
+      let mut rest = data.get(after_keyword + 2..)?;
+      for _ in 0..2 {
+        rest = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+      }
+      Some(rest)
+    }
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::Png;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn check(limits: Limits, chunks: &[Chunk]) -> Result<()> {
+        let mut check = LimitCheck::new(limits);
+        let mut offset = Png::header().len();
+        for chunk in chunks {
+            check.header(chunk.length(), offset)?;
+            check.chunk(chunk, offset)?;
+            offset += chunk.length() as usize + 12;
+        }
+        Ok(())
+    }
+
+    fn ztxt(text: &[u8]) -> Chunk {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(zlib::deflate(text));
+        Chunk::new(ChunkType::ZTXT, data)
+    }
+
+    #[test]
+    fn test_chunk_limits() {
+        let chunks = vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![0; 100]),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ];
+        assert_eq!(check(Limits::default(), &chunks), Ok(()));
+        assert_eq!(
+            check(Limits { max_chunk_length: 99, ..Limits::NONE }, &chunks),
+            Err(Error::LimitExceeded { limit: "chunk length", value: 100, max: 99, offset: 33 })
+        );
+        assert_eq!(
+            check(Limits { max_chunks: 2, ..Limits::NONE }, &chunks),
+            Err(Error::LimitExceeded { limit: "chunk count", value: 3, max: 2, offset: 145 })
+        );
+    }
+
+    #[test]
+    fn test_default_chunk_count() {
+        let mut chunks = vec![Chunk::new(ChunkType::IHDR, vec![0; 13])];
+        chunks.extend((0..100_000).map(|_| Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![])));
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        assert_eq!(
+            Png::try_from(bytes.as_slice()).err(),
+            Some(Error::LimitExceeded { limit: "chunk count", value: 100_001, max: 100_000, offset: 8 + 25 + 99_999 * 12 })
+        );
+        assert!(Png::from_reader(bytes.as_slice()).is_err());
+        assert_eq!(Png::parse_with(&bytes, crate::png::ParseMode::Strict, Limits::NONE).unwrap().0.chunks().len(), 100_001);
+    }
+
+    #[test]
+    fn test_inflated_size() {
+        let limits = Limits { max_inflated_size: 1000, ..Limits::NONE };
+        assert_eq!(check(limits, &[ztxt(&[b'a'; 1000])]), Ok(()));
+        assert!(matches!(check(limits, &[ztxt(&[b'a'; 1_000_000])]), Err(Error::LimitExceeded { limit: "inflated size", .. })));
+
+        let mut itxt = b"Comment\0\x01\0en\0\0".to_vec();
+        itxt.extend(zlib::deflate(&[b'a'; 1_000_000]));
+        assert!(check(limits, &[Chunk::new(ChunkType::ITXT, itxt)]).is_err());
+
+        // Uncompressed iTXt and broken chunks are left to the chunk parsers
+        assert_eq!(check(limits, &[Chunk::new(ChunkType::ITXT, b"Comment\0\0\0\0\0a".to_vec())]), Ok(()));
+        assert_eq!(check(limits, &[Chunk::new(ChunkType::ZTXT, b"no keyword".to_vec())]), Ok(()));
+    }
+
+    #[test]
+    fn test_inflate_ratio() {
+        let image = zlib::deflate(&[0; 1_000_000]);
+        let split: Vec<Chunk> = image.chunks(100).map(|data| Chunk::new(ChunkType::IDAT, data.to_vec())).collect();
+
+        assert_eq!(check(Limits { max_inflate_ratio: 2000, ..Limits::NONE }, &split), Ok(()));
+        assert!(matches!(
+            check(Limits { max_inflate_ratio: 10, ..Limits::NONE }, &split),
+            Err(Error::LimitExceeded { limit: "inflate ratio", max: 10, offset: 8, .. })
+        ));
+
+        let noise: Vec<u8> = (0..10_000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let corrupt = Chunk::new(ChunkType::IDAT, noise);
+        assert_eq!(check(Limits { max_inflate_ratio: 1, ..Limits::NONE }, &[corrupt]), Ok(()));
+    }
+}
@@ -0,0 +1,180 @@
+use crate::chunks::Ihdr;
+use crate::error::{Error, Result};
+use crate::pixels::ImageData;
+
+// Payloads hidden in the pixels instead of a chunk. Every sample carries
+// some of its low bits, for 16-bit samples those of the low byte. A header
+// goes first, one bit per sample, saying how many bits per sample the
+// payload after it uses and how long it is.
+//
+//   magic (4) | bits per sample (1) | length (4) | payload
+//
+// Indexed images and samples under 8 bits are refused, flipping their low
+// bits visibly changes the image.
+const MAGIC: [u8; 4] = *b"\x89PNL";
+const HEADER_SIZE: usize = 9;
+const HEADER_SAMPLES: usize = HEADER_SIZE * 8;
+
+fn check_format(ihdr: &Ihdr) -> Result<()> {
+  if ihdr.color_type == 3 || ihdr.bit_depth < 8 {
+    return Err(Error::Payload(format!(
+      "lsb: needs 8 or 16-bit samples without a palette, not color type {} at bit depth {}",
+      ihdr.color_type, ihdr.bit_depth
+    )));
+  }
+  Ok(())
+}
+
+fn check_bits(bits: u8) -> Result<()> {
+  if !(1..=8).contains(&bits) {
+    return Err(Error::Payload(format!("lsb: {} bits per sample, expected 1 to 8", bits)));
+  }
+  Ok(())
+}
+
+fn sample_count(ihdr: &Ihdr) -> usize {
+  ihdr.width as usize * ihdr.height as usize * ihdr.channels()
+}
+
+// How many payload bytes fit in an image, using `bits` low bits of each sample
+pub fn capacity(ihdr: &Ihdr, bits: u8) -> Result<usize> {
+  check_format(ihdr)?;
+  check_bits(bits)?;
+  Ok(sample_count(ihdr).saturating_sub(HEADER_SAMPLES) * bits as usize / 8)
+}
+
+// The byte of each sample whose low bits are used. Callers check the format.
+fn carriers(image: &mut ImageData) -> impl Iterator<Item = &mut u8> {
+  let step = if image.ihdr.bit_depth == 16 { 2 } else { 1 };
+  image.data.iter_mut().skip(step - 1).step_by(step)
+}
+
+// The same bytes, read only
+pub fn samples(image: &ImageData) -> impl Iterator<Item = u8> + '_ {
+  let step = if image.ihdr.bit_depth == 16 { 2 } else { 1 };
+  image.data.iter().skip(step - 1).step_by(step).copied()
+}
+
+fn write_bits<'a>(carriers: &mut impl Iterator<Item = &'a mut u8>, data: &[u8], bits: u8) {
+  let mask = ((1u16 << bits) - 1) as u8;
+  let mut stream = data.iter().flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1)).peekable();
+
+  while stream.peek().is_some() {
+    // Capacity is checked up front, so there's always a sample left
+    let Some(carrier) = carriers.next() else { return };
+    let value = (0..bits).fold(0, |value, _| value << 1 | stream.next().unwrap_or(0));
+    *carrier = (*carrier & !mask) | value;
+  }
+}
+
+fn read_bits(carriers: &mut impl Iterator<Item = u8>, length: usize, bits: u8) -> Option<Vec<u8>> {
+  let mut data = Vec::with_capacity(length);
+  let (mut pending, mut count) = (0u32, 0);
+
+  while data.len() < length {
+    let carrier = carriers.next()?;
+    for i in (0..bits).rev() {
+      pending = pending << 1 | ((carrier >> i) & 1) as u32;
+      count += 1;
+      if count == 8 {
+        data.push(pending as u8);
+        (pending, count) = (0, 0);
+      }
+    }
+  }
+  data.truncate(length);
+  Some(data)
+}
+
+// Hides `payload` in the low `bits` bits of every sample of `image`
+pub fn embed(image: &mut ImageData, payload: &[u8], bits: u8) -> Result<()> {
+  let capacity = capacity(&image.ihdr, bits)?;
+  if payload.len() > capacity {
+    return Err(Error::Payload(format!(
+      "lsb: a {} byte payload doesn't fit, the image holds {} bytes at {} bit(s) per sample",
+      payload.len(),
+      capacity,
+      bits
+    )));
+  }
+
+  let length = payload.len() as u32;
+  let header = [&MAGIC[..], &[bits], &length.to_be_bytes()].concat();
+  let mut carriers = carriers(image);
+  write_bits(&mut carriers, &header, 1);
+  write_bits(&mut carriers, payload, bits);
+  Ok(())
+}
+
+// The payload hidden in `image` by `embed`
+pub fn extract(image: &ImageData) -> Result<Vec<u8>> {
+  check_format(&image.ihdr)?;
+  let mut carriers = samples(image);
+
+  let not_found = || Error::Payload("lsb: no payload found in the pixels".to_string());
+  let header = read_bits(&mut carriers, HEADER_SIZE, 1).ok_or_else(not_found)?;
+  if header[..4] != MAGIC {
+    return Err(not_found());
+  }
+  let bits = header[4];
+  check_bits(bits)?;
+
+  let length = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+  if length > capacity(&image.ihdr, bits)? {
+    return Err(Error::Payload(format!("lsb: header claims {} bytes, more than the image holds", length)));
+  }
+  read_bits(&mut carriers, length, bits).ok_or_else(not_found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32, height: u32, bit_depth: u8, color_type: u8) -> ImageData {
+        let ihdr = Ihdr { width, height, bit_depth, color_type, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let size = ihdr.height as usize * crate::pixels::row_bytes(&ihdr, width as usize);
+        ImageData { ihdr, data: (0..size).map(|i| (i * 37 % 256) as u8).collect() }
+    }
+
+    #[test]
+    fn test_embed_extract() {
+        for (bit_depth, color_type, bits) in [(8, 2, 1), (8, 6, 3), (16, 0, 8), (8, 4, 2)] {
+            let mut cover = image(20, 10, bit_depth, color_type);
+            let original = cover.clone();
+            let payload: Vec<u8> = (0..capacity(&cover.ihdr, bits).unwrap()).map(|i| (i * 13) as u8).collect();
+
+            embed(&mut cover, &payload, bits).unwrap();
+            assert_eq!(extract(&cover).unwrap(), payload);
+
+            // Only the low bits of each sample change
+            let high = !(((1u16 << bits) - 1) as u8);
+            let step = if bit_depth == 16 { 2 } else { 1 };
+            for (i, (a, b)) in cover.data.iter().zip(&original.data).enumerate() {
+                if (i + 1) % step == 0 {
+                    assert_eq!(a & high, b & high);
+                } else {
+                    assert_eq!(a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        // 200 pixels, 3 samples each, less the 72 header samples
+        assert_eq!(capacity(&image(20, 10, 8, 2).ihdr, 1).unwrap(), (600 - 72) / 8);
+        assert_eq!(capacity(&image(20, 10, 16, 2).ihdr, 2).unwrap(), (600 - 72) / 4);
+        assert_eq!(capacity(&image(2, 2, 8, 0).ihdr, 1).unwrap(), 0);
+        assert!(capacity(&image(20, 10, 8, 3).ihdr, 1).is_err());
+        assert!(capacity(&image(20, 10, 4, 0).ihdr, 1).is_err());
+        assert!(capacity(&image(20, 10, 8, 2).ihdr, 9).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut cover = image(10, 10, 8, 0);
+        assert!(embed(&mut cover, &[0; 100], 1).is_err());
+        assert!(extract(&cover).is_err());
+        assert!(extract(&image(2, 2, 8, 0)).is_err());
+    }
+}
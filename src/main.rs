@@ -1,35 +1,36 @@
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
 
-use chunk_type::ChunkType;
-use chunk::{Chunk, Error};
-use png::Png;
-
-// pub type Error = Box<dyn std::error::Error>;
-// pub type Result<T> = std::result::Result<T, Error>;
-
-fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, Error> {
-    use std::str::FromStr;
-
-    let chunk_type = ChunkType::from_str(chunk_type)?;
-    let data: Vec<u8> = data.bytes().collect();
-
-    Ok(Chunk::new(chunk_type, data))
-}
-
-fn testing_chunks() -> Vec<Chunk> {
-    vec![
-        chunk_from_strings("FrSt", "I am the first chunkd").unwrap(),
-        chunk_from_strings("miDl", "I am another chunkd").unwrap(),
-        chunk_from_strings("LASt", "I am the last chunkd").unwrap(),
-    ]
+use args::{Cli, GlobalArgs};
+use clap::Parser;
+use commands::ExitReason;
+use log::Level;
+use std::io::Write;
+
+// Status messages are logged at info level and printed bare, anything more
+// verbose is prefixed with its level
+fn init_logging(global: &GlobalArgs) {
+  env_logger::Builder::new()
+    .filter_level(global.log_level())
+    .format(|buf, record| match record.level() {
+      Level::Info => writeln!(buf, "{}", record.args()),
+      level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+    })
+    .init();
 }
 
 fn main() {
-  let png = Png::from_chunks(testing_chunks());
-
-  println!("{}", png)
+  let reason = match Cli::try_parse() {
+    Ok(cli) => {
+      init_logging(&cli.global);
+      commands::execute(cli)
+    }
+    Err(e) => {
+      // --help and --version also arrive here, on stdout
+      let _ = e.print();
+      if e.use_stderr() { ExitReason::Usage } else { ExitReason::Success }
+    }
+  };
+
+  std::process::exit(reason.code());
 }
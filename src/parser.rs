@@ -0,0 +1,211 @@
+use crate::chunk::{self, CrcHasher};
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::limits::{LimitCheck, Limits};
+use crate::png::{self, Png};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+// Chunk data is read and checksummed this many bytes at a time
+const BLOCK_SIZE: usize = 64 * 1024;
+
+// What `PngParser` reports about a chunk: everything but its data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkInfo {
+  pub chunk_type: ChunkType,
+  pub length: u32,
+  pub offset: usize,
+  pub crc_ok: bool,
+}
+
+// Walks the chunks of PNG files without keeping their data, up to IEND.
+// The read buffer and the list of chunks are kept from one file to the
+// next, so going through thousands of files doesn't allocate for every
+// chunk.
+pub struct PngParser {
+  buffer: Vec<u8>,
+  chunks: Vec<ChunkInfo>,
+  limits: Limits,
+}
+
+impl Default for PngParser {
+  fn default() -> Self {
+    PngParser::new()
+  }
+}
+
+impl PngParser {
+  pub fn new() -> PngParser {
+    PngParser::with_limits(Limits::default())
+  }
+
+  // Like `new`, but every file parsed has to stay within `limits`
+  pub fn with_limits(limits: Limits) -> PngParser {
+    PngParser { buffer: vec![0; BLOCK_SIZE], chunks: vec![], limits }
+  }
+
+  // The chunks of the PNG read from `reader`, valid until the next parse
+  pub fn parse<R: Read>(&mut self, mut reader: R) -> Result<&[ChunkInfo]> {
+    self.chunks.clear();
+
+    let mut header = [0; 8];
+    if png::read_full(&mut reader, &mut header)? < header.len() {
+      return Err(Error::TooSmall);
+    }
+    if &header != Png::header() {
+      return Err(Error::InvalidSignature(header));
+    }
+
+    let mut limits = LimitCheck::new(self.limits);
+    let mut offset = header.len();
+    loop {
+      let mut head = [0; 8];
+      match png::read_full(&mut reader, &mut head)? {
+        0 => break,
+        8 => {}
+        read => return Err(Error::TruncatedChunk { expected: 12, actual: read, offset }),
+      }
+
+      let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+      if length as usize > chunk::MAX_LENGTH {
+        return Err(Error::LengthFieldInvalid { length, offset });
+      }
+      let type_bytes = [head[4], head[5], head[6], head[7]];
+      let chunk_type = ChunkType::try_from(type_bytes).map_err(|_| Error::ChunkTypeNotValid(type_bytes))?;
+      limits.header(length, offset)?;
+
+      let size = length as usize + 12;
+      let truncated = |missing: usize| Error::TruncatedChunk { expected: size, actual: size - missing, offset };
+
+      // Only compressed text is kept, and only while its limit is checked
+      let mut data = vec![];
+      let needs_data = limits.needs_data(&chunk_type);
+      let mut hasher = CrcHasher::new(&chunk_type);
+      let mut remaining = length as usize;
+      while remaining > 0 {
+        let block = &mut self.buffer[..remaining.min(BLOCK_SIZE)];
+        let read = png::read_full(&mut reader, block)?;
+        hasher.update(&block[..read]);
+        remaining -= read;
+        if read < block.len() {
+          return Err(truncated(remaining + 4));
+        }
+        if chunk_type == ChunkType::IDAT {
+          limits.image_data(block, offset)?;
+        } else if needs_data {
+          data.extend_from_slice(block);
+        }
+      }
+      if needs_data {
+        limits.data(&chunk_type, &data, offset)?;
+      }
+
+      let mut crc = [0; 4];
+      let read = png::read_full(&mut reader, &mut crc)?;
+      if read < crc.len() {
+        return Err(truncated(crc.len() - read));
+      }
+
+      let crc_ok = hasher.finish() == u32::from_be_bytes(crc);
+      self.chunks.push(ChunkInfo { chunk_type, length, offset, crc_ok });
+      offset += size;
+      // Whatever follows IEND isn't part of the image
+      if chunk_type == ChunkType::IEND {
+        break;
+      }
+    }
+
+    Ok(&self.chunks)
+  }
+
+  pub fn parse_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&[ChunkInfo]> {
+    self.parse(BufReader::new(File::open(path)?))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn png_bytes() -> Vec<u8> {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![7; BLOCK_SIZE * 2 + 5]),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_parse() {
+        let bytes = png_bytes();
+        let mut parser = PngParser::new();
+
+        let chunks = parser.parse(bytes.as_slice()).unwrap();
+        let expected = vec![
+            ChunkInfo { chunk_type: ChunkType::IHDR, length: 13, offset: 8, crc_ok: true },
+            ChunkInfo { chunk_type: ChunkType::from_str("ruSt").unwrap(), length: BLOCK_SIZE as u32 * 2 + 5, offset: 33, crc_ok: true },
+            ChunkInfo { chunk_type: ChunkType::IEND, length: 0, offset: bytes.len() - 12, crc_ok: true },
+        ];
+        assert_eq!(chunks, expected);
+
+        // The parser is reused, only the new file's chunks come back
+        let mut corrupt = bytes.clone();
+        corrupt[40] ^= 1;
+        let chunks = parser.parse(corrupt.as_slice()).unwrap();
+        assert_eq!(chunks.iter().map(|chunk| chunk.crc_ok).collect::<Vec<_>>(), vec![true, false, true]);
+
+        // Bytes after IEND are never read as chunks
+        let mut trailing = bytes.clone();
+        trailing.extend(Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![]).as_bytes());
+        trailing.extend(b"PK");
+        assert_eq!(parser.parse(trailing.as_slice()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let bytes = png_bytes();
+        let mut parser = PngParser::with_limits(Limits { max_chunks: 2, ..Limits::NONE });
+        assert_eq!(
+            parser.parse(bytes.as_slice()),
+            Err(Error::LimitExceeded { limit: "chunk count", value: 3, max: 2, offset: bytes.len() - 12 })
+        );
+
+        let mut parser = PngParser::with_limits(Limits { max_chunk_length: BLOCK_SIZE, ..Limits::NONE });
+        assert!(matches!(parser.parse(bytes.as_slice()), Err(Error::LimitExceeded { limit: "chunk length", offset: 33, .. })));
+        assert_eq!(PngParser::new().parse(bytes.as_slice()).unwrap().len(), 3);
+
+        // The data limits hold even though the data isn't kept
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend(crate::zlib::deflate(&[b'a'; 1_000_000]));
+        let bytes = Png::from_chunks(vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::ZTXT, ztxt),
+            Chunk::new(ChunkType::IDAT, crate::zlib::deflate(&[0; 1_000_000])),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ])
+        .as_bytes();
+        let mut parser = PngParser::with_limits(Limits { max_inflated_size: 1000, ..Limits::NONE });
+        assert!(matches!(parser.parse(bytes.as_slice()), Err(Error::LimitExceeded { limit: "inflated size", offset: 33, .. })));
+        let mut parser = PngParser::with_limits(Limits { max_inflate_ratio: 10, ..Limits::NONE });
+        assert!(matches!(parser.parse(bytes.as_slice()), Err(Error::LimitExceeded { limit: "inflate ratio", .. })));
+        assert_eq!(PngParser::new().parse(bytes.as_slice()).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let bytes = png_bytes();
+        let mut parser = PngParser::new();
+
+        assert_eq!(parser.parse(&bytes[..4]), Err(Error::TooSmall));
+        assert_eq!(parser.parse(&b"GIF89a\0\0"[..]), Err(Error::InvalidSignature(*b"GIF89a\0\0")));
+        assert_eq!(parser.parse(&bytes[..12]), Err(Error::TruncatedChunk { expected: 12, actual: 4, offset: 8 }));
+        assert_eq!(parser.parse(&bytes[..40]), Err(Error::TruncatedChunk { expected: 12, actual: 7, offset: 33 }));
+        assert_eq!(parser.parse(&bytes[..45]), Err(Error::TruncatedChunk { expected: BLOCK_SIZE * 2 + 17, actual: 12, offset: 33 }));
+        assert_eq!(parser.parse(&bytes[..bytes.len() - 14]), Err(Error::TruncatedChunk { expected: BLOCK_SIZE * 2 + 17, actual: BLOCK_SIZE * 2 + 15, offset: 33 }));
+    }
+}
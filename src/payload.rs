@@ -0,0 +1,571 @@
+use crate::error::{Error, Result};
+use crate::zlib;
+use age::x25519::{Identity, Recipient};
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+// Packed payloads start with this, then a format version, the compression
+// method, how the rest is encrypted and flags. Anything else is a bare
+// payload, which is how every payload was stored before packing existed.
+//
+//   magic (4) | version (1) | compression (1) | encryption (1) | flags (1) | [id] | body | tag
+//
+// Named payloads carry their id, its length (1) and UTF-8 bytes, right
+// after the flags. It is part of the header, so it stays readable when the
+// body is encrypted and is covered by the tag and the passphrase AEAD.
+//
+// Passphrase encrypted bodies are the argon2 salt (16), the ChaCha20-Poly1305
+// nonce (12) and the sealed, already compressed, data. Bodies encrypted to
+// recipients are a binary age file. Authenticated payloads end with an
+// HMAC-SHA256 tag over everything before it. Version 1 had neither the
+// encryption nor the flags byte, version 2 had no flags.
+const MAGIC: [u8; 4] = *b"\x89PNE";
+const VERSION: u8 = 3;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 32;
+
+const AUTHENTICATED: u8 = 1;
+const NAMED: u8 = 2;
+
+const UNENCRYPTED: u8 = 0;
+const PASSPHRASE: u8 = 1;
+const RECIPIENTS: u8 = 2;
+
+// Everything that can lock or unlock a payload. Packing uses the passphrase
+// or the recipients, unpacking the passphrase or the identities. Both use
+// the auth key, to add and to check the tag.
+#[derive(Clone, Copy, Default)]
+pub struct Keys<'a> {
+  pub passphrase: Option<&'a str>,
+  pub recipients: &'a [Recipient],
+  pub identities: &'a [Identity],
+  pub auth_key: Option<&'a [u8]>,
+}
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 19;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+  None,
+  Zlib,
+  Zstd,
+}
+
+impl Compression {
+  fn id(self) -> u8 {
+    match self {
+      Compression::None => 0,
+      Compression::Zlib => 1,
+      Compression::Zstd => 2,
+    }
+  }
+
+  fn from_id(id: u8) -> Option<Compression> {
+    match id {
+      0 => Some(Compression::None),
+      1 => Some(Compression::Zlib),
+      2 => Some(Compression::Zstd),
+      _ => None,
+    }
+  }
+}
+
+impl FromStr for Compression {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s {
+      "none" => Ok(Compression::None),
+      "zlib" => Ok(Compression::Zlib),
+      "zstd" => Ok(Compression::Zstd),
+      _ => Err(Error::Payload(format!("unknown compression {:?}, expected none, zlib or zstd", s))),
+    }
+  }
+}
+
+impl fmt::Display for Compression {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Compression::None => "none",
+      Compression::Zlib => "zlib",
+      Compression::Zstd => "zstd",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_unsupported() -> Error {
+  Error::Payload("payload: zstd support is not compiled in, rebuild with the zstd feature".to_string())
+}
+
+fn compress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+  match compression {
+    Compression::None => Ok(data.to_vec()),
+    Compression::Zlib => Ok(zlib::deflate(data)),
+    #[cfg(feature = "zstd")]
+    Compression::Zstd => Ok(zstd::encode_all(data, ZSTD_LEVEL)?),
+    #[cfg(not(feature = "zstd"))]
+    Compression::Zstd => Err(zstd_unsupported()),
+  }
+}
+
+fn decompress(data: &[u8], compression: Compression) -> Result<Vec<u8>> {
+  match compression {
+    Compression::None => Ok(data.to_vec()),
+    Compression::Zlib => zlib::inflate("payload", data),
+    #[cfg(feature = "zstd")]
+    Compression::Zstd => zstd::decode_all(data).map_err(|e| Error::Payload(format!("payload: {}", e))),
+    #[cfg(not(feature = "zstd"))]
+    Compression::Zstd => Err(zstd_unsupported()),
+  }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+  let mut key = [0; 32];
+  Argon2::default()
+    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+    .map_err(|e| Error::Payload(format!("payload: key derivation failed: {}", e)))?;
+  Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+// The envelope header is authenticated along with the data, so the
+// compression method can't be swapped without decryption failing
+fn seal(header: &[u8], data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+  let mut salt = [0; SALT_SIZE];
+  OsRng.fill_bytes(&mut salt);
+  let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+  let sealed = derive_key(passphrase, &salt)?
+    .encrypt(&nonce, Payload { msg: data, aad: header })
+    .map_err(|_| Error::Payload("payload: encryption failed".to_string()))?;
+  Ok([&salt[..], &nonce[..], &sealed[..]].concat())
+}
+
+fn open(header: &[u8], body: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+  if body.len() < SALT_SIZE + NONCE_SIZE {
+    return Err(Error::Payload("payload: encrypted body is truncated".to_string()));
+  }
+
+  let (salt, rest) = body.split_at(SALT_SIZE);
+  let (nonce, sealed) = rest.split_at(NONCE_SIZE);
+  derive_key(passphrase, salt)?
+    .decrypt(nonce.into(), Payload { msg: sealed, aad: header })
+    .map_err(|_| Error::Payload("payload: decryption failed, wrong passphrase or corrupted data".to_string()))
+}
+
+// age authenticates the whole file itself, so unlike `seal` the envelope
+// header isn't covered. Tampering with it still fails, in decompression.
+fn seal_to(recipients: &[Recipient], data: &[u8]) -> Result<Vec<u8>> {
+  let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))
+    .map_err(|e| Error::Payload(format!("payload: {}", e)))?;
+
+  let mut sealed = vec![];
+  let mut writer = encryptor.wrap_output(&mut sealed)?;
+  writer.write_all(data)?;
+  writer.finish()?;
+  Ok(sealed)
+}
+
+fn open_with(identities: &[Identity], body: &[u8]) -> Result<Vec<u8>> {
+  let failed = |e: age::DecryptError| Error::Payload(format!("payload: {}", e));
+  let decryptor = age::Decryptor::new(body).map_err(failed)?;
+  let mut reader = decryptor.decrypt(identities.iter().map(|identity| identity as &dyn age::Identity)).map_err(failed)?;
+
+  let mut opened = vec![];
+  reader.read_to_end(&mut opened).map_err(|e| Error::Payload(format!("payload: {}", e)))?;
+  Ok(opened)
+}
+
+fn hmac(key: &[u8]) -> Hmac<Sha256> {
+  // HMAC takes keys of any length
+  <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap()
+}
+
+// Checks the tag at the end of `data` and returns what it covers
+fn verify<'a>(data: &'a [u8], key: Option<&[u8]>) -> Result<&'a [u8]> {
+  let key = key.ok_or_else(|| Error::Payload("payload: authenticated, a key is needed to verify it".to_string()))?;
+  if data.len() < TAG_SIZE {
+    return Err(Error::Payload("payload: authentication tag is truncated".to_string()));
+  }
+
+  let (signed, tag) = data.split_at(data.len() - TAG_SIZE);
+  let mut mac = hmac(key);
+  mac.update(signed);
+  mac.verify_slice(tag).map_err(|_| {
+    Error::Payload("payload: authentication failed, the payload was tampered with or the key is wrong".to_string())
+  })?;
+  Ok(signed)
+}
+
+pub fn is_packed(data: &[u8]) -> bool {
+  data.starts_with(&MAGIC)
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+  is_packed(data) && data.get(MAGIC.len()).is_some_and(|&version| version >= 2) && data.get(MAGIC.len() + 2).is_some_and(|&id| id != UNENCRYPTED)
+}
+
+pub fn is_authenticated(data: &[u8]) -> bool {
+  is_packed(data) && data.get(MAGIC.len()).is_some_and(|&version| version >= 3) && data.get(MAGIC.len() + 3).is_some_and(|&flags| flags & AUTHENTICATED != 0)
+}
+
+// Compresses `data`, encrypts it to the passphrase or recipients in `keys`,
+// and puts the header, with `id` if given, in front so `unpack` knows how to
+// undo it. With an auth key the result is tagged last, so the tag covers
+// the ciphertext.
+pub fn pack(data: &[u8], compression: Compression, keys: &Keys, id: Option<&str>) -> Result<Vec<u8>> {
+  let encryption = match (keys.passphrase, keys.recipients) {
+    (Some(_), []) => PASSPHRASE,
+    (None, []) => UNENCRYPTED,
+    (None, _) => RECIPIENTS,
+    (Some(_), _) => return Err(Error::Payload("payload: can't encrypt to both a passphrase and recipients".to_string())),
+  };
+  let mut flags = if keys.auth_key.is_some() { AUTHENTICATED } else { 0 };
+  if id.is_some() {
+    flags |= NAMED;
+  }
+  let mut packed = MAGIC.to_vec();
+  packed.extend([VERSION, compression.id(), encryption, flags]);
+  if let Some(id) = id {
+    let length = u8::try_from(id.len())
+      .ok()
+      .filter(|&length| length > 0)
+      .ok_or_else(|| Error::Payload(format!("payload: id must be 1 to 255 bytes, {:?} is {}", id, id.len())))?;
+    packed.push(length);
+    packed.extend(id.as_bytes());
+  }
+
+  let compressed = compress(data, compression)?;
+  let body = match keys.passphrase {
+    Some(passphrase) => seal(&packed, &compressed, passphrase)?,
+    None if encryption == RECIPIENTS => seal_to(keys.recipients, &compressed)?,
+    None => compressed,
+  };
+  packed.extend(body);
+
+  if let Some(key) = keys.auth_key {
+    let mut mac = hmac(key);
+    mac.update(&packed);
+    packed.extend(mac.finalize().into_bytes());
+  }
+  Ok(packed)
+}
+
+// The most `pack` adds to `length` bytes of data that doesn't compress at
+// all. Compression estimates are the libraries' own worst case bounds, and
+// an age header's random grease stanza is taken at its largest.
+pub fn overhead(length: usize, compression: Compression, keys: &Keys, id: Option<&str>) -> usize {
+  let mut overhead = MAGIC.len() + 4 + id.map_or(0, |id| 1 + id.len());
+  overhead += match compression {
+    Compression::None => 0,
+    Compression::Zlib => (length >> 12) + (length >> 14) + (length >> 25) + 13,
+    Compression::Zstd => (length >> 8) + ((128 << 10) - length.min(128 << 10)) / 2048,
+  };
+  let compressed = length + overhead;
+  if keys.passphrase.is_some() {
+    overhead += SALT_SIZE + NONCE_SIZE + 16;
+  } else if !keys.recipients.is_empty() {
+    // Version line, a stanza per recipient, the grease stanza, the MAC line,
+    // the stream nonce and a tag for every 64 KiB of data
+    overhead += 22 + 98 * keys.recipients.len() + 202 + 48 + 16 + 16 * compressed.div_ceil(64 << 10).max(1);
+  }
+  if keys.auth_key.is_some() {
+    overhead += TAG_SIZE;
+  }
+  overhead
+}
+
+// The original bytes of a packed payload. Bare payloads are returned as
+// they are. With an auth key, anything that isn't tagged with it is refused
+// before being looked at any further.
+pub fn unpack(data: &[u8], keys: &Keys) -> Result<Vec<u8>> {
+  let unauthenticated = || Error::Payload("payload: not authenticated, but a key to verify it was given".to_string());
+  if !is_packed(data) {
+    return match keys.auth_key {
+      Some(_) => Err(unauthenticated()),
+      None => Ok(data.to_vec()),
+    };
+  }
+
+  let envelope = parse_header(data)?;
+  let data = match (envelope.authenticated, keys.auth_key) {
+    (true, key) => verify(data, key)?,
+    (false, Some(_)) => return Err(unauthenticated()),
+    (false, None) => data,
+  };
+  if data.len() < envelope.size {
+    return Err(truncated());
+  }
+
+  let (header, body) = data.split_at(envelope.size);
+  let compressed = match envelope.encryption {
+    UNENCRYPTED => body.to_vec(),
+    PASSPHRASE => match keys.passphrase {
+      Some(passphrase) => open(header, body, passphrase)?,
+      None => return Err(Error::Payload("payload: encrypted with a passphrase, which is needed to decrypt it".to_string())),
+    },
+    RECIPIENTS if keys.identities.is_empty() => {
+      return Err(Error::Payload("payload: encrypted to recipients, an identity is needed to decrypt it".to_string()))
+    }
+    RECIPIENTS => open_with(keys.identities, body)?,
+    id => return Err(Error::Payload(format!("payload: unknown encryption method {}", id))),
+  };
+  decompress(&compressed, envelope.compression)
+}
+
+fn truncated() -> Error {
+  Error::Payload("payload: header is truncated".to_string())
+}
+
+// What the header of a packed payload says about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+  pub version: u8,
+  pub compression: Compression,
+  pub encrypted: bool,
+  pub authenticated: bool,
+  pub id: Option<String>,
+  encryption: u8,
+  size: usize,
+}
+
+// The header of a packed payload, None for a bare one
+pub fn envelope(data: &[u8]) -> Result<Option<Envelope>> {
+  if is_packed(data) { parse_header(data).map(Some) } else { Ok(None) }
+}
+
+fn parse_header(data: &[u8]) -> Result<Envelope> {
+  let version = *data.get(MAGIC.len()).ok_or_else(truncated)?;
+  let fixed_size = match version {
+    1 => MAGIC.len() + 2,
+    2 => MAGIC.len() + 3,
+    VERSION => MAGIC.len() + 4,
+    _ => return Err(Error::Payload(format!("payload: unsupported format version {}", version))),
+  };
+  if data.len() < fixed_size {
+    return Err(truncated());
+  }
+
+  let compression = Compression::from_id(data[MAGIC.len() + 1])
+    .ok_or_else(|| Error::Payload(format!("payload: unknown compression method {}", data[MAGIC.len() + 1])))?;
+  let encryption = if version >= 2 { data[MAGIC.len() + 2] } else { UNENCRYPTED };
+  let flags = if version >= 3 { data[MAGIC.len() + 3] } else { 0 };
+  if flags & !(AUTHENTICATED | NAMED) != 0 {
+    return Err(Error::Payload(format!("payload: unknown flags {:#04x}", flags)));
+  }
+
+  let (id, size) = if flags & NAMED != 0 {
+    let length = *data.get(fixed_size).ok_or_else(truncated)? as usize;
+    let id = data.get(fixed_size + 1..fixed_size + 1 + length).ok_or_else(truncated)?;
+    let id = String::from_utf8(id.to_vec()).map_err(|_| Error::Payload("payload: id isn't valid UTF-8".to_string()))?;
+    (Some(id), fixed_size + 1 + length)
+  } else {
+    (None, fixed_size)
+  };
+
+  Ok(Envelope {
+    version,
+    compression,
+    encrypted: encryption != UNENCRYPTED,
+    authenticated: flags & AUTHENTICATED != 0,
+    id,
+    encryption,
+    size,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_unpack() {
+        let data = b"payload ".repeat(100);
+
+        for compression in [Compression::None, Compression::Zlib] {
+            let packed = pack(&data, compression, &Keys::default(), None).unwrap();
+            assert!(is_packed(&packed));
+            assert!(!is_encrypted(&packed));
+            assert_eq!(packed[5], compression.id());
+            assert_eq!(unpack(&packed, &Keys::default()).unwrap(), data);
+        }
+        assert!(pack(&data, Compression::Zlib, &Keys::default(), None).unwrap().len() < 100);
+        // Older envelopes have fewer header bytes
+        assert_eq!(unpack(b"\x89PNE\x01\x00old", &Keys::default()).unwrap(), b"old");
+        assert_eq!(unpack(b"\x89PNE\x02\x00\x00old", &Keys::default()).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_bare_payload() {
+        assert!(!is_packed(b"just a message"));
+        assert_eq!(unpack(b"just a message", &Keys::default()).unwrap(), b"just a message");
+        assert_eq!(unpack(b"", &Keys::default()).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(unpack(b"\x89PNE", &Keys::default()).is_err());
+        assert!(unpack(b"\x89PNE\x03\x00\x00", &Keys::default()).is_err());
+        assert!(unpack(b"\x89PNE\x04\x00\x00\x00data", &Keys::default()).is_err());
+        assert!(unpack(b"\x89PNE\x03\x07\x00\x00data", &Keys::default()).is_err());
+        assert!(unpack(b"\x89PNE\x03\x00\x07\x00data", &Keys::default()).is_err());
+        assert!(unpack(b"\x89PNE\x03\x00\x00\x80data", &Keys::default()).is_err());
+        assert!(unpack(b"\x89PNE\x03\x01\x00\x00not zlib", &Keys::default()).is_err());
+    }
+
+    fn passphrase(passphrase: &str) -> Keys<'_> {
+        Keys { passphrase: Some(passphrase), ..Keys::default() }
+    }
+
+    #[test]
+    fn test_encrypted() {
+        let data = b"secret ".repeat(20);
+        let packed = pack(&data, Compression::Zlib, &passphrase("hunter2"), None).unwrap();
+
+        assert!(is_encrypted(&packed));
+        assert!(!packed.windows(6).any(|window| window == b"secret"));
+        assert_eq!(unpack(&packed, &passphrase("hunter2")).unwrap(), data);
+        assert!(unpack(&packed, &passphrase("hunter3")).is_err());
+        assert!(unpack(&packed, &Keys::default()).is_err());
+
+        // The header is authenticated
+        let mut tampered = packed.clone();
+        tampered[5] = Compression::None.id();
+        assert!(unpack(&tampered, &passphrase("hunter2")).is_err());
+        // Each envelope gets its own salt and nonce
+        assert_ne!(pack(&data, Compression::Zlib, &passphrase("hunter2"), None).unwrap(), packed);
+        assert!(unpack(&packed[..8 + SALT_SIZE], &passphrase("hunter2")).is_err());
+    }
+
+    #[test]
+    fn test_authenticated() {
+        let data = b"signed, sealed, delivered".to_vec();
+        let key = Keys { auth_key: Some(b"shared secret"), ..Keys::default() };
+        let packed = pack(&data, Compression::None, &key, None).unwrap();
+
+        assert!(is_authenticated(&packed));
+        assert_eq!(packed.len(), 8 + data.len() + TAG_SIZE);
+        assert_eq!(unpack(&packed, &key).unwrap(), data);
+
+        // Any flipped bit, header, body or tag, is caught
+        for index in [5, 10, packed.len() - 1] {
+            let mut tampered = packed.clone();
+            tampered[index] ^= 1;
+            assert!(unpack(&tampered, &key).is_err());
+        }
+        assert!(unpack(&packed, &Keys { auth_key: Some(b"wrong secret"), ..Keys::default() }).is_err());
+        assert!(unpack(&packed, &Keys::default()).is_err());
+        assert!(unpack(&packed[..20], &key).is_err());
+
+        // Nor can the tag simply be left off
+        assert!(unpack(&pack(&data, Compression::None, &Keys::default(), None).unwrap(), &key).is_err());
+        assert!(unpack(&data, &key).is_err());
+
+        let both = Keys { passphrase: Some("hunter2"), ..key };
+        let packed = pack(&data, Compression::Zlib, &both, None).unwrap();
+        assert!(is_encrypted(&packed) && is_authenticated(&packed));
+        assert_eq!(unpack(&packed, &both).unwrap(), data);
+    }
+
+    #[test]
+    fn test_recipients() {
+        let data = b"for your eyes only".to_vec();
+        let (alice, bob, eve) = (Identity::generate(), Identity::generate(), Identity::generate());
+        let recipients = [alice.to_public(), bob.to_public()];
+        let packed = pack(&data, Compression::Zlib, &Keys { recipients: &recipients, ..Keys::default() }, None).unwrap();
+
+        assert!(is_encrypted(&packed));
+        for identity in [alice, bob] {
+            assert_eq!(unpack(&packed, &Keys { identities: &[identity], ..Keys::default() }).unwrap(), data);
+        }
+        assert!(unpack(&packed, &Keys { identities: &[eve], ..Keys::default() }).is_err());
+        assert!(unpack(&packed, &passphrase("hunter2")).is_err());
+
+        let both = Keys { passphrase: Some("hunter2"), recipients: &recipients, ..Keys::default() };
+        assert!(pack(&data, Compression::None, &both, None).is_err());
+    }
+
+    #[test]
+    fn test_named() {
+        let data = b"one of several".to_vec();
+        let packed = pack(&data, Compression::Zlib, &Keys::default(), Some("notes")).unwrap();
+        let header = envelope(&packed).unwrap().unwrap();
+
+        assert_eq!(header.id.as_deref(), Some("notes"));
+        assert_eq!((header.compression, header.encrypted, header.authenticated), (Compression::Zlib, false, false));
+        assert_eq!(unpack(&packed, &Keys::default()).unwrap(), data);
+        assert_eq!(envelope(b"bare").unwrap(), None);
+        assert!(pack(&data, Compression::None, &Keys::default(), Some("")).is_err());
+        assert!(pack(&data, Compression::None, &Keys::default(), Some(&"x".repeat(256))).is_err());
+
+        // The id stays readable under encryption, but can't be changed
+        let packed = pack(&data, Compression::None, &passphrase("hunter2"), Some("notes")).unwrap();
+        assert_eq!(envelope(&packed).unwrap().unwrap().id.as_deref(), Some("notes"));
+        let mut tampered = packed.clone();
+        tampered[9] = b'N';
+        assert_eq!(envelope(&tampered).unwrap().unwrap().id.as_deref(), Some("Notes"));
+        assert!(unpack(&tampered, &passphrase("hunter2")).is_err());
+        assert!(unpack(&packed[..12], &passphrase("hunter2")).is_err());
+    }
+
+    #[test]
+    fn test_overhead() {
+        let noise = |length: usize| {
+            let mut data = vec![0; length];
+            OsRng.fill_bytes(&mut data);
+            data
+        };
+        let recipients = [Identity::generate().to_public(), Identity::generate().to_public()];
+        let options = [
+            (Compression::None, Keys::default(), None),
+            (Compression::None, Keys::default(), Some("id")),
+            (Compression::Zlib, Keys { auth_key: Some(b"key"), ..Keys::default() }, None),
+            (Compression::None, passphrase("hunter2"), Some("notes")),
+            (Compression::Zlib, Keys { recipients: &recipients, ..Keys::default() }, None),
+        ];
+
+        for (compression, keys, id) in options {
+            for length in [0, 1000, 70000] {
+                let packed = pack(&noise(length), compression, &keys, id).unwrap();
+                let bound = overhead(length, compression, &keys, id);
+                assert!(packed.len() <= length + bound);
+                assert!(packed.len() + 300 > length + bound);
+            }
+        }
+        assert_eq!(overhead(1000, Compression::None, &Keys::default(), None), 8);
+    }
+
+    #[test]
+    fn test_compression_names() {
+        for compression in [Compression::None, Compression::Zlib, Compression::Zstd] {
+            assert_eq!(Compression::from_str(&compression.to_string()), Ok(compression));
+        }
+        assert!(Compression::from_str("gzip").is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd() {
+        let data = b"payload ".repeat(100);
+        let packed = pack(&data, Compression::Zstd, &Keys::default(), None).unwrap();
+        assert!(packed.len() < 100);
+        assert_eq!(unpack(&packed, &Keys::default()).unwrap(), data);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_zstd_unsupported() {
+        assert!(pack(b"data", Compression::Zstd, &Keys::default(), None).is_err());
+        assert!(unpack(b"\x89PNE\x03\x02\x00\x00data", &Keys::default()).is_err());
+    }
+}
@@ -0,0 +1,453 @@
+use crate::error::{Error, Result};
+use crate::chunks::{Ihdr, Palette, Transparency};
+
+// Column and row of each Adam7 pass's first pixel, then the spacing between
+// its pixels
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+  (0, 0, 8, 8),
+  (4, 0, 8, 8),
+  (0, 4, 4, 8),
+  (2, 0, 4, 4),
+  (0, 2, 2, 4),
+  (1, 0, 2, 2),
+  (0, 1, 1, 2),
+];
+
+// Raw samples laid out row by row as IHDR describes them, without filter
+// bytes. Rows of images with less than 8 bits per pixel are padded to a
+// whole byte, like in the PNG stream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageData {
+  pub ihdr: Ihdr,
+  pub data: Vec<u8>,
+}
+
+impl ImageData {
+  pub fn row_bytes(&self) -> usize {
+    row_bytes(&self.ihdr, self.ihdr.width as usize)
+  }
+
+  pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+    self.data.chunks(self.row_bytes().max(1))
+  }
+
+  // Expands every pixel to 8-bit RGBA. Indexed images need their palette,
+  // and tRNS, when given, supplies alpha for the color types without it.
+  pub fn to_rgba8(&self, palette: Option<&Palette>, transparency: Option<&Transparency>) -> Result<Vec<u8>> {
+    let ihdr = &self.ihdr;
+    let channels = ihdr.channels();
+    let depth = ihdr.bit_depth;
+    let max = ((1u32 << depth) - 1) as u16;
+    let scale = |value: u16| (value as u32 * 255 / max as u32) as u8;
+
+    let mut rgba = Vec::with_capacity(ihdr.width as usize * ihdr.height as usize * 4);
+    for row in self.rows() {
+      for x in 0..ihdr.width as usize {
+        let s = |channel: usize| sample(row, x * channels + channel, depth);
+        let pixel = match (ihdr.color_type, transparency) {
+          (0, Some(&Transparency::Gray(key))) => [scale(s(0)), scale(s(0)), scale(s(0)), if s(0) == key { 0 } else { 255 }],
+          (0, _) => [scale(s(0)), scale(s(0)), scale(s(0)), 255],
+          (2, Some(&Transparency::Rgb(r, g, b))) => {
+            [scale(s(0)), scale(s(1)), scale(s(2)), if (s(0), s(1), s(2)) == (r, g, b) { 0 } else { 255 }]
+          }
+          (2, _) => [scale(s(0)), scale(s(1)), scale(s(2)), 255],
+          (3, _) => {
+            let index = s(0) as u8;
+            let entry = palette.and_then(|palette| palette.get(index)).ok_or_else(|| {
+              Error::InvalidChunkData(format!("pixel refers to palette entry {} but there is no such entry", index))
+            })?;
+            let alpha = match transparency {
+              Some(Transparency::Palette(alpha)) => alpha.get(index as usize).copied().unwrap_or(255),
+              _ => 255,
+            };
+            [entry.r, entry.g, entry.b, alpha]
+          }
+          (4, _) => [scale(s(0)), scale(s(0)), scale(s(0)), scale(s(1))],
+          _ => [scale(s(0)), scale(s(1)), scale(s(2)), scale(s(3))],
+        };
+        rgba.extend_from_slice(&pixel);
+      }
+    }
+    Ok(rgba)
+  }
+}
+
+// Reads the `index`th sample of a row, whatever its bit depth
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+  match bit_depth {
+    16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+    8 => row[index] as u16,
+    bits => {
+      let bits = bits as usize;
+      let shift = 8 - bits - (index * bits) % 8;
+      ((row[index * bits / 8] >> shift) & ((1 << bits) - 1) as u8) as u16
+    }
+  }
+}
+
+pub fn row_bytes(ihdr: &Ihdr, width: usize) -> usize {
+  (width * ihdr.bits_per_pixel()).div_ceil(8)
+}
+
+// Distance to the corresponding byte of the previous pixel, at least one
+pub fn filter_distance(ihdr: &Ihdr) -> usize {
+  ihdr.bits_per_pixel().div_ceil(8)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+  let p = a as i16 + b as i16 - c as i16;
+  let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+  if pa <= pb && pa <= pc {
+    a
+  } else if pb <= pc {
+    b
+  } else {
+    c
+  }
+}
+
+// Reverses the filter of one scanline in place, given the already unfiltered
+// previous line (all zeros for the first)
+fn unfilter_line(filter: u8, line: &mut [u8], previous: &[u8], distance: usize) -> Result<()> {
+  match filter {
+    0 => {}
+    1 => {
+      for i in distance..line.len() {
+        line[i] = line[i].wrapping_add(line[i - distance]);
+      }
+    }
+    2 => {
+      for (byte, up) in line.iter_mut().zip(previous) {
+        *byte = byte.wrapping_add(*up);
+      }
+    }
+    3 => {
+      for i in 0..line.len() {
+        let left = if i >= distance { line[i - distance] } else { 0 };
+        line[i] = line[i].wrapping_add(((left as u16 + previous[i] as u16) / 2) as u8);
+      }
+    }
+    4 => {
+      for i in 0..line.len() {
+        let (left, upper_left) = if i >= distance { (line[i - distance], previous[i - distance]) } else { (0, 0) };
+        line[i] = line[i].wrapping_add(paeth(left, previous[i], upper_left));
+      }
+    }
+    filter => return Err(Error::InvalidChunkData(format!("IDAT: unknown filter type {}", filter))),
+  }
+  Ok(())
+}
+
+fn filter_line(filter: u8, line: &[u8], previous: &[u8], distance: usize) -> Vec<u8> {
+  let left = |i: usize| if i >= distance { line[i - distance] } else { 0 };
+  let upper_left = |i: usize| if i >= distance { previous[i - distance] } else { 0 };
+
+  (0..line.len())
+    .map(|i| match filter {
+      1 => line[i].wrapping_sub(left(i)),
+      2 => line[i].wrapping_sub(previous[i]),
+      3 => line[i].wrapping_sub(((left(i) as u16 + previous[i] as u16) / 2) as u8),
+      4 => line[i].wrapping_sub(paeth(left(i), previous[i], upper_left(i))),
+      _ => line[i],
+    })
+    .collect()
+}
+
+// Filters every scanline of `pixels`. Like libpng, palette and sub-byte
+// images aren't filtered; everything else gets whichever filter gives the
+// smallest sum of absolute values, a cheap guess at what deflates best.
+fn filter(pixels: &[u8], ihdr: &Ihdr, width: usize, height: usize) -> Vec<u8> {
+  let row = row_bytes(ihdr, width);
+  if row == 0 {
+    return vec![];
+  }
+
+  let distance = filter_distance(ihdr);
+  let adaptive = ihdr.color_type != 3 && ihdr.bit_depth >= 8;
+  let score = |line: &[u8]| line.iter().map(|&b| (b as i8).unsigned_abs() as usize).sum::<usize>();
+  let zeros = vec![0; row];
+
+  let mut filtered = Vec::with_capacity((row + 1) * height);
+  for y in 0..height {
+    let line = &pixels[y * row..(y + 1) * row];
+    let previous = if y == 0 { &zeros[..] } else { &pixels[(y - 1) * row..y * row] };
+
+    let (filter, bytes) = if adaptive {
+      (0..5u8)
+        .map(|filter| (filter, filter_line(filter, line, previous, distance)))
+        .min_by_key(|(_, bytes)| score(bytes))
+        .unwrap()
+    } else {
+      (0, line.to_vec())
+    };
+    filtered.push(filter);
+    filtered.extend(bytes);
+  }
+  filtered
+}
+
+// Unfilters `height` scanlines of `width` pixels from the start of `data`,
+// returning the pixels and the number of bytes consumed
+fn unfilter(data: &[u8], ihdr: &Ihdr, width: usize, height: usize) -> Result<(Vec<u8>, usize)> {
+  let row = row_bytes(ihdr, width);
+  if width == 0 || height == 0 {
+    return Ok((vec![], 0));
+  }
+
+  let needed = (row + 1) * height;
+  if data.len() < needed {
+    return Err(Error::InvalidChunkData(format!("IDAT: image data is {} bytes, expected at least {}", data.len(), needed)));
+  }
+
+  let distance = filter_distance(ihdr);
+  let mut pixels = vec![0; row * height];
+  let zeros = vec![0; row];
+  for y in 0..height {
+    let line = &data[y * (row + 1)..(y + 1) * (row + 1)];
+    let (done, rest) = pixels.split_at_mut(y * row);
+    let current = &mut rest[..row];
+    current.copy_from_slice(&line[1..]);
+
+    let previous = if y == 0 { &zeros[..] } else { &done[(y - 1) * row..] };
+    unfilter_line(line[0], current, previous, distance)?;
+  }
+  Ok((pixels, needed))
+}
+
+// Copies pixel `from_x` of one row to pixel `to_x` of another, handling
+// pixels smaller than a byte
+fn copy_pixel(from: &[u8], from_x: usize, to: &mut [u8], to_x: usize, bits: usize) {
+  if bits >= 8 {
+    let size = bits / 8;
+    to[to_x * size..(to_x + 1) * size].copy_from_slice(&from[from_x * size..(from_x + 1) * size]);
+    return;
+  }
+
+  let mask = (1u8 << bits) - 1;
+  let shift = |x: usize| 8 - bits - (x * bits) % 8;
+  let value = (from[from_x * bits / 8] >> shift(from_x)) & mask;
+  let byte = &mut to[to_x * bits / 8];
+  *byte = (*byte & !(mask << shift(to_x))) | (value << shift(to_x));
+}
+
+// Size in pixels of an Adam7 pass, which is empty for very small images
+pub fn pass_size(ihdr: &Ihdr, pass: usize) -> (usize, usize) {
+  let (x0, y0, dx, dy) = ADAM7[pass];
+  let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+  ((width + dx - 1 - x0) / dx, (height + dy - 1 - y0) / dy)
+}
+
+// Size of the inflated IDAT stream IHDR calls for: every scanline of every
+// pass along with its filter type byte
+pub fn filtered_size(ihdr: &Ihdr) -> usize {
+  let size = |(width, height): (usize, usize)| if width == 0 { 0 } else { height * (1 + row_bytes(ihdr, width)) };
+  if ihdr.interlace_method == 0 {
+    return size((ihdr.width as usize, ihdr.height as usize));
+  }
+  (0..ADAM7.len()).map(|pass| size(pass_size(ihdr, pass))).sum()
+}
+
+// Reverses filtering and, for interlaced images, Adam7 on the inflated IDAT
+// stream
+pub fn decode(data: &[u8], ihdr: &Ihdr) -> Result<ImageData> {
+  let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+
+  if ihdr.interlace_method == 0 {
+    let (pixels, _) = unfilter(data, ihdr, width, height)?;
+    return Ok(ImageData { ihdr: *ihdr, data: pixels });
+  }
+
+  let row = row_bytes(ihdr, width);
+  let bits = ihdr.bits_per_pixel();
+  let mut pixels = vec![0; row * height];
+  let mut offset = 0;
+
+  for (pass, &(x0, y0, dx, dy)) in ADAM7.iter().enumerate() {
+    let (pass_width, pass_height) = pass_size(ihdr, pass);
+    let (pass_pixels, used) = unfilter(&data[offset..], ihdr, pass_width, pass_height)?;
+    offset += used;
+
+    let pass_row = row_bytes(ihdr, pass_width);
+    for y in 0..pass_height {
+      let from = &pass_pixels[y * pass_row..(y + 1) * pass_row];
+      let to = &mut pixels[(y0 + y * dy) * row..(y0 + y * dy + 1) * row];
+      for x in 0..pass_width {
+        copy_pixel(from, x, to, x0 + x * dx, bits);
+      }
+    }
+  }
+  Ok(ImageData { ihdr: *ihdr, data: pixels })
+}
+
+// The inverse of `decode`: interlaces the pixels if IHDR asks for it and
+// filters each scanline, ready to be deflated into IDAT
+pub fn encode(image: &ImageData) -> Result<Vec<u8>> {
+  let ihdr = &image.ihdr;
+  let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+  let row = row_bytes(ihdr, width);
+  if image.data.len() != row * height {
+    return Err(Error::InvalidChunkData(format!("image data is {} bytes, expected {}", image.data.len(), row * height)));
+  }
+
+  if ihdr.interlace_method == 0 {
+    return Ok(filter(&image.data, ihdr, width, height));
+  }
+
+  let bits = ihdr.bits_per_pixel();
+  let mut encoded = vec![];
+  for (pass, &(x0, y0, dx, dy)) in ADAM7.iter().enumerate() {
+    let (pass_width, pass_height) = pass_size(ihdr, pass);
+    let pass_row = row_bytes(ihdr, pass_width);
+    let mut pass_pixels = vec![0; pass_row * pass_height];
+
+    for y in 0..pass_height {
+      let from = &image.data[(y0 + y * dy) * row..(y0 + y * dy + 1) * row];
+      let to = &mut pass_pixels[y * pass_row..(y + 1) * pass_row];
+      for x in 0..pass_width {
+        copy_pixel(from, x0 + x * dx, to, x, bits);
+      }
+    }
+    encoded.extend(filter(&pass_pixels, ihdr, pass_width, pass_height));
+  }
+  Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8, interlace_method: u8) -> Ihdr {
+        Ihdr { width, height, bit_depth, color_type, compression_method: 0, filter_method: 0, interlace_method }
+    }
+
+    #[test]
+    fn test_unfilter_each_type() {
+        let header = ihdr(2, 1, 8, 2, 0);
+        let raw = [10, 20, 30, 15, 25, 35];
+
+        // Sub: each byte minus the byte three to the left
+        let sub = [1, 10, 20, 30, 5, 5, 5];
+        assert_eq!(decode(&sub, &header).unwrap().data, raw);
+
+        // Up, average and Paeth only look at the left neighbour on the first row
+        let up = [2, 10, 20, 30, 15, 25, 35];
+        assert_eq!(decode(&up, &header).unwrap().data, raw);
+        let average = [3, 10, 20, 30, 10, 15, 20];
+        assert_eq!(decode(&average, &header).unwrap().data, raw);
+        let paeth = [4, 10, 20, 30, 5, 5, 5];
+        assert_eq!(decode(&paeth, &header).unwrap().data, raw);
+    }
+
+    #[test]
+    fn test_unfilter_with_previous_row() {
+        let header = ihdr(2, 3, 8, 0, 0);
+        // None, then average of left and up, then Paeth picking up and left
+        let data = [0, 1, 2, 3, 4, 2, 4, 2, 3];
+        assert_eq!(decode(&data, &header).unwrap().data, [1, 2, 4, 5, 6, 9]);
+
+        let data = [0, 1, 2, 2, 1, 1, 2, 255, 0];
+        assert_eq!(decode(&data, &header).unwrap().data, [1, 2, 2, 3, 1, 3]);
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let header = ihdr(2, 2, 8, 0, 0);
+        assert!(decode(&[0, 1, 2, 0, 1], &header).is_err());
+        assert!(decode(&[5, 1, 2, 0, 1, 2], &header).is_err());
+    }
+
+    #[test]
+    fn test_row_bytes() {
+        assert_eq!(row_bytes(&ihdr(3, 1, 1, 0, 0), 3), 1);
+        assert_eq!(row_bytes(&ihdr(9, 1, 1, 0, 0), 9), 2);
+        assert_eq!(row_bytes(&ihdr(3, 1, 16, 6, 0), 3), 24);
+        assert_eq!(filter_distance(&ihdr(3, 1, 2, 0, 0)), 1);
+        assert_eq!(filter_distance(&ihdr(3, 1, 16, 2, 0)), 6);
+    }
+
+    #[test]
+    fn test_adam7() {
+        // 3x3 grayscale: passes 1, 4, 5, 6 and 7 hold 1, 1, 2, 2 and 3 pixels
+        let header = ihdr(3, 3, 8, 0, 1);
+        let sizes: Vec<(usize, usize)> = (0..7).map(|pass| pass_size(&header, pass)).collect();
+        assert_eq!(sizes, vec![(1, 1), (0, 1), (1, 0), (1, 1), (2, 1), (1, 2), (3, 1)]);
+
+        let data = [0, 0, 0, 2, 0, 6, 8, 0, 1, 0, 7, 0, 3, 4, 5];
+        assert_eq!(decode(&data, &header).unwrap().data, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_filter_each_type() {
+        let line = [10, 20, 30, 15, 25, 35];
+        let previous = [5, 5, 5, 5, 5, 5];
+
+        for filter in 0..5 {
+            let mut filtered = filter_line(filter, &line, &previous, 3);
+            unfilter_line(filter, &mut filtered, &previous, 3).unwrap();
+            assert_eq!(filtered, line);
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let images = [
+            (ihdr(5, 4, 8, 2, 0), (0..60).map(|i| (i * 7) as u8).collect::<Vec<u8>>()),
+            (ihdr(5, 4, 16, 0, 1), (0..40).map(|i| (i * 13) as u8).collect()),
+            (ihdr(9, 9, 1, 0, 1), (0..18).map(|i| (i * 37) as u8).collect()),
+            (ihdr(3, 2, 4, 3, 0), vec![0x12, 0x30, 0x45, 0x60]),
+        ];
+
+        for (header, data) in images {
+            // Padding bits at the end of sub-byte rows aren't preserved
+            let image = decode(&encode(&ImageData { ihdr: header, data: data.clone() }).unwrap(), &header).unwrap();
+            let reencoded = decode(&encode(&image).unwrap(), &header).unwrap();
+            assert_eq!(reencoded, image);
+            if header.bit_depth >= 8 {
+                assert_eq!(image.data, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_wrong_size() {
+        assert!(encode(&ImageData { ihdr: ihdr(2, 2, 8, 0, 0), data: vec![0; 3] }).is_err());
+    }
+
+    #[test]
+    fn test_adam7_sub_byte() {
+        // 2x2 at 1 bit: pass 1 holds (0,0), pass 6 (1,0) and pass 7 row 1
+        let header = ihdr(2, 2, 1, 0, 1);
+        let data = [0, 0x80, 0, 0x00, 0, 0x40];
+        assert_eq!(decode(&data, &header).unwrap().data, [0x80, 0x40]);
+    }
+
+    #[test]
+    fn test_filtered_size() {
+        assert_eq!(filtered_size(&ihdr(2, 1, 8, 2, 0)), 7);
+        assert_eq!(filtered_size(&ihdr(10, 3, 1, 0, 0)), 9);
+        // The 2x2 image above: three passes of one one-byte row each
+        assert_eq!(filtered_size(&ihdr(2, 2, 1, 0, 1)), 6);
+        let image = ImageData { ihdr: ihdr(9, 7, 8, 6, 1), data: vec![0; 9 * 7 * 4] };
+        assert_eq!(filtered_size(&image.ihdr), encode(&image).unwrap().len());
+    }
+
+    #[test]
+    fn test_to_rgba8() {
+        let gray = ImageData { ihdr: ihdr(4, 1, 2, 0, 0), data: vec![0b00_01_10_11] };
+        assert_eq!(gray.to_rgba8(None, None).unwrap(), [0, 0, 0, 255, 85, 85, 85, 255, 170, 170, 170, 255, 255, 255, 255, 255]);
+        assert_eq!(gray.to_rgba8(None, Some(&Transparency::Gray(1))).unwrap()[4..8], [85, 85, 85, 0]);
+
+        let rgb16 = ImageData { ihdr: ihdr(1, 1, 16, 2, 0), data: vec![0xff, 0xff, 0x80, 0x00, 0, 0] };
+        assert_eq!(rgb16.to_rgba8(None, None).unwrap(), [255, 127, 0, 255]);
+
+        let gray_alpha = ImageData { ihdr: ihdr(1, 1, 8, 4, 0), data: vec![10, 20] };
+        assert_eq!(gray_alpha.to_rgba8(None, None).unwrap(), [10, 10, 10, 20]);
+
+        let palette = Palette::new(vec![crate::chunks::Rgb { r: 1, g: 2, b: 3 }, crate::chunks::Rgb { r: 4, g: 5, b: 6 }]).unwrap();
+        let indexed = ImageData { ihdr: ihdr(2, 1, 4, 3, 0), data: vec![0x10] };
+        assert_eq!(indexed.to_rgba8(Some(&palette), Some(&Transparency::Palette(vec![7]))).unwrap(), [4, 5, 6, 255, 1, 2, 3, 7]);
+        assert!(indexed.to_rgba8(None, None).is_err());
+        let out_of_range = ImageData { ihdr: ihdr(1, 1, 4, 3, 0), data: vec![0x20] };
+        assert!(out_of_range.to_rgba8(Some(&palette), None).is_err());
+    }
+}
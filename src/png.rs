@@ -1,250 +1,2056 @@
-use crate::chunk::{Chunk, Error};
+use crate::checksum::crc32;
+use crate::chunk::{self, Chunk, ChunkRef, CrcHasher};
+use crate::diagnostics::{self, Diagnostic, Diagnostics};
+use crate::error::{Error, Result};
+use crate::index::{ChunkIndex, IndexEntry};
+use crate::limits::{LimitCheck, Limits};
+use crate::salvage::Salvage;
+use crate::chunk_type::ChunkType;
+use crate::chunks::{FrameControl, FrameData, Ihdr, Palette, Text, Transparency};
+use crate::pixels::{self, ImageData};
+use crate::zlib;
 use std::convert::TryFrom;
 use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use rayon::prelude::*;
 
 pub struct Png {
-  chunks: Vec<Chunk>
+  chunks: Vec<Chunk>,
+  // Whatever follows IEND in the file, kept so that it survives edits
+  trailing: Vec<u8>,
 }
 
-impl Png {
-  const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+// A PNG file mapped into memory. Chunks are parsed as borrowed views, so
+// even very large files can be inspected without being read into RAM.
+#[cfg(feature = "mmap")]
+pub struct PngMap {
+  map: memmap2::Mmap,
+}
 
-  pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-    Self { chunks }
+#[cfg(feature = "mmap")]
+impl PngMap {
+  pub fn bytes(&self) -> &[u8] {
+    &self.map
   }
-  
-  pub fn append_chunk(&mut self, chunk: Chunk) {
-    self.chunks.push(chunk)
+
+  pub fn chunks(&self) -> Result<Vec<ChunkRef<'_>>> {
+    Png::parse_borrowed(&self.map)
   }
-  
-  pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, Error> {
-    let mut index_to_remove = None;
-    
-    for (index, value) in self.chunks.iter().enumerate() {
-      if value.chunk_type().to_string() == chunk_type.to_string() {
-        index_to_remove = Some(index);
+}
+
+// Ancillary chunks the spec requires to appear in a particular place
+const BEFORE_PLTE: [&str; 5] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB"];
+const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+const BEFORE_IDAT: [&str; 4] = ["pHYs", "sPLT", "eXIf", "acTL"];
+
+// Size of the IDAT chunks written when the image data is replaced
+const IDAT_CHUNK_SIZE: usize = 8192;
+
+#[derive(Debug, PartialEq)]
+pub struct OrderViolation {
+  pub index: usize,
+  pub message: String,
+}
+
+// Checks the chunk ordering rules of the PNG spec against a list of chunk
+// type names. Missing chunks are not reported, only misplaced ones.
+pub fn check_order<S: AsRef<str>>(chunk_types: &[S]) -> Vec<OrderViolation> {
+  let names: Vec<&str> = chunk_types.iter().map(|name| name.as_ref()).collect();
+  let first = |name: &str| names.iter().position(|n| *n == name);
+  let last = |name: &str| names.iter().rposition(|n| *n == name);
+  let mut violations = vec![];
+  let mut violation = |index: usize, message: String| violations.push(OrderViolation { index, message });
+
+  // A crushed iOS PNG has its CgBI chunk in front of IHDR
+  let start = usize::from(names.first() == Some(&"CgBI"));
+  match names.get(start) {
+    Some(&name) if name != "IHDR" => violation(start, format!("first chunk is {}, expected IHDR", name)),
+    _ => {}
+  }
+
+  if let Some(iend) = last("IEND") {
+    if iend + 1 < names.len() {
+      violation(iend + 1, format!("{} chunk after IEND", names[iend + 1]));
+    }
+  }
+
+  let plte = first("PLTE");
+  let idat = first("IDAT");
+
+  if let (Some(plte), Some(idat)) = (plte, idat) {
+    if plte > idat {
+      violation(plte, "PLTE chunk after IDAT".to_string());
+    }
+  }
+
+  if let (Some(start), Some(end)) = (idat, last("IDAT")) {
+    for (index, name) in names.iter().enumerate().take(end).skip(start) {
+      if *name != "IDAT" {
+        violation(index, format!("{} chunk between IDAT chunks", name));
       }
     }
+  }
 
-    if let Some(index) = index_to_remove {
-      Ok(self.chunks.remove(index))
-    } else {
-      Err(Error::ChunkDoesNotExsist)
+  for (index, &name) in names.iter().enumerate() {
+    if plte.is_some_and(|plte| index > plte) && BEFORE_PLTE.contains(&name) {
+      violation(index, format!("{} chunk after PLTE", name));
+    }
+    if plte.is_some_and(|plte| index < plte) && AFTER_PLTE.contains(&name) {
+      violation(index, format!("{} chunk before PLTE", name));
+    }
+    let before_idat = BEFORE_PLTE.contains(&name) || AFTER_PLTE.contains(&name) || BEFORE_IDAT.contains(&name);
+    if idat.is_some_and(|idat| index > idat) && before_idat {
+      violation(index, format!("{} chunk after IDAT", name));
     }
   }
-  
-  pub const fn header() -> &'static [u8; 8] {
-    &Png::STANDARD_HEADER
+
+  violations.sort_by_key(|violation| violation.index);
+  violations
+}
+
+// Chunks the spec allows at most once per file
+const UNIQUE_CHUNK_TYPES: [&str; 15] = [
+  "IHDR", "PLTE", "IEND", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS", "pHYs", "tIME", "eXIf", "acTL",
+];
+
+// A chunk that repeats an earlier one, either a chunk type that may only
+// appear once or a tEXt chunk reusing a keyword
+#[derive(Debug, PartialEq)]
+pub struct Duplicate {
+  pub index: usize,
+  // The index of the chunk it repeats
+  pub first: usize,
+  pub keyword: Option<String>,
+}
+
+// Finds the repeats among chunks given as type names and data. Repeated
+// tEXt keywords are allowed by the spec but leave readers to pick one of
+// the values, so they're worth flagging all the same.
+pub fn find_duplicates<S: AsRef<str>, D: AsRef<[u8]>>(chunks: &[(S, D)]) -> Vec<Duplicate> {
+  let mut seen: Vec<(&str, &[u8], usize)> = vec![];
+  let mut duplicates = vec![];
+  for (index, (name, data)) in chunks.iter().enumerate() {
+    let name = name.as_ref();
+    let key = match name {
+      "tEXt" => {
+        let data = data.as_ref();
+        &data[..data.iter().position(|&b| b == 0).unwrap_or(data.len())]
+      }
+      _ if UNIQUE_CHUNK_TYPES.contains(&name) => &[],
+      _ => continue,
+    };
+    match seen.iter().find(|(n, k, _)| *n == name && *k == key) {
+      Some(&(_, _, first)) => {
+        let keyword = (name == "tEXt").then(|| key.iter().map(|&b| b as char).collect());
+        duplicates.push(Duplicate { index, first, keyword });
+      }
+      None => seen.push((name, key, index)),
+    }
   }
-  
-  pub fn chunks(&self) -> &[Chunk] {
-    self.chunks.as_slice()
+  duplicates
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CrcFix {
+  pub index: usize,
+  pub stored: u32,
+  pub computed: u32,
+}
+
+// How the parser treats a file that breaks the rules. Strict turns down
+// any bad CRC, truncated chunk or invalid chunk type. Lenient keeps
+// everything that can still be read and reports what it had to overlook.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+  #[default]
+  Strict,
+  Lenient,
+}
+
+// What a parse found besides the chunks. The diagnostics are all warnings:
+// whatever a lenient parse stepped over, each bad CRC again and any data
+// after IEND. Strict mode fails where lenient mode warns, so only data
+// after IEND shows up there.
+#[derive(Debug, Default, PartialEq)]
+pub struct ParseReport {
+  pub crc_fixes: Vec<CrcFix>,
+  pub diagnostics: Diagnostics,
+  // Where the data after IEND starts in the file, which the chunks kept
+  // can't tell once a lenient parse has skipped some
+  pub trailing_offset: usize,
+}
+
+// One APNG frame: its fcTL and the zlib stream from its IDAT or fdAT chunks
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+  pub control: FrameControl,
+  pub data: Vec<u8>,
+}
+
+// The chunks of a PNG read one at a time, each checked against its CRC.
+// Each chunk's data is read straight into the chunk and only grows as bytes
+// actually arrive, and its CRC is taken on the way. Iteration stops after
+// the first error, or after IEND. In lenient mode bad CRCs are recomputed,
+// chunks with an invalid type are skipped, and a broken length or a cut
+// off chunk ends the file instead of failing it.
+pub struct ChunkReader<R> {
+  reader: R,
+  offset: usize,
+  index: usize,
+  failed: bool,
+  ended: bool,
+  mode: ParseMode,
+  limits: LimitCheck,
+}
+
+impl<R: Read> ChunkReader<R> {
+  // Reads and checks the signature
+  pub fn new(reader: R) -> Result<ChunkReader<R>> {
+    ChunkReader::with_limits(reader, Limits::default())
   }
-  
-  pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-    for i in &self.chunks {
-      if i.chunk_type().to_string() == chunk_type.to_string() {
-        return Some(&i)
+
+  // Like `new`, but the chunks have to stay within `limits`. Lengths and
+  // counts are checked before a chunk's data is read.
+  pub fn with_limits(reader: R, limits: Limits) -> Result<ChunkReader<R>> {
+    ChunkReader::with_mode(reader, ParseMode::Strict, limits)
+  }
+
+  // Like `with_limits`, reading in `mode`. Limits hold in either mode.
+  pub fn with_mode(mut reader: R, mode: ParseMode, limits: Limits) -> Result<ChunkReader<R>> {
+    let mut header = [0; 8];
+    if read_full(&mut reader, &mut header)? < header.len() {
+      return Err(Error::TooSmall);
+    }
+    if header != Png::STANDARD_HEADER {
+      return Err(Error::InvalidSignature(header));
+    }
+    let limits = LimitCheck::new(limits);
+    Ok(ChunkReader { reader, offset: header.len(), index: 0, failed: false, ended: false, mode, limits })
+  }
+
+  // The offset, length and type of the next chunk with a valid type
+  fn read_header(&mut self) -> Result<Option<(usize, u32, ChunkType)>> {
+    loop {
+      let offset = self.offset;
+      let mut head = [0; 8];
+      match read_full(&mut self.reader, &mut head)? {
+        // End of input on a chunk boundary is the end of the file
+        0 => return Ok(None),
+        8 => {}
+        read => return Err(Error::TruncatedChunk { expected: 12, actual: read, offset }),
+      }
+
+      let length = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+      if length as usize > chunk::MAX_LENGTH {
+        return Err(Error::LengthFieldInvalid { length, offset });
+      }
+      let type_bytes = [head[4], head[5], head[6], head[7]];
+      match ChunkType::try_from(type_bytes) {
+        Ok(chunk_type) => return Ok(Some((offset, length, chunk_type))),
+        // The chunk is framed, only its type is bad, so it can be stepped over
+        Err(_) if self.mode == ParseMode::Lenient => {
+          let skipped = io::copy(&mut self.reader.by_ref().take(length as u64 + 4), &mut io::sink())? as usize + 8;
+          log::warn!("skipping {} bytes at offset {:#x}: {}", skipped, offset, Error::ChunkTypeNotValid(type_bytes));
+          self.offset += skipped;
+        }
+        Err(_) => return Err(Error::ChunkTypeNotValid(type_bytes)),
       }
     }
+  }
+
+  fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+    let Some((offset, length, chunk_type)) = self.read_header()? else {
+      return Ok(None);
+    };
+    self.limits.header(length, offset)?;
+
+    let mut data = vec![];
+    self.reader.by_ref().take(length as u64).read_to_end(&mut data)?;
+    let mut hasher = CrcHasher::new(&chunk_type);
+    hasher.update(&data);
+
+    let mut crc = [0; 4];
+    let read = data.len() + read_full(&mut self.reader, &mut crc)?;
+    let size = length as usize + 12;
+    if read + 8 < size {
+      return Err(Error::TruncatedChunk { expected: size, actual: read + 8, offset });
+    }
+
+    let chunk = Chunk::with_crc(chunk_type, data, hasher.finish());
+    if Png::check_crc(&chunk, u32::from_be_bytes(crc), offset, self.index, self.mode == ParseMode::Lenient, &mut vec![])? {
+      log::warn!("recomputed the bad CRC of {} chunk {} at offset {:#x}", chunk.chunk_type(), self.index, offset);
+    }
+    self.limits.chunk(&chunk, offset)?;
 
-    None
+    self.offset += size;
+    self.index += 1;
+    self.ended = *chunk.chunk_type() == ChunkType::IEND;
+    Ok(Some(chunk))
   }
-  
-  pub fn as_bytes(&self) -> Vec<u8> {
-    let header: Vec<u8> = Png::header().iter().copied().collect();
-    let body: Vec<u8> = self
-        .chunks
-        .iter()
-        .flat_map(|c| c.as_bytes().into_iter())
-        .collect::<Vec<_>>();
 
-    header.into_iter().chain(body.into_iter()).collect()
+  // Reads everything after IEND. Empty if the chunks haven't all been
+  // read yet, or the file ended without an IEND.
+  pub fn trailing_data(&mut self) -> Result<Vec<u8>> {
+    let mut trailing = vec![];
+    if self.ended {
+      self.reader.read_to_end(&mut trailing)?;
+    }
+    Ok(trailing)
   }
 }
 
-impl fmt::Display for Png {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-       for i in &self.chunks {
-         writeln!(f, "{}", i)?;
-       }
-      Ok(())
+impl<R: Read> Iterator for ChunkReader<R> {
+  type Item = Result<Chunk>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.failed || self.ended {
+      return None;
     }
+    let chunk = match self.read_chunk() {
+      // Nothing after a broken length field or a cut off chunk can be trusted
+      Err(e @ (Error::LengthFieldInvalid { .. } | Error::TruncatedChunk { .. })) if self.mode == ParseMode::Lenient => {
+        log::warn!("{}, dropped the rest of the file", e);
+        self.failed = true;
+        return None;
+      }
+      chunk => chunk.transpose(),
+    };
+    self.failed = matches!(chunk, Some(Err(_)));
+    chunk
+  }
 }
 
-impl TryFrom<&[u8]> for Png {
-  type Error = Error;
+impl Png {
+  const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
-  fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+  pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
+    Self { chunks, trailing: vec![] }
+  }
+  
+  // Parses `value` in `mode`, within `limits`. Whatever the mode, the
+  // signature has to be there and going over a limit is an error.
+  pub fn parse_with(value: &[u8], mode: ParseMode, limits: Limits) -> Result<(Png, ParseReport)> {
     if value.len() < Png::STANDARD_HEADER.len() {
       return Err(Error::TooSmall);
     }
 
     let mut index = 8;
     let mut chunks = vec![];
+    let mut report = ParseReport::default();
+    let mut limits = LimitCheck::new(limits);
 
     let header = &value[..index];
 
     let header: [u8; 8] = header.try_into().unwrap();
 
     if header != Png::STANDARD_HEADER {
-      return Err(Error::InvalidHeader(header));
+      return Err(Error::InvalidSignature(header));
     }
 
     while index < value.len() {
-      let inner_val = &value[index..];
-      let next_chunk = Chunk::try_from(inner_val)?;
+      let (next_chunk, stored) = match Chunk::try_from_lenient(&value[index..]) {
+        Ok(parsed) => parsed,
+        Err(e) if mode == ParseMode::Strict => return Err(e.offset_by(index)),
+        // The chunk is framed, only its type is bad, so it can be stepped over
+        Err(e @ Error::ChunkTypeNotValid(_)) => {
+          let size = chunk::framed_length(&value[index..]).map_err(|e| e.offset_by(index))? + 12;
+          log::warn!("skipping {} bytes at offset {:#x}: {}", size, index, e);
+          let message = format!("skipped a {} byte chunk: {}", size, e);
+          report.diagnostics.push(Diagnostic::warning(diagnostics::INVALID_CHUNK_TYPE, index, message));
+          index += size;
+          continue;
+        }
+        // Nothing after a broken length field can be trusted
+        Err(e) => {
+          let code = match e {
+            Error::LengthFieldInvalid { .. } => diagnostics::LENGTH_TOO_LARGE,
+            _ => diagnostics::TRUNCATED_CHUNK,
+          };
+          let message = format!("{}, dropped the last {} bytes", e.offset_by(index), value.len() - index);
+          log::warn!("{}", message);
+          report.diagnostics.push(Diagnostic::warning(code, index, message));
+          index = value.len();
+          break;
+        }
+      };
+      limits.header(next_chunk.length(), index)?;
+      if Png::check_crc(&next_chunk, stored, index, chunks.len(), mode == ParseMode::Lenient, &mut report.crc_fixes)? {
+        let message = format!("{} chunk CRC mismatch (stored {:#010x}, computed {:#010x})", next_chunk.chunk_type(), stored, next_chunk.crc());
+        report.diagnostics.push(Diagnostic::warning(diagnostics::CRC_MISMATCH, index, message).in_chunk(chunks.len()));
+      }
+      limits.chunk(&next_chunk, index)?;
+
       index += (next_chunk.length()+12) as usize;
+      let is_end = *next_chunk.chunk_type() == ChunkType::IEND;
       chunks.push(next_chunk);
+      if is_end {
+        break;
+      }
     }
 
-    Ok( Self {chunks} )
-    
+    let trailing = value.get(index..).unwrap_or_default().to_vec();
+    report.trailing_offset = index;
+    if !trailing.is_empty() {
+      log::debug!("{} bytes after IEND at offset {:#x}", trailing.len(), index);
+      report.diagnostics.push(Diagnostic::warning(diagnostics::TRAILING_DATA, index, format!("{} bytes after IEND", trailing.len())));
+    }
+    Ok((Self { chunks, trailing }, report))
   }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::chunk_type::ChunkType;
-    use crate::chunk::Chunk;
-    use std::convert::TryFrom;
+  // Parses `value` into views borrowing from it, for read-only work where
+  // copying every chunk's data into a `Png` would be wasted
+  pub fn parse_borrowed(value: &[u8]) -> Result<Vec<ChunkRef<'_>>> {
+    Png::parse_borrowed_with_limits(value, Limits::default())
+  }
 
-    fn testing_chunks() -> Vec<Chunk> {
-        vec![
-            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
-            chunk_from_strings("miDl", "I am another chunk").unwrap(),
-            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
-        ]
+  pub fn parse_borrowed_with_limits(value: &[u8], limits: Limits) -> Result<Vec<ChunkRef<'_>>> {
+    if value.len() < Png::STANDARD_HEADER.len() {
+      return Err(Error::TooSmall);
     }
 
-    fn testing_png() -> Png {
-        let chunks = testing_chunks();
-        Png::from_chunks(chunks)
+    let header: [u8; 8] = value[..8].try_into().unwrap();
+    if header != Png::STANDARD_HEADER {
+      return Err(Error::InvalidSignature(header));
     }
 
-    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, Error> {
-        use std::str::FromStr;
+    let mut limits = LimitCheck::new(limits);
+    let mut index = header.len();
+    let mut chunks = vec![];
+    while index < value.len() {
+      let chunk = ChunkRef::try_from(&value[index..]).map_err(|e| e.offset_by(index))?;
+      log::trace!("chunk {} at offset {}: {} bytes of data", chunks.len(), index, chunk.length());
+      limits.header(chunk.length(), index)?;
+      limits.data(chunk.chunk_type(), chunk.data(), index)?;
 
-        let chunk_type = ChunkType::from_str(chunk_type)?;
-        let data: Vec<u8> = data.bytes().collect();
+      index += chunk.length() as usize + 12;
+      let is_end = *chunk.chunk_type() == ChunkType::IEND;
+      chunks.push(chunk);
+      if is_end {
+        break;
+      }
+    }
 
-        Ok(Chunk::new(chunk_type, data))
+    Ok(chunks)
+  }
+
+  // Checks the stored CRC of every chunk in `value` on rayon's thread pool
+  // without building chunks, reporting each mismatch like the lenient parser
+  pub fn verify_crcs_parallel(value: &[u8]) -> Result<Vec<CrcFix>> {
+    if value.len() < Png::STANDARD_HEADER.len() {
+      return Err(Error::TooSmall);
     }
 
-    #[test]
-    fn test_from_chunks() {
-        let chunks = testing_chunks();
-        let png = Png::from_chunks(chunks);
+    let header: [u8; 8] = value[..8].try_into().unwrap();
+    if header != Png::STANDARD_HEADER {
+      return Err(Error::InvalidSignature(header));
+    }
 
-        assert_eq!(png.chunks().len(), 3);
+    let mut spans = vec![];
+    let mut rest = &value[8..];
+    while !rest.is_empty() {
+      let offset = value.len() - rest.len();
+      let length = chunk::framed_length(rest).map_err(|e| e.offset_by(offset))?;
+      let (chunk, tail) = rest.split_at(length + 12);
+      spans.push(chunk);
+      rest = tail;
+      if &chunk[4..8] == b"IEND" {
+        break;
+      }
     }
 
-    #[test]
-    fn test_valid_from_bytes() {
-        let chunk_bytes: Vec<u8> = testing_chunks()
-            .into_iter()
-            .flat_map(|chunk| chunk.as_bytes())
-            .collect();
+    Ok(spans
+      .par_iter()
+      .enumerate()
+      .filter_map(|(index, chunk)| {
+        let (covered, crc) = chunk[4..].split_at(chunk.len() - 8);
+        let stored = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+        let computed = crc32(covered);
+        (stored != computed).then_some(CrcFix { index, stored, computed })
+      })
+      .collect())
+  }
 
-        let bytes: Vec<u8> = Png::STANDARD_HEADER
-            .iter()
-            .chain(chunk_bytes.iter())
-            .copied()
-            .collect();
+  // Shared by both parsers: logs the chunk found at `offset` and either
+  // rejects a bad CRC or, when lenient, records it as a fix. Returns
+  // whether the CRC was bad and had to be fixed.
+  fn check_crc(chunk: &Chunk, stored: u32, offset: usize, index: usize, lenient: bool, fixes: &mut Vec<CrcFix>) -> Result<bool> {
+    log::trace!("chunk {} at offset {}: {} bytes of data", index, offset, chunk.length());
 
-        let png = Png::try_from(bytes.as_ref());
+    if chunk.crc() == stored {
+      log::debug!("parsed {} chunk ({} bytes), CRC {:08x} ok", chunk.chunk_type(), chunk.length(), stored);
+      return Ok(false);
+    }
 
-        assert!(png.is_ok());
+    log::debug!("{} chunk CRC mismatch: stored {:08x}, computed {:08x}", chunk.chunk_type(), stored, chunk.crc());
+    if !lenient {
+      return Err(Error::CrcMismatch(chunk.crc(), stored));
     }
+    fixes.push(CrcFix { index, stored, computed: chunk.crc() });
+    Ok(true)
+  }
 
-    #[test]
-    fn test_invalid_header() {
-        let chunk_bytes: Vec<u8> = testing_chunks()
-            .into_iter()
-            .flat_map(|chunk| chunk.as_bytes())
-            .collect();
+  // Parses `value` in lenient mode. Every chunk with a bad CRC is
+  // reported and comes back with its CRC recomputed, anything else that
+  // was stepped over is only logged.
+  pub fn try_from_lenient(value: &[u8]) -> Result<(Png, Vec<CrcFix>)> {
+    let (png, report) = Png::parse_with(value, ParseMode::Lenient, Limits::default())?;
+    Ok((png, report.crc_fixes))
+  }
 
-        let bytes: Vec<u8> = [13, 80, 78, 71, 13, 10, 26, 10]
-            .iter()
-            .chain(chunk_bytes.iter())
-            .copied()
-            .collect();
+  // Reads the signature and then one chunk at a time, so the file is never
+  // held in memory in full on top of the parsed chunks
+  pub fn from_reader<R: Read>(reader: R) -> Result<Png> {
+    Png::from_reader_with_limits(reader, Limits::default())
+  }
 
-        let png = Png::try_from(bytes.as_ref());
+  // For files that can't be trusted: reading stops with
+  // `Error::LimitExceeded` as soon as the file goes over any of `limits`
+  pub fn from_reader_with_limits<R: Read>(reader: R, limits: Limits) -> Result<Png> {
+    let mut reader = ChunkReader::with_limits(reader, limits)?;
+    let chunks = reader.by_ref().collect::<Result<Vec<Chunk>>>()?;
+    let trailing = reader.trailing_data()?;
+    Ok(Self { chunks, trailing })
+  }
 
-        assert!(png.is_err());
+  // Every chunk that can still be read from a damaged file, with an error
+  // for each stretch that had to be skipped to find the next one
+  pub fn salvage<R: Read>(reader: R) -> Salvage {
+    Salvage::new(reader)
+  }
+
+  pub fn salvage_with_limits<R: Read>(reader: R, limits: Limits) -> Salvage {
+    Salvage::with_limits(reader, limits)
+  }
+
+  // Copies the PNG in `reader` to `writer` a chunk at a time, writing
+  // whatever `f` returns in place of each chunk: the chunk itself to keep
+  // it, nothing to drop it, or other chunks alongside or instead. Only the
+  // chunk in hand is in memory, so a file of any size can be edited. Any
+  // data after IEND is copied across untouched.
+  pub fn transform<R, W, F>(reader: R, writer: W, f: F) -> Result<()>
+  where
+    R: Read,
+    W: Write,
+    F: FnMut(Chunk) -> Result<Vec<Chunk>>,
+  {
+    Png::transform_with(reader, writer, ParseMode::Strict, Limits::default(), f)
+  }
+
+  // Like `transform`, reading the chunks in `mode` and within `limits` the
+  // way `ChunkReader::with_mode` does
+  pub fn transform_with<R, W, F>(reader: R, mut writer: W, mode: ParseMode, limits: Limits, mut f: F) -> Result<()>
+  where
+    R: Read,
+    W: Write,
+    F: FnMut(Chunk) -> Result<Vec<Chunk>>,
+  {
+    let mut chunks = ChunkReader::with_mode(reader, mode, limits)?;
+    writer.write_all(Png::header())?;
+    for chunk in chunks.by_ref() {
+      for chunk in f(chunk?)? {
+        chunk.write_to(&mut writer)?;
+      }
     }
+    writer.write_all(&chunks.trailing_data()?)?;
+    writer.flush()?;
+    Ok(())
+  }
 
-    #[test]
-    fn test_invalid_chunk() {
-        let mut chunk_bytes: Vec<u8> = testing_chunks()
-            .into_iter()
-            .flat_map(|chunk| chunk.as_bytes())
-            .collect();
+  // Writes chunk by chunk, without first building the whole file in memory
+  pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+    writer.write_all(Png::header())?;
+    for chunk in &self.chunks {
+      chunk.write_to(&mut writer)?;
+    }
+    writer.write_all(&self.trailing)?;
+    Ok(())
+  }
 
-        #[rustfmt::skip]
-        let mut bad_chunk = vec![
-            0, 0, 0, 5,         // length
-            32, 117, 83, 116,   // Chunk Type (bad)
-            65, 64, 65, 66, 67, // Data
-            1, 2, 3, 4, 5       // CRC (bad)
-        ];
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Png> {
+    Png::from_reader(BufReader::new(File::open(path)?))
+  }
 
-        chunk_bytes.append(&mut bad_chunk);
+  // Finds where each chunk of the file at `path` is without reading their
+  // data, for loading just the chunks that are needed
+  pub fn index<P: AsRef<Path>>(path: P) -> Result<ChunkIndex<BufReader<File>>> {
+    ChunkIndex::new(BufReader::new(File::open(path)?))
+  }
 
-        let png = Png::try_from(chunk_bytes.as_ref());
+  #[cfg(feature = "mmap")]
+  pub fn open_mmap<P: AsRef<Path>>(path: P) -> Result<PngMap> {
+    let file = File::open(path)?;
+    // Safety: the mapping is only ever read. If another process truncates
+    // the file while it is mapped, reads may fault, as with any mmap.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
 
-        assert!(png.is_err());
+    if map.len() < Png::STANDARD_HEADER.len() {
+      return Err(Error::TooSmall);
+    }
+    let header: [u8; 8] = map[..8].try_into().unwrap();
+    if header != Png::STANDARD_HEADER {
+      return Err(Error::InvalidSignature(header));
     }
 
+    Ok(PngMap { map })
+  }
+
+  // Writes the signature and every chunk to `path`, replacing any existing file
+  pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    self.write_to(&mut file)?;
+    file.flush()?;
+    Ok(())
+  }
 
-    #[test]
-    fn test_list_chunks() {
-        let png = testing_png();
-        let chunks = png.chunks();
-        assert_eq!(chunks.len(), 3);
+  // Like `save`, but compares against the chunks already in the file and
+  // only writes what changed. A same-size change is written over the old
+  // bytes. Otherwise everything from the first changed chunk on is written
+  // and the file resized, which is a full rewrite only when the very first
+  // chunk changed. Chunks count as unchanged when their type, length and CRC
+  // match. Returns how many bytes were written.
+  pub fn save_in_place<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+    let path = path.as_ref();
+    let old = match Png::index(path) {
+      // Data after IEND moves with every chunk before it, so leave it to a
+      // full write rather than track it
+      Ok(index) if index.trailing_length() > 0 || !self.trailing.is_empty() => {
+        log::debug!("rewriting {} in full: data after IEND", path.display());
+        self.save(path)?;
+        return Ok(self.as_bytes().len());
+      }
+      Ok(index) => index.entries().to_vec(),
+      Err(e) => {
+        log::debug!("rewriting {} in full: {}", path.display(), e);
+        self.save(path)?;
+        return Ok(self.as_bytes().len());
+      }
+    };
+    let new = IndexEntry::of(self);
+
+    let same = |a: &IndexEntry, b: &IndexEntry| (a.chunk_type, a.length, a.crc) == (b.chunk_type, b.length, b.crc);
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| same(a, b)).count();
+    if prefix == old.len() && prefix == new.len() {
+      return Ok(0);
     }
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| same(a, b)).count();
 
-    #[test]
-    fn test_chunk_by_type() {
-        let png = testing_png();
-        let chunk = png.chunk_by_type("FrSt").unwrap();
-        assert_eq!(&chunk.chunk_type().to_string(), "FrSt");
-        assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
+    let end = |entries: &[IndexEntry]| entries.last().map_or(Png::header().len(), |entry| entry.offset + entry.length as usize + 12);
+    let start = old.get(prefix).map_or(end(&old), |entry| entry.offset);
+    let old_size = old.get(old.len() - suffix).map_or(end(&old), |entry| entry.offset) - start;
+    let new_size = new.get(new.len() - suffix).map_or(end(&new), |entry| entry.offset) - start;
 
+    // Past a same-size change the layout is as before, so the tail stays
+    let last = if old_size == new_size { new.len() - suffix } else { new.len() };
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.seek(SeekFrom::Start(start as u64))?;
+    let mut writer = BufWriter::new(&file);
+    for chunk in &self.chunks[prefix..last] {
+      chunk.write_to(&mut writer)?;
+    }
+    writer.flush()?;
+    drop(writer);
+    if old_size != new_size {
+      file.set_len(end(&new) as u64)?;
     }
 
-    #[test]
-    fn test_append_chunk() {
-        let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
-        let chunk = png.chunk_by_type("TeSt").unwrap();
-        assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
-        assert_eq!(&chunk.data_as_string().unwrap(), "Message");
+    let written = new.get(last).map_or(end(&new), |entry| entry.offset) - start;
+    log::debug!("wrote {} bytes of {} at offset {}", written, path.display(), start);
+    Ok(written)
+  }
+
+  pub fn append_chunk(&mut self, chunk: Chunk) {
+    // Anything after IEND is ignored by decoders, so keep IEND last
+    match self.chunks.last() {
+      Some(last) if last.chunk_type().to_string() == "IEND" => {
+        let index = self.chunks.len() - 1;
+        self.chunks.insert(index, chunk)
+      }
+      _ => self.chunks.push(chunk)
     }
+  }
+  
+  // Stores `text` under `keyword` in whichever chunk `Text::new` picks. The
+  // first text chunk of any flavor with that keyword is replaced where it
+  // is and any later ones are removed, so the keyword is only set once.
+  // Returns how many chunks were removed.
+  pub fn set_text(&mut self, keyword: &str, text: &str) -> Result<usize> {
+    let chunk = Chunk::from(Text::new(keyword, text)?);
+    let positions: Vec<usize> = (0..self.chunks.len())
+      .filter(|&index| matches!(Text::from_chunk(&self.chunks[index]), Some(Ok(text)) if text.keyword() == keyword))
+      .collect();
 
-    #[test]
-    fn test_remove_chunk() {
-        let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
-        png.remove_chunk("TeSt").unwrap();
-        let chunk = png.chunk_by_type("TeSt");
-        assert!(chunk.is_none());
+    let Some((&first, rest)) = positions.split_first() else {
+      self.append_chunk(chunk);
+      return Ok(0);
+    };
+    for &index in rest.iter().rev() {
+      self.chunks.remove(index);
     }
+    self.chunks[first] = chunk;
+    Ok(rest.len())
+  }
 
-    #[test]
-    fn test_png_from_image_file() {
-        let png = Png::try_from(&PNG_FILE[..]);
-        assert!(png.is_ok());
+  pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+    if index > self.chunks.len() {
+      return Err(Error::IndexOutOfRange(index));
     }
 
-    #[test]
-    fn test_as_bytes() {
-        let png = Png::try_from(&PNG_FILE[..]).unwrap();
-        let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.to_vec();
-        assert_eq!(actual, expected);
+    self.chunks.insert(index, chunk);
+    Ok(())
+  }
+
+  // Inserts right before the first chunk of `chunk_type`, e.g. placing
+  // ancillary chunks ahead of the first IDAT as the spec requires
+  pub fn insert_before_type(&mut self, chunk_type: impl AsRef<str>, chunk: Chunk) -> Result<()> {
+    let index = self
+      .chunks
+      .iter()
+      .position(|value| value.chunk_type().as_str() == chunk_type.as_ref())
+      .ok_or(Error::ChunkNotFound)?;
+
+    self.chunks.insert(index, chunk);
+    Ok(())
+  }
+
+  // Inserts right after the last chunk of `chunk_type`, so a run of chunks
+  // such as IDAT is never split
+  pub fn insert_after_type(&mut self, chunk_type: impl AsRef<str>, chunk: Chunk) -> Result<()> {
+    let index = self
+      .chunks
+      .iter()
+      .rposition(|value| value.chunk_type().as_str() == chunk_type.as_ref())
+      .ok_or(Error::ChunkNotFound)?;
+
+    self.chunks.insert(index + 1, chunk);
+    Ok(())
+  }
+  
+  // Swaps the data of the first chunk of `chunk_type`, keeping its position,
+  // and returns the chunk that was replaced
+  pub fn replace_chunk(&mut self, chunk_type: impl AsRef<str>, data: Vec<u8>) -> Result<Chunk> {
+    let index = self
+      .chunks
+      .iter()
+      .position(|value| value.chunk_type().as_str() == chunk_type.as_ref())
+      .ok_or(Error::ChunkNotFound)?;
+
+    let new_chunk = Chunk::try_new(*self.chunks[index].chunk_type(), data)?;
+    Ok(std::mem::replace(&mut self.chunks[index], new_chunk))
+  }
+  
+  pub fn remove_chunk(&mut self, chunk_type: impl AsRef<str>) -> Result<Chunk> {
+    let index_to_remove = self
+      .chunks
+      .iter()
+      .position(|value| value.chunk_type().as_str() == chunk_type.as_ref());
+
+    if let Some(index) = index_to_remove {
+      Ok(self.chunks.remove(index))
+    } else {
+      Err(Error::ChunkNotFound)
+    }
+  }
+  
+  // Removes every chunk matching `predicate`, returning them in file order
+  pub fn remove_chunks_where<F: FnMut(&Chunk) -> bool>(&mut self, mut predicate: F) -> Vec<Chunk> {
+    let (removed, kept) = std::mem::take(&mut self.chunks)
+      .into_iter()
+      .partition(|chunk| predicate(chunk));
+    self.chunks = kept;
+    removed
+  }
+  
+  pub const fn header() -> &'static [u8; 8] {
+    &Png::STANDARD_HEADER
+  }
+  
+  pub fn chunks(&self) -> impl ExactSizeIterator<Item = &Chunk> + DoubleEndedIterator {
+    self.chunks.iter()
+  }
+
+  // For callers that need random access by index
+  pub fn as_slice(&self) -> &[Chunk] {
+    &self.chunks
+  }
+
+  // The bytes the file had after IEND, which decoders ignore. Polyglots and
+  // a lot of steganography tools put their payload there.
+  pub fn trailing_data(&self) -> &[u8] {
+    &self.trailing
+  }
+
+  // Replaces what gets written after the last chunk, empty to strip it
+  pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+    self.trailing = data;
+  }
+
+  pub fn chunk_by_type(&self, chunk_type: impl AsRef<str>) -> Option<&Chunk> {
+    self.chunks.iter().find(|chunk| chunk.chunk_type().as_str() == chunk_type.as_ref())
+  }
+
+  // Every chunk of `chunk_type` in file order, e.g. all tEXt or IDAT chunks
+  pub fn chunks_by_type<'a>(&'a self, chunk_type: impl AsRef<str> + 'a) -> impl Iterator<Item = &'a Chunk> + 'a {
+    self
+      .chunks
+      .iter()
+      .filter(move |chunk| chunk.chunk_type().as_str() == chunk_type.as_ref())
+  }
+
+  // Removes every chunk of `chunk_type`, returning them in file order
+  pub fn remove_all_by_type(&mut self, chunk_type: impl AsRef<str>) -> Vec<Chunk> {
+    self.remove_chunks_where(|chunk| chunk.chunk_type().as_str() == chunk_type.as_ref())
+  }
+  
+  pub fn validate_order(&self) -> Vec<OrderViolation> {
+    let names: Vec<String> = self.chunks.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+    check_order(&names)
+  }
+
+  pub fn find_duplicates(&self) -> Vec<Duplicate> {
+    let chunks: Vec<(&str, &[u8])> = self.chunks.iter().map(|chunk| (chunk.chunk_type().as_str(), chunk.data())).collect();
+    find_duplicates(&chunks)
+  }
+
+  pub fn ihdr(&self) -> Result<Ihdr> {
+    match self.chunk_by_type("IHDR") {
+      Some(chunk) => Ihdr::try_from(chunk),
+      None => Err(Error::ChunkNotFound)
+    }
+  }
+  
+  // The compressed image data: every IDAT chunk's data, in order
+  pub fn compressed_image_data(&self) -> Vec<u8> {
+    self.chunks_by_type("IDAT").flat_map(|chunk| chunk.data().iter().copied()).collect()
+  }
+
+  // Inflates the IDAT stream into filtered scanlines, each starting with its
+  // filter type byte
+  pub fn image_data(&self) -> Result<Vec<u8>> {
+    if self.chunk_by_type("IDAT").is_none() {
+      return Err(Error::ChunkNotFound);
+    }
+    zlib::inflate("IDAT", &self.compressed_image_data())
+  }
+
+  // Inflates the IDAT stream just to confirm it's intact, Adler-32 included,
+  // since chunk CRCs can't catch a payload that was corrupted before it was
+  // framed. Returns the inflated size.
+  pub fn check_image_data(&self) -> Result<usize> {
+    if self.chunk_by_type("IDAT").is_none() {
+      return Err(Error::ChunkNotFound);
+    }
+
+    zlib::check(&self.compressed_image_data()).map_err(|(position, message)| {
+      let mut offset = Png::header().len();
+      let mut spans = vec![];
+      for chunk in &self.chunks {
+        if chunk.chunk_type().to_string() == "IDAT" {
+          spans.push((offset + 8, chunk.data().len()));
+        }
+        offset += chunk.length() as usize + 12;
+      }
+      Error::InvalidChunkData(format!("IDAT: {} at offset {:#x}", message, stream_offset(spans, position)))
+    })
+  }
+
+  // Unfilters and deinterlaces the image data into raw samples
+  pub fn decode_pixels(&self) -> Result<ImageData> {
+    pixels::decode(&self.image_data()?, &self.ihdr()?)
+  }
+
+  // A minimal PNG holding just `image`: IHDR, IDAT and IEND
+  pub fn from_image_data(image: &ImageData) -> Result<Png> {
+    let mut png = Png::from_chunks(vec![Chunk::new(ChunkType::IEND, vec![])]);
+    png.set_image_data(image)?;
+    Ok(png)
+  }
+
+  // Decodes the image to 8-bit RGBA, looking up palette entries in PLTE and
+  // taking transparency from tRNS
+  pub fn decode_rgba8(&self) -> Result<Vec<u8>> {
+    let image = self.decode_pixels()?;
+    let palette = self.chunk_by_type("PLTE").map(Palette::try_from).transpose()?;
+    let transparency = self.chunk_by_type("tRNS").map(|chunk| Transparency::decode(chunk, &image.ihdr)).transpose()?;
+    image.to_rgba8(palette.as_ref(), transparency.as_ref())
+  }
+
+  // Replaces the image with `image`: its pixels are filtered, deflated and
+  // split into IDAT chunks where the old ones were, and IHDR is rewritten
+  // to match. Every other chunk is kept.
+  pub fn set_image_data(&mut self, image: &ImageData) -> Result<()> {
+    image.ihdr.validate()?;
+    let compressed = zlib::deflate(&pixels::encode(image)?);
+    self.set_compressed_image_data(&compressed, IDAT_CHUNK_SIZE);
+
+    match self.chunks.first().map(|chunk| chunk.chunk_type().to_string()) {
+      Some(first) if first == "IHDR" => self.chunks[0] = Chunk::from(image.ihdr),
+      _ => self.chunks.insert(0, Chunk::from(image.ihdr)),
+    }
+    Ok(())
+  }
+
+  // Swaps the IDAT chunks for `compressed` split into chunks of at most
+  // `chunk_size` bytes, placed where the first IDAT was or before IEND
+  pub fn set_compressed_image_data(&mut self, compressed: &[u8], chunk_size: usize) {
+    let position = self.chunks.iter().position(|chunk| chunk.chunk_type().to_string() == "IDAT");
+    self.remove_all_by_type("IDAT");
+
+    let idat = compressed.chunks(chunk_size.max(1)).map(|data| Chunk::new(ChunkType::IDAT, data.to_vec()));
+    let at = position.unwrap_or_else(|| match self.chunks.last() {
+      Some(last) if last.chunk_type().to_string() == "IEND" => self.chunks.len() - 1,
+      _ => self.chunks.len(),
+    });
+    self.chunks.splice(at..at, idat);
+  }
+
+  // Redistributes the compressed image data over IDAT chunks of at most
+  // `chunk_size` bytes, leaving the data itself untouched. Returns the new
+  // number of IDAT chunks.
+  pub fn resplit_idat(&mut self, chunk_size: usize) -> usize {
+    if self.chunk_by_type("IDAT").is_none() {
+      return 0;
+    }
+
+    self.set_compressed_image_data(&self.compressed_image_data(), chunk_size);
+    self.chunks_by_type("IDAT").count()
+  }
+
+  // Reassembles the frames of an APNG. IDAT only belongs to the animation
+  // when an fcTL comes before it, otherwise it's a default image that
+  // players without APNG support show instead.
+  pub fn frames(&self) -> Result<Vec<Frame>> {
+    if self.chunk_by_type("acTL").is_none() {
+      return Err(Error::ChunkNotFound);
+    }
+
+    let mut frames: Vec<Frame> = vec![];
+    for chunk in &self.chunks {
+      match &chunk.chunk_type().to_string()[..] {
+        "fcTL" => frames.push(Frame { control: FrameControl::try_from(chunk)?, data: vec![] }),
+        // Only the first frame can be made of IDAT chunks
+        "IDAT" if frames.len() == 1 => frames[0].data.extend_from_slice(chunk.data()),
+        "fdAT" => match frames.last_mut() {
+          Some(frame) => frame.data.extend(FrameData::try_from(chunk)?.data),
+          None => return Err(Error::InvalidChunkData("fdAT chunk before the first fcTL".to_string())),
+        },
+        _ => {}
+      }
+    }
+    Ok(frames)
+  }
+
+  // A standalone PNG showing just `frame`, without composing it onto the
+  // previous frames. Chunks that describe the image as a whole, such as PLTE
+  // or gAMA, are copied over.
+  pub fn frame_png(&self, frame: &Frame) -> Result<Png> {
+    let mut ihdr = self.ihdr()?;
+    ihdr.width = frame.control.width;
+    ihdr.height = frame.control.height;
+
+    let mut chunks = vec![Chunk::from(ihdr)];
+    for chunk in &self.chunks[1..] {
+      match &chunk.chunk_type().to_string()[..] {
+        "IDAT" | "fdAT" | "IEND" => break,
+        "acTL" | "fcTL" => {}
+        _ => chunks.push(chunk.clone()),
+      }
+    }
+    chunks.push(Chunk::new(ChunkType::IDAT, frame.data.clone()));
+    chunks.push(Chunk::new(ChunkType::IEND, vec![]));
+    Ok(Png::from_chunks(chunks))
+  }
+
+  pub fn as_bytes(&self) -> Vec<u8> {
+    let header: Vec<u8> = Png::header().to_vec();
+    let body: Vec<u8> = self
+        .chunks
+        .iter()
+        .flat_map(|c| c.as_bytes().into_iter())
+        .collect::<Vec<_>>();
+
+    header.into_iter().chain(body).chain(self.trailing.iter().copied()).collect()
+  }
+}
+
+impl IntoIterator for Png {
+  type Item = Chunk;
+  type IntoIter = std::vec::IntoIter<Chunk>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.chunks.into_iter()
+  }
+}
+
+impl<'a> IntoIterator for &'a Png {
+  type Item = &'a Chunk;
+  type IntoIter = std::slice::Iter<'a, Chunk>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.chunks.iter()
+  }
+}
+
+// Maps `position` in the concatenated IDAT data to a file offset, given the
+// file offset and length of each IDAT chunk's data. Positions past the end
+// map to the end of the last IDAT.
+pub fn stream_offset(spans: impl IntoIterator<Item = (usize, usize)>, position: usize) -> usize {
+  let mut remaining = position;
+  let mut end = 0;
+  for (offset, length) in spans {
+    if remaining < length {
+      return offset + remaining;
+    }
+    remaining -= length;
+    end = offset + length;
+  }
+  end
+}
+
+// Like `Read::read_exact`, but reports how much was read when the input
+// ends early instead of failing
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+  let mut filled = 0;
+  while filled < buf.len() {
+    match reader.read(&mut buf[filled..]) {
+      Ok(0) => break,
+      Ok(read) => filled += read,
+      Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+      Err(e) => return Err(e.into()),
+    }
+  }
+  Ok(filled)
+}
+
+impl fmt::Display for Png {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+       for i in &self.chunks {
+         writeln!(f, "{}", i)?;
+       }
+      Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+  type Error = Error;
+
+  fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+    Png::parse_with(value, ParseMode::Strict, Limits::default()).map(|(png, _)| png)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+    use std::convert::TryFrom;
+
+    // Small as a file, but a megabyte of text and of image data inflated
+    fn limited_png() -> Png {
+        let mut ztxt = b"Comment\0\0".to_vec();
+        ztxt.extend(zlib::deflate(&[b'a'; 1_000_000]));
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::ZTXT, ztxt),
+            Chunk::new(ChunkType::IDAT, zlib::deflate(&[0; 1_000_000])),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ])
+    }
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
+    }
+
+    fn testing_png() -> Png {
+        let chunks = testing_chunks();
+        Png::from_chunks(chunks)
+    }
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
+        use std::str::FromStr;
+
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let data: Vec<u8> = data.bytes().collect();
+
+        Ok(Chunk::new(chunk_type, data))
+    }
+
+    #[test]
+    fn test_from_chunks() {
+        let chunks = testing_chunks();
+        let png = Png::from_chunks(chunks);
+
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_valid_from_bytes() {
+        let chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        let bytes: Vec<u8> = Png::STANDARD_HEADER
+            .iter()
+            .chain(chunk_bytes.iter())
+            .copied()
+            .collect();
+
+        let png = Png::try_from(bytes.as_ref());
+
+        assert!(png.is_ok());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        let bytes: Vec<u8> = [13, 80, 78, 71, 13, 10, 26, 10]
+            .iter()
+            .chain(chunk_bytes.iter())
+            .copied()
+            .collect();
+
+        let png = Png::try_from(bytes.as_ref());
+
+        assert!(png.is_err());
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let mut chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        #[rustfmt::skip]
+        let mut bad_chunk = vec![
+            0, 0, 0, 5,         // length
+            32, 117, 83, 116,   // Chunk Type (bad)
+            65, 64, 65, 66, 67, // Data
+            1, 2, 3, 4, 5       // CRC (bad)
+        ];
+
+        chunk_bytes.append(&mut bad_chunk);
+
+        let png = Png::try_from(chunk_bytes.as_ref());
+
+        assert!(png.is_err());
+    }
+
+
+    #[test]
+    fn test_lenient_fixes_bad_crc() {
+        let mut bytes = PNG_FILE.to_vec();
+        // Last byte of the IHDR CRC
+        bytes[32] ^= 0xff;
+
+        assert!(Png::try_from(bytes.as_ref()).is_err());
+
+        let (png, fixes) = Png::try_from_lenient(bytes.as_ref()).unwrap();
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].index, 0);
+        assert_eq!(fixes[0].computed, png.as_slice()[0].crc());
+        assert_ne!(fixes[0].stored, fixes[0].computed);
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_verify_crcs_parallel() {
+        assert_eq!(Png::verify_crcs_parallel(&PNG_FILE), Ok(vec![]));
+
+        let mut corrupt = PNG_FILE.to_vec();
+        corrupt[32] ^= 0xff;
+        let (_, fixes) = Png::try_from_lenient(&corrupt).unwrap();
+        assert_eq!(Png::verify_crcs_parallel(&corrupt), Ok(fixes));
+        corrupt.extend(b"after the end");
+        assert_eq!(Png::verify_crcs_parallel(&corrupt).unwrap().len(), 1);
+
+        assert_eq!(Png::verify_crcs_parallel(&PNG_FILE[..20]), Err(Error::TruncatedChunk { expected: 25, actual: 12, offset: 8 }));
+    }
+
+    #[test]
+    fn test_parse_modes() {
+        let mut bytes = PNG_FILE.to_vec();
+        // The type of the second chunk, after a 13 byte IHDR
+        bytes[37] = b'1';
+        bytes[32] ^= 0xff;
+        // Cut into IEND, leaving its header
+        bytes.truncate(bytes.len() - 4);
+        let chunk_count = Png::try_from(&PNG_FILE[..]).unwrap().chunks().len();
+
+        // Strict stops at the first problem
+        assert!(matches!(Png::parse_with(&bytes, ParseMode::Strict, Limits::NONE), Err(Error::CrcMismatch(_, _))));
+        bytes[32] ^= 0xff;
+        assert_eq!(Png::parse_with(&bytes, ParseMode::Strict, Limits::NONE).err(), Some(Error::ChunkTypeNotValid(*b"1RGB")));
+        bytes[32] ^= 0xff;
+
+        let (png, report) = Png::parse_with(&bytes, ParseMode::Lenient, Limits::NONE).unwrap();
+        assert_eq!(png.chunks().len(), chunk_count - 2);
+        assert_eq!(report.crc_fixes.len(), 1);
+        assert_eq!(report.crc_fixes[0].index, 0);
+        let found: Vec<(&str, usize, Option<usize>)> = report.diagnostics.iter().map(|d| (d.code, d.offset, d.chunk)).collect();
+        assert_eq!(found, vec![
+            (diagnostics::CRC_MISMATCH, 8, Some(0)),
+            (diagnostics::INVALID_CHUNK_TYPE, 33, None),
+            (diagnostics::TRUNCATED_CHUNK, PNG_FILE.len() - 12, None),
+        ]);
+        let warnings: Vec<&Diagnostic> = report.diagnostics.warnings().collect();
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[1].message.starts_with("skipped a "));
+        assert!(warnings[2].message.ends_with("dropped the last 8 bytes"));
+
+        // A length over 2^31 - 1 is a warning too, and nothing after it is read
+        let mut oversized = PNG_FILE.to_vec();
+        oversized[33..37].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        let (png, report) = Png::parse_with(&oversized, ParseMode::Lenient, Limits::NONE).unwrap();
+        assert_eq!(png.chunks().len(), 1);
+        assert_eq!(report.diagnostics.iter().next().unwrap().code, diagnostics::LENGTH_TOO_LARGE);
+        assert_eq!(report.diagnostics.iter().next().unwrap().message, format!(
+            "chunk at offset 0x21 claims 2147483648 bytes of data, over the 2^31 - 1 limit, dropped the last {} bytes",
+            PNG_FILE.len() - 33
+        ));
+        assert_eq!(
+            Png::parse_with(&oversized, ParseMode::Strict, Limits::NONE).err(),
+            Some(Error::LengthFieldInvalid { length: 0x8000_0000, offset: 33 })
+        );
+
+        // Limits hold in lenient mode too
+        let limits = Limits { max_chunks: 1, ..Limits::NONE };
+        assert!(matches!(Png::parse_with(&bytes, ParseMode::Lenient, limits), Err(Error::LimitExceeded { .. })));
+        assert_eq!(Png::parse_with(&bytes[..4], ParseMode::Lenient, Limits::NONE).err(), Some(Error::TooSmall));
+    }
+
+    #[test]
+    fn test_lenient_without_errors() {
+        let (_, fixes) = Png::try_from_lenient(&PNG_FILE[..]).unwrap();
+        assert!(fixes.is_empty());
+    }
+
+    #[test]
+    fn test_list_chunks() {
+        let png = testing_png();
+        let chunks = png.as_slice();
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_chunk_by_type() {
+        let png = testing_png();
+        let chunk = png.chunk_by_type("FrSt").unwrap();
+        assert_eq!(&chunk.chunk_type().to_string(), "FrSt");
+        assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
+
+    }
+
+    #[test]
+    fn test_append_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let chunk = png.chunk_by_type("TeSt").unwrap();
+        assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunk.data_as_string().unwrap(), "Message");
+    }
+
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let chunks = png.as_slice();
+        assert_eq!(&chunks[3].chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunks[4].chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = testing_png();
+        png.insert_chunk(0, chunk_from_strings("TeSt", "Start").unwrap()).unwrap();
+        png.insert_chunk(4, chunk_from_strings("TeSt", "End").unwrap()).unwrap();
+
+        let chunks = png.as_slice();
+        assert_eq!(&chunks[0].data_as_string().unwrap(), "Start");
+        assert_eq!(&chunks[1].chunk_type().to_string(), "FrSt");
+        assert_eq!(&chunks[4].data_as_string().unwrap(), "End");
+    }
+
+    #[test]
+    fn test_insert_chunk_out_of_range() {
+        let mut png = testing_png();
+        let result = png.insert_chunk(4, chunk_from_strings("TeSt", "Message").unwrap());
+        assert_eq!(result, Err(Error::IndexOutOfRange(4)));
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_insert_before_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+        png.insert_before_type("miDl", chunk_from_strings("TeSt", "inserted").unwrap()).unwrap();
+
+        let types: Vec<String> = png.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "TeSt", "miDl", "LASt", "miDl"]);
+    }
+
+    #[test]
+    fn test_insert_after_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+        png.insert_after_type("miDl", chunk_from_strings("TeSt", "inserted").unwrap()).unwrap();
+
+        let types: Vec<String> = png.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "miDl", "LASt", "miDl", "TeSt"]);
+    }
+
+    #[test]
+    fn test_insert_by_missing_type() {
+        let mut png = testing_png();
+        let before = png.insert_before_type("IDAT", chunk_from_strings("TeSt", "a").unwrap());
+        let after = png.insert_after_type("IDAT", chunk_from_strings("TeSt", "b").unwrap());
+
+        assert_eq!(before, Err(Error::ChunkNotFound));
+        assert_eq!(after, Err(Error::ChunkNotFound));
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let mut png = testing_png();
+        let old = png.replace_chunk("miDl", b"Replaced".to_vec()).unwrap();
+
+        assert_eq!(&old.data_as_string().unwrap(), "I am another chunk");
+        let chunk = &png.as_slice()[1];
+        assert_eq!(&chunk.chunk_type().to_string(), "miDl");
+        assert_eq!(&chunk.data_as_string().unwrap(), "Replaced");
+        assert_eq!(chunk.length(), 8);
+        assert_eq!(chunk.crc(), chunk_from_strings("miDl", "Replaced").unwrap().crc());
+        assert_eq!(png.replace_chunk("TeSt", vec![]).err(), Some(Error::ChunkNotFound));
+        let oversized = vec![0; chunk::MAX_LENGTH + 1];
+        assert_eq!(png.replace_chunk("miDl", oversized).err(), Some(Error::ChunkTooLarge(chunk::MAX_LENGTH + 1)));
+    }
+
+    #[test]
+    fn test_set_text() {
+        use crate::chunks::{CompressedText, TextChunk};
+
+        let mut png = testing_png();
+        assert_eq!(png.set_text("Author", "Jane"), Ok(0));
+        png.insert_chunk(1, CompressedText::new("Author", "old").unwrap().into()).unwrap();
+        png.append_chunk(TextChunk::new("Title", "kept").unwrap().into());
+        png.append_chunk(TextChunk::new("Author", "older").unwrap().into());
+
+        // The zTXt at 1 is replaced and both tEXt chunks after it are removed
+        assert_eq!(png.set_text("Author", "Jürgen"), Ok(2));
+        let keywords: Vec<String> = png.chunks().filter_map(|chunk| Text::from_chunk(chunk)?.ok()).map(|text| text.keyword().to_string()).collect();
+        assert_eq!(keywords, vec!["Author", "Title"]);
+        assert_eq!(png.chunks().len(), 5);
+        assert_eq!(png.as_slice()[1].chunk_type().as_str(), "tEXt");
+        assert_eq!(png.as_slice()[1].data(), b"Author\0J\xfcrgen");
+
+        // Text that Latin-1 can't hold goes in an iTXt
+        assert_eq!(png.set_text("Author", "山田"), Ok(0));
+        assert_eq!(png.as_slice()[1].chunk_type().as_str(), "iTXt");
+        assert_eq!(Text::from_chunk(&png.as_slice()[1]).unwrap().unwrap().text(), Ok("山田".to_string()));
+        assert!(png.set_text("", "no keyword").is_err());
+    }
+
+    #[test]
+    fn test_remove_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.remove_chunk("TeSt").unwrap();
+        let chunk = png.chunk_by_type("TeSt");
+        assert!(chunk.is_none());
+    }
+
+    #[test]
+    fn test_remove_chunk_removes_first_match() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "First").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Second").unwrap());
+        let removed = png.remove_chunk("TeSt").unwrap();
+        assert_eq!(&removed.data_as_string().unwrap(), "First");
+        let chunk = png.chunk_by_type("TeSt").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "Second");
+    }
+
+    #[test]
+    fn test_remove_missing_chunk() {
+        let mut png = testing_png();
+        assert_eq!(png.remove_chunk("TeSt").err(), Some(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut png = testing_png();
+        let removed = png.remove_chunks_where(|c| !c.chunk_type().is_critical());
+
+        assert_eq!(removed.len(), 1);
+        assert_eq!(&removed[0].chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.remove_chunks_where(|_| false).is_empty());
+    }
+
+    fn messages(violations: &[OrderViolation]) -> Vec<(usize, &str)> {
+        violations.iter().map(|v| (v.index, v.message.as_str())).collect()
+    }
+
+    #[test]
+    fn test_check_order_valid() {
+        let names = ["IHDR", "gAMA", "PLTE", "tRNS", "pHYs", "IDAT", "IDAT", "tEXt", "IEND"];
+        assert!(check_order(&names).is_empty());
+        assert!(Png::try_from(&PNG_FILE[..]).unwrap().validate_order().is_empty());
+    }
+
+    #[test]
+    fn test_check_order_critical() {
+        let names = ["tEXt", "IHDR", "IDAT", "tIME", "IDAT", "PLTE", "IEND", "zTXt"];
+        assert_eq!(messages(&check_order(&names)), vec![
+            (0, "first chunk is tEXt, expected IHDR"),
+            (3, "tIME chunk between IDAT chunks"),
+            (5, "PLTE chunk after IDAT"),
+            (7, "zTXt chunk after IEND"),
+        ]);
+    }
+
+    #[test]
+    fn test_check_order_cgbi() {
+        assert!(check_order(&["CgBI", "IHDR", "IDAT", "IEND"]).is_empty());
+        assert_eq!(messages(&check_order(&["CgBI", "IDAT", "IEND"])), vec![(1, "first chunk is IDAT, expected IHDR")]);
+        assert_eq!(messages(&check_order(&["IHDR", "CgBI"])), vec![]);
+    }
+
+    #[test]
+    fn test_check_order_ancillary() {
+        let names = ["IHDR", "tRNS", "PLTE", "sRGB", "IDAT", "pHYs", "IEND"];
+        assert_eq!(messages(&check_order(&names)), vec![
+            (1, "tRNS chunk before PLTE"),
+            (3, "sRGB chunk after PLTE"),
+            (5, "pHYs chunk after IDAT"),
+        ]);
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let mut png = testing_png();
+        png.insert_chunk(0, chunk_from_strings("IHDR", "header").unwrap()).unwrap();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        assert!(png.validate_order().is_empty());
+
+        png.append_chunk(chunk_from_strings("IHDR", "again").unwrap());
+        assert_eq!(messages(&png.validate_order()), vec![]);
+        png.insert_chunk(6, chunk_from_strings("teXt", "late").unwrap()).unwrap();
+        assert_eq!(messages(&png.validate_order()), vec![(6, "teXt chunk after IEND")]);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let names = [("IHDR", &b""[..]), ("tEXt", b"Title\0a"), ("tIME", b""), ("tEXt", b"Title\0b"), ("tEXt", b"Title"), ("tIME", b"")];
+        assert_eq!(find_duplicates(&names), vec![
+            Duplicate { index: 3, first: 1, keyword: Some("Title".to_string()) },
+            Duplicate { index: 4, first: 1, keyword: Some("Title".to_string()) },
+            Duplicate { index: 5, first: 2, keyword: None },
+        ]);
+
+        let mut png = testing_png();
+        assert!(png.find_duplicates().is_empty());
+        png.insert_chunk(0, chunk_from_strings("IHDR", "header").unwrap()).unwrap();
+        png.insert_chunk(2, chunk_from_strings("IHDR", "again").unwrap()).unwrap();
+        assert_eq!(png.find_duplicates(), vec![Duplicate { index: 2, first: 0, keyword: None }]);
+    }
+
+    fn apng_chunks(idat_in_animation: bool) -> Vec<Chunk> {
+        use crate::chunks::{AnimationControl, BlendOp, DisposeOp};
+
+        let fctl = |sequence_number, width| Chunk::from(FrameControl {
+            sequence_number,
+            width,
+            height: 1,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        });
+        let fdat = |sequence_number, data: &[u8]| Chunk::from(FrameData { sequence_number, data: data.to_vec() });
+
+        let mut chunks = vec![
+            Chunk::from(Ihdr {
+                width: 4,
+                height: 1,
+                bit_depth: 8,
+                color_type: 3,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            }),
+            chunk_from_strings("PLTE", "abc").unwrap(),
+            Chunk::from(AnimationControl { num_frames: 2, num_plays: 0 }),
+        ];
+        if idat_in_animation {
+            chunks.push(fctl(0, 4));
+        }
+        chunks.push(chunk_from_strings("IDAT", "default").unwrap());
+        chunks.extend([fctl(1, 2), fdat(2, b"sec"), fdat(3, b"ond"), chunk_from_strings("IEND", "").unwrap()]);
+        chunks
+    }
+
+    #[test]
+    fn test_image_data() {
+        let scanlines = [0, 1, 2, 3, 0, 4, 5, 6];
+        let compressed = zlib::deflate(&scanlines);
+        let (first, second) = compressed.split_at(5);
+
+        let mut png = testing_png();
+        assert_eq!(png.image_data(), Err(Error::ChunkNotFound));
+
+        png.append_chunk(Chunk::new(ChunkType::IDAT, first.to_vec()));
+        png.append_chunk(Chunk::new(ChunkType::IDAT, second.to_vec()));
+        assert_eq!(png.compressed_image_data(), compressed);
+        assert_eq!(png.image_data().unwrap(), scanlines);
+
+        png.remove_chunk("IDAT").unwrap();
+        assert!(matches!(png.image_data(), Err(Error::InvalidChunkData(_))));
+    }
+
+    #[test]
+    fn test_check_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.check_image_data(), Ok(50 * (1 + 50 * 4)));
+
+        // Flip a bit in the Adler-32 at the end of the only IDAT chunk
+        let mut data = png.chunk_by_type("IDAT").unwrap().data().to_vec();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let idat_offset = 8 + png.as_slice().iter().take_while(|c| c.chunk_type().to_string() != "IDAT").map(|c| c.length() as usize + 12).sum::<usize>();
+        png.replace_chunk("IDAT", data).unwrap();
+
+        let Err(Error::InvalidChunkData(message)) = png.check_image_data() else { panic!("expected a checksum error") };
+        assert!(message.starts_with("IDAT: Adler-32 mismatch"));
+        assert!(message.ends_with(&format!("at offset {:#x}", idat_offset + 8 + last - 3)));
+        assert_eq!(testing_png().check_image_data(), Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_decode_rgba8() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let rgba = png.decode_rgba8().unwrap();
+        assert_eq!(rgba, png.decode_pixels().unwrap().data);
+
+        // A 2x1 indexed image, with the second entry half transparent
+        let ihdr = Ihdr { width: 2, height: 1, bit_depth: 8, color_type: 3, compression_method: 0, filter_method: 0, interlace_method: 0 };
+        let chunk = |chunk_type: &str, data: &[u8]| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec());
+        let mut png = Png::from_chunks(vec![chunk("PLTE", &[1, 2, 3, 4, 5, 6]), chunk("tRNS", &[255, 128]), chunk("IEND", &[])]);
+        png.set_image_data(&ImageData { ihdr, data: vec![1, 0] }).unwrap();
+        assert_eq!(png.decode_rgba8().unwrap(), [4, 5, 6, 128, 1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn test_resplit_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let compressed = png.compressed_image_data();
+
+        assert_eq!(png.resplit_idat(100), compressed.len().div_ceil(100));
+        assert!(png.chunks_by_type("IDAT").all(|chunk| chunk.length() <= 100));
+        assert_eq!(png.compressed_image_data(), compressed);
+        assert_eq!(png.as_slice()[4].chunk_type().to_string(), "IDAT");
+
+        assert_eq!(png.resplit_idat(usize::MAX), 1);
+        assert_eq!(png.compressed_image_data(), compressed);
+        assert_eq!(Png::try_from(&png.as_bytes()[..]).unwrap().as_bytes(), PNG_FILE);
+
+        let mut empty = testing_png();
+        let before = empty.as_bytes();
+        assert_eq!(empty.resplit_idat(100), 0);
+        assert_eq!(empty.as_bytes(), before);
+    }
+
+    #[test]
+    fn test_stream_offset() {
+        let spans = [(100, 10), (200, 5)];
+        assert_eq!(stream_offset(spans, 0), 100);
+        assert_eq!(stream_offset(spans, 9), 109);
+        assert_eq!(stream_offset(spans, 10), 200);
+        assert_eq!(stream_offset(spans, 15), 205);
+    }
+
+    #[test]
+    fn test_decode_pixels() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.decode_pixels().unwrap();
+
+        assert_eq!(image.data.len(), 50 * 50 * 4);
+        assert_eq!(image.data.iter().map(|&b| b as usize).sum::<usize>(), 680403);
+        assert_eq!(image.rows().nth(25).unwrap()[100..104], [240, 240, 240, 255]);
+    }
+
+    #[test]
+    fn test_set_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut image = png.decode_pixels().unwrap();
+        image.data[0] = 0;
+        image.data.truncate(10 * 50 * 4);
+        image.ihdr.height = 10;
+        png.set_image_data(&image).unwrap();
+
+        let types: Vec<String> = png.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "sRGB", "gAMA", "pHYs", "IDAT", "RuSt", "IEND"]);
+        assert_eq!(png.ihdr().unwrap().height, 10);
+
+        let reparsed = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(reparsed.decode_pixels().unwrap(), image);
+    }
+
+    #[test]
+    fn test_set_compressed_image_data() {
+        let mut png = testing_png();
+        png.set_compressed_image_data(&[1, 2, 3, 4, 5], 2);
+
+        let idat: Vec<&[u8]> = png.chunks_by_type("IDAT").map(|c| c.data()).collect();
+        assert_eq!(idat, vec![&[1, 2][..], &[3, 4], &[5]]);
+        assert_eq!(png.compressed_image_data(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let png = Png::from_chunks(apng_chunks(true));
+        let frames = png.frames().unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, b"default");
+        assert_eq!(frames[1].control.sequence_number, 1);
+        assert_eq!(frames[1].data, b"second");
+
+        let png = Png::from_chunks(apng_chunks(false));
+        let frames = png.frames().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].data, b"second");
+
+        assert_eq!(testing_png().frames(), Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_frame_png() {
+        let png = Png::from_chunks(apng_chunks(true));
+        let frames = png.frames().unwrap();
+        let frame = png.frame_png(&frames[1]).unwrap();
+
+        let types: Vec<String> = frame.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(frame.ihdr().unwrap().width, 2);
+        assert_eq!(frame.chunk_by_type("IDAT").unwrap().data(), b"second");
+    }
+
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+
+        assert_eq!(ihdr, Ihdr {
+            width: 50,
+            height: 50,
+            bit_depth: 8,
+            color_type: 6,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        });
+        assert_eq!(ihdr.color_type_name(), "truecolor with alpha");
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        assert_eq!(testing_png().ihdr(), Err(Error::ChunkNotFound));
+
+        let mut png = testing_png();
+        png.insert_chunk(0, chunk_from_strings("IHDR", "too short").unwrap()).unwrap();
+        assert!(matches!(png.ihdr(), Err(Error::InvalidChunkData(_))));
+    }
+
+    #[test]
+    fn test_parse_borrowed() {
+        let chunks = Png::parse_borrowed(&PNG_FILE).unwrap();
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+
+        assert_eq!(chunks.len(), png.chunks().len());
+        for (view, chunk) in chunks.iter().zip(png.chunks()) {
+            assert_eq!(view.chunk_type(), chunk.chunk_type());
+            assert_eq!(view.data(), chunk.data());
+            assert_eq!(view.crc(), chunk.crc());
+        }
+
+        assert_eq!(Png::parse_borrowed(&PNG_FILE[..4]), Err(Error::TooSmall));
+        assert!(matches!(Png::parse_borrowed(&PNG_FILE[1..]), Err(Error::InvalidSignature(_))));
+        assert_eq!(
+            Png::parse_borrowed(&PNG_FILE[..PNG_FILE.len() - 1]),
+            Err(Error::TruncatedChunk { expected: 12, actual: 11, offset: PNG_FILE.len() - 12 })
+        );
+
+        let mut bytes = limited_png().as_bytes();
+        assert_eq!(Png::parse_borrowed(&bytes).unwrap().len(), 4);
+        // Whatever follows IEND is left alone
+        bytes.extend(b"after the end");
+        assert_eq!(Png::parse_borrowed(&bytes).unwrap().len(), 4);
+        let limits = Limits { max_chunk_length: 13, ..Limits::NONE };
+        assert!(matches!(Png::parse_borrowed_with_limits(&bytes, limits), Err(Error::LimitExceeded { limit: "chunk length", offset: 33, .. })));
+        let limits = Limits { max_inflated_size: 1000, ..Limits::NONE };
+        assert!(matches!(Png::parse_borrowed_with_limits(&bytes, limits), Err(Error::LimitExceeded { limit: "inflated size", offset: 33, .. })));
+        let limits = Limits { max_inflate_ratio: 10, ..Limits::NONE };
+        assert!(matches!(Png::parse_borrowed_with_limits(&bytes, limits), Err(Error::LimitExceeded { limit: "inflate ratio", .. })));
+    }
+
+    #[test]
+    fn test_png_from_image_file() {
+        let png = Png::try_from(&PNG_FILE[..]);
+        assert!(png.is_ok());
+    }
+
+    #[test]
+    fn test_as_bytes() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let actual = png.as_bytes();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let png = Png::from_reader(&PNG_FILE[..]).unwrap();
+        assert_eq!(png.chunks().len(), Png::try_from(&PNG_FILE[..]).unwrap().chunks().len());
+        assert!(Png::from_reader(&PNG_FILE[..4]).is_err());
+    }
+
+    // Hands out at most three bytes per read, like a slow pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(3).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_from_reader_streams() {
+        let png = Png::from_reader(Trickle(&PNG_FILE[..])).unwrap();
+        assert_eq!(png.as_bytes(), PNG_FILE);
+    }
+
+    #[test]
+    fn test_from_reader_errors() {
+        assert_eq!(Png::from_reader(&PNG_FILE[..4]).err(), Some(Error::TooSmall));
+        assert_eq!(Png::from_reader(&b"GIF89a.."[..]).err(), Some(Error::InvalidSignature(*b"GIF89a..")));
+        // Two bytes into the first length field
+        assert_eq!(Png::from_reader(&PNG_FILE[..10]).err(), Some(Error::TruncatedChunk { expected: 12, actual: 2, offset: 8 }));
+        // Cut off in the middle of the IHDR data
+        assert_eq!(Png::from_reader(&PNG_FILE[..20]).err(), Some(Error::TruncatedChunk { expected: 25, actual: 12, offset: 8 }));
+
+        let mut corrupt = PNG_FILE.to_vec();
+        corrupt[32] ^= 0xff;
+        assert!(matches!(Png::from_reader(corrupt.as_slice()), Err(Error::CrcMismatch(_, _))));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = PNG_FILE.to_vec();
+        // A chunk after IEND is as much trailing data as anything else
+        bytes.extend(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()).as_bytes());
+        bytes.extend(b"PK\x03\x04");
+        let trailing = &bytes[PNG_FILE.len()..];
+        let count = Png::try_from(&PNG_FILE[..]).unwrap().chunks().len();
+
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), count);
+        assert_eq!(png.trailing_data(), trailing);
+        assert_eq!(png.as_bytes(), bytes);
+
+        let streamed = Png::from_reader(Trickle(&bytes)).unwrap();
+        assert_eq!(streamed.trailing_data(), trailing);
+        let (lenient, report) = Png::parse_with(&bytes, ParseMode::Lenient, Limits::NONE).unwrap();
+        assert_eq!(lenient.trailing_data(), trailing);
+        let found: Vec<&Diagnostic> = report.diagnostics.iter().collect();
+        assert_eq!(found, vec![&Diagnostic::warning(diagnostics::TRAILING_DATA, PNG_FILE.len(), format!("{} bytes after IEND", trailing.len()))]);
+        assert_eq!(report.trailing_offset, PNG_FILE.len());
+
+        // A skipped chunk still counts towards where the trailing data is
+        let mut skipped = bytes.clone();
+        skipped[37] = b'1';
+        let (lenient, report) = Png::parse_with(&skipped, ParseMode::Lenient, Limits::NONE).unwrap();
+        assert_eq!(lenient.trailing_data(), trailing);
+        assert_eq!(report.trailing_offset, PNG_FILE.len());
+
+        let mut out = vec![];
+        Png::transform(bytes.as_slice(), &mut out, |chunk| Ok(vec![chunk])).unwrap();
+        assert_eq!(out, bytes);
+        assert!(Png::try_from(&PNG_FILE[..]).unwrap().trailing_data().is_empty());
+    }
+
+    #[test]
+    fn test_save_and_from_file() {
+        let path = std::env::temp_dir().join(format!("pngne-{}-png-save.png", std::process::id()));
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.save(&path).unwrap();
+
+        let saved = std::fs::read(&path).unwrap();
+        let loaded = Png::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved, PNG_FILE);
+        assert_eq!(loaded.as_bytes(), png.as_bytes());
+        assert!(matches!(Png::from_file(&path), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_transform() {
+        let mut out = vec![];
+        Png::transform(&PNG_FILE[..], &mut out, |chunk| match chunk.chunk_type().as_str() {
+            "IEND" => Ok(vec![Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()), chunk]),
+            _ => Ok(vec![chunk]),
+        })
+        .unwrap();
+
+        let mut expected = Png::try_from(&PNG_FILE[..]).unwrap();
+        expected.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()));
+        assert_eq!(out, expected.as_bytes());
+
+        let mut out = vec![];
+        Png::transform(&PNG_FILE[..], &mut out, |chunk| Ok(if chunk.chunk_type().is_critical() { vec![chunk] } else { vec![] })).unwrap();
+        let mut expected = Png::try_from(&PNG_FILE[..]).unwrap();
+        expected.remove_chunks_where(|chunk| !chunk.chunk_type().is_critical());
+        assert_eq!(out, expected.as_bytes());
+
+        assert!(Png::transform(&PNG_FILE[..20], vec![], |chunk| Ok(vec![chunk])).is_err());
+        assert_eq!(Png::transform(&PNG_FILE[..], vec![], |_| Err(Error::ChunkNotFound)), Err(Error::ChunkNotFound));
+    }
+
+    #[test]
+    fn test_transform_with() {
+        let copy = |bytes: &[u8], mode: ParseMode, limits: Limits| {
+            let mut out = vec![];
+            Png::transform_with(bytes, &mut out, mode, limits, |chunk| Ok(vec![chunk])).map(|()| out)
+        };
+        let limits = Limits { max_chunks: 2, ..Limits::NONE };
+        assert!(matches!(copy(&PNG_FILE, ParseMode::Strict, limits), Err(Error::LimitExceeded { .. })));
+        assert!(matches!(copy(&PNG_FILE, ParseMode::Lenient, limits), Err(Error::LimitExceeded { .. })));
+
+        // The same damage as in test_parse_modes: a bad CRC, an invalid
+        // chunk type and IEND cut short
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[37] = b'1';
+        bytes[32] ^= 0xff;
+        bytes.truncate(bytes.len() - 4);
+        assert!(matches!(copy(&bytes, ParseMode::Strict, Limits::NONE), Err(Error::CrcMismatch(_, _))));
+        let (lenient, _) = Png::parse_with(&bytes, ParseMode::Lenient, Limits::NONE).unwrap();
+        assert_eq!(copy(&bytes, ParseMode::Lenient, Limits::NONE), Ok(lenient.as_bytes()));
+
+        let bytes = limited_png().as_bytes();
+        assert!(copy(&bytes, ParseMode::Strict, Limits::NONE).is_ok());
+        for (limit, limits) in [
+            ("chunk length", Limits { max_chunk_length: 13, ..Limits::NONE }),
+            ("inflated size", Limits { max_inflated_size: 1000, ..Limits::NONE }),
+            ("inflate ratio", Limits { max_inflate_ratio: 10, ..Limits::NONE }),
+        ] {
+            for mode in [ParseMode::Strict, ParseMode::Lenient] {
+                let result = copy(&bytes, mode, limits);
+                assert!(matches!(result, Err(Error::LimitExceeded { limit: found, .. }) if found == limit), "{}: {:?}", limit, mode);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_reader() {
+        let count = Png::try_from(&PNG_FILE[..]).unwrap().chunks().len();
+        let mut chunks = ChunkReader::new(&PNG_FILE[..PNG_FILE.len() - 1]).unwrap();
+        assert_eq!(chunks.by_ref().take(count - 1).filter(Result::is_ok).count(), count - 1);
+        assert!(matches!(chunks.next(), Some(Err(Error::TruncatedChunk { .. }))));
+        assert!(chunks.next().is_none());
+    }
+
+    #[test]
+    fn test_from_reader_with_limits() {
+        let count = Png::try_from(&PNG_FILE[..]).unwrap().chunks().len();
+        let limits = Limits { max_chunks: count, ..Limits::NONE };
+        assert!(Png::from_reader_with_limits(&PNG_FILE[..], limits).is_ok());
+        let limits = Limits { max_chunks: count - 1, ..Limits::NONE };
+        assert!(matches!(Png::from_reader_with_limits(&PNG_FILE[..], limits), Err(Error::LimitExceeded { limit: "chunk count", .. })));
+
+        // A header claiming 1 GiB is turned down before any of it is read
+        let mut bytes = Png::header().to_vec();
+        bytes.extend([0x40, 0, 0, 0]);
+        bytes.extend(b"ruSt");
+        let limits = Limits { max_chunk_length: 1 << 20, ..Limits::NONE };
+        assert_eq!(
+            Png::from_reader_with_limits(bytes.as_slice(), limits).err(),
+            Some(Error::LimitExceeded { limit: "chunk length", value: 1 << 30, max: 1 << 20, offset: 8 })
+        );
+        assert_eq!(
+            Png::parse_with(&PNG_FILE, ParseMode::Lenient, Limits { max_chunks: 1, ..Limits::NONE }).err(),
+            Some(Error::LimitExceeded { limit: "chunk count", value: 2, max: 1, offset: 33 })
+        );
+    }
+
+    #[test]
+    fn test_save_in_place() {
+        let path = std::env::temp_dir().join(format!("pngne-{}-png-in-place.png", std::process::id()));
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::IDAT, vec![1; 5000]),
+            Chunk::new(ChunkType::TEXT, b"Title\0before".to_vec()),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ]);
+        let saved = |png: &Png| std::fs::read(&path).unwrap() == png.as_bytes();
+
+        // Nothing there to compare with, so the whole file is written
+        assert_eq!(png.save_in_place(&path).unwrap(), 5081);
+        assert!(saved(&png));
+        assert_eq!(png.save_in_place(&path).unwrap(), 0);
+
+        // Same size, only the tEXt chunk is written
+        png.replace_chunk("tEXt", b"Title\0after!".to_vec()).unwrap();
+        assert_eq!(png.save_in_place(&path).unwrap(), 24);
+        assert!(saved(&png));
+
+        // A new chunk before IEND moves IEND along with it
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()));
+        assert_eq!(png.save_in_place(&path).unwrap(), 18 + 12);
+        assert!(saved(&png));
+
+        // Shrinking truncates the file
+        png.remove_chunk("tEXt").unwrap();
+        png.remove_chunk("ruSt").unwrap();
+        assert_eq!(png.save_in_place(&path).unwrap(), 12);
+        assert!(saved(&png));
+
+        // Data after IEND is rewritten along with everything else, and
+        // doesn't outlive being stripped
+        png.set_trailing_data(b"appended".to_vec());
+        assert_eq!(png.save_in_place(&path).unwrap(), 5057 + 8);
+        assert!(saved(&png));
+        png.set_trailing_data(vec![]);
+        assert_eq!(png.save_in_place(&path).unwrap(), 5057);
+        let saved = saved(&png);
+        std::fs::remove_file(&path).unwrap();
+        assert!(saved);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_open_mmap() {
+        let path = std::env::temp_dir().join(format!("pngne-{}-png-mmap.png", std::process::id()));
+        std::fs::write(&path, PNG_FILE).unwrap();
+        let map = Png::open_mmap(&path).unwrap();
+
+        assert_eq!(map.bytes(), PNG_FILE);
+        let chunks = map.chunks().unwrap();
+        assert_eq!(chunks.len(), 7);
+        assert_eq!(&chunks[4].chunk_type().to_string(), "IDAT");
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend(b"after the end");
+        std::fs::write(&path, &bytes).unwrap();
+        assert_eq!(Png::open_mmap(&path).unwrap().chunks().unwrap().len(), 7);
+
+        std::fs::write(&path, b"GIF89a, not a png").unwrap();
+        let invalid = Png::open_mmap(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(invalid, Err(Error::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_from_file_rejects_bad_signature() {
+        let path = std::env::temp_dir().join(format!("pngne-{}-png-bad-signature.png", std::process::id()));
+        std::fs::write(&path, b"GIF89a, not a png").unwrap();
+        let result = Png::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(Error::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_write_to() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut written = vec![];
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, PNG_FILE.to_vec());
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_to_reports_errors() {
+        let png = testing_png();
+        assert_eq!(png.write_to(Broken), Err(Error::Io("disk full".to_string())));
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+
+        let found: Vec<String> = png
+            .chunks_by_type("miDl")
+            .map(|c| c.data_as_string().unwrap())
+            .collect();
+        assert_eq!(found, vec!["I am another chunk", "I am a second middle chunk"]);
+        assert_eq!(png.chunks_by_type(ChunkType::TEXT).count(), 0);
+    }
+
+    #[test]
+    fn test_remove_all_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am a second middle chunk").unwrap());
+
+        let removed = png.remove_all_by_type("miDl");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(&removed[1].data_as_string().unwrap(), "I am a second middle chunk");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.remove_all_by_type("miDl").is_empty());
+    }
+
+    #[test]
+    fn test_chunks_iterator() {
+        let png = testing_png();
+        let types: Vec<String> = png.chunks().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "miDl", "LASt"]);
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunks().next_back().unwrap().chunk_type().to_string(), "LASt");
+    }
+
+    #[test]
+    fn test_into_iterator() {
+        let png = testing_png();
+        let mut borrowed = 0;
+        for chunk in &png {
+            borrowed += chunk.length();
+        }
+
+        let owned: Vec<Chunk> = png.into_iter().filter(|c| c.chunk_type().is_critical()).collect();
+        assert_eq!(borrowed, 20 + 18 + 19);
+        assert_eq!(owned.len(), 2);
     }
 
     #[test]
@@ -0,0 +1,207 @@
+use crate::chunk::{Chunk, ChunkRef};
+use crate::chunk_type::ChunkType;
+use crate::error::{ChunkError, Error};
+use crate::limits::{LimitCheck, Limits};
+use crate::png::Png;
+use std::convert::TryFrom;
+use std::io::Read;
+
+// The chunks of a damaged PNG. Where a chunk can't be read its error comes
+// back instead, and reading carries on from the next offset where a whole
+// chunk with a matching CRC starts. A CRC is the only reliable sign of a
+// chunk boundary, and finding one can mean looking any distance ahead, so
+// the input is read in full first. If reading fails part way, whatever did
+// arrive is salvaged and the read error comes last. Going over a limit
+// isn't damage, so its error ends the salvage. Neither is whatever follows
+// IEND, which is kept as trailing data.
+pub struct Salvage {
+  bytes: Vec<u8>,
+  offset: usize,
+  started: bool,
+  read_error: Option<Error>,
+  limits: LimitCheck,
+  // Where the data after IEND starts, once IEND has been read
+  trailing: Option<usize>,
+}
+
+impl Salvage {
+  pub fn new<R: Read>(reader: R) -> Salvage {
+    Salvage::with_limits(reader, Limits::default())
+  }
+
+  pub fn with_limits<R: Read>(mut reader: R, limits: Limits) -> Salvage {
+    let mut bytes = vec![];
+    let read_error = reader.read_to_end(&mut bytes).err().map(Error::from);
+    Salvage { bytes, offset: 0, started: false, read_error, limits: LimitCheck::new(limits), trailing: None }
+  }
+
+  // The bytes after IEND, empty until IEND has come back
+  pub fn trailing_data(&self) -> &[u8] {
+    self.trailing.map_or(&[], |start| &self.bytes[start..])
+  }
+
+  // Where the next readable chunk at or after `from` starts
+  fn resync(&self, from: usize) -> usize {
+    (from..self.bytes.len())
+      .find(|&at| ChunkRef::try_from(&self.bytes[at..]).is_ok())
+      .unwrap_or(self.bytes.len())
+  }
+
+  fn skip(&mut self, from: usize, error: Error) -> ChunkError {
+    let next = self.resync(from);
+    let offset = self.offset;
+    self.offset = next;
+    log::debug!("skipped {} bytes from offset {:#x}: {}", next - offset, offset, error);
+    ChunkError { offset, skipped: next - offset, error }
+  }
+}
+
+impl Iterator for Salvage {
+  type Item = Result<Chunk, ChunkError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if !self.started && !self.bytes.is_empty() {
+      self.started = true;
+      let header = Png::header();
+      if self.bytes.starts_with(header) {
+        self.offset = header.len();
+      } else if self.bytes.len() < header.len() {
+        return Some(Err(self.skip(0, Error::TooSmall)));
+      } else {
+        let mut found = [0; 8];
+        found.copy_from_slice(&self.bytes[..8]);
+        return Some(Err(self.skip(0, Error::InvalidSignature(found))));
+      }
+    }
+
+    if self.offset >= self.bytes.len() || self.trailing.is_some() {
+      let error = self.read_error.take()?;
+      return Some(Err(ChunkError { offset: self.bytes.len(), skipped: 0, error }));
+    }
+
+    match ChunkRef::try_from(&self.bytes[self.offset..]) {
+      Ok(chunk) => {
+        let offset = self.offset;
+        let checked = self.limits.header(chunk.length(), offset);
+        if let Err(error) = checked.and_then(|_| self.limits.data(chunk.chunk_type(), chunk.data(), offset)) {
+          self.offset = self.bytes.len();
+          return Some(Err(ChunkError { offset, skipped: self.offset - offset, error }));
+        }
+        self.offset += chunk.length() as usize + 12;
+        if *chunk.chunk_type() == ChunkType::IEND {
+          self.trailing = Some(self.offset);
+        }
+        Some(Ok(chunk.to_chunk()))
+      }
+      Err(e) => {
+        let error = e.offset_by(self.offset);
+        Some(Err(self.skip(self.offset + 1, error)))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::new(ChunkType::IHDR, vec![0; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"first".to_vec()),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"second".to_vec()),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ]
+    }
+
+    fn salvage(bytes: &[u8]) -> Vec<Result<Chunk, ChunkError>> {
+        Png::salvage(bytes).collect()
+    }
+
+    #[test]
+    fn test_salvage_intact() {
+        let bytes = Png::from_chunks(chunks()).as_bytes();
+        let salvaged: Vec<Chunk> = salvage(&bytes).into_iter().map(Result::unwrap).collect();
+        assert_eq!(salvaged, chunks());
+        assert!(salvage(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_salvage_damaged() {
+        let mut bytes = Png::from_chunks(chunks()).as_bytes();
+        // Garbage between IHDR and the first ruSt and a bad CRC on the
+        // second ruSt
+        bytes[50 + 8 + 6] ^= 1;
+        bytes.splice(33..33, *b"garbage");
+
+        let salvaged = salvage(&bytes);
+        assert_eq!(salvaged.len(), 5);
+        assert_eq!(salvaged[0], Ok(chunks()[0].clone()));
+        assert_eq!(
+            salvaged[1],
+            Err(ChunkError { offset: 33, skipped: 7, error: Error::TruncatedChunk { expected: 0x67617262 + 12, actual: 54, offset: 33 } })
+        );
+        assert_eq!(salvaged[2], Ok(chunks()[1].clone()));
+        assert!(matches!(salvaged[3], Err(ChunkError { offset: 57, skipped: 18, error: Error::CrcMismatch(_, _) })));
+        assert_eq!(salvaged[4], Ok(chunks()[3].clone()));
+
+        // Without IEND, the start of another chunk is damage
+        let mut bytes = Png::from_chunks(chunks()[..3].to_vec()).as_bytes();
+        let end = bytes.len();
+        bytes.extend([0; 3]);
+        let salvaged = salvage(&bytes);
+        assert_eq!(salvaged.len(), 4);
+        assert_eq!(salvaged[3], Err(ChunkError { offset: end, skipped: 3, error: Error::TruncatedChunk { expected: 12, actual: 3, offset: end } }));
+    }
+
+    #[test]
+    fn test_salvage_trailing() {
+        let mut bytes = Png::from_chunks(chunks()).as_bytes();
+        bytes.extend(b"\0\0\0after the end, not a chunk");
+        let mut salvage = Png::salvage(bytes.as_slice());
+        assert!(salvage.trailing_data().is_empty());
+        let salvaged: Vec<Chunk> = salvage.by_ref().map(Result::unwrap).collect();
+        assert_eq!(salvaged, chunks());
+        assert_eq!(salvage.trailing_data(), b"\0\0\0after the end, not a chunk");
+    }
+
+    #[test]
+    fn test_salvage_signature() {
+        let mut bytes = Png::from_chunks(chunks()).as_bytes();
+        bytes[1] = b'Q';
+        let salvaged = salvage(&bytes);
+        assert_eq!(salvaged.len(), 5);
+        assert!(matches!(salvaged[0], Err(ChunkError { offset: 0, skipped: 8, error: Error::InvalidSignature(_) })));
+        assert_eq!(salvaged[1], Ok(chunks()[0].clone()));
+
+        // A bare run of chunks has nothing to skip
+        let raw: Vec<u8> = chunks().iter().flat_map(Chunk::as_bytes).collect();
+        let salvaged = salvage(&raw);
+        assert!(matches!(salvaged[0], Err(ChunkError { offset: 0, skipped: 0, .. })));
+        assert_eq!(salvaged[1..].iter().filter(|chunk| chunk.is_ok()).count(), 4);
+        assert_eq!(salvage(b"\x89PN"), vec![Err(ChunkError { offset: 0, skipped: 3, error: Error::TooSmall })]);
+    }
+
+    #[test]
+    fn test_salvage_limits() {
+        let bytes = Png::from_chunks(chunks()).as_bytes();
+        let salvaged: Vec<_> = Png::salvage_with_limits(bytes.as_slice(), Limits { max_chunks: 2, ..Limits::NONE }).collect();
+        assert_eq!(salvaged.len(), 3);
+        assert_eq!(
+            salvaged[2],
+            Err(ChunkError { offset: 50, skipped: bytes.len() - 50, error: Error::LimitExceeded { limit: "chunk count", value: 3, max: 2, offset: 50 } })
+        );
+
+        let limits = Limits { max_chunk_length: 5, ..Limits::NONE };
+        let salvaged: Vec<_> = Png::salvage_with_limits(bytes.as_slice(), limits).collect();
+        assert!(matches!(salvaged[..], [Err(ChunkError { offset: 8, error: Error::LimitExceeded { .. }, .. })]));
+
+        let mut chunks = chunks();
+        chunks.insert(1, Chunk::new(ChunkType::IDAT, crate::zlib::deflate(&[0; 1_000_000])));
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        let limits = Limits { max_inflate_ratio: 10, ..Limits::NONE };
+        let salvaged: Vec<_> = Png::salvage_with_limits(bytes.as_slice(), limits).collect();
+        assert!(matches!(salvaged[..], [Ok(_), Err(ChunkError { offset: 33, error: Error::LimitExceeded { limit: "inflate ratio", .. }, .. })]));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Chunk types are their four letters, chunk data is base64 and a PNG is
+// its list of chunks, plus whatever follows IEND in base64 when there is
+// something:
+//
+//   {"chunks": [{"chunk_type": "tEXt", "data": "...", "crc": 123}, ...], "trailing": "..."}
+//
+// The CRC is written for the reader's benefit. It may be left out when
+// deserializing, but if it is there it has to match the type and data.
+impl Serialize for ChunkType {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(self.as_str())
+  }
+}
+
+impl<'de> Deserialize<'de> for ChunkType {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    ChunkType::from_str(&name).map_err(|e| de::Error::custom(format!("invalid chunk type {:?}: {}", name, e)))
+  }
+}
+
+#[derive(Serialize)]
+struct ChunkOut<'a> {
+  chunk_type: &'a ChunkType,
+  data: String,
+  crc: u32,
+}
+
+#[derive(Deserialize)]
+struct ChunkIn {
+  chunk_type: ChunkType,
+  data: String,
+  crc: Option<u32>,
+}
+
+impl Serialize for Chunk {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    ChunkOut { chunk_type: self.chunk_type(), data: STANDARD.encode(self.data()), crc: self.crc() }.serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Chunk {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let input = ChunkIn::deserialize(deserializer)?;
+    let data = STANDARD
+      .decode(&input.data)
+      .map_err(|e| de::Error::custom(format!("{} chunk data isn't base64: {}", input.chunk_type, e)))?;
+
+    let chunk = Chunk::try_new(input.chunk_type, data).map_err(|e| de::Error::custom(format!("{} chunk: {}", input.chunk_type, e)))?;
+    match input.crc {
+      Some(crc) if crc != chunk.crc() => Err(de::Error::custom(format!(
+        "{} chunk CRC is {:#010x} but its type and data give {:#010x}",
+        input.chunk_type,
+        crc,
+        chunk.crc()
+      ))),
+      _ => Ok(chunk),
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct PngOut<'a> {
+  chunks: &'a [Chunk],
+  #[serde(skip_serializing_if = "String::is_empty")]
+  trailing: String,
+}
+
+#[derive(Deserialize)]
+struct PngIn {
+  chunks: Vec<Chunk>,
+  trailing: Option<String>,
+}
+
+impl Serialize for Png {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    PngOut { chunks: self.as_slice(), trailing: STANDARD.encode(self.trailing_data()) }.serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Png {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let input = PngIn::deserialize(deserializer)?;
+    let trailing = match input.trailing {
+      Some(trailing) => STANDARD.decode(&trailing).map_err(|e| de::Error::custom(format!("trailing data isn't base64: {}", e)))?,
+      None => vec![],
+    };
+    let mut png = Png::from_chunks(input.chunks);
+    png.set_trailing_data(trailing);
+    Ok(png)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::TEXT, b"Title\0pngne".to_vec()),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![0, 159, 146, 150]),
+            Chunk::new(ChunkType::IEND, vec![]),
+        ]);
+
+        let json = serde_json::to_string(&png).unwrap();
+        assert!(json.starts_with(r#"{"chunks":[{"chunk_type":"tEXt","data":"VGl0bGUAcG5nbmU=","crc":"#));
+
+        let reloaded: Png = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.as_bytes(), png.as_bytes());
+        assert!(!json.contains("trailing"));
+    }
+
+    #[test]
+    fn test_trailing_round_trip() {
+        let mut png = Png::from_chunks(vec![Chunk::new(ChunkType::IEND, vec![])]);
+        png.set_trailing_data(b"PK\x03\x04 after IEND".to_vec());
+
+        let json = serde_json::to_string(&png).unwrap();
+        assert!(json.ends_with(r#""trailing":"UEsDBCBhZnRlciBJRU5E"}"#), "{}", json);
+        let reloaded: Png = serde_json::from_str(&json).unwrap();
+        assert_eq!(reloaded.as_bytes(), png.as_bytes());
+        assert_eq!(reloaded.trailing_data(), b"PK\x03\x04 after IEND");
+
+        assert!(serde_json::from_str::<Png>(r#"{"chunks": [], "trailing": "not base64!"}"#).is_err());
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let chunk: Chunk = serde_json::from_str(r#"{"chunk_type": "ruSt", "data": "aGk="}"#).unwrap();
+        assert_eq!(chunk.data(), b"hi");
+
+        assert!(serde_json::from_str::<ChunkType>(r#""ru5t""#).is_err());
+        assert!(serde_json::from_str::<Chunk>(r#"{"chunk_type": "ruSt", "data": "not base64!"}"#).is_err());
+        assert!(serde_json::from_str::<Chunk>(r#"{"chunk_type": "ruSt", "data": "aGk=", "crc": 1}"#).is_err());
+    }
+}
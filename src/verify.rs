@@ -0,0 +1,568 @@
+use crate::checksum::crc32;
+use crate::chunk::{self, Chunk};
+use crate::error::{Error, Result};
+use crate::chunk_type::ChunkType;
+use crate::limits::{LimitCheck, Limits};
+use crate::diagnostics::{self, Diagnostic, Diagnostics};
+use crate::chunks::{check_sequence, sequence_number, AnimationControl, Background, Chromaticities, FrameControl, Gamma, IccProfile, Ihdr, Palette, SignificantBits, Srgb, Transparency};
+use crate::png::{self, Png};
+use crate::zlib;
+use rayon::prelude::*;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+pub struct Report {
+  pub chunk_count: usize,
+  // In file order. Warnings are allowed by the spec, so only errors fail a
+  // file.
+  pub diagnostics: Diagnostics,
+}
+
+impl Report {
+  pub fn is_ok(&self) -> bool {
+    !self.diagnostics.has_errors()
+  }
+}
+
+// Walks `bytes` chunk by chunk without building a `Png`, so that every
+// problem in the file is reported instead of only the first one
+pub fn verify(bytes: &[u8]) -> Report {
+  check(bytes, false, Limits::default())
+}
+
+// Going over a limit is reported as an error and ends the walk there.
+// Nothing past it is checked, since that would mean inflating or holding
+// what the limit is there to turn down.
+pub fn verify_with_limits(bytes: &[u8], limits: Limits) -> Report {
+  check(bytes, false, limits)
+}
+
+// Same as `verify`, but CRCs, which dominate for large files, are computed
+// on the current rayon thread pool
+pub fn verify_parallel(bytes: &[u8]) -> Report {
+  check(bytes, true, Limits::default())
+}
+
+pub fn verify_parallel_with_limits(bytes: &[u8], limits: Limits) -> Report {
+  check(bytes, true, limits)
+}
+
+fn check(bytes: &[u8], parallel: bool, limits: Limits) -> Report {
+  let mut found = Diagnostics::new();
+  let mut chunks: Vec<(usize, String)> = vec![];
+  // Chunk index, bytes covered by the CRC and the stored CRC
+  let mut crcs: Vec<(usize, &[u8], u32)> = vec![];
+  let mut contents: Vec<&[u8]> = vec![];
+
+  let header = Png::header();
+  if bytes.len() < header.len() || &bytes[..header.len()] != header {
+    found.push(Diagnostic::error(diagnostics::INVALID_SIGNATURE, 0, "invalid PNG signature"));
+  }
+
+  let mut limits = LimitCheck::new(limits);
+  let mut exceeded = false;
+  let mut offset = header.len();
+  while offset < bytes.len() {
+    let rest = &bytes[offset..];
+    if rest.len() < 12 {
+      found.push(Diagnostic::error(diagnostics::TRUNCATED_CHUNK, offset, format!("truncated chunk ({} trailing bytes)", rest.len())));
+      break;
+    }
+
+    let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+    let type_bytes = [rest[4], rest[5], rest[6], rest[7]];
+    let name = String::from_utf8_lossy(&type_bytes).to_string();
+
+    if length > chunk::MAX_LENGTH {
+      found.push(Diagnostic::error(
+        diagnostics::LENGTH_TOO_LARGE,
+        offset,
+        format!("{} chunk declares {} bytes, over the 2^31 - 1 limit", name, length),
+      ));
+      break;
+    }
+    if rest.len() < length + 12 {
+      found.push(Diagnostic::error(
+        diagnostics::TRUNCATED_CHUNK,
+        offset,
+        format!("{} chunk declares {} bytes but the file ends early", name, length),
+      ));
+      break;
+    }
+
+    let chunk_type = ChunkType::try_from(type_bytes);
+    match chunk_type {
+      Ok(chunk_type) if !chunk_type.is_reserved_bit_valid() => {
+        found.push(Diagnostic::error(diagnostics::RESERVED_BIT, offset, format!("{} chunk has the reserved bit set", name)));
+      }
+      Ok(_) => {}
+      Err(_) => {
+        found.push(Diagnostic::error(diagnostics::INVALID_CHUNK_TYPE, offset, format!("invalid chunk type {:?}", type_bytes)));
+      }
+    }
+
+    let data = &rest[8..length + 8];
+    let checked = limits.header(length as u32, offset).and_then(|_| match chunk_type {
+      Ok(chunk_type) => limits.data(&chunk_type, data, offset),
+      Err(_) => Ok(()),
+    });
+    if let Err(e) = checked {
+      found.push(Diagnostic::error(diagnostics::LIMIT_EXCEEDED, offset, e.to_string()).in_chunk(chunks.len()));
+      exceeded = true;
+      break;
+    }
+
+    let stored = u32::from_be_bytes(rest[length + 8..length + 12].try_into().unwrap());
+    crcs.push((chunks.len(), &rest[4..length + 8], stored));
+    contents.push(data);
+
+    let is_end = name == "IEND";
+    chunks.push((offset, name));
+    offset += length + 12;
+    if is_end {
+      break;
+    }
+  }
+
+  // Decoders stop at IEND, so anything after it is only seen by tools
+  // that look for it
+  if offset < bytes.len() && !exceeded {
+    found.push(Diagnostic::warning(diagnostics::TRAILING_DATA, offset, format!("{} bytes after IEND", bytes.len() - offset)));
+  }
+
+  let mismatch = |&(index, data, stored): &(usize, &[u8], u32)| {
+    let computed = crc32(data);
+    (computed != stored).then_some((index, stored, computed))
+  };
+  let mismatches: Vec<(usize, u32, u32)> = if parallel {
+    crcs.par_iter().filter_map(mismatch).collect()
+  } else {
+    crcs.iter().filter_map(mismatch).collect()
+  };
+
+  for (index, stored, computed) in mismatches {
+    let (offset, name) = &chunks[index];
+    let message = format!("{} chunk CRC mismatch (stored {:#010x}, computed {:#010x})", name, stored, computed);
+    found.push(Diagnostic::error(diagnostics::CRC_MISMATCH, *offset, message).in_chunk(index));
+  }
+
+  if !exceeded {
+    check_order(&chunks, bytes.len(), &mut found);
+    check_duplicates(&chunks, &contents, &mut found);
+    check_contents(&chunks, &contents, &mut found);
+    if chunks.first().is_some_and(|(_, name)| name == "CgBI") {
+      // The image data of a crushed file is a bare deflate stream
+      found.push(Diagnostic::error(diagnostics::CGBI, header.len(), "Apple CgBI file, `pngne uncrush` makes it a standard PNG"));
+    } else {
+      check_image_data(&chunks, &contents, &mut found);
+    }
+  }
+
+  let spans: Vec<(usize, usize)> = chunks.iter().zip(&contents).map(|((offset, _), data)| (*offset, data.len() + 12)).collect();
+  found.locate(&spans);
+  found.sort();
+  Report { chunk_count: chunks.len(), diagnostics: found }
+}
+
+fn check_order(chunks: &[(usize, String)], end: usize, found: &mut Diagnostics) {
+  let names: Vec<&str> = chunks.iter().map(|(_, name)| name.as_str()).collect();
+
+  if chunks.is_empty() {
+    found.push(Diagnostic::error(diagnostics::NO_CHUNKS, end, "file contains no chunks"));
+  }
+  if !names.contains(&"IEND") {
+    found.push(Diagnostic::error(diagnostics::MISSING_CHUNK, end, "missing IEND chunk"));
+  }
+  if !names.contains(&"IDAT") && !chunks.is_empty() {
+    found.push(Diagnostic::error(diagnostics::MISSING_CHUNK, end, "missing IDAT chunk"));
+  }
+
+  for violation in png::check_order(&names) {
+    found.push(Diagnostic::error(diagnostics::CHUNK_ORDER, chunks[violation.index].0, violation.message).in_chunk(violation.index));
+  }
+}
+
+// A chunk type repeated against the spec fails the file, a repeated tEXt
+// keyword only warns
+fn check_duplicates(chunks: &[(usize, String)], contents: &[&[u8]], found: &mut Diagnostics) {
+  let named: Vec<(&str, &[u8])> = chunks.iter().zip(contents).map(|((_, name), data)| (name.as_str(), *data)).collect();
+  for duplicate in png::find_duplicates(&named) {
+    let (offset, name) = &chunks[duplicate.index];
+    let first = format!("chunk {} at offset {:#x}", duplicate.first, chunks[duplicate.first].0);
+    let diagnostic = match duplicate.keyword {
+      Some(keyword) => Diagnostic::warning(
+        diagnostics::DUPLICATE_KEYWORD,
+        *offset,
+        format!("{} chunk {} repeats the keyword {:?} of {}", name, duplicate.index, keyword, first),
+      ),
+      None => Diagnostic::error(diagnostics::DUPLICATE_CHUNK, *offset, format!("duplicate {} chunk {}, first is {}", name, duplicate.index, first)),
+    };
+    found.push(diagnostic.in_chunk(duplicate.index));
+  }
+}
+
+// Chunk CRCs can't catch image data that was corrupted before it was framed,
+// so inflate the IDAT stream and check its Adler-32 as well
+fn check_image_data(chunks: &[(usize, String)], contents: &[&[u8]], found: &mut Diagnostics) {
+  let idat: Vec<usize> = (0..chunks.len()).filter(|&i| chunks[i].1 == "IDAT").collect();
+  if idat.is_empty() {
+    return;
+  }
+
+  let stream: Vec<u8> = idat.iter().flat_map(|&i| contents[i].iter().copied()).collect();
+  if let Err((position, message)) = zlib::check(&stream) {
+    let spans = idat.iter().map(|&i| (chunks[i].0 + 8, contents[i].len()));
+    found.push(Diagnostic::error(diagnostics::IMAGE_DATA, png::stream_offset(spans, position), format!("IDAT: {}", message)));
+  }
+}
+
+type ContentCheck = fn(&Chunk) -> Result<()>;
+
+// Decodes the chunks whose layout is known and checks them against each
+// other
+fn check_contents(chunks: &[(usize, String)], contents: &[&[u8]], found: &mut Diagnostics) {
+  let mut report = |offset: usize, e: Error| found.push(Diagnostic::error(diagnostics::INVALID_CHUNK_DATA, offset, e.to_string()));
+  let first = |name: &str| {
+    let index = chunks.iter().position(|(_, n)| n == name)?;
+    Some((chunks[index].0, Chunk::new(ChunkType::from_str(name).unwrap(), contents[index].to_vec())))
+  };
+
+  let ihdr = first("IHDR").and_then(|(offset, chunk)| Ihdr::try_from(&chunk).map_err(|e| report(offset, e)).ok());
+
+  if let Some((offset, chunk)) = first("PLTE") {
+    let checked = Palette::try_from(&chunk).and_then(|palette| ihdr.map_or(Ok(()), |ihdr| palette.validate_for(&ihdr)));
+    if let Err(e) = checked {
+      report(offset, e);
+    }
+  } else if ihdr.is_some_and(|ihdr| ihdr.color_type == 3) {
+    report(8, Error::InvalidChunkData("indexed image has no PLTE chunk".to_string()));
+  }
+
+  if let Some(ihdr) = ihdr {
+    let palette = first("PLTE").and_then(|(_, chunk)| Palette::try_from(&chunk).ok());
+
+    if let Some((offset, chunk)) = first("tRNS") {
+      let checked = Transparency::decode(&chunk, &ihdr)
+        .and_then(|trns| palette.as_ref().map_or(Ok(()), |palette| trns.validate_for(palette)));
+      if let Err(e) = checked {
+        report(offset, e);
+      }
+    }
+    if let Some((offset, chunk)) = first("bKGD") {
+      let checked = Background::decode(&chunk, &ihdr)
+        .and_then(|bkgd| palette.as_ref().map_or(Ok(()), |palette| bkgd.validate_for(palette)));
+      if let Err(e) = checked {
+        report(offset, e);
+      }
+    }
+    if let Some((offset, chunk)) = first("sBIT") {
+      if let Err(e) = SignificantBits::decode(&chunk, &ihdr) {
+        report(offset, e);
+      }
+    }
+  }
+
+  let checks: [(&str, ContentCheck); 4] = [
+    ("gAMA", |chunk| Gamma::try_from(chunk).map(drop)),
+    ("cHRM", |chunk| Chromaticities::try_from(chunk).map(drop)),
+    ("sRGB", |chunk| Srgb::try_from(chunk).map(drop)),
+    ("iCCP", |chunk| IccProfile::try_from(chunk)?.profile().map(drop)),
+  ];
+  for (name, check) in checks {
+    if let Some((offset, Err(e))) = first(name).map(|(offset, chunk)| (offset, check(&chunk))) {
+      report(offset, e);
+    }
+  }
+
+  if let (Some((offset, _)), Some(_)) = (first("iCCP"), first("sRGB")) {
+    report(offset, Error::InvalidChunkData("sRGB and iCCP must not both be present".to_string()));
+  }
+
+  check_animation(chunks, contents, ihdr, &mut report);
+}
+
+// Checks acTL against the fcTL chunks, each frame against the canvas, and
+// that fcTL and fdAT sequence numbers count up without gaps
+fn check_animation(chunks: &[(usize, String)], contents: &[&[u8]], ihdr: Option<Ihdr>, report: &mut impl FnMut(usize, Error)) {
+  let decode = |index: usize| Chunk::new(ChunkType::from_str(&chunks[index].1).unwrap(), contents[index].to_vec());
+  let actl = chunks.iter().position(|(_, name)| name == "acTL");
+  let mut frames = 0;
+  let mut sequence = vec![];
+
+  for (index, (offset, name)) in chunks.iter().enumerate() {
+    if let Some(number) = sequence_number(name, contents[index]) {
+      sequence.push((*offset, number));
+    }
+    if name == "fcTL" {
+      frames += 1;
+      let checked = FrameControl::try_from(&decode(index)).and_then(|fctl| ihdr.map_or(Ok(()), |ihdr| fctl.validate_for(&ihdr)));
+      if let Err(e) = checked {
+        report(*offset, e);
+      }
+    }
+  }
+
+  match actl.map(|index| (chunks[index].0, AnimationControl::try_from(&decode(index)))) {
+    Some((offset, Ok(actl))) if actl.num_frames != frames => {
+      report(offset, Error::InvalidChunkData(format!("acTL declares {} frames but there are {} fcTL chunks", actl.num_frames, frames)));
+    }
+    Some((offset, Err(e))) => report(offset, e),
+    None if frames > 0 => report(chunks[0].0, Error::InvalidChunkData("fcTL chunks without an acTL chunk".to_string())),
+    _ => {}
+  }
+
+  let numbers: Vec<u32> = sequence.iter().map(|&(_, number)| number).collect();
+  for (position, expected, found) in check_sequence(&numbers) {
+    report(sequence[position].0, Error::InvalidChunkData(format!("sequence number {}, expected {}", found, expected)));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IDAT chunks each get a complete, empty zlib stream; decoders ignore
+    // whatever follows the first one
+    fn chunk(chunk_type: &str) -> Chunk {
+        let data = if chunk_type == "IDAT" { zlib::deflate(&[]) } else { vec![1, 2, 3] };
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn ihdr(color_type: u8) -> Chunk {
+        let data = vec![0, 0, 0, 1, 0, 0, 0, 1, 8, color_type, 0, 0, 0];
+        Chunk::new(ChunkType::IHDR, data)
+    }
+
+    fn png_bytes(chunk_types: &[&str]) -> Vec<u8> {
+        let chunks = chunk_types.iter().map(|&c| if c == "IHDR" { ihdr(2) } else { chunk(c) }).collect();
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn messages(report: &Report) -> Vec<&str> {
+        report.diagnostics.errors().map(|d| d.message.as_str()).collect()
+    }
+
+    fn warnings(report: &Report) -> Vec<&Diagnostic> {
+        report.diagnostics.warnings().collect()
+    }
+
+    #[test]
+    fn test_verify_valid() {
+        let report = verify(&png_bytes(&["IHDR", "PLTE", "IDAT", "IDAT", "IEND"]));
+        assert!(report.is_ok());
+        assert_eq!(report.chunk_count, 5);
+    }
+
+    #[test]
+    fn test_verify_reports_every_problem() {
+        let mut bytes = png_bytes(&["tEXt", "IHDR", "IDAT", "PLTE", "IHDR", "IEND"]);
+        bytes[0] = 0;
+        // Corrupt the CRC of the tEXt chunk
+        bytes[8 + 14] ^= 0xff;
+
+        let report = verify(&bytes);
+        assert_eq!(messages(&report), vec![
+            "invalid PNG signature",
+            "tEXt chunk CRC mismatch (stored 0x9c1d5aa8, computed 0x9c1d5a57)",
+            "first chunk is tEXt, expected IHDR",
+            "PLTE chunk after IDAT",
+            "duplicate IHDR chunk 4, first is chunk 1 at offset 0x17",
+        ]);
+    }
+
+    #[test]
+    fn test_verify_duplicates() {
+        let text = |data: &[u8]| Chunk::new(ChunkType::TEXT, data.to_vec());
+        let gama = || Chunk::new(ChunkType::from_str("gAMA").unwrap(), 45455u32.to_be_bytes().to_vec());
+        let chunks = vec![
+            ihdr(2),
+            gama(),
+            text(b"Title\0one"),
+            gama(),
+            text(b"Title\0two"),
+            text(b"Author\0me"),
+            chunk("IDAT"),
+            chunk("tIME"),
+            chunk("tIME"),
+            chunk("IEND"),
+        ];
+        let report = verify(&Png::from_chunks(chunks).as_bytes());
+
+        assert_eq!(messages(&report), vec![
+            "duplicate gAMA chunk 3, first is chunk 1 at offset 0x21",
+            "duplicate tIME chunk 8, first is chunk 7 at offset 0x94",
+        ]);
+        let first = report.diagnostics.iter().next().unwrap();
+        assert_eq!((first.code, first.offset, first.chunk), (diagnostics::DUPLICATE_CHUNK, 0x46, Some(3)));
+        assert_eq!(warnings(&report).len(), 1);
+        assert_eq!(warnings(&report)[0].message, "tEXt chunk 4 repeats the keyword \"Title\" of chunk 2 at offset 0x31");
+        assert_eq!(warnings(&report)[0].code, diagnostics::DUPLICATE_KEYWORD);
+    }
+
+    #[test]
+    fn test_verify_cgbi() {
+        let zlib = zlib::deflate(&[0, 1, 2, 3]);
+        let chunks = vec![
+            Chunk::new(ChunkType::CGBI, vec![0x50, 0, 0x20, 0x06]),
+            ihdr(6),
+            Chunk::new(ChunkType::IDAT, zlib[2..zlib.len() - 4].to_vec()),
+            chunk("IEND"),
+        ];
+        let report = verify(&Png::from_chunks(chunks).as_bytes());
+        assert_eq!(messages(&report), vec!["Apple CgBI file, `pngne uncrush` makes it a standard PNG"]);
+        let cgbi = report.diagnostics.iter().next().unwrap();
+        assert_eq!((cgbi.code, cgbi.offset, cgbi.chunk), (diagnostics::CGBI, 8, Some(0)));
+    }
+
+    #[test]
+    fn test_verify_idat_and_iend() {
+        let report = verify(&png_bytes(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND", "tEXt"]));
+        assert_eq!(messages(&report), vec!["tEXt chunk between IDAT chunks"]);
+        assert_eq!(warnings(&report), vec![&Diagnostic::warning(diagnostics::TRAILING_DATA, 103, "15 bytes after IEND")]);
+
+        // Bytes after IEND needn't be chunks, and are no reason to fail
+        let mut bytes = png_bytes(&["IHDR", "IDAT", "IEND"]);
+        bytes.extend(b"PK\x03\x04");
+        let report = verify(&bytes);
+        assert!(report.is_ok());
+        assert_eq!(report.chunk_count, 3);
+        assert_eq!(warnings(&report)[0].message, "4 bytes after IEND");
+
+        let mut bytes = png_bytes(&["IHDR", "IDAT", "IEND"]);
+        let iend = bytes.len() - 15;
+        bytes[iend..iend + 4].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+        assert_eq!(messages(&verify(&bytes)), vec!["IEND chunk declares 2147483648 bytes, over the 2^31 - 1 limit", "missing IEND chunk"]);
+
+        let report = verify(&png_bytes(&["IHDR"]));
+        assert_eq!(messages(&report), vec!["missing IEND chunk", "missing IDAT chunk"]);
+    }
+
+    #[test]
+    fn test_verify_parallel() {
+        let mut bytes = png_bytes(&["IHDR", "IDAT", "IDAT", "IDAT", "IEND"]);
+        // Corrupt the CRCs of the first and last IDAT chunks
+        bytes[8 + 25 + 19] ^= 0xff;
+        bytes[8 + 25 + 20 * 2 + 19] ^= 0xff;
+
+        let sequential = verify(&bytes);
+        let parallel = verify_parallel(&bytes);
+        assert_eq!(parallel.diagnostics, sequential.diagnostics);
+        let located: Vec<(usize, Option<usize>)> = parallel.diagnostics.iter().map(|d| (d.offset, d.chunk)).collect();
+        assert_eq!(located, vec![(33, Some(1)), (73, Some(3))]);
+    }
+
+    #[test]
+    fn test_verify_limits() {
+        let bytes = png_bytes(&["IHDR", "IDAT", "IEND"]);
+        let report = verify_with_limits(&bytes, Limits { max_chunks: 2, ..Limits::NONE });
+        let found: Vec<(&str, usize)> = report.diagnostics.iter().map(|d| (d.code, d.offset)).collect();
+        assert_eq!(found, vec![(diagnostics::LIMIT_EXCEEDED, 8 + 25 + 20)]);
+        assert_eq!(report.chunk_count, 2);
+        assert!(verify_with_limits(&bytes, Limits { max_chunks: 3, ..Limits::NONE }).is_ok());
+
+        // The profile is turned down without being inflated
+        let icc: Chunk = IccProfile::new("profile", &vec![0; 10_000_000]).unwrap().into();
+        let bytes = Png::from_chunks(vec![ihdr(2), icc, chunk("IDAT"), chunk("IEND")]).as_bytes();
+        let limits = Limits { max_inflated_size: 1_000_000, ..Limits::NONE };
+        let report = verify_parallel_with_limits(&bytes, limits);
+        assert_eq!(messages(&report).len(), 1);
+        assert!(messages(&report)[0].contains("inflated size"), "{:?}", messages(&report));
+        assert_eq!(report.diagnostics.iter().next().unwrap().chunk, Some(1));
+    }
+
+    #[test]
+    fn test_verify_truncated() {
+        let bytes = png_bytes(&["IHDR", "IDAT", "IEND"]);
+        let report = verify(&bytes[..bytes.len() - 2]);
+
+        assert_eq!(report.chunk_count, 2);
+        assert_eq!(messages(&report)[0], "IEND chunk declares 3 bytes but the file ends early");
+    }
+
+    #[test]
+    fn test_verify_contents() {
+        let bad_ihdr = Chunk::new(ChunkType::IHDR, vec![0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0]);
+        let bytes = Png::from_chunks(vec![bad_ihdr, chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["IHDR: bit depth 4 is not allowed for color type 2"]);
+
+        let bytes = Png::from_chunks(vec![ihdr(3), chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["indexed image has no PLTE chunk"]);
+
+        let bytes = Png::from_chunks(vec![ihdr(0), chunk("PLTE"), chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["PLTE: not allowed for color type 0"]);
+
+        let bytes = Png::from_chunks(vec![ihdr(3), chunk("PLTE"), chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert!(verify(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_verify_color_chunks() {
+        let srgb = Chunk::new(ChunkType::SRGB, vec![0]);
+        let icc: Chunk = IccProfile::new("profile", b"icc").unwrap().into();
+        let bytes = Png::from_chunks(vec![ihdr(2), srgb, icc, chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["sRGB and iCCP must not both be present"]);
+
+        let gama = Chunk::new(ChunkType::GAMA, vec![0; 4]);
+        let bytes = Png::from_chunks(vec![ihdr(2), gama, chunk("cHRM"), chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["gAMA: gamma must not be zero", "cHRM must be 32 bytes, got 3"]);
+    }
+
+    #[test]
+    fn test_verify_transparency_chunks() {
+        let trns = Chunk::new(ChunkType::TRNS, vec![0, 1]);
+        let bytes = Png::from_chunks(vec![ihdr(6), trns, chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["tRNS: not allowed for color type 6, which has an alpha channel"]);
+
+        let plte = Chunk::new(ChunkType::PLTE, vec![0; 6]);
+        let bkgd = Chunk::new(ChunkType::BKGD, vec![2]);
+        let sbit = Chunk::new(ChunkType::SBIT, vec![8, 8, 9]);
+        let bytes = Png::from_chunks(vec![ihdr(3), sbit, plte, bkgd, chunk("IDAT"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec![
+            "sBIT: 9 significant bits, expected 1 to 8",
+            "bKGD: refers to palette entry 2 but PLTE has 2",
+        ]);
+    }
+
+    #[test]
+    fn test_verify_animation() {
+        let actl = || Chunk::new(ChunkType::ACTL, vec![0, 0, 0, 2, 0, 0, 0, 0]);
+        let fctl = |sequence: u8, width: u8| {
+            let mut data = vec![0, 0, 0, sequence, 0, 0, 0, width, 0, 0, 0, 1];
+            data.extend([0; 14]);
+            Chunk::new(ChunkType::FCTL, data)
+        };
+        let fdat = |sequence: u8| Chunk::new(ChunkType::FDAT, vec![0, 0, 0, sequence, 1]);
+
+        let valid = vec![ihdr(2), actl(), fctl(0, 1), chunk("IDAT"), fctl(1, 1), fdat(2), chunk("IEND")];
+        assert!(verify(&Png::from_chunks(valid).as_bytes()).is_ok());
+
+        let broken = vec![ihdr(2), actl(), fctl(0, 2), chunk("IDAT"), fctl(1, 1), fdat(3), fctl(4, 1), chunk("IEND")];
+        assert_eq!(messages(&verify(&Png::from_chunks(broken).as_bytes())), vec![
+            "acTL declares 2 frames but there are 3 fcTL chunks",
+            "fcTL: frame 0 (2x1 at 0,0) lies outside the 1x1 canvas",
+            "sequence number 3, expected 2",
+        ]);
+    }
+
+    #[test]
+    fn test_verify_image_data() {
+        let mut stream = zlib::deflate(b"scanlines");
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        let (first, second) = stream.split_at(4);
+        let idat = |data: &[u8]| Chunk::new(ChunkType::IDAT, data.to_vec());
+
+        let bytes = Png::from_chunks(vec![ihdr(2), idat(first), idat(second), chunk("IEND")]).as_bytes();
+        let report = verify(&bytes);
+        let found: Vec<&Diagnostic> = report.diagnostics.iter().collect();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].message.starts_with("IDAT: Adler-32 mismatch"));
+        assert_eq!(found[0].chunk, Some(2));
+        // IHDR, then the first IDAT, then 12 header bytes, then the second
+        // IDAT's deflate data
+        assert_eq!(found[0].offset, 8 + 25 + 16 + 8 + second.len() - 4);
+
+        let bytes = Png::from_chunks(vec![ihdr(2), idat(b"not zlib"), chunk("IEND")]).as_bytes();
+        assert_eq!(messages(&verify(&bytes)), vec!["IDAT: invalid zlib header"]);
+    }
+}
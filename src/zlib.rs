@@ -0,0 +1,214 @@
+use crate::error::{Error, Result};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use std::io::{Read, Write};
+
+const ADLER_MODULUS: u32 = 65521;
+
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+  deflate_level(data, Compression::default().level())
+}
+
+// `level` goes from 0 (store only) to 9 (smallest)
+pub fn deflate_level(data: &[u8], level: u32) -> Vec<u8> {
+  let mut encoder = ZlibEncoder::new(vec![], Compression::new(level));
+  // Writing to a Vec can't fail
+  encoder.write_all(data).unwrap();
+  encoder.finish().unwrap()
+}
+
+// Much slower than deflate_level(data, 9), but usually a few percent smaller
+#[cfg(feature = "zopfli")]
+pub fn deflate_zopfli(data: &[u8]) -> Vec<u8> {
+  let mut compressed = vec![];
+  zopfli::compress(zopfli::Options::default(), zopfli::Format::Zlib, data, &mut compressed).unwrap();
+  compressed
+}
+
+// `chunk_type` names the chunk the stream came from in error messages
+pub fn inflate(chunk_type: &str, data: &[u8]) -> Result<Vec<u8>> {
+  let mut inflated = vec![];
+  ZlibDecoder::new(data)
+    .read_to_end(&mut inflated)
+    .map_err(|e| Error::InvalidChunkData(format!("{}: {}", chunk_type, e)))?;
+  Ok(inflated)
+}
+
+// Like `inflate`, for a bare deflate stream without the zlib header and
+// Adler-32 around it
+pub fn inflate_raw(chunk_type: &str, data: &[u8]) -> Result<Vec<u8>> {
+  let mut inflated = vec![];
+  DeflateDecoder::new(data)
+    .read_to_end(&mut inflated)
+    .map_err(|e| Error::InvalidChunkData(format!("{}: {}", chunk_type, e)))?;
+  Ok(inflated)
+}
+
+// How many bytes `data` inflates to, giving up once it passes `max` so
+// that a zip bomb costs no more than `max` bytes of work. A corrupt stream
+// counts up to where it breaks.
+pub fn inflated_size(data: &[u8], max: usize) -> usize {
+  let mut decoder = ZlibDecoder::new(data);
+  let mut buffer = [0; 8 * 1024];
+  let mut size = 0;
+  while size <= max {
+    match decoder.read(&mut buffer) {
+      Ok(0) | Err(_) => break,
+      Ok(read) => size += read,
+    }
+  }
+  size
+}
+
+struct Adler32 {
+  a: u32,
+  b: u32,
+}
+
+impl Adler32 {
+  fn new() -> Self {
+    Self { a: 1, b: 0 }
+  }
+
+  fn update(&mut self, data: &[u8]) {
+    // 5552 bytes is the most that can be summed before b can overflow
+    for block in data.chunks(5552) {
+      for &byte in block {
+        self.a += byte as u32;
+        self.b += self.a;
+      }
+      self.a %= ADLER_MODULUS;
+      self.b %= ADLER_MODULUS;
+    }
+  }
+
+  fn finish(&self) -> u32 {
+    (self.b << 16) | self.a
+  }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+  let mut adler = Adler32::new();
+  adler.update(data);
+  adler.finish()
+}
+
+// Inflates a zlib stream only to check it, without keeping the output.
+// Unlike `inflate`, this tells a corrupt deflate stream apart from a bad
+// Adler-32, and says how far into `data` the problem is. Returns the size
+// of the inflated data.
+pub fn check(data: &[u8]) -> Result<usize, (usize, String)> {
+  if data.len() < 2 {
+    return Err((0, "zlib stream is truncated".to_string()));
+  }
+  let (cmf, flg) = (data[0], data[1]);
+  if cmf & 0x0f != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) || flg & 0x20 != 0 {
+    return Err((0, "invalid zlib header".to_string()));
+  }
+
+  let deflated = &data[2..];
+  let mut decompress = Decompress::new(false);
+  let mut buffer = vec![0; 64 * 1024];
+  let mut adler = Adler32::new();
+
+  loop {
+    let consumed = decompress.total_in() as usize;
+    let produced = decompress.total_out();
+    let status = decompress
+      .decompress(&deflated[consumed..], &mut buffer, FlushDecompress::None)
+      .map_err(|e| (2 + decompress.total_in() as usize, format!("corrupt deflate data: {}", e)))?;
+
+    adler.update(&buffer[..(decompress.total_out() - produced) as usize]);
+
+    match status {
+      Status::StreamEnd => break,
+      _ if decompress.total_in() as usize == consumed && decompress.total_out() == produced => {
+        return Err((data.len(), "zlib stream ends early".to_string()));
+      }
+      _ => {}
+    }
+  }
+
+  let end = 2 + decompress.total_in() as usize;
+  let Some(trailer) = data.get(end..end + 4) else {
+    return Err((end, "missing Adler-32 checksum".to_string()));
+  };
+  let stored = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+  let computed = adler.finish();
+  if stored != computed {
+    return Err((end, format!("Adler-32 mismatch (stored {:#010x}, computed {:#010x})", stored, computed)));
+  }
+  Ok(decompress.total_out() as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(20);
+        let stored = deflate_level(&data, 0);
+        let best = deflate_level(&data, 9);
+
+        assert!(best.len() < stored.len());
+        assert_eq!(inflate("IDAT", &stored).unwrap(), data);
+        assert_eq!(inflate("IDAT", &best).unwrap(), data);
+        assert!(inflate("IDAT", &best[..best.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_inflate_raw() {
+        let data = b"no header, no checksum".repeat(10);
+        let zlib = deflate(&data);
+        assert_eq!(inflate_raw("IDAT", &zlib[2..zlib.len() - 4]).unwrap(), data);
+        assert!(inflate("IDAT", &zlib[2..zlib.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_inflated_size() {
+        let compressed = deflate(&[0; 100_000]);
+        assert_eq!(inflated_size(&compressed, usize::MAX), 100_000);
+        assert!(inflated_size(&compressed, 1000) <= 1000 + 8 * 1024);
+        assert!(inflated_size(&compressed, 1000) > 1000);
+        assert_eq!(inflated_size(b"not zlib", usize::MAX), 0);
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[0xff; 10000]), 0xb623eb2b);
+    }
+
+    #[test]
+    fn test_check() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(20);
+        let compressed = deflate(&data);
+        assert_eq!(check(&compressed), Ok(data.len()));
+
+        let mut bad_adler = compressed.clone();
+        *bad_adler.last_mut().unwrap() ^= 1;
+        let (offset, message) = check(&bad_adler).unwrap_err();
+        assert_eq!(offset, compressed.len() - 4);
+        assert!(message.starts_with("Adler-32 mismatch"));
+
+        assert_eq!(check(&compressed[..compressed.len() - 2]).unwrap_err().1, "missing Adler-32 checksum");
+        assert_eq!(check(&compressed[..10]).unwrap_err(), (10, "zlib stream ends early".to_string()));
+        assert_eq!(check(&[0x78, 0x00]).unwrap_err().1, "invalid zlib header");
+
+        // A stored block whose length and its complement disagree
+        let corrupt = [0x78, 0x01, 0x01, 0x05, 0x00, 0x00, 0x00];
+        let (offset, message) = check(&corrupt).unwrap_err();
+        assert!(offset <= corrupt.len());
+        assert!(message.starts_with("corrupt deflate data"));
+    }
+
+    #[cfg(feature = "zopfli")]
+    #[test]
+    fn test_zopfli() {
+        let data = b"the quick brown fox jumps over the lazy dog ".repeat(20);
+        assert_eq!(inflate("IDAT", &deflate_zopfli(&data)).unwrap(), data);
+    }
+}